@@ -1,20 +1,57 @@
-use std::io;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::mem;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
 
 use actix_cors::Cors;
+use actix_web::error::{BlockingError, InternalError, JsonPayloadError};
+use actix_web::http::StatusCode;
 use actix_web::middleware::Logger;
-use actix_web::{get, post, web, App, HttpResponse, HttpServer};
+use actix_web_actors::ws;
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
 
-use crate::audio_engine::messages::{Command, Response};
-use crate::authorization::TokenAuthorization;
-use crate::theme::Theme;
+use actix_web::{delete, get, patch, post, put, web, App, HttpRequest, HttpResponse, HttpServer};
+use chrono::{DateTime, Utc};
 
-pub type ChannelSender = Sender<Command>;
-pub type ResponseReceiver = Receiver<Response>;
+use crate::audio_engine::encoder::encode_preview;
+use crate::audio_engine::engine::heartbeat::Heartbeat;
+use crate::audio_engine::engine::state_version::StateVersion;
+use crate::audio_engine::loader::error::AudioFileLoaderError;
+use crate::audio_engine::messages::{
+    ApiError, Command, EngineEvent, ErrorCode, MissingSampleBehavior, NormalizeMode, OutputChannel,
+    Request, Response, SoundChanges, TriggerAction,
+};
+use crate::audio_engine::normalize::normalize_samples;
+use crate::authorization::{ScopedToken, TokenAuthorization, TokenScope, TokenStore};
+use crate::config::{Config, ConfigSource};
+use crate::idempotency::Idempotency;
+use crate::import::{import_theme, ImportFormat};
+use crate::lint::lint_theme;
+use crate::samplesdb::{self, Backup, SamplesDBError, SharedSamplesDB};
+use crate::satellite::{SatelliteMessage, Satellites};
+use crate::sessions::Sessions;
+use crate::settings::Settings;
+use crate::theme::{Theme, Waveform};
+
+pub type ChannelSender = Sender<Request>;
 
 pub mod api_response {
     use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::audio_engine::backends::base::{BackendCapabilities, Equalizer, SourcePool};
+    use crate::audio_engine::engine::LoadState;
+    use crate::audio_engine::messages::{
+        DecodeWarning, DelayedCommandStatus, EngineEvent, LibrarySample, LimiterStatus,
+        LockoutStatus, NormalizedSample, OverlayTrack, PlaylistStatus, PreloadStatus, SoundStatus,
+    };
+    use crate::audio_engine::resampler::Resampler;
+    use crate::lint::LintMessage;
 
     #[derive(Serialize)]
     pub struct Status {
@@ -24,248 +61,3071 @@ pub mod api_response {
         pub sounds_playing: Vec<String>,
         pub sounds_playing_next: HashMap<String, u64>,
         pub previewing: Vec<String>,
+        pub load_state: LoadState,
+        pub auditioning: Option<String>,
+        pub preview_queue: Vec<String>,
+        pub clients: Vec<String>,
+        pub last_change_by: Option<String>,
+        pub ducking: bool,
+        pub sounds: HashMap<String, SoundStatus>,
+        pub macros_running: Vec<String>,
+        pub playlists: Vec<PlaylistStatus>,
+        pub lockout: Option<LockoutStatus>,
+        pub preload: Option<PreloadStatus>,
+        pub scene: Option<String>,
+        pub solo: Option<String>,
+        pub device_lost: bool,
+        pub source_pool: Option<SourcePool>,
+        pub waiting_for_source: Vec<String>,
+        pub eq: Equalizer,
+        pub limiter: LimiterStatus,
+        pub time_scale: f32,
+        pub group_volumes: HashMap<String, f32>,
+        pub version: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct Overlay {
+        pub theme: Option<String>,
+        pub music: Option<OverlayTrack>,
+        pub ambience: Vec<String>,
+        /// State version of the engine, as in the status
+        pub version: u64,
+    }
+
+    /// An engine event as sent to /events, with the state version of the engine
+    #[derive(Serialize)]
+    pub struct Event<'a> {
+        #[serde(flatten)]
+        pub event: &'a EngineEvent,
+        pub version: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct LoadTheme {
+        pub skipped: Vec<String>,
+        pub placeholders: Vec<String>,
+        /// Theme loaded just before that never got to play because of this one
+        pub superseded: Option<String>,
+        pub warnings: Vec<DecodeWarning>,
+    }
+
+    #[derive(Serialize)]
+    pub struct FireTrigger {
+        /// Sounds that were triggered
+        pub sounds: Vec<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct PreloadTheme {
+        /// Files of the theme that are being loaded, see `preload` in the status
+        pub sounds: usize,
+    }
+
+    #[derive(Serialize)]
+    pub struct SoundPatch {
+        pub warnings: Vec<DecodeWarning>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Session {
+        pub session: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct Lint {
+        pub messages: Vec<LintMessage>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Normalized {
+        pub samples: Vec<NormalizedSample>,
+        pub failed: Vec<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct DelayedCommand {
+        pub id: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct Health {
+        pub healthy: bool,
+        /// Milliseconds since the audio thread last passed through its loop
+        pub heartbeat_age: Option<u64>,
+        pub engine_alive: bool,
+        /// Unknown while the engine doesn't answer
+        pub device_alive: Option<bool>,
+        pub database_alive: Option<bool>,
+        pub database_error: Option<String>,
+        /// Only backends with a limited number of sources have a count
+        pub free_sources: Option<usize>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Configuration {
+        pub backend: String,
+        pub device: Option<String>,
+        pub output_rate: Option<i32>,
+        pub resampler: Resampler,
+        /// Only backends with a limited number of sources have a count
+        pub sources: Option<usize>,
+        /// How far the sources may grow
+        pub max_sources: Option<usize>,
+        pub capabilities: BackendCapabilities,
+        pub sound_library: PathBuf,
+        /// sqlite or postgres
+        pub database: String,
+        pub snapshots: bool,
+        pub pause_fade: u64,
+        pub theme_fade: u64,
+        pub default_crossfade: u64,
+        pub max_previews: usize,
+        pub queue_previews: bool,
+        pub ducking: bool,
+        pub command_log: bool,
+        pub tick: u64,
+        /// Cargo features the server was built with
+        pub features: Vec<&'static str>,
+    }
+
+    #[derive(Serialize)]
+    pub struct SoundPosition {
+        pub position: f32,
+        pub length: f32,
+        pub playing: bool,
+    }
+
+    #[derive(Serialize)]
+    pub struct DelayedCommands {
+        pub commands: Vec<DelayedCommandStatus>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Reload {
+        pub applied: Vec<String>,
+        pub restart_required: Vec<String>,
     }
 
     #[derive(Serialize)]
     pub struct SoundLibrary {
-        pub samples: Vec<String>,
+        pub samples: Vec<LibrarySample>,
+    }
+
+    /// The library as listed before samples had ids and formats, each sample being its
+    /// path and tags. Kept at `GET /library` for the clients relying on it.
+    #[derive(Serialize)]
+    pub enum LegacySoundLibrary {
+        SoundLibrary { samples: Vec<(String, Vec<String>)> },
     }
 }
 
+// Header clients use to identify the session they registered via `POST /session`
+const SESSION_HEADER: &str = "X-Session";
+
+// How often the overlay event stream checks for a changed soundscape
+const OVERLAY_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Milliseconds the audio thread may go without passing through its loop before /health
+// reports it as stuck, loading big themes takes a while
+const HEARTBEAT_TIMEOUT_MS: u64 = 5000;
+
+// How often the engine event stream asks for new events
+const ENGINE_EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+// How long a request waits for the engine to respond before giving up on it
+const ENGINE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Size of the chunks a backup is sent in, and how many of them may wait for the client
+const BACKUP_CHUNK_SIZE: usize = 64 * 1024;
+const BACKUP_CHUNKS: usize = 16;
+
 struct APIData {
     sender: ChannelSender,
-    receiver: ResponseReceiver,
+    heartbeat: Heartbeat,
+    version: StateVersion,
+    /// Shared by all rooms, library-wide work runs on it directly so the engines keep playing
+    samplesdb: SharedSamplesDB,
+    sessions: Mutex<Sessions>,
+    satellites: Mutex<Satellites>,
+    tokens: Arc<RwLock<TokenStore>>,
+    /// Configuration currently in effect and where to read it again from on reload, shared
+    /// by all rooms like the tokens
+    config: Arc<Mutex<Config>>,
+    config_source: ConfigSource,
+    /// Ids of all rooms run by this server
+    rooms: Vec<String>,
+    /// Last encoded sample preview, browsers ask for it in several ranges
+    preview_cache: Mutex<Option<(i64, Arc<Vec<u8>>)>>,
 }
 
 impl APIData {
-    fn new(sender: ChannelSender, receiver: ResponseReceiver) -> Self {
-        Self { sender, receiver }
+    fn new(
+        sender: ChannelSender,
+        heartbeat: Heartbeat,
+        version: StateVersion,
+        samplesdb: SharedSamplesDB,
+        config: Arc<Mutex<Config>>,
+        config_source: ConfigSource,
+        tokens: Arc<RwLock<TokenStore>>,
+        rooms: Vec<String>,
+    ) -> Self {
+        Self {
+            sender,
+            heartbeat,
+            version: version.clone(),
+            samplesdb,
+            sessions: Mutex::new(Sessions::new()),
+            satellites: Mutex::new(Satellites::new(version)),
+            tokens,
+            config,
+            config_source,
+            rooms,
+            preview_cache: Mutex::new(None),
+        }
     }
 }
 
-type APIDataType = web::Data<Arc<Mutex<APIData>>>;
+type APIDataType = web::Data<Arc<APIData>>;
+
+fn session_id(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(SESSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn error_response(error: ApiError) -> HttpResponse {
+    let status = match error.code {
+        ErrorCode::NoThemeLoaded => StatusCode::CONFLICT,
+        ErrorCode::UnknownSound
+        | ErrorCode::UnknownMacro
+        | ErrorCode::UnknownPlaylist
+        | ErrorCode::SampleNotFound
+        | ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        ErrorCode::TooManyPreviews => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::InvalidValue | ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+        ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ErrorCode::DownloadFailed => StatusCode::BAD_GATEWAY,
+        ErrorCode::Database | ErrorCode::Backend | ErrorCode::Internal => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        ErrorCode::EngineUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::EngineTimeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorCode::DecodeWarning => StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorCode::LockedOut => StatusCode::LOCKED,
+    };
+
+    HttpResponse::build(status).json(Response::Error(error))
+}
+
+fn check_theme_scope(req: &HttpRequest, theme: Option<&str>) -> Result<(), HttpResponse> {
+    match req.extensions().get::<TokenScope>() {
+        Some(scope) if !theme.map_or(false, |theme| scope.allows_theme(theme)) => {
+            Err(error_response(ApiError::new(
+                ErrorCode::Forbidden,
+                "This token may not control this theme!",
+            )))
+        }
+        _ => Ok(()),
+    }
+}
 
 macro_rules! send_message {
-    ($sender: expr, $receiver: expr, $response: path, $message: expr) => {{
-        $sender
-            .send($message)
-            .expect("Failed to communicate with audio engine!");
-
-        match $receiver.recv() {
-            Ok(r) => match r {
-                Response::Error { message } => Err(message),
-                $response { .. } => Ok(r),
-                _ => panic!("Internal Error!"),
-            },
-            Err(_) => panic!("Internal Error!"),
+    ($sender: expr, $response: path, $message: expr) => {{
+        let (request, response) = Request::new($message);
+
+        // The channel takes requests from all workers at once without locking, the
+        // engine wakes up as soon as one comes in
+        let received = match $sender.send(request) {
+            // Waiting for the engine blocks, keep the workers free meanwhile
+            Ok(_) => web::block(move || response.recv_timeout(ENGINE_TIMEOUT)).await,
+            Err(_) => Err(BlockingError::Error(RecvTimeoutError::Disconnected)),
+        };
+
+        // Both ends of the channel are dropped once the audio thread has stopped
+        match received {
+            Ok(Response::Error(error)) => Err(error),
+            Ok(r @ $response { .. }) => Ok(r),
+            Ok(_) => panic!("Internal Error!"),
+            Err(BlockingError::Error(RecvTimeoutError::Timeout)) => Err(ApiError::engine_timeout()),
+            Err(_) => Err(ApiError::engine_unavailable()),
         }
     }};
 
-    ($sender: expr, $receiver: expr, $message: expr) => {{
-        send_message!($sender, $receiver, Response::Success, $message)
+    ($sender: expr, $message: expr) => {{
+        send_message!($sender, Response::Success, $message)
     }};
 }
 
-#[post("/pause")]
-async fn pause(state: APIDataType) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+/// Scoped tokens may only control the loaded theme if it is one of theirs. The engine
+/// checks the theme right before running the command, so no other theme can be loaded
+/// in between.
+fn scoped(req: &HttpRequest, command: Command) -> Command {
+    match req.extensions().get::<TokenScope>() {
+        Some(scope) if !scope.themes.is_empty() => Command::Scoped {
+            themes: scope.themes.clone(),
+            command: Box::new(command),
+        },
+        _ => command,
+    }
+}
 
-    match send_message!(api_data.sender, api_data.receiver, Command::Pause) {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+/// Like `send_message!`, with the command limited to the themes of scoped tokens
+macro_rules! send_scoped {
+    ($req: expr, $sender: expr, $response: path, $message: expr) => {{
+        send_message!($sender, $response, scoped(&$req, $message))
+    }};
+
+    ($req: expr, $sender: expr, $message: expr) => {{
+        send_message!($sender, scoped(&$req, $message))
+    }};
+}
+
+/// Changes to the whole library or server are out of reach of scoped tokens, they only
+/// control their themes
+fn check_unscoped(req: &HttpRequest) -> Result<(), HttpResponse> {
+    if req.extensions().get::<TokenScope>().is_some() {
+        return Err(error_response(ApiError::new(
+            ErrorCode::Forbidden,
+            "This token may only control its themes!",
+        )));
     }
+
+    Ok(())
 }
 
-#[post("/play")]
-async fn play(state: APIDataType) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+#[derive(Deserialize)]
+struct NewSession {
+    name: String,
+}
 
-    match send_message!(api_data.sender, api_data.receiver, Command::Play) {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+#[post("/session")]
+async fn create_session(state: APIDataType, payload: web::Json<NewSession>) -> HttpResponse {
+    let session = state
+        .sessions
+        .lock()
+        .unwrap()
+        .register(payload.into_inner().name);
+
+    HttpResponse::Ok().json(api_response::Session { session })
+}
+
+#[delete("/session")]
+async fn delete_session(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    match session_id(&req) {
+        Some(id) if state.sessions.lock().unwrap().remove(id) => HttpResponse::Ok().finish(),
+        _ => error_response(ApiError::new(ErrorCode::NotFound, "No such session!")),
+    }
+}
+
+#[post("/pause")]
+async fn pause(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::Pause) {
+        Ok(_) => {
+            state
+                .satellites
+                .lock()
+                .unwrap()
+                .broadcast(&SatelliteMessage::Pause);
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[post("/play")]
+async fn play(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::Play) {
+        Ok(_) => {
+            state
+                .satellites
+                .lock()
+                .unwrap()
+                .broadcast(&SatelliteMessage::Play);
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
     }
 }
 
 #[derive(Deserialize)]
 struct PreviewSound {
     name: String,
+    /// Play at full scale instead of at the volume the sound is set up to have in the mix
+    #[serde(default)]
+    raw: bool,
 }
 
 #[post("/preview")]
-async fn preview(state: APIDataType, payload: web::Json<PreviewSound>) -> HttpResponse {
-    let api_data = state.lock().unwrap();
-    match send_message!(
-        api_data.sender,
-        api_data.receiver,
+async fn preview(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<PreviewSound>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
         Command::PreviewSound {
-            sound: payload.name.clone()
+            sound: payload.name.clone(),
+            raw: payload.raw,
         }
     ) {
         Ok(_) => HttpResponse::Ok().finish(),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Parses a `Range: bytes=...` header into the first and last byte to send. Multi-range
+/// requests get the whole file, which is an allowed answer to them.
+fn byte_range(req: &HttpRequest, length: usize) -> Result<Option<(usize, usize)>, ()> {
+    let range = match req
+        .headers()
+        .get("Range")
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(range) if range.starts_with("bytes=") && !range.contains(',') => {
+            range["bytes=".len()..].trim().to_string()
+        }
+        _ => return Ok(None),
+    };
+
+    let mut bounds = range.splitn(2, '-');
+    let (start, end) = match (bounds.next(), bounds.next()) {
+        (Some(start), Some(end)) => (start.trim(), end.trim()),
+        _ => return Err(()),
+    };
+
+    let (start, end) = if start.is_empty() {
+        // The last `end` bytes
+        let suffix: usize = end.parse().map_err(|_| ())?;
+        if suffix == 0 {
+            return Err(());
+        }
+        (length.saturating_sub(suffix), length.saturating_sub(1))
+    } else {
+        let start: usize = start.parse().map_err(|_| ())?;
+        let end = if end.is_empty() {
+            length.saturating_sub(1)
+        } else {
+            end.parse::<usize>()
+                .map_err(|_| ())?
+                .min(length.saturating_sub(1))
+        };
+        (start, end)
+    };
+
+    if start >= length || start > end {
+        return Err(());
+    }
+
+    Ok(Some((start, end)))
+}
+
+async fn encoded_preview(state: &APIDataType, sample: i64) -> Result<Arc<Vec<u8>>, ApiError> {
+    if let Some((id, ref data)) = *state.preview_cache.lock().unwrap() {
+        if id == sample {
+            return Ok(data.clone());
+        }
+    }
+
+    let path = match send_message!(
+        state.sender,
+        Response::SamplePath,
+        Command::GetSamplePath { sample }
+    ) {
+        Ok(Response::SamplePath { path }) => path,
+        Ok(_) => unreachable!(),
+        Err(error) => return Err(error),
+    };
+
+    // Decoding and encoding takes a while for long files, keep the workers free meanwhile
+    let data = match web::block(move || encode_preview(&path)).await {
+        Ok(data) => Arc::new(data),
+        Err(BlockingError::Error(AudioFileLoaderError::UnsupportedFileFormat(format, _))) => {
+            return Err(ApiError::new(
+                ErrorCode::UnsupportedMediaType,
+                format!("Can't decode '{}' files!", format),
+            ))
+        }
+        Err(BlockingError::Error(e)) => return Err(ApiError::new(ErrorCode::Backend, e)),
+        Err(BlockingError::Canceled) => {
+            return Err(ApiError::new(
+                ErrorCode::Backend,
+                "Encoding the preview was canceled",
+            ))
+        }
+    };
+
+    *state.preview_cache.lock().unwrap() = Some((sample, data.clone()));
+    Ok(data)
+}
+
+/// The sample as Ogg Opus, for web clients to listen to it without the server playing it
+#[get("/samples/{id}/preview")]
+async fn sample_preview(state: APIDataType, req: HttpRequest, id: web::Path<i64>) -> HttpResponse {
+    let data = match encoded_preview(&state, id.into_inner()).await {
+        Ok(data) => data,
+        Err(error) => return error_response(error),
+    };
+
+    match byte_range(&req, data.len()) {
+        Ok(None) => HttpResponse::Ok()
+            .header("Accept-Ranges", "bytes")
+            .content_type("audio/ogg")
+            .body(data.to_vec()),
+        Ok(Some((start, end))) => HttpResponse::build(StatusCode::PARTIAL_CONTENT)
+            .header("Accept-Ranges", "bytes")
+            .header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end, data.len()),
+            )
+            .content_type("audio/ogg")
+            .body(data[start..=end].to_vec()),
+        Err(()) => HttpResponse::build(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("Content-Range", format!("bytes */{}", data.len()))
+            .finish(),
+    }
+}
+
+/// Peaks for drawing the sample's waveform, computed when the library is scanned
+#[get("/samples/{id}/waveform")]
+async fn sample_waveform(state: APIDataType, id: web::Path<i64>) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::Waveform,
+        Command::GetWaveform {
+            sample: id.into_inner()
+        }
+    ) {
+        Ok(Response::Waveform { waveform }) => HttpResponse::Ok().json(waveform),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct LoadThemeQuery {
+    #[serde(default)]
+    on_missing: MissingSampleBehavior,
+    /// Fail instead of only warning about samples that likely sound wrong
+    #[serde(default)]
+    strict: bool,
+}
+
+/// The loaded theme the way it was uploaded, to tweak it and upload it again
+#[get("/theme")]
+async fn loaded_theme(state: APIDataType) -> HttpResponse {
+    match send_message!(state.sender, Response::Theme, Command::GetTheme) {
+        Ok(Response::Theme { theme }) => HttpResponse::Ok().json(theme),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
     }
 }
 
 #[post("/theme")]
-async fn theme(state: APIDataType, payload: web::Json<Theme>) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+async fn theme(
+    state: APIDataType,
+    req: HttpRequest,
+    query: web::Query<LoadThemeQuery>,
+    payload: web::Json<Theme>,
+) -> HttpResponse {
+    if let Err(response) = check_theme_scope(&req, Some(&payload.name)) {
+        return response;
+    }
+
+    // The engine takes the theme, keep a copy for the satellites
+    let theme_json = match serde_json::to_value(&*payload) {
+        Ok(theme_json) => theme_json,
+        Err(e) => {
+            return error_response(ApiError::new(
+                ErrorCode::InvalidValue,
+                format!("Failed to read theme '{}': {}", payload.name, e),
+            ))
+        }
+    };
+
     match send_message!(
-        api_data.sender,
-        api_data.receiver,
+        state.sender,
+        Response::LoadTheme,
         Command::LoadTheme {
-            theme: payload.into_inner()
+            theme: payload.into_inner(),
+            on_missing: query.on_missing,
+            strict: query.strict,
         }
     ) {
+        Ok(Response::LoadTheme {
+            skipped,
+            placeholders,
+            superseded,
+            warnings,
+            ..
+        }) => {
+            state
+                .satellites
+                .lock()
+                .unwrap()
+                .broadcast(&SatelliteMessage::LoadTheme {
+                    theme: theme_json,
+                    on_missing: query.on_missing,
+                });
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().json(api_response::LoadTheme {
+                skipped,
+                placeholders,
+                superseded,
+                warnings,
+            })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Stores a theme on the server, replacing a stored theme of the same name
+#[post("/themes")]
+async fn save_theme(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<serde_json::Value>,
+) -> HttpResponse {
+    let theme = payload.into_inner();
+
+    // Don't store anything that can't be loaded later on
+    let name = match serde_json::from_value::<Theme>(theme.clone()) {
+        Ok(parsed) => parsed.name,
+        Err(e) => return error_response(ApiError::new(ErrorCode::InvalidValue, e)),
+    };
+
+    // Scoped tokens could otherwise overwrite the stored themes of others
+    if let Err(response) = check_theme_scope(&req, Some(&name)) {
+        return response;
+    }
+
+    match send_message!(state.sender, Command::SaveTheme { name, theme }) {
         Ok(_) => HttpResponse::Ok().finish(),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Err(error) => error_response(error),
     }
 }
 
-#[derive(Deserialize)]
-struct Trigger {
-    name: String,
+#[get("/themes")]
+async fn stored_themes(state: APIDataType) -> HttpResponse {
+    match send_message!(state.sender, Response::ThemeList, Command::GetStoredThemes) {
+        Ok(Response::ThemeList { themes }) => HttpResponse::Ok().json(themes),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
 }
 
-#[post("/trigger")]
-async fn trigger(state: APIDataType, payload: web::Json<Trigger>) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+#[get("/themes/{name}")]
+async fn stored_theme(state: APIDataType, name: web::Path<String>) -> HttpResponse {
     match send_message!(
-        api_data.sender,
-        api_data.receiver,
-        Command::Trigger {
-            sound: payload.name.clone()
+        state.sender,
+        Response::StoredTheme,
+        Command::GetStoredTheme {
+            name: name.into_inner()
         }
     ) {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Ok(Response::StoredTheme { theme }) => HttpResponse::Ok().json(theme),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
     }
 }
 
-#[get("/status")]
-async fn status(state: APIDataType) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+#[post("/themes/{name}/load")]
+async fn load_stored_theme(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    query: web::Query<LoadThemeQuery>,
+) -> HttpResponse {
+    if let Err(response) = check_theme_scope(&req, Some(name.as_str())) {
+        return response;
+    }
+
+    let name = name.into_inner();
 
     match send_message!(
-        api_data.sender,
-        api_data.receiver,
-        Response::Status,
-        Command::GetStatus
+        state.sender,
+        Response::LoadTheme,
+        Command::LoadStoredTheme {
+            name: name.clone(),
+            on_missing: query.on_missing,
+            strict: query.strict,
+        }
     ) {
-        Ok(status) => HttpResponse::Ok().json(status),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Ok(Response::LoadTheme {
+            skipped,
+            placeholders,
+            superseded,
+            warnings,
+            ..
+        }) => {
+            if let Ok(Response::StoredTheme { theme }) = send_message!(
+                state.sender,
+                Response::StoredTheme,
+                Command::GetStoredTheme { name }
+            ) {
+                state
+                    .satellites
+                    .lock()
+                    .unwrap()
+                    .broadcast(&SatelliteMessage::LoadTheme {
+                        theme,
+                        on_missing: query.on_missing,
+                    });
+            }
+
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().json(api_response::LoadTheme {
+                skipped,
+                placeholders,
+                superseded,
+                warnings,
+            })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
     }
 }
 
-#[get("/library")]
-async fn library(state: APIDataType) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+/// Loads the files of a stored theme in the background, so switching to it later on
+/// doesn't hitch
+#[post("/themes/{name}/preload")]
+async fn preload_stored_theme(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> HttpResponse {
+    if let Err(response) = check_theme_scope(&req, Some(name.as_str())) {
+        return response;
+    }
 
     match send_message!(
-        api_data.sender,
-        api_data.receiver,
-        Response::SoundLibrary,
-        Command::GetSoundLibrary
+        state.sender,
+        Response::PreloadTheme,
+        Command::PreloadTheme {
+            name: name.into_inner()
+        }
     ) {
-        Ok(library) => HttpResponse::Ok().json(library),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Ok(Response::PreloadTheme { sounds }) => {
+            HttpResponse::Accepted().json(api_response::PreloadTheme { sounds })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
     }
 }
 
 #[derive(Deserialize)]
-struct Volume {
-    value: f32,
+struct ImportQuery {
+    format: ImportFormat,
 }
 
-#[post("/volume")]
-async fn volume(state: APIDataType, payload: web::Json<Volume>) -> HttpResponse {
-    let api_data = state.lock().unwrap();
-    match send_message!(
-        api_data.sender,
-        api_data.receiver,
-        Command::SetVolume {
-            value: payload.value
+/// Converts an export of another soundscape tool into a theme without loading it
+#[post("/theme/import")]
+async fn import(
+    query: web::Query<ImportQuery>,
+    payload: web::Json<serde_json::Value>,
+) -> HttpResponse {
+    match import_theme(query.format, &payload) {
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(message) => error_response(ApiError::new(ErrorCode::InvalidValue, message)),
+    }
+}
+
+#[post("/theme/lint")]
+async fn lint(state: APIDataType, payload: web::Json<Theme>) -> HttpResponse {
+    // Don't hold the lock while linting, it reads the headers of the files
+    let library_path = state.config.lock().unwrap().sound_library();
+    let theme = payload.into_inner();
+
+    // A theme with many files takes a while, keep the workers free meanwhile
+    match web::block(move || Ok::<_, ()>(lint_theme(&theme, Some(&library_path)))).await {
+        Ok(messages) => HttpResponse::Ok().json(api_response::Lint { messages }),
+        Err(_) => error_response(ApiError::new(
+            ErrorCode::Internal,
+            "Linting the theme was canceled",
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct Trigger {
+    name: String,
+    /// toggle (the default), on, off or once
+    #[serde(default)]
+    action: TriggerAction,
+}
+
+#[derive(Deserialize)]
+struct Lockout {
+    /// Seconds to hold back triggers for
+    duration: f32,
+    /// Queue triggers and release them afterwards instead of rejecting them
+    #[serde(default)]
+    queue: bool,
+}
+
+#[derive(Deserialize)]
+struct Limiter {
+    enabled: bool,
+    /// Sum of the sounds' gains the mix may reach before it is turned down
+    ceiling: Option<f32>,
+}
+
+/// Turns the output down while the sounds playing stack up to more than it can take
+#[post("/limiter")]
+async fn limiter(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<Limiter>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetLimiter {
+            enabled: payload.enabled,
+            ceiling: payload.ceiling,
         }
     ) {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
     }
 }
 
-#[get("/driver")]
-async fn driver(state: APIDataType) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+/// Holds back triggers for a while, e.g. during a narration beat
+#[post("/lockout")]
+async fn lockout(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<Lockout>,
+) -> HttpResponse {
+    if payload.duration <= 0.0 {
+        return error_response(ApiError::new(
+            ErrorCode::InvalidValue,
+            "The duration of a lockout must be positive!",
+        ));
+    }
 
-    match send_message!(
-        api_data.sender,
-        api_data.receiver,
-        Response::Driver,
-        Command::GetDriver
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetLockout {
+            duration: (payload.duration * 1000.0) as u64,
+            queue: payload.queue,
+        }
     ) {
-        Ok(driver) => HttpResponse::Ok().json(driver),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
     }
 }
 
-#[get("/driverlist")]
-async fn driverlist(state: APIDataType) -> HttpResponse {
-    let api_data = state.lock().unwrap();
+/// Lifts the lockout early, releasing the queued triggers
+#[delete("/lockout")]
+async fn end_lockout(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetLockout {
+            duration: 0,
+            queue: false,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
 
-    match send_message!(
-        api_data.sender,
-        api_data.receiver,
-        Response::DriverList,
-        Command::GetDriverList
+#[post("/trigger")]
+async fn trigger(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<Trigger>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::trigger(payload.name.clone(), payload.action)
     ) {
-        Ok(driverlist) => HttpResponse::Ok().json(driverlist),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Ok(_) => {
+            state
+                .satellites
+                .lock()
+                .unwrap()
+                .broadcast(&SatelliteMessage::Trigger {
+                    sound: payload.name.clone(),
+                    action: payload.action,
+                });
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
     }
 }
 
 #[derive(Deserialize)]
-struct Driver {
-    id: i32,
+struct Scene {
+    name: String,
 }
 
-#[post("/driver")]
-async fn set_driver(state: APIDataType, payload: web::Json<Driver>) -> HttpResponse {
-    let api_data = state.lock().unwrap();
-    match send_message!(
-        api_data.sender,
-        api_data.receiver,
-        Command::SetDriver { id: payload.id }
+/// Switches between the moods of the loaded theme without loading another one
+#[post("/scene")]
+async fn scene(state: APIDataType, req: HttpRequest, payload: web::Json<Scene>) -> HttpResponse {
+    let name = payload.into_inner().name;
+
+    match send_scoped!(req, state.sender, Command::SetScene { name: name.clone() }) {
+        Ok(_) => {
+            state
+                .satellites
+                .lock()
+                .unwrap()
+                .broadcast(&SatelliteMessage::SetScene { name });
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct FireTriggerQuery {
+    #[serde(default)]
+    action: TriggerAction,
+}
+
+/// Triggers all sounds listening to a trigger name at once, e.g. "combat" starting the
+/// drums and stopping the tavern chatter
+#[post("/trigger/{name}")]
+async fn fire_trigger(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    query: web::Query<FireTriggerQuery>,
+) -> HttpResponse {
+    let trigger = name.into_inner();
+
+    match send_scoped!(
+        req,
+        state.sender,
+        Response::FireTrigger,
+        Command::FireTrigger {
+            trigger: trigger.clone(),
+            action: query.action,
+        }
     ) {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(message) => HttpResponse::BadRequest().json(Response::Error { message }),
+        Ok(Response::FireTrigger { sounds }) => {
+            state
+                .satellites
+                .lock()
+                .unwrap()
+                .broadcast(&SatelliteMessage::FireTrigger {
+                    trigger,
+                    action: query.action,
+                });
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().json(api_response::FireTrigger { sounds })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
     }
 }
 
-pub async fn start_web_service(
-    host: String,
-    port: u32,
-    sender: ChannelSender,
-    receiver: ResponseReceiver,
-    allowed_token: String,
-) -> io::Result<()> {
-    let data = Arc::new(Mutex::new(APIData::new(sender, receiver)));
+/// Ids of the rooms, each is controlled through the same routes below /rooms/{id}
+#[get("/rooms")]
+async fn room_list(state: APIDataType) -> HttpResponse {
+    HttpResponse::Ok().json(&state.rooms)
+}
 
-    HttpServer::new(move || {
-        App::new()
-            .data(data.clone())
-            .wrap(Logger::default())
-            .wrap(TokenAuthorization::new(&allowed_token))
-            .service(play)
-            .service(pause)
-            .service(preview)
-            .service(status)
-            .service(theme)
-            .service(trigger)
-            .service(library)
-            .service(volume)
-            .service(driver)
-            .service(driverlist)
-            .service(set_driver)
-    })
-    .bind(format!("{}:{}", host, port))?
-    .start()
-    .await
+#[get("/status")]
+async fn status(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    // Polling the status doubles as the clients' heartbeat
+    state.sessions.lock().unwrap().touch(session_id(&req));
+
+    match send_message!(state.sender, Response::Status, Command::GetStatus) {
+        Ok(mut status) => {
+            // Presence is tracked by the API, the engine doesn't know about clients
+            if let Response::Status {
+                ref mut clients,
+                ref mut last_change_by,
+                ..
+            } = status
+            {
+                let sessions = state.sessions.lock().unwrap();
+                *clients = sessions.connected();
+                *last_change_by = sessions.last_change_by();
+            }
+
+            HttpResponse::Ok().json(status)
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+async fn fetch_overlay(state: &APIDataType) -> Result<api_response::Overlay, ApiError> {
+    match send_message!(state.sender, Response::Overlay, Command::GetOverlay)? {
+        Response::Overlay {
+            theme,
+            music,
+            ambience,
+        } => Ok(api_response::Overlay {
+            theme,
+            music,
+            ambience,
+            version: state.version.get(),
+        }),
+        _ => unreachable!(),
+    }
+}
+
+#[get("/overlay")]
+async fn overlay(state: APIDataType) -> HttpResponse {
+    match fetch_overlay(&state).await {
+        Ok(overlay) => HttpResponse::Ok().json(overlay),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Server-sent events variant of /overlay, pushes the overlay whenever it changes
+#[get("/overlay/events")]
+async fn overlay_events(state: APIDataType) -> HttpResponse {
+    let events = futures::stream::unfold((state, None), |(state, last)| async move {
+        loop {
+            if last.is_some() {
+                actix_rt::time::delay_for(OVERLAY_EVENT_INTERVAL).await;
+            }
+
+            let data = match fetch_overlay(&state).await {
+                Ok(overlay) => serde_json::to_string(&overlay).unwrap(),
+                Err(error) => {
+                    warn!("Closing overlay event stream: {}", error);
+                    return None;
+                }
+            };
+
+            if last.as_ref() != Some(&data) {
+                let event = web::Bytes::from(format!("data: {}\n\n", data));
+                return Some((Ok::<_, actix_web::Error>(event), (state, Some(data))));
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(events)
+}
+
+async fn fetch_events(state: &APIDataType, since: u64) -> Result<Vec<EngineEvent>, ApiError> {
+    match send_message!(state.sender, Response::Events, Command::GetEvents { since })? {
+        Response::Events { events } => Ok(events),
+        _ => unreachable!(),
+    }
+}
+
+/// Server-sent events with warnings of the engine, e.g. when it runs out of sources. New
+/// clients get what happens from now on, reconnecting ones what they missed (as far as
+/// the engine still has it) via `Last-Event-ID`.
+#[get("/events")]
+async fn engine_events(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    let last_id = match last_event_id {
+        Some(id) => id,
+        None => match fetch_events(&state, 0).await {
+            Ok(events) => events.last().map_or(0, |event| event.id),
+            Err(error) => return error_response(error),
+        },
+    };
+
+    let events = futures::stream::unfold((state, last_id), |(state, last_id)| async move {
+        loop {
+            actix_rt::time::delay_for(ENGINE_EVENT_INTERVAL).await;
+
+            let events = match fetch_events(&state, last_id).await {
+                Ok(events) => events,
+                Err(error) => {
+                    warn!("Closing engine event stream: {}", error);
+                    return None;
+                }
+            };
+
+            if let Some(last) = events.last() {
+                let last_id = last.id;
+                let version = state.version.get();
+                let data: String = events
+                    .iter()
+                    .map(|event| {
+                        let frame = api_response::Event { event, version };
+                        format!(
+                            "id: {}\ndata: {}\n\n",
+                            event.id,
+                            serde_json::to_string(&frame).unwrap()
+                        )
+                    })
+                    .collect();
+
+                let chunk = web::Bytes::from(data);
+                return Some((Ok::<_, actix_web::Error>(chunk), (state, last_id)));
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(events)
+}
+
+/// Satellites started with `--satellite` connect here via websocket to receive the
+/// theme, play/pause and triggers of this instance
+#[get("/satellite")]
+async fn satellite(state: APIDataType, req: HttpRequest, stream: web::Payload) -> HttpResponse {
+    if ws::handshake(&req).is_err() {
+        return error_response(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "Expected a websocket handshake!",
+        ));
+    }
+
+    let socket = state.satellites.lock().unwrap().connect();
+    ws::start(socket, &req, stream).unwrap_or_else(|e| e.into())
+}
+
+async fn fetch_library(state: &APIDataType) -> Result<Vec<LibrarySample>, ApiError> {
+    match send_message!(
+        state.sender,
+        Response::SoundLibrary,
+        Command::GetSoundLibrary
+    )? {
+        Response::SoundLibrary { samples } => Ok(samples),
+        _ => unreachable!(),
+    }
+}
+
+/// Paths and tags of all samples, in the shape of the first version of the API
+#[get("/library")]
+async fn library(state: APIDataType) -> HttpResponse {
+    match fetch_library(&state).await {
+        Ok(samples) => {
+            let samples = samples
+                .into_iter()
+                .map(|sample| (sample.path, sample.tags))
+                .collect();
+            HttpResponse::Ok().json(api_response::LegacySoundLibrary::SoundLibrary { samples })
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+/// All samples with their ids, tags, durations and formats
+#[get("/v2/library")]
+async fn library_v2(state: APIDataType) -> HttpResponse {
+    match fetch_library(&state).await {
+        Ok(samples) => HttpResponse::Ok().json(api_response::SoundLibrary { samples }),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct SoundPatch {
+    file: Option<String>,
+    crossfade: Option<u64>,
+    #[serde(default)]
+    strict: bool,
+    #[serde(flatten)]
+    changes: SoundChanges,
+}
+
+/// Swaps the file of a running sound and/or tweaks its parameters, without uploading the
+/// whole theme again. Nothing changes if the file can't be swapped.
+#[patch("/sounds/{name}")]
+async fn patch_sound(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Json<SoundPatch>,
+) -> HttpResponse {
+    let payload = payload.into_inner();
+    let sound = name.into_inner();
+
+    if payload.file.is_none() && payload.changes.is_empty() {
+        return error_response(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "Nothing to change!",
+        ));
+    }
+
+    // The engine applies the changes along with the file in one go
+    let warnings = match payload.file {
+        Some(file) => match send_scoped!(
+            req,
+            state.sender,
+            Response::ReplaceSoundFile,
+            Command::ReplaceSoundFile {
+                sound,
+                file,
+                crossfade: payload.crossfade,
+                strict: payload.strict,
+                changes: payload.changes,
+            }
+        ) {
+            Ok(Response::ReplaceSoundFile { warnings }) => warnings,
+            Ok(_) => unreachable!(),
+            Err(error) => return error_response(error),
+        },
+        None => match send_scoped!(
+            req,
+            state.sender,
+            Command::UpdateSound {
+                sound,
+                changes: payload.changes,
+            }
+        ) {
+            Ok(_) => Vec::new(),
+            Err(error) => return error_response(error),
+        },
+    };
+
+    state
+        .sessions
+        .lock()
+        .unwrap()
+        .record_change(session_id(&req));
+    HttpResponse::Ok().json(api_response::SoundPatch { warnings })
+}
+
+#[derive(Deserialize)]
+struct Position {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+/// Places the sound in the room, or relative to the listener if its position mode is
+/// listener. It stops following the path of its theme.
+#[post("/sounds/{name}/position")]
+async fn sound_position(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Json<Position>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetSoundPosition {
+            sound: name.into_inner(),
+            position: (payload.x, payload.y, payload.z),
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+/// State of a sound, e.g. whether it waits for a trigger or is playing
+#[get("/sounds/{name}")]
+async fn sound_status(state: APIDataType, name: web::Path<String>) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::SoundStatus,
+        Command::GetSoundStatus {
+            sound: name.into_inner()
+        }
+    ) {
+        Ok(Response::SoundStatus { status }) => HttpResponse::Ok().json(status),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// How far a sound has played, in seconds
+#[get("/sounds/{name}/position")]
+async fn sound_playback_position(state: APIDataType, name: web::Path<String>) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::SoundPosition,
+        Command::GetSoundPosition {
+            sound: name.into_inner()
+        }
+    ) {
+        Ok(Response::SoundPosition {
+            position,
+            length,
+            playing,
+        }) => HttpResponse::Ok().json(api_response::SoundPosition {
+            position,
+            length,
+            playing,
+        }),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[post("/sounds/{name}/seek")]
+async fn seek_sound(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Json<Seek>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SeekSound {
+            sound: name.into_inner(),
+            position: payload.position,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+/// Moves the listener, e.g. along with the party on a map
+#[post("/listener/position")]
+async fn listener_position(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<Position>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetListenerPosition {
+            position: (payload.x, payload.y, payload.z),
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+fn get_default_up() -> (f32, f32, f32) {
+    (0.0, 1.0, 0.0)
+}
+
+#[derive(Deserialize)]
+struct Orientation {
+    at: (f32, f32, f32),
+    #[serde(default = "get_default_up")]
+    up: (f32, f32, f32),
+}
+
+#[post("/listener/orientation")]
+async fn listener_orientation(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<Orientation>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetListenerOrientation {
+            at: payload.at,
+            up: payload.up,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct SoundCounters {
+    loops: Option<u32>,
+    repeats: Option<u32>,
+}
+
+#[post("/sounds/{name}/counters")]
+async fn sound_counters(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Json<SoundCounters>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetSoundCounters {
+            sound: name.into_inner(),
+            loops: payload.loops,
+            repeats: payload.repeats,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum StopAt {
+    Now,
+    LoopEnd,
+}
+
+impl Default for StopAt {
+    fn default() -> Self {
+        StopAt::Now
+    }
+}
+
+#[derive(Deserialize)]
+struct StopSound {
+    #[serde(default)]
+    at: StopAt,
+}
+
+#[post("/sounds/{name}/stop")]
+async fn stop_sound(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    query: web::Query<StopSound>,
+) -> HttpResponse {
+    let sound = name.into_inner();
+
+    let command = match query.at {
+        StopAt::Now => Command::StopSound { sound },
+        StopAt::LoopEnd => Command::StopSoundAtLoopEnd { sound },
+    };
+
+    match send_scoped!(req, state.sender, command) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+async fn arm_sound_response(
+    state: APIDataType,
+    req: HttpRequest,
+    sound: String,
+    armed: bool,
+) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::ArmSound { sound, armed }) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+/// Keeps a trigger sound ready to play, so the first trigger starts it without delay
+#[post("/sounds/{name}/arm")]
+async fn arm_sound(state: APIDataType, req: HttpRequest, name: web::Path<String>) -> HttpResponse {
+    arm_sound_response(state, req, name.into_inner(), true).await
+}
+
+#[post("/sounds/{name}/disarm")]
+async fn disarm_sound(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> HttpResponse {
+    arm_sound_response(state, req, name.into_inner(), false).await
+}
+
+async fn mute_sound_response(
+    state: APIDataType,
+    req: HttpRequest,
+    sound: String,
+    flag: bool,
+) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::SetSoundMuted { sound, flag }) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+/// Silences a sound while mixing, it keeps running and can be unmuted at any time
+#[post("/sounds/{name}/mute")]
+async fn mute_sound(state: APIDataType, req: HttpRequest, name: web::Path<String>) -> HttpResponse {
+    mute_sound_response(state, req, name.into_inner(), true).await
+}
+
+#[post("/sounds/{name}/unmute")]
+async fn unmute_sound(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> HttpResponse {
+    mute_sound_response(state, req, name.into_inner(), false).await
+}
+
+/// Silences all sounds but this one until the solo is cleared
+#[post("/sounds/{name}/solo")]
+async fn solo_sound(state: APIDataType, req: HttpRequest, name: web::Path<String>) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SoloSound {
+            sound: name.into_inner()
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[delete("/solo")]
+async fn clear_solo(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::ClearSolo) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct Trim {
+    gain: f32,
+}
+
+#[post("/sounds/{name}/trim")]
+async fn trim_sound(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Json<Trim>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetSoundTrim {
+            sound: name.into_inner(),
+            gain: payload.gain,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/settings")]
+async fn get_settings(state: APIDataType) -> HttpResponse {
+    match send_message!(state.sender, Response::Settings, Command::GetSettings) {
+        Ok(Response::Settings { settings }) => HttpResponse::Ok().json(settings),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Updates the fields given in the body, the others keep their stored value
+#[put("/settings")]
+async fn update_settings(state: APIDataType, payload: web::Json<Settings>) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::Settings,
+        Command::UpdateSettings {
+            settings: payload.into_inner(),
+        }
+    ) {
+        Ok(Response::Settings { settings }) => HttpResponse::Ok().json(settings),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/settings/{client}/{key}")]
+async fn get_client_setting(state: APIDataType, path: web::Path<(String, String)>) -> HttpResponse {
+    let (client, key) = path.into_inner();
+
+    match send_message!(
+        state.sender,
+        Response::ClientSetting,
+        Command::GetClientSetting { client, key }
+    ) {
+        Ok(Response::ClientSetting { value: Some(value) }) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(value),
+        Ok(_) => error_response(ApiError::new(ErrorCode::NotFound, "Setting not found!")),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Stores any JSON document for a client, e.g. its keymap under `/settings/{client}/keymap`
+#[put("/settings/{client}/{key}")]
+async fn set_client_setting(
+    state: APIDataType,
+    path: web::Path<(String, String)>,
+    payload: web::Json<serde_json::Value>,
+) -> HttpResponse {
+    let (client, key) = path.into_inner();
+
+    match send_message!(
+        state.sender,
+        Command::SetClientSetting {
+            client,
+            key,
+            value: payload.into_inner().to_string(),
+        }
+    ) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[post("/macros/{name}/run")]
+async fn run_macro(state: APIDataType, req: HttpRequest, name: web::Path<String>) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::RunMacro {
+            name: name.into_inner()
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+async fn skip_track(
+    state: APIDataType,
+    req: HttpRequest,
+    playlist: String,
+    back: bool,
+) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::SkipTrack { playlist, back }) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[post("/playlists/{name}/next")]
+async fn next_track(state: APIDataType, req: HttpRequest, name: web::Path<String>) -> HttpResponse {
+    skip_track(state, req, name.into_inner(), false).await
+}
+
+#[post("/playlists/{name}/previous")]
+async fn previous_track(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> HttpResponse {
+    skip_track(state, req, name.into_inner(), true).await
+}
+
+#[derive(Deserialize)]
+struct Seek {
+    /// Seconds into the current track or sound
+    position: f32,
+}
+
+#[post("/playlists/{name}/seek")]
+async fn seek_track(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Json<Seek>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SeekTrack {
+            playlist: name.into_inner(),
+            position: payload.position,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct DelayCommand {
+    /// Seconds from now
+    delay: Option<f32>,
+    /// Point in time instead of a delay, e.g. "2019-11-30T22:00:00+01:00"
+    at: Option<String>,
+    /// The command as it is sent to the engine, e.g. "Pause" or {"Trigger": {"sound": "Bell"}}
+    command: Command,
+}
+
+/// Milliseconds until the command of the payload should run
+fn command_delay(payload: &DelayCommand) -> Result<u64, ApiError> {
+    match (payload.delay, payload.at.as_ref()) {
+        (Some(delay), None) if delay >= 0.0 => Ok((delay * 1000.0) as u64),
+        (None, Some(at)) => {
+            let at = DateTime::parse_from_rfc3339(at).map_err(|e| {
+                ApiError::new(
+                    ErrorCode::InvalidValue,
+                    format!("Invalid time '{}': {}", at, e),
+                )
+            })?;
+            let delay = at.with_timezone(&Utc) - Utc::now();
+
+            if delay.num_milliseconds() < 0 {
+                return Err(ApiError::new(
+                    ErrorCode::InvalidValue,
+                    "The time has already passed!",
+                ));
+            }
+            Ok(delay.num_milliseconds() as u64)
+        }
+        _ => Err(ApiError::new(
+            ErrorCode::InvalidValue,
+            "Either a delay of at least 0 seconds or a time is needed!",
+        )),
+    }
+}
+
+/// Runs a single command later on, e.g. pausing once a session is over
+#[post("/commands/delayed")]
+async fn delay_command(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<DelayCommand>,
+) -> HttpResponse {
+    // Scoped tokens may only switch to their own themes, later on just as well
+    if let Command::LoadStoredTheme { ref name, .. } = payload.command {
+        if let Err(response) = check_theme_scope(&req, Some(name)) {
+            return response;
+        }
+    }
+
+    let delay = match command_delay(&payload) {
+        Ok(delay) => delay,
+        Err(error) => return error_response(error),
+    };
+
+    // The theme is checked again when the command runs, another one may be loaded by then
+    let command = Command::DelayCommand {
+        delay,
+        command: Box::new(scoped(&req, payload.into_inner().command)),
+    };
+
+    match send_scoped!(req, state.sender, Response::DelayedCommand, command) {
+        Ok(Response::DelayedCommand { id }) => {
+            HttpResponse::Ok().json(api_response::DelayedCommand { id })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/commands/delayed")]
+async fn delayed_commands(state: APIDataType) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::DelayedCommands,
+        Command::GetDelayedCommands
+    ) {
+        Ok(Response::DelayedCommands { commands }) => {
+            HttpResponse::Ok().json(api_response::DelayedCommands { commands })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[delete("/commands/delayed/{id}")]
+async fn cancel_delayed_command(
+    state: APIDataType,
+    req: HttpRequest,
+    id: web::Path<u64>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::CancelDelayedCommand {
+            id: id.into_inner()
+        }
+    ) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Summary of the last library scan: new, missing and broken samples, total length, ...
+#[get("/library/report")]
+async fn library_report(state: APIDataType) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::LibraryReport,
+        Command::GetLibraryReport
+    ) {
+        Ok(Response::LibraryReport { report }) => HttpResponse::Ok().json(report),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Runs library-wide work on the shared samples database on a blocking thread, so neither
+/// the workers nor the engines wait for it
+async fn with_samplesdb<F, I>(state: &APIDataType, work: F) -> Result<I, ApiError>
+where
+    F: FnOnce(&SharedSamplesDB) -> Result<I, SamplesDBError> + Send + 'static,
+    I: Send + 'static,
+{
+    let samplesdb = state.samplesdb.clone();
+
+    match web::block(move || work(&samplesdb)).await {
+        Ok(result) => Ok(result),
+        Err(BlockingError::Error(e)) => Err(ApiError::from(&e)),
+        Err(BlockingError::Canceled) => Err(ApiError::new(
+            ErrorCode::Backend,
+            "The library task was canceled",
+        )),
+    }
+}
+
+/// Picks up samples added to or removed from the library since the last scan
+#[post("/library/rescan")]
+async fn rescan_library(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    if let Err(response) = check_unscoped(&req) {
+        return response;
+    }
+
+    match with_samplesdb(&state, samplesdb::rescan).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct StartAudition {
+    tag: Option<String>,
+    query: Option<String>,
+    /// Seconds each sample plays
+    seconds: Option<f32>,
+    /// Apply the stored gain (ReplayGain or normalization) of each sample, so it is heard
+    /// the way it plays in a theme
+    #[serde(default)]
+    normalized: bool,
+}
+
+/// Plays a few seconds of each sample with the tag or matching the query, one after the other
+#[post("/library/audition")]
+async fn start_audition(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<StartAudition>,
+) -> HttpResponse {
+    let payload = payload.into_inner();
+
+    match send_scoped!(
+        req,
+        state.sender,
+        Response::Audition,
+        Command::StartAudition {
+            tag: payload.tag,
+            query: payload.query,
+            seconds: payload.seconds,
+            normalized: payload.normalized,
+        }
+    ) {
+        Ok(Response::Audition { samples }) => HttpResponse::Ok().json(samples),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[post("/library/audition/next")]
+async fn next_audition(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::NextAudition) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[delete("/library/audition")]
+async fn stop_audition(state: APIDataType, req: HttpRequest) -> HttpResponse {
+    match send_scoped!(req, state.sender, Command::StopAudition) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/tags")]
+async fn tags(state: APIDataType) -> HttpResponse {
+    match send_message!(state.sender, Response::Tags, Command::GetTags) {
+        Ok(Response::Tags { tags }) => HttpResponse::Ok().json(tags),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/tags/{tag}")]
+async fn samples_by_tag(state: APIDataType, tag: web::Path<String>) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::SoundLibrary,
+        Command::GetSamplesByTag {
+            tag: tag.into_inner()
+        }
+    ) {
+        Ok(Response::SoundLibrary { samples }) => {
+            HttpResponse::Ok().json(api_response::SoundLibrary { samples })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct SampleTag {
+    tag: String,
+}
+
+/// Tags a sample, unknown tags are created on the fly
+#[post("/samples/{id}/tags")]
+async fn add_sample_tag(
+    state: APIDataType,
+    req: HttpRequest,
+    id: web::Path<i64>,
+    payload: web::Json<SampleTag>,
+) -> HttpResponse {
+    if let Err(response) = check_unscoped(&req) {
+        return response;
+    }
+
+    let tag = payload.into_inner().tag.trim().to_string();
+    if tag.is_empty() {
+        return error_response(ApiError::new(
+            ErrorCode::InvalidValue,
+            "Tags can't be empty!",
+        ));
+    }
+
+    match send_message!(
+        state.sender,
+        Command::AddSampleTag {
+            sample: id.into_inner(),
+            tag,
+        }
+    ) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[delete("/samples/{id}/tags/{tag}")]
+async fn remove_sample_tag(
+    state: APIDataType,
+    req: HttpRequest,
+    path: web::Path<(i64, String)>,
+) -> HttpResponse {
+    if let Err(response) = check_unscoped(&req) {
+        return response;
+    }
+
+    let (sample, tag) = path.into_inner();
+
+    match send_message!(state.sender, Command::RemoveSampleTag { sample, tag }) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct Normalize {
+    #[serde(default)]
+    mode: NormalizeMode,
+    /// Level in dBFS, -1 for peak and -20 for loudness if not given
+    target: Option<f32>,
+    /// Samples to normalize with POST /library/normalize, all of them if not given
+    samples: Option<Vec<i64>>,
+}
+
+async fn normalize_response(
+    state: &APIDataType,
+    samples: Option<Vec<i64>>,
+    mode: NormalizeMode,
+    target: Option<f32>,
+) -> HttpResponse {
+    let target = target.unwrap_or_else(|| mode.default_target());
+    if target > 0.0 {
+        return error_response(ApiError::new(
+            ErrorCode::InvalidValue,
+            "The target must not be above 0 dBFS!",
+        ));
+    }
+
+    // Measuring decodes every sample as a whole, the engines keep playing meanwhile
+    let result = with_samplesdb(state, move |samplesdb| {
+        normalize_samples(samplesdb, samples, mode, target)
+    })
+    .await;
+    let (samples, failed) = match result {
+        Ok(result) => result,
+        Err(error) => return error_response(error),
+    };
+
+    match send_message!(state.sender, Command::RefreshSampleGains) {
+        Ok(_) => HttpResponse::Ok().json(api_response::Normalized { samples, failed }),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Stores a gain bringing the sample to the target level, the file itself stays as it is
+#[post("/library/samples/{id}/normalize")]
+async fn normalize_sample(
+    state: APIDataType,
+    id: web::Path<i64>,
+    payload: Option<web::Json<Normalize>>,
+) -> HttpResponse {
+    let (mode, target) = payload.map_or((NormalizeMode::default(), None), |payload| {
+        (payload.mode, payload.target)
+    });
+
+    normalize_response(&state, Some(vec![id.into_inner()]), mode, target).await
+}
+
+#[post("/library/normalize")]
+async fn normalize_library(
+    state: APIDataType,
+    payload: Option<web::Json<Normalize>>,
+) -> HttpResponse {
+    match payload {
+        Some(payload) => {
+            let payload = payload.into_inner();
+            normalize_response(&state, payload.samples, payload.mode, payload.target).await
+        }
+        None => normalize_response(&state, None, NormalizeMode::default(), None).await,
+    }
+}
+
+/// Plays the sample as it is again
+#[delete("/library/samples/{id}/normalize")]
+async fn reset_sample_gain(state: APIDataType, id: web::Path<i64>) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Command::ResetSampleGain {
+            sample: id.into_inner()
+        }
+    ) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct LibrarySearch {
+    query: String,
+}
+
+#[get("/library/search")]
+async fn search_library(state: APIDataType, search: web::Query<LibrarySearch>) -> HttpResponse {
+    match send_message!(
+        state.sender,
+        Response::SoundLibrary,
+        Command::SearchLibrary {
+            query: search.query.clone()
+        }
+    ) {
+        Ok(Response::SoundLibrary { samples }) => {
+            HttpResponse::Ok().json(api_response::SoundLibrary { samples })
+        }
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct Volume {
+    value: f32,
+}
+
+#[post("/volume")]
+async fn volume(state: APIDataType, req: HttpRequest, payload: web::Json<Volume>) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetVolume {
+            value: payload.value
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct EqBands {
+    low: f32,
+    mid: f32,
+    high: f32,
+}
+
+/// Master EQ on the output, each band is a gain between 0.0 and 1.0
+#[post("/eq")]
+async fn eq(state: APIDataType, req: HttpRequest, payload: web::Json<EqBands>) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetEq {
+            low: payload.low,
+            mid: payload.mid,
+            high: payload.high,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+/// Volume of every sound of the group, on top of their own
+#[post("/groups/{name}/volume")]
+async fn group_volume(
+    state: APIDataType,
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Json<Volume>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetGroupVolume {
+            group: name.into_inner(),
+            value: payload.value,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct TimeScale {
+    value: f32,
+    #[serde(default)]
+    pitch: bool,
+}
+
+#[post("/timescale")]
+async fn timescale(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<TimeScale>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::SetTimeScale {
+            value: payload.value,
+            pitch: payload.pitch,
+        }
+    ) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct OutputTestQuery {
+    #[serde(default)]
+    channel: OutputChannel,
+}
+
+/// Plays a short tone on the given channel for speaker checks, no library file needed
+#[post("/output/test")]
+async fn output_test(
+    state: APIDataType,
+    req: HttpRequest,
+    query: web::Query<OutputTestQuery>,
+) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::PlayTestTone {
+            channel: query.channel,
+        }
+    ) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+fn get_default_beep_frequency() -> f32 {
+    880.0
+}
+
+fn get_default_beep_duration() -> f32 {
+    0.2
+}
+
+fn get_default_beep_volume() -> f32 {
+    0.5
+}
+
+#[derive(Deserialize)]
+struct Beep {
+    #[serde(default)]
+    waveform: Waveform,
+    #[serde(default = "get_default_beep_frequency")]
+    frequency: f32,
+    #[serde(default = "get_default_beep_duration")]
+    duration: f32,
+    #[serde(default = "get_default_beep_volume")]
+    volume: f32,
+}
+
+#[post("/beep")]
+async fn beep(state: APIDataType, req: HttpRequest, payload: web::Json<Beep>) -> HttpResponse {
+    match send_scoped!(
+        req,
+        state.sender,
+        Command::PlayBeep {
+            waveform: payload.waveform,
+            frequency: payload.frequency,
+            duration: payload.duration,
+            volume: payload.volume,
+        }
+    ) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// For probes of container orchestration, answers 503 if anything is wrong. A stuck audio
+/// thread isn't sent a request, it would never answer.
+#[get("/health")]
+async fn health(state: APIDataType) -> HttpResponse {
+    let heartbeat_age = state.heartbeat.age();
+
+    let mut health = api_response::Health {
+        healthy: false,
+        heartbeat_age,
+        engine_alive: heartbeat_age.map_or(false, |age| age <= HEARTBEAT_TIMEOUT_MS),
+        device_alive: None,
+        database_alive: None,
+        database_error: None,
+        free_sources: None,
+    };
+
+    if health.engine_alive {
+        match send_message!(state.sender, Response::Health, Command::GetHealth) {
+            Ok(Response::Health {
+                device_alive,
+                database_error,
+                source_pool,
+            }) => {
+                health.device_alive = Some(device_alive);
+                health.database_alive = Some(database_error.is_none());
+                health.database_error = database_error;
+                health.free_sources = source_pool.map(|pool| pool.limit.saturating_sub(pool.used));
+            }
+            Ok(_) => unreachable!(),
+            Err(error) => {
+                warn!("Engine didn't answer the health check: {}", error);
+                health.engine_alive = false;
+            }
+        }
+    }
+
+    health.healthy = health.engine_alive
+        && health.device_alive == Some(true)
+        && health.database_alive == Some(true);
+
+    if health.healthy {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+/// The configuration in effect and what the backend can do, without any secrets
+#[get("/config")]
+async fn configuration(state: APIDataType) -> HttpResponse {
+    let (backend, sound_library, database, snapshots) = {
+        let config = state.config.lock().unwrap();
+        let database = config.database();
+        let database =
+            if database.starts_with("postgres://") || database.starts_with("postgresql://") {
+                "postgres"
+            } else {
+                "sqlite"
+            };
+
+        (
+            config.backend(),
+            config.sound_library(),
+            database.to_string(),
+            config.backup_dir.is_some(),
+        )
+    };
+
+    let mut features = Vec::new();
+    if cfg!(feature = "postgresql") {
+        features.push("postgresql");
+    }
+    if cfg!(feature = "test-support") {
+        features.push("test-support");
+    }
+
+    match send_message!(
+        state.sender,
+        Response::EngineConfig,
+        Command::GetEngineConfig
+    ) {
+        Ok(Response::EngineConfig {
+            capabilities,
+            device,
+            output_rate,
+            resampler,
+            source_pool,
+            pause_fade,
+            theme_fade,
+            default_crossfade,
+            max_previews,
+            queue_previews,
+            ducking,
+            command_log,
+            tick,
+        }) => HttpResponse::Ok().json(api_response::Configuration {
+            backend,
+            device,
+            output_rate,
+            resampler,
+            sources: source_pool.map(|pool| pool.total),
+            max_sources: source_pool.map(|pool| pool.limit),
+            capabilities,
+            sound_library,
+            database,
+            snapshots,
+            pause_fade,
+            theme_fade,
+            default_crossfade,
+            max_previews,
+            queue_previews,
+            ducking,
+            command_log,
+            tick,
+            features,
+        }),
+        Ok(_) => unreachable!(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/driver")]
+async fn driver(state: APIDataType) -> HttpResponse {
+    match send_message!(state.sender, Response::Driver, Command::GetDriver) {
+        Ok(driver) => HttpResponse::Ok().json(driver),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/driverlist")]
+async fn driverlist(state: APIDataType) -> HttpResponse {
+    match send_message!(state.sender, Response::DriverList, Command::GetDriverList) {
+        Ok(driverlist) => HttpResponse::Ok().json(driverlist),
+        Err(error) => error_response(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct Driver {
+    id: i32,
+}
+
+#[post("/driver")]
+async fn set_driver(
+    state: APIDataType,
+    req: HttpRequest,
+    payload: web::Json<Driver>,
+) -> HttpResponse {
+    match send_message!(state.sender, Command::SetDriver { id: payload.id }) {
+        Ok(_) => {
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .record_change(session_id(&req));
+            HttpResponse::Ok().finish()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+/// Applies the given configuration, or the one read again from the configuration file
+/// and command line if there is no body
+#[post("/admin/reload")]
+async fn reload(state: APIDataType, body: web::Bytes) -> HttpResponse {
+    // An empty body means reading the configuration again, a broken one is an error
+    let config = if body.iter().all(u8::is_ascii_whitespace) {
+        match state.config_source.read() {
+            Ok(config) => config,
+            Err(e) => return error_response(ApiError::new(ErrorCode::InvalidValue, e)),
+        }
+    } else {
+        match serde_json::from_slice::<Config>(&body) {
+            Ok(config) => config,
+            Err(e) => {
+                return error_response(ApiError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("Invalid configuration: {}", e),
+                ))
+            }
+        }
+    };
+
+    // The logger lets everything through, the level set here is all that filters
+    let log_level = match config.log_level {
+        Some(ref log_level) => match log::LevelFilter::from_str(log_level) {
+            Ok(level) if level <= log::STATIC_MAX_LEVEL => Some(level),
+            Ok(_) => {
+                return error_response(ApiError::new(
+                    ErrorCode::InvalidValue,
+                    format!(
+                        "Log level '{}' is above the maximum of this build ({})!",
+                        log_level,
+                        log::STATIC_MAX_LEVEL
+                    ),
+                ))
+            }
+            Err(_) => {
+                return error_response(ApiError::new(
+                    ErrorCode::InvalidValue,
+                    format!("Unknown log level '{}'!", log_level),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    // A new library is scanned right away, samples are looked up in it from then on
+    let library = config.sound_library();
+    let library_changed =
+        config.sound_library.is_some() && library != state.config.lock().unwrap().sound_library();
+    if library_changed {
+        if !library.is_dir() {
+            return error_response(ApiError::new(
+                ErrorCode::InvalidValue,
+                format!(
+                    "Sound library '{}' is not a directory!",
+                    library.to_string_lossy()
+                ),
+            ));
+        }
+
+        let rescanned = with_samplesdb(&state, move |samplesdb| {
+            let previous = mem::replace(&mut samplesdb.lock().unwrap().base_path, library);
+            samplesdb::rescan(samplesdb).map_err(|e| {
+                samplesdb.lock().unwrap().base_path = previous;
+                e
+            })
+        })
+        .await;
+
+        if let Err(error) = rescanned {
+            return error_response(error);
+        }
+    }
+
+    let mut current = state.config.lock().unwrap();
+    let mut applied = Vec::new();
+    let mut restart_required = Vec::new();
+
+    if let Some(level) = log_level {
+        log::set_max_level(level);
+        applied.push("log_level".to_string());
+        current.log_level = config.log_level.clone();
+    }
+
+    if library_changed {
+        applied.push("sound_library".to_string());
+        current.sound_library = config.sound_library.clone();
+    }
+
+    if let Some(token) = config.token {
+        state
+            .tokens
+            .write()
+            .unwrap()
+            .set_access_token(token.clone());
+        applied.push("token".to_string());
+        current.token = Some(token);
+    }
+
+    if let Some(tokens) = config.tokens {
+        state
+            .tokens
+            .write()
+            .unwrap()
+            .set_named_tokens(tokens.clone());
+        applied.push("tokens".to_string());
+        current.tokens = Some(tokens);
+    }
+
+    // Only what actually differs from what the server was started with needs a restart
+    for (name, changed) in &[
+        (
+            "host",
+            config.host.is_some() && config.host() != current.host(),
+        ),
+        (
+            "port",
+            config.port.is_some() && config.port() != current.port(),
+        ),
+        (
+            "threads",
+            config.threads.is_some() && config.threads() != current.threads(),
+        ),
+        (
+            "database",
+            config.database.is_some() && config.database() != current.database(),
+        ),
+        (
+            "backend",
+            config.backend.is_some() && config.backend() != current.backend(),
+        ),
+    ] {
+        if *changed {
+            restart_required.push(name.to_string());
+        }
+    }
+
+    info!(
+        "Reloaded configuration, applied: {:?}, restart required: {:?}",
+        applied, restart_required
+    );
+
+    HttpResponse::Ok().json(api_response::Reload {
+        applied,
+        restart_required,
+    })
+}
+
+#[derive(Deserialize)]
+struct BackupQuery {
+    /// Include the sample files, which makes the backup about as large as the library
+    #[serde(default)]
+    samples: bool,
+}
+
+/// Passes what is written on as chunks of a streamed response, waiting while the client
+/// lags behind
+struct ChunkWriter(mpsc::Sender<Result<web::Bytes, actix_web::Error>>);
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        futures::executor::block_on(self.0.send(Ok(web::Bytes::from(buf.to_vec()))))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The client went away"))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[get("/admin/backup")]
+async fn backup(state: APIDataType, query: web::Query<BackupQuery>) -> HttpResponse {
+    let samples = query.samples;
+    let backup = match with_samplesdb(&state, move |samplesdb| {
+        samplesdb.lock().unwrap().backup(samples)
+    })
+    .await
+    {
+        Ok(backup) => backup,
+        Err(error) => return error_response(error),
+    };
+
+    // The sample files are read and encoded one at a time while the backup is sent
+    let (sender, chunks) = mpsc::channel(BACKUP_CHUNKS);
+    thread::spawn(move || {
+        let mut writer = BufWriter::with_capacity(BACKUP_CHUNK_SIZE, ChunkWriter(sender));
+        let result = serde_json::to_writer(&mut writer, &backup)
+            .map_err(|e| e.to_string())
+            .and_then(|_| writer.flush().map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            warn!("Failed to send backup: {}", e);
+            // Breaks off the response, so the client doesn't take it for a whole backup
+            let error = actix_web::error::ErrorInternalServerError(e);
+            let _ = futures::executor::block_on(writer.get_mut().0.send(Err(error)));
+        }
+    });
+
+    HttpResponse::Ok()
+        .header(
+            "Content-Disposition",
+            "attachment; filename=\"sinfonia-backup.json\"",
+        )
+        .content_type("application/json")
+        .streaming(chunks)
+}
+
+/// Backups with samples are about as large as the library, so they aren't subject to the
+/// payload limit and are buffered in a file instead of memory
+#[post("/admin/restore")]
+async fn restore(state: APIDataType, mut payload: web::Payload) -> HttpResponse {
+    let path = std::env::temp_dir().join(format!(
+        "sinfonia-restore-{}.json",
+        Utc::now().timestamp_nanos()
+    ));
+
+    let mut file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => return error_response(ApiError::new(ErrorCode::Backend, e)),
+    };
+    while let Some(chunk) = payload.next().await {
+        let written = match chunk {
+            Ok(chunk) => file
+                .write_all(&chunk)
+                .map_err(|e| ApiError::new(ErrorCode::Backend, e)),
+            Err(e) => Err(ApiError::new(ErrorCode::InvalidRequest, e)),
+        };
+        if let Err(error) = written {
+            let _ = fs::remove_file(&path);
+            return error_response(error);
+        }
+    }
+    drop(file);
+
+    let backup_path = path.clone();
+    let result = with_samplesdb(&state, move |samplesdb| {
+        let backup: Backup = File::open(&backup_path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).map_err(|e| e.to_string()))
+            .map_err(SamplesDBError::BackupError)?;
+
+        samplesdb.lock().unwrap().restore(&backup)
+    })
+    .await;
+    let _ = fs::remove_file(&path);
+
+    if let Err(error) = result {
+        return error_response(error);
+    }
+
+    match send_message!(state.sender, Command::ReloadSettings) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let code = match err {
+        JsonPayloadError::Overflow => ErrorCode::PayloadTooLarge,
+        JsonPayloadError::ContentType => ErrorCode::UnsupportedMediaType,
+        _ => ErrorCode::InvalidRequest,
+    };
+
+    debug!("Rejected JSON payload: {}", err);
+    let response = error_response(ApiError::new(code, &err));
+    InternalError::from_response(err, response).into()
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_session)
+        .service(delete_session)
+        .service(play)
+        .service(pause)
+        .service(preview)
+        .service(status)
+        .service(overlay)
+        .service(overlay_events)
+        .service(engine_events)
+        .service(health)
+        .service(configuration)
+        .service(satellite)
+        .service(theme)
+        .service(loaded_theme)
+        .service(lint)
+        .service(save_theme)
+        .service(stored_themes)
+        .service(stored_theme)
+        .service(load_stored_theme)
+        .service(preload_stored_theme)
+        .service(import)
+        .service(patch_sound)
+        .service(sound_counters)
+        .service(sound_position)
+        .service(sound_status)
+        .service(sound_playback_position)
+        .service(seek_sound)
+        .service(listener_position)
+        .service(listener_orientation)
+        .service(stop_sound)
+        .service(arm_sound)
+        .service(disarm_sound)
+        .service(mute_sound)
+        .service(unmute_sound)
+        .service(solo_sound)
+        .service(clear_solo)
+        .service(run_macro)
+        .service(next_track)
+        .service(previous_track)
+        .service(seek_track)
+        .service(delay_command)
+        .service(delayed_commands)
+        .service(cancel_delayed_command)
+        .service(trim_sound)
+        .service(get_settings)
+        .service(update_settings)
+        .service(get_client_setting)
+        .service(set_client_setting)
+        .service(trigger)
+        .service(fire_trigger)
+        .service(scene)
+        .service(lockout)
+        .service(end_lockout)
+        .service(library)
+        .service(library_v2)
+        .service(search_library)
+        .service(library_report)
+        .service(rescan_library)
+        .service(start_audition)
+        .service(next_audition)
+        .service(stop_audition)
+        .service(tags)
+        .service(samples_by_tag)
+        .service(add_sample_tag)
+        .service(sample_preview)
+        .service(sample_waveform)
+        .service(remove_sample_tag)
+        .service(normalize_sample)
+        .service(normalize_library)
+        .service(reset_sample_gain)
+        .service(volume)
+        .service(eq)
+        .service(limiter)
+        .service(group_volume)
+        .service(timescale)
+        .service(output_test)
+        .service(beep)
+        .service(driver)
+        .service(driverlist)
+        .service(set_driver)
+        .service(reload)
+        .service(backup)
+        .service(restore);
+}
+
+/// An additional engine, reachable below /rooms/{id}
+pub struct Room {
+    pub id: String,
+    pub sender: ChannelSender,
+    pub heartbeat: Heartbeat,
+    pub version: StateVersion,
+}
+
+pub async fn start_web_service(
+    sender: ChannelSender,
+    heartbeat: Heartbeat,
+    version: StateVersion,
+    samplesdb: SharedSamplesDB,
+    rooms: Vec<Room>,
+    config: Config,
+    config_source: ConfigSource,
+    scoped_tokens: Vec<ScopedToken>,
+    max_payload: usize,
+) -> io::Result<()> {
+    let address = format!("{}:{}", config.host(), config.port());
+    let tokens = Arc::new(RwLock::new(TokenStore::new(
+        config.token(),
+        config.tokens.clone().unwrap_or_default(),
+        scoped_tokens,
+    )));
+    let config = Arc::new(Mutex::new(config));
+    let room_ids: Vec<String> = rooms.iter().map(|room| room.id.clone()).collect();
+    let room_data: Vec<(String, Arc<APIData>)> = rooms
+        .into_iter()
+        .map(|room| {
+            let data = APIData::new(
+                room.sender,
+                room.heartbeat,
+                room.version,
+                samplesdb.clone(),
+                config.clone(),
+                config_source.clone(),
+                tokens.clone(),
+                room_ids.clone(),
+            );
+            (room.id, Arc::new(data))
+        })
+        .collect();
+    let data = Arc::new(APIData::new(
+        sender,
+        heartbeat,
+        version,
+        samplesdb,
+        config,
+        config_source,
+        tokens.clone(),
+        room_ids,
+    ));
+    let idempotency = Idempotency::default();
+
+    HttpServer::new(move || {
+        let mut app = App::new()
+            .data(data.clone())
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(max_payload)
+                    .error_handler(json_error_handler),
+            )
+            .wrap(idempotency.clone())
+            .wrap(Logger::default())
+            .wrap(TokenAuthorization::new(tokens.clone()))
+            .service(room_list);
+
+        // Every room has the same routes as the default one, talking to its own engine
+        for (id, room) in &room_data {
+            app = app.service(
+                web::scope(&format!("/rooms/{}", id))
+                    .data(room.clone())
+                    .configure(routes),
+            );
+        }
+
+        app.configure(routes)
+    })
+    .bind(address)?
+    .start()
+    .await
+}
+
+/// The routes of the web service with token authorization, called without a server so
+/// scenarios go through the same checks as clients
+#[cfg(feature = "test-support")]
+pub struct TestApi {
+    data: Arc<APIData>,
+    tokens: Arc<RwLock<TokenStore>>,
+}
+
+#[cfg(feature = "test-support")]
+impl TestApi {
+    pub fn new(
+        sender: ChannelSender,
+        heartbeat: Heartbeat,
+        version: StateVersion,
+        samplesdb: SharedSamplesDB,
+        config: Config,
+    ) -> Self {
+        let tokens = Arc::new(RwLock::new(TokenStore::new(
+            config.token(),
+            Vec::new(),
+            Vec::new(),
+        )));
+        let data = APIData::new(
+            sender,
+            heartbeat,
+            version,
+            samplesdb,
+            Arc::new(Mutex::new(config)),
+            ConfigSource::default(),
+            tokens.clone(),
+            Vec::new(),
+        );
+
+        Self {
+            data: Arc::new(data),
+            tokens,
+        }
+    }
+
+    /// Sends a request with an optional bearer token and JSON body, returns the status
+    /// and body of the response
+    pub async fn call(
+        &self,
+        method: actix_web::http::Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, web::Bytes) {
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(self.data.clone())
+                .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                .wrap(TokenAuthorization::new(self.tokens.clone()))
+                .configure(routes),
+        )
+        .await;
+
+        let mut request = actix_web::test::TestRequest::with_uri(path).method(method);
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        if let Some(body) = body {
+            request = request.set_json(&body);
+        }
+
+        let response = actix_web::test::call_service(&mut app, request.to_request()).await;
+        let status = response.status();
+
+        (status, actix_web::test::read_body(response).await)
+    }
 }
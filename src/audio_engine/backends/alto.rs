@@ -1,13 +1,53 @@
 use alto;
-use alto::{Source, SourceState};
+use alto::{DeviceObject, Source, SourceState};
 
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CStr, CString};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::rc::Rc;
+use std::sync::{Arc, Weak};
 
-use crate::audio_engine::backends::base::{AudioBackend, AudioEntityData};
+use crate::audio_engine::backends::base::{
+    AudioBackend, AudioEntityData, BackendCapabilities, CustomReverb, DecodedFile, DecodedFiles,
+    Effect, Equalizer, SourcePool,
+};
 use crate::audio_engine::backends::error::AudioBackendError;
 use crate::audio_engine::loader;
+use crate::audio_engine::loader::base::AudioStream;
+use crate::audio_engine::resampler::{RateConverter, Resampler, SampleRateStatus};
+
+// Rate assumed when the implementation doesn't tell which one it mixes at
+const DEFAULT_OUTPUT_RATE: i32 = 44100;
+
+// Sample rate used when monitoring the capture device, plenty for level detection
+const CAPTURE_RATE: u32 = 16000;
+
+// Files longer than this many seconds are streamed instead of decoded into memory at once
+const STREAM_MIN_SECONDS: f32 = 60.0;
+
+// Size of the buffers a stream is split into and how many of them are queued at a time
+const STREAM_BUFFER_FRAMES: usize = 32768;
+const STREAM_BUFFERS: usize = 4;
+
+// Sources created along with a context, more are created when they are all in use
+const INITIAL_SOURCES: usize = 32;
+
+// Effects a sound can be sent through on top of its reverb. They are chained on one
+// send where the implementation can, else they get one send each out of the 8 the
+// context asks for, implementations may give fewer.
+pub const MAX_EFFECTS: usize = 7;
+
+/// Names of the reverb presets themes may use
+pub const REVERB_PRESETS: [&str; 7] = [
+    "none",
+    "underwater",
+    "forest",
+    "spacestation",
+    "spacestation_smallroom",
+    "spacestation_mediumroom",
+    "chapel",
+];
 
 fn reverb_name_to_ref(reverb: &str) -> Option<&'static alto::efx::EaxReverbProperties> {
     match reverb {
@@ -25,42 +65,503 @@ fn reverb_name_to_ref(reverb: &str) -> Option<&'static alto::efx::EaxReverbPrope
     }
 }
 
+/// Sets up the reverb as the custom reverb of that name, or else as the preset
+fn load_reverb(
+    reverb: &mut alto::efx::ReverbEffect,
+    name: &str,
+    custom: &HashMap<String, CustomReverb>,
+) -> Result<(), AudioBackendError> {
+    let custom = match custom.get(name) {
+        Some(custom) => custom.clamped().0,
+        None => {
+            if let Some(preset) = reverb_name_to_ref(name) {
+                reverb.set_preset(preset)?;
+            }
+            return Ok(());
+        }
+    };
+
+    if let Some(preset) = reverb_name_to_ref(&custom.preset) {
+        reverb.set_preset(preset)?;
+    }
+
+    if let Some(value) = custom.density {
+        reverb.set_density(value)?;
+    }
+    if let Some(value) = custom.diffusion {
+        reverb.set_diffusion(value)?;
+    }
+    if let Some(value) = custom.gain {
+        reverb.set_gain(value)?;
+    }
+    if let Some(value) = custom.gain_hf {
+        reverb.set_gainhf(value)?;
+    }
+    if let Some(value) = custom.decay_time {
+        reverb.set_decay_time(value)?;
+    }
+    if let Some(value) = custom.decay_hf_ratio {
+        reverb.set_decay_hfratio(value)?;
+    }
+    if let Some(value) = custom.reflections_gain {
+        reverb.set_reflections_gain(value)?;
+    }
+    if let Some(value) = custom.reflections_delay {
+        reverb.set_reflections_delay(value)?;
+    }
+    if let Some(value) = custom.late_reverb_gain {
+        reverb.set_late_reverb_gain(value)?;
+    }
+    if let Some(value) = custom.late_reverb_delay {
+        reverb.set_late_reverb_delay(value)?;
+    }
+    if let Some(value) = custom.air_absorption_gain_hf {
+        reverb.set_air_absorption_gainhf(value)?;
+    }
+    if let Some(value) = custom.room_rolloff_factor {
+        reverb.set_room_rolloff_factor(value)?;
+    }
+    if let Some(value) = custom.decay_hf_limit {
+        reverb.set_decay_hflimit(value)?;
+    }
+
+    Ok(())
+}
+
+/// An effect of a sound's chain, kept alive along with the slot it is loaded into
+enum OpenALEffect {
+    Reverb(alto::efx::ReverbEffect),
+    Echo(alto::efx::EchoEffect),
+    Chorus(alto::efx::ChorusEffect),
+    Distortion(alto::efx::DistortionEffect),
+}
+
+// AL_SOFT_effect_target: the slot an effect slot feeds into instead of the mix
+const AL_EFFECTSLOT_TARGET_SOFT: alto::sys::ALenum = 0x199C;
+
+type SetSlotParam = unsafe extern "C" fn(alto::sys::ALuint, alto::sys::ALenum, alto::sys::ALint);
+
+/// Looks up `alAuxiliaryEffectSloti` if the implementation can feed slots into each
+/// other. alto has no wrapper for effect targets, so this goes to the raw API.
+fn slot_target_setter(context: &alto::Context) -> Option<SetSlotParam> {
+    let api = context.device().alto().raw_api();
+    unsafe {
+        let extension = b"AL_SOFT_effect_target\0";
+        if api.alIsExtensionPresent(extension.as_ptr() as *const alto::sys::ALchar)
+            != alto::sys::AL_TRUE
+        {
+            return None;
+        }
+
+        let function = b"alAuxiliaryEffectSloti\0";
+        let address = api.alGetProcAddress(function.as_ptr() as *const alto::sys::ALchar);
+        if address.is_null() {
+            None
+        } else {
+            Some(std::mem::transmute::<_, SetSlotParam>(address))
+        }
+    }
+}
+
+/// Feeds the output of `slot` into `target` rather than the mix. The context of the
+/// slots is current, alto leaves it so after setting them up.
+fn set_slot_target(
+    set: SetSlotParam,
+    slot: &alto::efx::AuxEffectSlot,
+    target: &alto::efx::AuxEffectSlot,
+) -> bool {
+    let api = slot.context().device().alto().raw_api();
+    unsafe {
+        set(
+            slot.as_raw(),
+            AL_EFFECTSLOT_TARGET_SOFT,
+            target.as_raw() as alto::sys::ALint,
+        );
+        api.alGetError() == alto::sys::AL_NO_ERROR
+    }
+}
+
+/// Creates the effect and loads it into a slot of its own
+fn load_effect(
+    context: &alto::Context,
+    effect: &Effect,
+    reverbs: &HashMap<String, CustomReverb>,
+) -> Result<(alto::efx::AuxEffectSlot, OpenALEffect), AudioBackendError> {
+    let mut slot = context.new_aux_effect_slot()?;
+
+    let (effect, problems) = effect.clamped();
+    for problem in problems {
+        warn!("{}: {}", effect.name(), problem);
+    }
+
+    let loaded = match effect {
+        Effect::Reverb { ref preset, .. } => {
+            let mut reverb = context.new_effect::<alto::efx::ReverbEffect>()?;
+            load_reverb(&mut reverb, preset, reverbs)?;
+            slot.set_effect(&reverb)?;
+            OpenALEffect::Reverb(reverb)
+        }
+        Effect::Echo {
+            delay,
+            lr_delay,
+            damping,
+            feedback,
+            spread,
+            ..
+        } => {
+            let mut echo = context.new_effect::<alto::efx::EchoEffect>()?;
+            echo.set_delay(delay)?;
+            echo.set_lrdelay(lr_delay)?;
+            echo.set_damping(damping)?;
+            echo.set_feedback(feedback)?;
+            echo.set_spread(spread)?;
+            slot.set_effect(&echo)?;
+            OpenALEffect::Echo(echo)
+        }
+        Effect::Chorus {
+            rate,
+            depth,
+            feedback,
+            delay,
+            ..
+        } => {
+            let mut chorus = context.new_effect::<alto::efx::ChorusEffect>()?;
+            chorus.set_rate(rate)?;
+            chorus.set_depth(depth)?;
+            chorus.set_feedback(feedback)?;
+            chorus.set_delay(delay)?;
+            slot.set_effect(&chorus)?;
+            OpenALEffect::Chorus(chorus)
+        }
+        Effect::Distortion {
+            edge,
+            gain,
+            lowpass_cutoff,
+            ..
+        } => {
+            let mut distortion = context.new_effect::<alto::efx::DistortionEffect>()?;
+            distortion.set_edge(edge)?;
+            distortion.set_gain(gain)?;
+            distortion.set_lowpass_cutoff(lowpass_cutoff)?;
+            slot.set_effect(&distortion)?;
+            OpenALEffect::Distortion(distortion)
+        }
+    };
+
+    slot.set_gain(effect.wet())?;
+    Ok((slot, loaded))
+}
+
+/// Runs `$body` with `$src` bound to the source the entity plays on. Static and streaming
+/// sources share no object safe trait, so the body is expanded once for each.
+macro_rules! with_source {
+    ($entity:expr, $src:ident => $body:expr, $otherwise:expr) => {
+        if let Some(ref mut source) = $entity.source {
+            let $src = &mut source.handle;
+            $body
+        } else if let Some($src) = $entity
+            .stream
+            .as_mut()
+            .and_then(|stream| stream.source.as_mut())
+        {
+            $body
+        } else {
+            $otherwise
+        }
+    };
+}
+
+/// Puts interleaved samples into a buffer, reusing `free` if given. OpenAL only
+/// positions mono buffers, stereo ones are played as they are.
+fn fill_buffer(
+    context: &alto::Context,
+    free: Option<alto::Buffer>,
+    samples: Vec<i16>,
+    channels: usize,
+    sample_rate: i32,
+) -> Result<alto::Buffer, AudioBackendError> {
+    if channels == 2 {
+        let frames: Vec<_> = samples
+            .chunks_exact(2)
+            .map(|frame| alto::Stereo {
+                left: frame[0],
+                right: frame[1],
+            })
+            .collect();
+
+        match free {
+            Some(mut buffer) => {
+                buffer.set_data(frames, sample_rate)?;
+                Ok(buffer)
+            }
+            None => Ok(context.new_buffer(frames, sample_rate)?),
+        }
+    } else {
+        let frames: Vec<_> = samples
+            .into_iter()
+            .map(|sample| alto::Mono { center: sample })
+            .collect();
+
+        match free {
+            Some(mut buffer) => {
+                buffer.set_data(frames, sample_rate)?;
+                Ok(buffer)
+            }
+            None => Ok(context.new_buffer(frames, sample_rate)?),
+        }
+    }
+}
+
+/// What the samples of an entity were loaded from, to upload them again on another device
+#[derive(Clone)]
+enum BufferOrigin {
+    File {
+        path: PathBuf,
+        stereo: bool,
+    },
+    /// Generated samples can't be loaded again, so they are kept around
+    Samples {
+        samples: Arc<Vec<i16>>,
+        sample_rate: i32,
+    },
+}
+
+/// A long file played from a queue of buffers that is refilled while playing
+struct OpenALStream {
+    decoder: Box<dyn AudioStream>,
+    source: Option<alto::StreamingSource>,
+    // Frames in each buffer currently queued on the source, oldest first
+    queued: VecDeque<usize>,
+    // Frames of the buffers already played, for the position
+    played: usize,
+    // The decoder reached the end of the file
+    exhausted: bool,
+    // Rate the decoded samples are converted to, if it differs from the file's
+    resample_to: Option<i32>,
+    resampler: Resampler,
+    // The queue holds the start of the file already, playing doesn't have to wait for it
+    primed: bool,
+}
+
+impl OpenALStream {
+    fn play(&mut self, context: &alto::Context) -> Result<(), AudioBackendError> {
+        let paused = self
+            .source
+            .as_ref()
+            .map_or(false, |source| source.state() == SourceState::Paused);
+
+        if !paused && !self.primed {
+            self.prime(context)?;
+        }
+        self.primed = false;
+
+        if let Some(ref mut source) = self.source {
+            source.play();
+        }
+
+        Ok(())
+    }
+
+    /// Starts over at the beginning with a fresh queue, without playing it yet
+    fn prime(&mut self, context: &alto::Context) -> Result<(), AudioBackendError> {
+        self.source = None;
+        self.queued.clear();
+        self.played = 0;
+        self.exhausted = false;
+        self.decoder.rewind()?;
+
+        self.source = Some(context.new_streaming_source()?);
+        self.fill()?;
+        self.primed = true;
+
+        Ok(())
+    }
+
+    /// Lets go of the source and the queued buffers of a stream that isn't playing
+    fn release(&mut self) {
+        self.source = None;
+        self.queued.clear();
+        self.primed = false;
+    }
+
+    /// Queues decoded buffers until STREAM_BUFFERS are queued, reusing the played ones
+    fn fill(&mut self) -> Result<(), AudioBackendError> {
+        let source = match self.source {
+            Some(ref mut source) => source,
+            None => return Ok(()),
+        };
+
+        let mut free = Vec::new();
+        for _ in 0..source.buffers_processed() {
+            free.push(source.unqueue_buffer()?);
+            self.played += self.queued.pop_front().unwrap_or(0);
+        }
+
+        while !self.exhausted && self.queued.len() < STREAM_BUFFERS {
+            let samples = self.decoder.read(STREAM_BUFFER_FRAMES)?;
+            if samples.is_empty() {
+                self.exhausted = true;
+                break;
+            }
+
+            // Counted in frames of the file, that's what the position is measured in
+            let channels = self.decoder.channels();
+            let length = samples.len() / channels;
+            let sample_rate = self.decoder.sample_rate();
+            let (samples, sample_rate) = match self.resample_to {
+                Some(rate) => (
+                    self.resampler
+                        .resample(&samples, channels, sample_rate, rate),
+                    rate,
+                ),
+                None => (samples, sample_rate),
+            };
+
+            let buffer = fill_buffer(source.context(), free.pop(), samples, channels, sample_rate)?;
+
+            source.queue_buffer(buffer).map_err(|(e, _)| e)?;
+            self.queued.push_back(length);
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<(), AudioBackendError> {
+        self.fill()?;
+
+        if let Some(ref mut source) = self.source {
+            // The source ran dry before the next buffer was queued, pick up where it stopped
+            if source.state() == SourceState::Stopped && !self.queued.is_empty() {
+                warn!("Stream ran out of buffers, resuming");
+                source.play();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Continues the decoder at the position and queues the buffers from there
+    fn seek(&mut self, context: &alto::Context, seconds: f32) -> Result<(), AudioBackendError> {
+        let state = self.source.as_ref().map(|source| source.state());
+
+        self.source = None;
+        self.queued.clear();
+        self.exhausted = false;
+        self.primed = false;
+
+        let frame = (seconds.max(0.0) * self.decoder.sample_rate() as f32) as usize;
+        self.decoder.seek(frame)?;
+        self.played = frame;
+
+        self.source = Some(context.new_streaming_source()?);
+        self.fill()?;
+
+        if let Some(ref mut source) = self.source {
+            match state {
+                Some(SourceState::Playing) => source.play(),
+                // Playing a stream that isn't paused starts it over, so keep it paused
+                Some(SourceState::Paused) => {
+                    source.play();
+                    source.pause();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn played_seconds(&self) -> f32 {
+        self.played as f32 / self.decoder.sample_rate() as f32
+    }
+}
+
 pub struct OpenALEntityData {
-    buffer: Arc<alto::Buffer>,
+    /// Set for files decoded at once, long files are streamed instead
+    buffer: Option<Arc<alto::Buffer>>,
     source: Option<OpenALSource>,
+    stream: Option<OpenALStream>,
     lowpass: Option<alto::efx::LowpassFilter>,
     highpass: Option<alto::efx::HighpassFilter>,
     bandpass: Option<alto::efx::BandpassFilter>,
     efx_slot: Option<alto::efx::AuxEffectSlot>,
     reverb: Option<alto::efx::ReverbEffect>,
+    /// Effects on the sends after the reverb's
+    chain: Vec<(alto::efx::AuxEffectSlot, OpenALEffect)>,
     length: f32,
+    origin: BufferOrigin,
+    /// Generation of the device the buffers and effects were created on
+    device: u64,
+    /// Filters and reverb belong to the device too, so they are set again after switching
+    lowpass_amount: Option<f32>,
+    highpass_amount: Option<f32>,
+    reverb_preset: Option<String>,
+    effects: Vec<Effect>,
+    /// Custom reverbs of the loaded theme, shared with the backend
+    reverbs: Rc<RefCell<HashMap<String, CustomReverb>>>,
+    /// Master EQ, shared with the backend, and the one the filter was last set with
+    eq: Rc<Cell<Equalizer>>,
+    eq_applied: Option<Equalizer>,
+    /// Seconds played at the last update while playing. A lost device stops its sources,
+    /// so this is where they pick up again on the next one.
+    last_position: f32,
 }
 
-impl AudioEntityData for OpenALEntityData {
-    type Backend = OpenALBackend;
-
-    fn pause(&mut self) {
-        if let Some(ref mut src) = self.source {
-            src.handle.pause();
-        }
+impl OpenALEntityData {
+    fn has_source(&self) -> bool {
+        self.source.is_some()
+            || self
+                .stream
+                .as_ref()
+                .map_or(false, |stream| stream.source.is_some())
     }
 
-    fn stop(&mut self, backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
-        if let Some(ref mut src) = self.source {
-            src.handle.stop();
-        }
+    /// Sets the direct filter from the sound's low and high pass along with the master
+    /// EQ. The filter has no mid band: its gain is the mid, the low and high shelves are
+    /// set relative to it.
+    fn apply_filter(&mut self) -> Result<(), AudioBackendError> {
+        let eq = self.eq.get();
+        let low = eq.low * (1.0 - self.highpass_amount.unwrap_or(0.0));
+        let high = eq.high * (1.0 - self.lowpass_amount.unwrap_or(0.0));
+        // Filters only cut, so the mid can't go below the louder of the other bands
+        let gain = eq.mid.max(low).max(high).max(0.0).min(1.0);
+        let relative = |band: f32| {
+            if gain > 0.0 {
+                (band / gain).max(0.0).min(1.0)
+            } else {
+                0.0
+            }
+        };
 
-        self.efx_slot = None;
-        self.reverb = None;
+        let needed = self.bandpass.is_some()
+            || self.lowpass_amount.is_some()
+            || self.highpass_amount.is_some()
+            || !eq.is_flat();
 
-        if self.source.is_some() {
-            backend.free_source(self.source.take().unwrap())?;
-        }
+        with_source!(
+            self,
+            src => {
+                if needed {
+                    if self.bandpass.is_none() {
+                        self.bandpass =
+                            Some(src.context().new_filter::<alto::efx::BandpassFilter>()?);
+                    }
 
-        Ok(())
+                    let bandpass = self.bandpass.as_mut().unwrap();
+                    bandpass.set_gain(gain)?;
+                    bandpass.set_gainlf(relative(low))?;
+                    bandpass.set_gainhf(relative(high))?;
+                    src.set_direct_filter(&*bandpass)?;
+                }
+
+                self.eq_applied = Some(eq);
+                Ok(())
+            },
+            Err(AudioBackendError::NoSource)
+        )
     }
 
-    fn play(&mut self, backend: &mut Self::Backend) {
+    fn play_buffer(&mut self, backend: &mut OpenALBackend) {
         if self.source.is_none() {
             self.source = backend.get_source();
         }
@@ -69,7 +570,9 @@ impl AudioEntityData for OpenALEntityData {
             // Only set the buffer if this is a new source, not a paused one
             match src.handle.state() {
                 SourceState::Initial | SourceState::Stopped => {
-                    src.handle.set_buffer(self.buffer.clone()).unwrap();
+                    if let Some(ref buffer) = self.buffer {
+                        src.handle.set_buffer(buffer.clone()).unwrap();
+                    }
                 }
                 _ => {}
             };
@@ -80,123 +583,476 @@ impl AudioEntityData for OpenALEntityData {
         }
     }
 
-    fn is_playing(&mut self) -> bool {
+    fn clear_chain(&mut self) {
+        let sends = self.chain.len();
+        with_source!(
+            self,
+            src => {
+                for send in 1..=sends {
+                    src.clear_aux_send(send as i32);
+                }
+            },
+            ()
+        );
+
+        self.chain.clear();
+    }
+}
+
+impl AudioEntityData for OpenALEntityData {
+    type Backend = OpenALBackend;
+
+    fn pause(&mut self) {
+        with_source!(self, src => src.pause(), ())
+    }
+
+    fn stop(&mut self, backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
         if let Some(ref mut src) = self.source {
-            if src.handle.state() == alto::SourceState::Playing {
-                return true;
+            src.handle.stop();
+        }
+
+        if let Some(ref mut stream) = self.stream {
+            if let Some(mut source) = stream.source.take() {
+                source.stop();
+            }
+            stream.primed = false;
+        }
+
+        self.clear_chain();
+        self.efx_slot = None;
+        self.reverb = None;
+
+        if self.source.is_some() {
+            backend.free_source(self.source.take().unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    fn play(&mut self, backend: &mut Self::Backend) {
+        if let Some(ref mut stream) = self.stream {
+            if let Err(e) = stream.play(&backend.context) {
+                error!("Failed to start streaming: {}", e);
+            }
+        } else {
+            self.play_buffer(backend);
+        }
+
+        // New sources start out without a filter
+        if self.has_source() {
+            if let Err(e) = self.apply_filter() {
+                warn!("Failed to set the filter: {}", e);
             }
         }
+    }
 
-        false
+    fn is_playing(&mut self) -> bool {
+        with_source!(self, src => src.state() == alto::SourceState::Playing, false)
     }
 
     fn get_position(&mut self) -> f32 {
-        if let Some(ref mut src) = self.source {
-            if src.handle.state() != alto::SourceState::Playing {
-                return 0.0;
-            }
+        let played = self
+            .stream
+            .as_ref()
+            .map_or(0.0, OpenALStream::played_seconds);
+        let length = self.length;
+
+        with_source!(
+            self,
+            src => {
+                if src.state() != alto::SourceState::Playing {
+                    return 0.0;
+                }
+
+                (played + src.sec_offset()) / length
+            },
+            0.0
+        )
+    }
+
+    fn get_position_seconds(&mut self) -> f32 {
+        let played = self
+            .stream
+            .as_ref()
+            .map_or(0.0, OpenALStream::played_seconds);
 
-            return src.handle.sec_offset() / self.length;
+        with_source!(
+            self,
+            src => match src.state() {
+                SourceState::Playing | SourceState::Paused => played + src.sec_offset(),
+                _ => 0.0,
+            },
+            0.0
+        )
+    }
+
+    fn get_length(&mut self) -> f32 {
+        self.length
+    }
+
+    fn seek(&mut self, backend: &mut Self::Backend, seconds: f32) -> Result<(), AudioBackendError> {
+        let seconds = seconds.max(0.0).min(self.length);
+
+        if let Some(ref mut stream) = self.stream {
+            return stream.seek(&backend.context, seconds);
         }
 
-        0.0
+        match self.source {
+            Some(ref mut src) => Ok(src.handle.set_sec_offset(seconds)?),
+            None => Err(AudioBackendError::NoSource),
+        }
     }
 
     fn set_volume(&mut self, volume: f32) -> Result<(), AudioBackendError> {
-        if let Some(ref mut src) = self.source {
-            Ok(src.handle.set_gain(volume)?)
-        } else {
+        with_source!(
+            self,
+            src => {
+                // OpenAL clamps to the maximum gain, normalized samples may be raised above 1.0
+                if volume > 1.0 {
+                    src.set_max_gain(volume)?;
+                }
+                Ok(src.set_gain(volume)?)
+            },
             Err(AudioBackendError::NoSource)
-        }
+        )
     }
 
     fn set_pitch(&mut self, pitch: f32) -> Result<(), AudioBackendError> {
-        if let Some(ref mut src) = self.source {
-            Ok(src.handle.set_pitch(pitch)?)
-        } else {
+        with_source!(
+            self,
+            src => Ok(src.set_pitch(pitch)?),
             Err(AudioBackendError::NoSource)
-        }
+        )
     }
 
     fn set_lowpass(&mut self, amount: f32) -> Result<(), AudioBackendError> {
-        if let Some(ref mut src) = self.source {
-            if self.bandpass.is_none() {
-                self.bandpass = Some(
-                    src.handle
-                        .context()
-                        .new_filter::<alto::efx::BandpassFilter>()?,
-                );
-                src.handle
-                    .set_direct_filter(self.bandpass.as_ref().unwrap())?;
-            }
-
-            self.bandpass.as_mut().unwrap().set_gainhf(1.0 - amount)?;
-            src.handle
-                .set_direct_filter(self.bandpass.as_ref().unwrap())?;
-            Ok(())
-        } else {
-            Err(AudioBackendError::NoSource)
+        if !self.has_source() {
+            return Err(AudioBackendError::NoSource);
         }
+
+        self.lowpass_amount = Some(amount);
+        self.apply_filter()
     }
 
     fn set_highpass(&mut self, amount: f32) -> Result<(), AudioBackendError> {
-        if let Some(ref mut src) = self.source {
-            if self.bandpass.is_none() {
-                self.bandpass = Some(
-                    src.handle
-                        .context()
-                        .new_filter::<alto::efx::BandpassFilter>()?,
-                );
-            }
-
-            self.bandpass.as_mut().unwrap().set_gainlf(1.0 - amount)?;
-            src.handle
-                .set_direct_filter(self.bandpass.as_ref().unwrap())?;
-            Ok(())
-        } else {
-            Err(AudioBackendError::NoSource)
+        if !self.has_source() {
+            return Err(AudioBackendError::NoSource);
         }
+
+        self.highpass_amount = Some(amount);
+        self.apply_filter()
     }
 
     fn set_reverb(&mut self, reverb: &str) -> Result<(), AudioBackendError> {
-        if let Some(ref mut src) = self.source {
-            let preset = match reverb_name_to_ref(reverb) {
-                None => {
+        let reverbs = self.reverbs.clone();
+        let reverbs = reverbs.borrow();
+
+        with_source!(
+            self,
+            src => {
+                if !reverbs.contains_key(reverb) && reverb_name_to_ref(reverb).is_none() {
                     self.efx_slot = None;
                     self.reverb = None;
-                    src.handle.clear_aux_send(0);
+                    self.reverb_preset = None;
+                    src.clear_aux_send(0);
+                    return Ok(());
+                }
+                self.reverb_preset = Some(reverb.to_string());
+
+                if self.efx_slot.is_none() {
+                    self.efx_slot = Some(src.context().new_aux_effect_slot()?);
+                    self.reverb = Some(src.context().new_effect::<alto::efx::ReverbEffect>()?);
+                }
+
+                info!("Setting reverb {}: ...", reverb);
+                load_reverb(self.reverb.as_mut().unwrap(), reverb, &reverbs)?;
+                self.efx_slot
+                    .as_mut()
+                    .unwrap()
+                    .set_effect(self.reverb.as_ref().unwrap())
+                    .expect("Hello World1!");
+                src.set_aux_send(0, self.efx_slot.as_mut().unwrap())
+                    .expect("Hello World3!");
+
+                Ok(())
+            },
+            Err(AudioBackendError::NoSource)
+        )
+    }
+
+    fn set_effects(&mut self, effects: &[Effect]) -> Result<(), AudioBackendError> {
+        if self.effects.is_empty() && effects.is_empty() {
+            return Ok(());
+        }
+
+        self.clear_chain();
+        self.effects = effects.to_vec();
+
+        let reverbs = self.reverbs.clone();
+        let reverbs = reverbs.borrow();
+
+        with_source!(
+            self,
+            src => {
+                for effect in effects.iter().take(MAX_EFFECTS) {
+                    match load_effect(src.context(), effect, &reverbs) {
+                        Ok(loaded) => self.chain.push(loaded),
+                        Err(e) => warn!("Skipping the {} effect: {}", effect.name(), e),
+                    }
+                }
+                if self.chain.is_empty() {
                     return Ok(());
                 }
-                Some(p) => p,
+
+                // Each slot feeds the next one, the last one the mix. The reverb keeps
+                // the first send, the chain takes the second.
+                let chain = &self.chain;
+                let chained = slot_target_setter(src.context()).map_or(false, |set| {
+                    chain
+                        .windows(2)
+                        .all(|pair| set_slot_target(set, &pair[0].0, &pair[1].0))
+                });
+
+                if chained {
+                    if let Err(e) = src.set_aux_send(1, &mut self.chain[0].0) {
+                        warn!("No send left for the effects: {}", e);
+                        self.chain.clear();
+                    }
+                } else {
+                    warn!("Effect slots can't be chained here, sending to each of them");
+                    let mut fit = self.chain.len();
+                    for (index, (slot, _)) in self.chain.iter_mut().enumerate() {
+                        if let Err(e) = src.set_aux_send(index as i32 + 1, slot) {
+                            warn!("Only {} effect(s) fit, dropping the rest: {}", index, e);
+                            fit = index;
+                            break;
+                        }
+                    }
+                    self.chain.truncate(fit);
+                }
+
+                Ok(())
+            },
+            Err(AudioBackendError::NoSource)
+        )
+    }
+
+    fn set_pan(&mut self, pan: f32) -> Result<(), AudioBackendError> {
+        with_source!(
+            self,
+            src => {
+                // Place the source on a half circle in front of the listener
+                let pan = pan.max(-1.0).min(1.0);
+                src.set_relative(true)?;
+                src.set_position([pan, 0.0, -(1.0 - pan * pan).sqrt()])?;
+                Ok(())
+            },
+            Err(AudioBackendError::NoSource)
+        )
+    }
+
+    fn set_position(
+        &mut self,
+        position: (f32, f32, f32),
+        relative: bool,
+    ) -> Result<(), AudioBackendError> {
+        with_source!(
+            self,
+            src => {
+                // World positions stay where they are when the listener moves
+                src.set_relative(relative)?;
+                src.set_position([position.0, position.1, position.2])?;
+                Ok(())
+            },
+            Err(AudioBackendError::NoSource)
+        )
+    }
+
+    /// Holds on to a source with the buffer already attached. Streams decode the start of
+    /// the file and queue it on their source.
+    fn arm(&mut self, backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        if let Some(ref mut stream) = self.stream {
+            return if stream.primed {
+                Ok(())
+            } else {
+                stream.prime(&backend.context)
             };
+        }
+
+        if self.source.is_some() {
+            return Ok(());
+        }
 
-            if self.efx_slot.is_none() {
-                self.efx_slot = Some(src.handle.context().new_aux_effect_slot()?);
-                self.reverb = Some(
-                    src.handle
-                        .context()
-                        .new_effect::<alto::efx::ReverbEffect>()?,
-                );
+        self.source = backend.get_source();
+
+        match (self.source.as_mut(), self.buffer.as_ref()) {
+            (Some(src), Some(buffer)) => Ok(src.handle.set_buffer(buffer.clone())?),
+            (Some(_), None) => Ok(()),
+            (None, _) => Err(AudioBackendError::NoSource),
+        }
+    }
+
+    fn disarm(&mut self, backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        if let Some(ref mut stream) = self.stream {
+            let idle = stream
+                .source
+                .as_ref()
+                .map_or(false, |source| match source.state() {
+                    SourceState::Initial | SourceState::Stopped => true,
+                    _ => false,
+                });
+            if idle {
+                stream.release();
             }
+            return Ok(());
+        }
 
-            info!("Setting preset {}: ...", reverb);
-            self.reverb
-                .as_mut()
-                .unwrap()
-                .set_preset(preset)
-                .expect("Hello World2!");
-            self.efx_slot
-                .as_mut()
-                .unwrap()
-                .set_effect(self.reverb.as_ref().unwrap())
-                .expect("Hello World1!");
-            src.handle
-                .set_aux_send(0, self.efx_slot.as_mut().unwrap())
-                .expect("Hello World3!");
-
-            Ok(())
-        } else {
-            Err(AudioBackendError::NoSource)
+        let idle = match self.source {
+            Some(ref src) => match src.handle.state() {
+                SourceState::Initial | SourceState::Stopped => true,
+                _ => false,
+            },
+            None => false,
+        };
+
+        if idle {
+            backend.free_source(self.source.take().unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<(), AudioBackendError> {
+        // The master EQ changed since the filter was set
+        if self.eq_applied != Some(self.eq.get()) && self.has_source() {
+            self.apply_filter()?;
+        }
+
+        if with_source!(self, src => src.state() == SourceState::Playing, false) {
+            self.last_position = self.get_position_seconds();
+        }
+
+        match self.stream {
+            Some(ref mut stream) => stream.update(),
+            None => Ok(()),
+        }
+    }
+
+    /// Uploads the samples to the new device and gets a source there, set up the way the
+    /// old one was
+    fn source_file(&self) -> Option<(PathBuf, bool)> {
+        match self.origin {
+            BufferOrigin::File { ref path, stereo } => Some((path.clone(), stereo)),
+            BufferOrigin::Samples { .. } => None,
+        }
+    }
+
+    fn reopen(
+        &mut self,
+        backend: &mut Self::Backend,
+        playing: bool,
+        decoded: &mut DecodedFiles,
+    ) -> Result<(), AudioBackendError> {
+        if self.device == backend.generation {
+            return Ok(());
+        }
+
+        // Sources of a device that was lost are stopped already, their position is gone
+        let armed = !playing && self.has_source();
+        let seconds = match with_source!(self, src => Some(src.state()), None) {
+            Some(SourceState::Playing) | Some(SourceState::Paused) => self.get_position_seconds(),
+            _ => self.last_position,
+        };
+        let properties = with_source!(
+            self,
+            src => Some((
+                src.gain(),
+                src.max_gain(),
+                src.pitch(),
+                src.relative(),
+                src.position::<[f32; 3]>(),
+            )),
+            None
+        );
+
+        // Loaded first, so the entity stays on the old device if that fails
+        let reloaded = match self.origin {
+            BufferOrigin::File { ref path, stereo } => {
+                backend.reload_file(path, stereo, self.length, decoded)?
+            }
+            BufferOrigin::Samples {
+                ref samples,
+                sample_rate,
+            } => backend.load_samples(samples.to_vec(), sample_rate)?,
+        };
+
+        // Everything created on the old device goes along with it
+        self.source = None;
+        self.bandpass = None;
+        self.efx_slot = None;
+        self.reverb = None;
+        self.chain.clear();
+        self.eq_applied = None;
+        self.buffer = reloaded.buffer;
+        self.stream = reloaded.stream;
+        self.device = backend.generation;
+
+        if !playing {
+            // Armed sounds get a source with their buffer again
+            return if armed { self.arm(backend) } else { Ok(()) };
+        }
+
+        self.play(backend);
+
+        if let Some((gain, max_gain, pitch, relative, position)) = properties {
+            with_source!(
+                self,
+                src => {
+                    src.set_max_gain(max_gain)?;
+                    src.set_gain(gain)?;
+                    src.set_pitch(pitch)?;
+                    src.set_relative(relative)?;
+                    src.set_position(position)?;
+                },
+                ()
+            );
+        }
+
+        if let Some(amount) = self.lowpass_amount {
+            self.set_lowpass(amount)?;
+        }
+        if let Some(amount) = self.highpass_amount {
+            self.set_highpass(amount)?;
+        }
+        if let Some(reverb) = self.reverb_preset.clone() {
+            self.set_reverb(&reverb)?;
+        }
+        let effects = self.effects.split_off(0);
+        self.set_effects(&effects)?;
+
+        self.seek(backend, seconds)
+    }
+
+    fn is_waiting_for_source(&self) -> bool {
+        self.stream.is_none() && self.source.is_none()
+    }
+
+    /// Buffers are shared with the duplicate, streams are opened once more
+    fn duplicate(&self, backend: &mut Self::Backend) -> Result<Self, AudioBackendError> {
+        match self.buffer {
+            Some(ref buffer) if self.device == backend.generation => Ok(backend.new_entity(
+                Some(buffer.clone()),
+                None,
+                self.length,
+                self.origin.clone(),
+            )),
+            _ => match self.origin {
+                BufferOrigin::File { ref path, stereo } => backend.load_file(path, stereo),
+                BufferOrigin::Samples {
+                    ref samples,
+                    sample_rate,
+                } => backend.load_samples(samples.to_vec(), sample_rate),
+            },
         }
     }
 }
@@ -205,15 +1061,173 @@ pub struct OpenALSource {
     id: u32,
     used: bool,
     handle: alto::StaticSource,
+    /// Generation of the device the source was created on
+    device: u64,
 }
 
 pub struct OpenALBackend {
     alto: alto::Alto,
     context: alto::Context,
     sources: HashMap<u32, OpenALSource>,
+    pool_size: usize,
+    /// How far the pool may grow, lowered when the implementation can't create any more
+    max_sources: usize,
+    pool_exhausted: u64,
+    capture: Option<alto::Capture<alto::Mono<i16>>>,
+    input_level: f32,
+    rates: RateConverter,
+    /// Rate given with `--output-rate`, devices mix at their own rate otherwise
+    requested_rate: Option<i32>,
+    /// Index of the output device in `get_output_devices`
+    device_id: i32,
+    /// Custom reverbs of the loaded theme, shared with every entity
+    custom_reverbs: Rc<RefCell<HashMap<String, CustomReverb>>>,
+    /// Entities pick up changes of the master EQ with their next update
+    eq: Rc<Cell<Equalizer>>,
+    /// Name of the output device, indices change when devices come and go
+    device_name: CString,
+    /// Counts up with every switch of the device, entities from before have to be reopened
+    generation: u64,
+    /// Buffers uploaded to the current device by reopened entities, for the others of
+    /// the same file
+    reloaded: HashMap<(PathBuf, bool), Weak<alto::Buffer>>,
+    /// Listener settings, carried over to the context of a new device
+    volume: f32,
+    listener_position: [f32; 3],
+    listener_orientation: ([f32; 3], [f32; 3]),
+}
+
+/// Rate the context's device mixes at, as it may not be the one asked for. alto has no
+/// wrapper for ALC_FREQUENCY, so this goes to the raw API.
+fn mixing_rate(context: &alto::Context) -> i32 {
+    let device = context.device();
+    let mut rate: alto::sys::ALCint = 0;
+    unsafe {
+        device.alto().raw_api().alcGetIntegerv(
+            device.as_raw(),
+            alto::sys::ALC_FREQUENCY,
+            1,
+            &mut rate,
+        );
+    }
+
+    if rate > 0 {
+        rate
+    } else {
+        warn!(
+            "Failed to query the output rate, assuming {} Hz",
+            DEFAULT_OUTPUT_RATE
+        );
+        DEFAULT_OUTPUT_RATE
+    }
+}
+
+/// Creates a context on the device along with a pool of up to `count` static sources.
+/// Without an `output_rate` the device mixes at its own rate.
+fn open_context(
+    device: &alto::OutputDevice,
+    output_rate: Option<i32>,
+    generation: u64,
+    count: usize,
+) -> Result<(alto::Context, HashMap<u32, OpenALSource>), AudioBackendError> {
+    let ctx = device.new_context(Some(alto::ContextAttrs {
+        frequency: output_rate,
+        refresh: None,
+        mono_sources: None,
+        stereo_sources: None,
+        soft_hrtf_id: None,
+        soft_hrtf: None,
+        soft_output_limiter: None,
+        max_aux_sends: Some(8),
+    }))?;
+
+    let mut sources: HashMap<u32, OpenALSource> = HashMap::new();
+    for i in 0..count as u32 {
+        let src = match ctx.new_static_source() {
+            Ok(source) => source,
+            Err(_) => {
+                warn!("Failed to create {} static sources, created {}", count, i);
+                break;
+            }
+        };
+
+        sources.insert(
+            i + 1,
+            OpenALSource {
+                id: i + 1,
+                used: false,
+                handle: src,
+                device: generation,
+            },
+        );
+    }
+
+    Ok((ctx, sources))
 }
 
 impl OpenALBackend {
+    /// Opens the device with a new context and source pool, the listener is carried over.
+    /// Nothing changes if the device can't be opened.
+    fn open_output(&mut self, id: i32, name: &CStr) -> Result<(), AudioBackendError> {
+        let device = self.alto.open(Some(name))?;
+        let count = INITIAL_SOURCES.min(self.max_sources);
+        let (context, sources) =
+            open_context(&device, self.requested_rate, self.generation + 1, count)?;
+        if sources.is_empty() {
+            return Err(AudioBackendError::NoSource);
+        }
+
+        // Samples decoded from now on are resampled for the new device
+        let rate = mixing_rate(&context);
+        info!("Mixing at {} Hz", rate);
+        self.rates.set_output_rate(Some(rate));
+
+        context.set_gain(self.volume)?;
+        context.set_position(self.listener_position)?;
+        context.set_orientation(self.listener_orientation)?;
+
+        self.context = context;
+        self.pool_size = sources.len();
+        self.sources = sources;
+        self.device_id = id;
+        self.device_name = name.to_owned();
+        self.generation += 1;
+        self.reloaded.clear();
+
+        Ok(())
+    }
+
+    /// Loads a file of an entity moved to this device. Its buffer is shared with the
+    /// entities of the same file moved before, otherwise it is taken from `decoded` or
+    /// decoded here if it isn't there.
+    fn reload_file(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+        length: f32,
+        decoded: &mut DecodedFiles,
+    ) -> Result<OpenALEntityData, AudioBackendError> {
+        let key = (path.clone(), stereo);
+        let origin = BufferOrigin::File {
+            path: path.clone(),
+            stereo,
+        };
+
+        if let Some(buffer) = self.reloaded.get(&key).and_then(Weak::upgrade) {
+            return Ok(self.new_entity(Some(buffer), None, length, origin));
+        }
+
+        let entity = match decoded.remove(&key) {
+            Some(file) => self.load_decoded(path, stereo, file)?,
+            None => self.load_file(path, stereo)?,
+        };
+        if let Some(ref buffer) = entity.buffer {
+            self.reloaded.insert(key, Arc::downgrade(buffer));
+        }
+
+        Ok(entity)
+    }
+
     fn get_source(&mut self) -> Option<OpenALSource> {
         debug!(
             "Requesting source, {} sources available",
@@ -232,21 +1246,109 @@ impl OpenALBackend {
             return self.sources.remove(&free_source);
         }
 
+        if self.pool_size < self.max_sources {
+            match self.context.new_static_source() {
+                Ok(handle) => {
+                    self.pool_size += 1;
+                    debug!("Grew the source pool to {} sources", self.pool_size);
+
+                    return Some(OpenALSource {
+                        id: self.pool_size as u32,
+                        used: false,
+                        handle,
+                        device: self.generation,
+                    });
+                }
+                Err(e) => {
+                    // The implementation has a limit of its own
+                    warn!(
+                        "Failed to grow the source pool beyond {} sources: {}",
+                        self.pool_size, e
+                    );
+                    self.max_sources = self.pool_size;
+                }
+            }
+        }
+
+        warn!("All {} sources are in use", self.pool_size);
+        self.pool_exhausted += 1;
         None
     }
 
     fn reset_source(&self, source: &mut alto::StaticSource) -> Result<(), AudioBackendError> {
         source.set_gain(1.0)?;
+        source.set_max_gain(1.0)?;
         source.set_pitch(1.0)?;
         source.clear_direct_filter();
         source.clear_aux_send(0);
+        // Sounds without a position play right at the listener, wherever it moves
+        source.set_relative(true)?;
+        source.set_position([0.0, 0.0, 0.0])?;
         source.clear_buffer();
         source.stop();
 
         Ok(())
     }
 
+    fn load_channels(
+        &mut self,
+        samples: Vec<i16>,
+        sample_rate: i32,
+        channels: usize,
+        origin: BufferOrigin,
+    ) -> Result<OpenALEntityData, AudioBackendError> {
+        let length = (samples.len() / channels) as f32 / sample_rate as f32;
+
+        info!(
+            "Loaded {} samples ({} channel(s)) at rate {}",
+            samples.len(),
+            channels,
+            sample_rate
+        );
+
+        let (samples, sample_rate) = self.rates.convert(samples, sample_rate, channels);
+        let buf = fill_buffer(&self.context, None, samples, channels, sample_rate)?;
+
+        Ok(self.new_entity(Some(Arc::new(buf)), None, length, origin))
+    }
+
+    fn new_entity(
+        &self,
+        buffer: Option<Arc<alto::Buffer>>,
+        stream: Option<OpenALStream>,
+        length: f32,
+        origin: BufferOrigin,
+    ) -> OpenALEntityData {
+        OpenALEntityData {
+            buffer,
+            source: None,
+            stream,
+            lowpass: None,
+            highpass: None,
+            bandpass: None,
+            efx_slot: None,
+            reverb: None,
+            chain: Vec::new(),
+            reverbs: self.custom_reverbs.clone(),
+            eq: self.eq.clone(),
+            eq_applied: None,
+            length,
+            origin,
+            device: self.generation,
+            lowpass_amount: None,
+            highpass_amount: None,
+            reverb_preset: None,
+            effects: Vec::new(),
+            last_position: 0.0,
+        }
+    }
+
     fn free_source(&mut self, mut source: OpenALSource) -> Result<(), AudioBackendError> {
+        // Sources of the previous device are dropped along with it
+        if source.device != self.generation {
+            return Ok(());
+        }
+
         self.reset_source(&mut source.handle)?;
         self.sources.insert(source.id, source);
 
@@ -257,7 +1359,7 @@ impl OpenALBackend {
 impl AudioBackend for OpenALBackend {
     type EntityData = OpenALEntityData;
 
-    fn init() -> Self {
+    fn init(output_rate: Option<i32>, resampler: Resampler, max_sources: usize) -> Self {
         let alto = if let Ok(alto) = alto::Alto::load_default() {
             alto
         } else {
@@ -268,84 +1370,198 @@ impl AudioBackend for OpenALBackend {
             info!("Found device: {}", s.to_string_lossy());
         }
 
-        info!("Using output: {:?}", alto.default_output().unwrap());
-        let dev = alto.open(None).unwrap();
-        let ctx = dev
-            .new_context(Some(alto::ContextAttrs {
-                frequency: None,
-                refresh: None,
-                mono_sources: None,
-                stereo_sources: None,
-                soft_hrtf_id: None,
-                soft_hrtf: None,
-                soft_output_limiter: None,
-                max_aux_sends: Some(8),
-            }))
-            .unwrap();
-
-        // Try to create a pool of 32 static sources
-        let mut sources: HashMap<u32, OpenALSource> = HashMap::new();
-        let mut num_sources = 0;
-        for i in 0..32 {
-            let src = match ctx.new_static_source() {
-                Ok(source) => source,
-                Err(_) => {
-                    warn!("Failed to create 32 static sources, created {}", i);
-                    break;
-                }
-            };
+        let default_output = alto.default_output().unwrap_or_default();
+        info!("Using output: {:?}", default_output);
+        let device_id = alto
+            .enumerate_outputs()
+            .iter()
+            .position(|device| *device == default_output)
+            .unwrap_or(0) as i32;
 
-            sources.insert(
-                i + 1,
-                OpenALSource {
-                    id: i + 1,
-                    used: false,
-                    handle: src,
-                },
-            );
-            num_sources += 1;
-        }
+        let dev = alto.open(None).unwrap();
+        let max_sources = max_sources.max(1);
+        let (ctx, sources) =
+            open_context(&dev, output_rate, 0, INITIAL_SOURCES.min(max_sources)).unwrap();
 
-        if num_sources == 0 {
+        if sources.is_empty() {
             panic!("Failed to create a single static source, aborting ...");
         }
 
+        let mixing_rate = mixing_rate(&ctx);
+        info!("Mixing at {} Hz", mixing_rate);
+
         OpenALBackend {
             alto,
             context: ctx,
+            pool_size: sources.len(),
+            max_sources,
             sources,
+            pool_exhausted: 0,
+            capture: None,
+            input_level: 0.0,
+            rates: RateConverter::new(Some(mixing_rate), resampler),
+            requested_rate: output_rate,
+            device_id,
+            device_name: default_output,
+            custom_reverbs: Rc::new(RefCell::new(HashMap::new())),
+            eq: Rc::new(Cell::new(Equalizer::default())),
+            generation: 0,
+            reloaded: HashMap::new(),
+            volume: 1.0,
+            listener_position: [0.0, 0.0, 0.0],
+            listener_orientation: ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
         }
     }
 
-    fn load_file(&mut self, path: &PathBuf) -> Result<Self::EntityData, AudioBackendError> {
-        let (samples, sample_rate) = loader::get_loader_for_file(path)?.load(path)?;
+    fn load_file(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        let mut loader = loader::get_loader_for_file(path)?;
+        let length = loader.duration(path).unwrap_or(0.0);
 
-        let length = samples.len() as f32 / sample_rate as f32;
+        let origin = BufferOrigin::File {
+            path: path.clone(),
+            stereo,
+        };
 
-        info!("Loaded {} samples at rate {}", samples.len(), sample_rate);
+        if length < STREAM_MIN_SECONDS {
+            if stereo {
+                let (samples, sample_rate, channels) = loader.load_stereo(path)?;
+                return self.load_channels(samples, sample_rate, channels, origin);
+            }
 
-        let mut converted_samples = Vec::with_capacity(samples.len());
-        for sample in samples {
-            converted_samples.push(alto::Mono { center: sample });
+            let (samples, sample_rate) = loader.load(path)?;
+            return self.load_channels(samples, sample_rate, 1, origin);
         }
 
-        let buf = self.context.new_buffer(converted_samples, sample_rate)?;
-        let buf = Arc::new(buf);
+        info!(
+            "Streaming '{}', {:.0} seconds long",
+            path.to_string_lossy(),
+            length
+        );
+
+        let decoder = loader.stream(path, stereo)?;
+        let resample_to = self.rates.target_rate(decoder.sample_rate());
 
-        Ok(Self::EntityData {
-            buffer: buf,
+        let stream = OpenALStream {
+            decoder,
             source: None,
-            lowpass: None,
-            highpass: None,
-            bandpass: None,
-            efx_slot: None,
-            reverb: None,
-            length,
+            queued: VecDeque::new(),
+            played: 0,
+            exhausted: false,
+            resample_to,
+            resampler: self.rates.resampler(),
+            primed: false,
+        };
+
+        Ok(self.new_entity(None, Some(stream), length, origin))
+    }
+
+    /// Files long enough to be streamed are only decoded while playing
+    fn decode_file(path: &PathBuf, stereo: bool) -> Result<DecodedFile, AudioBackendError> {
+        let mut loader = loader::get_loader_for_file(path)?;
+        if loader.duration(path).unwrap_or(0.0) >= STREAM_MIN_SECONDS {
+            return Ok(DecodedFile::Stream);
+        }
+
+        if stereo {
+            let (samples, sample_rate, channels) = loader.load_stereo(path)?;
+            return Ok(DecodedFile::Samples {
+                samples,
+                sample_rate,
+                channels,
+            });
+        }
+
+        let (samples, sample_rate) = loader.load(path)?;
+        Ok(DecodedFile::Samples {
+            samples,
+            sample_rate,
+            channels: 1,
         })
     }
 
+    fn load_decoded(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+        decoded: DecodedFile,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        match decoded {
+            DecodedFile::Samples {
+                samples,
+                sample_rate,
+                channels,
+            } => {
+                let origin = BufferOrigin::File {
+                    path: path.clone(),
+                    stereo,
+                };
+                self.load_channels(samples, sample_rate, channels, origin)
+            }
+            DecodedFile::Stream => self.load_file(path, stereo),
+        }
+    }
+
+    fn load_samples(
+        &mut self,
+        samples: Vec<i16>,
+        sample_rate: i32,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        let origin = BufferOrigin::Samples {
+            samples: Arc::new(samples.clone()),
+            sample_rate,
+        };
+
+        self.load_channels(samples, sample_rate, 1, origin)
+    }
+
     fn set_volume(&mut self, volume: f32) {
         self.context.set_gain(volume).unwrap();
+        self.volume = volume;
+    }
+
+    /// Filters need ALC_EXT_EFX just like the low and high pass of the sounds
+    fn set_eq(&mut self, eq: Equalizer) -> Result<(), AudioBackendError> {
+        let efx = self
+            .context
+            .device()
+            .is_extension_present(alto::ext::Alc::Efx);
+        if !efx && !eq.is_flat() {
+            return Err(AudioBackendError::Unsupported("equalizer".to_owned()));
+        }
+
+        self.eq.set(eq);
+        Ok(())
+    }
+
+    fn set_listener_position(
+        &mut self,
+        position: (f32, f32, f32),
+    ) -> Result<(), AudioBackendError> {
+        let position = [position.0, position.1, position.2];
+        self.context.set_position(position)?;
+        self.listener_position = position;
+
+        Ok(())
+    }
+
+    fn set_listener_orientation(
+        &mut self,
+        at: (f32, f32, f32),
+        up: (f32, f32, f32),
+    ) -> Result<(), AudioBackendError> {
+        let orientation = ([at.0, at.1, at.2], [up.0, up.1, up.2]);
+        self.context.set_orientation(orientation)?;
+        self.listener_orientation = orientation;
+
+        Ok(())
+    }
+
+    fn set_custom_reverbs(&mut self, reverbs: &HashMap<String, CustomReverb>) {
+        *self.custom_reverbs.borrow_mut() = reverbs.clone();
     }
 
     fn get_output_devices(&mut self) -> Vec<String> {
@@ -357,11 +1573,116 @@ impl AudioBackend for OpenALBackend {
     }
 
     fn get_current_output_device(&mut self) -> i32 {
-        // TODO implement
-        0
+        self.device_id
     }
 
-    fn set_current_output_device(&mut self, _id: i32) {
-        // TODO implement
+    fn set_current_output_device(&mut self, id: i32) -> Result<(), AudioBackendError> {
+        let name = match self.alto.enumerate_outputs().into_iter().nth(id as usize) {
+            Some(name) => name,
+            None => return Err(AudioBackendError::UnknownDevice(id)),
+        };
+
+        info!("Switching output to: {}", name.to_string_lossy());
+        self.open_output(id, &name)
+    }
+
+    fn get_sample_rates(&mut self) -> SampleRateStatus {
+        self.rates.status()
+    }
+
+    fn is_device_alive(&mut self) -> bool {
+        // Without ALC_EXT_disconnect there is no telling, the device is assumed to be there
+        self.context.device().connected().unwrap_or(true)
+    }
+
+    /// Opens the lost device again once it is back, or the default device if it is
+    /// gone for good
+    fn reconnect(&mut self) -> Result<(), AudioBackendError> {
+        let outputs = self.alto.enumerate_outputs();
+        let default_output = self.alto.default_output().unwrap_or_default();
+
+        let id = match outputs.iter().position(|name| *name == self.device_name) {
+            Some(id) => id,
+            None => match outputs.iter().position(|name| *name == default_output) {
+                Some(id) => id,
+                None => return Err(AudioBackendError::NoDevice),
+            },
+        };
+
+        info!("Reconnecting output to: {}", outputs[id].to_string_lossy());
+        self.open_output(id as i32, &outputs[id])
+    }
+
+    fn get_capabilities(&mut self) -> BackendCapabilities {
+        // Filters and reverb need ALC_EXT_EFX, which not every implementation has
+        let effects = self
+            .context
+            .device()
+            .is_extension_present(alto::ext::Alc::Efx);
+
+        BackendCapabilities {
+            effects,
+            reverb_presets: if effects {
+                REVERB_PRESETS
+                    .iter()
+                    .map(|preset| preset.to_string())
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            positional: true,
+            input_monitor: true,
+        }
+    }
+
+    fn get_source_pool(&mut self) -> Option<SourcePool> {
+        Some(SourcePool {
+            used: self.pool_size - self.sources.len(),
+            total: self.pool_size,
+            limit: self.max_sources,
+            exhausted: self.pool_exhausted,
+        })
+    }
+
+    fn start_input_monitor(&mut self) -> Result<(), AudioBackendError> {
+        info!(
+            "Monitoring input: {:?}",
+            self.alto.default_capture().unwrap_or_default()
+        );
+
+        // Buffer up to 100ms of input, we poll a lot more often than that
+        let mut capture = self.alto.open_capture::<alto::Mono<i16>>(
+            None,
+            CAPTURE_RATE,
+            CAPTURE_RATE as i32 / 10,
+        )?;
+        capture.start();
+        self.capture = Some(capture);
+
+        Ok(())
+    }
+
+    fn get_input_level(&mut self) -> f32 {
+        if let Some(ref mut capture) = self.capture {
+            let available = capture.samples_len() as usize;
+
+            if available > 0 {
+                let mut samples = vec![alto::Mono { center: 0i16 }; available];
+
+                match capture.capture_samples(&mut samples[..]) {
+                    Ok(()) => {
+                        let peak = samples
+                            .iter()
+                            .map(|s| (s.center as i32).abs())
+                            .max()
+                            .unwrap_or(0);
+                        self.input_level = peak as f32 / std::i16::MAX as f32;
+                    }
+                    Err(e) => warn!("Failed to read from capture device: {}", e),
+                }
+            }
+        }
+
+        self.input_level
     }
 }
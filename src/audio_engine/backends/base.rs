@@ -1,6 +1,349 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::clock::Clock;
+use crate::audio_engine::loader;
+use crate::audio_engine::resampler::{Resampler, SampleRateStatus};
+
+/// A file decoded ahead of time, e.g. on a worker thread, so the backend only has to
+/// upload it
+pub enum DecodedFile {
+    Samples {
+        samples: Vec<i16>,
+        sample_rate: i32,
+        channels: usize,
+    },
+    /// Long enough to be streamed, it is decoded while playing
+    Stream,
+}
+
+/// Files decoded ahead of time by path and whether they are loaded in stereo
+pub type DecodedFiles = HashMap<(PathBuf, bool), DecodedFile>;
+
+/// Occupancy of a backend's pool of sources
+#[derive(Clone, Copy, Serialize)]
+pub struct SourcePool {
+    pub used: usize,
+    pub total: usize,
+    /// How far the pool grows when all sources are in use
+    pub limit: usize,
+    /// Requests that found no free source, since the backend started
+    pub exhausted: u64,
+}
+
+fn get_default_wet() -> f32 {
+    1.0
+}
+
+fn get_default_echo_delay() -> f32 {
+    0.1
+}
+
+fn get_default_echo_damping() -> f32 {
+    0.5
+}
+
+fn get_default_echo_feedback() -> f32 {
+    0.5
+}
+
+fn get_default_echo_spread() -> f32 {
+    -1.0
+}
+
+fn get_default_chorus_rate() -> f32 {
+    1.1
+}
+
+fn get_default_chorus_depth() -> f32 {
+    0.1
+}
+
+fn get_default_chorus_feedback() -> f32 {
+    0.25
+}
+
+fn get_default_chorus_delay() -> f32 {
+    0.016
+}
+
+fn get_default_distortion_edge() -> f32 {
+    0.2
+}
+
+fn get_default_distortion_gain() -> f32 {
+    0.05
+}
+
+fn get_default_distortion_cutoff() -> f32 {
+    8000.0
+}
+
+/// An effect of a sound's chain. The sound goes through the effects one after the other,
+/// backends that can't chain them send the sound to each of them side by side. `wet`
+/// (0.0 - 1.0) is how much of the signal goes through the effect. Defaults are those of EFX.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Effect {
+    Reverb {
+        preset: String,
+        #[serde(default = "get_default_wet")]
+        wet: f32,
+    },
+    Echo {
+        /// Seconds (0.0 - 0.207) between the sound and its first echo
+        #[serde(default = "get_default_echo_delay")]
+        delay: f32,
+        /// Seconds (0.0 - 0.404) between the first echo and the second
+        #[serde(default = "get_default_echo_delay")]
+        lr_delay: f32,
+        /// High frequencies lost with every echo (0.0 - 0.99)
+        #[serde(default = "get_default_echo_damping")]
+        damping: f32,
+        /// Gain (0.0 - 1.0) each echo is fed back with
+        #[serde(default = "get_default_echo_feedback")]
+        feedback: f32,
+        /// How far apart (-1.0 - 1.0) the echoes are panned
+        #[serde(default = "get_default_echo_spread")]
+        spread: f32,
+        #[serde(default = "get_default_wet")]
+        wet: f32,
+    },
+    Chorus {
+        /// Hz (0.0 - 10.0) of the modulation
+        #[serde(default = "get_default_chorus_rate")]
+        rate: f32,
+        #[serde(default = "get_default_chorus_depth")]
+        depth: f32,
+        /// -1.0 - 1.0
+        #[serde(default = "get_default_chorus_feedback")]
+        feedback: f32,
+        /// Seconds (0.0 - 0.016) of the average delay
+        #[serde(default = "get_default_chorus_delay")]
+        delay: f32,
+        #[serde(default = "get_default_wet")]
+        wet: f32,
+    },
+    Distortion {
+        /// Shape of the distortion (0.0 - 1.0)
+        #[serde(default = "get_default_distortion_edge")]
+        edge: f32,
+        /// Gain (0.01 - 1.0) of the distorted sound
+        #[serde(default = "get_default_distortion_gain")]
+        gain: f32,
+        /// Hz (80.0 - 24000.0) the distorted sound is cut off above
+        #[serde(default = "get_default_distortion_cutoff")]
+        lowpass_cutoff: f32,
+        #[serde(default = "get_default_wet")]
+        wet: f32,
+    },
+}
+
+/// Clamps `value` to what EFX accepts, noting it if it was out of range
+fn clamp(problems: &mut Vec<String>, name: &str, value: f32, min: f32, max: f32) -> f32 {
+    if value < min || value > max {
+        problems.push(format!(
+            "{} {} is out of range ({} - {}) and will be clamped",
+            name, value, min, max
+        ));
+    }
+
+    value.max(min).min(max)
+}
+
+impl Effect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Reverb { .. } => "reverb",
+            Effect::Echo { .. } => "echo",
+            Effect::Chorus { .. } => "chorus",
+            Effect::Distortion { .. } => "distortion",
+        }
+    }
+
+    pub fn wet(&self) -> f32 {
+        match *self {
+            Effect::Reverb { wet, .. }
+            | Effect::Echo { wet, .. }
+            | Effect::Chorus { wet, .. }
+            | Effect::Distortion { wet, .. } => wet,
+        }
+    }
+
+    /// The effect with its parameters clamped to what EFX accepts, along with the ones
+    /// that were out of range
+    pub fn clamped(&self) -> (Effect, Vec<String>) {
+        let mut problems = Vec::new();
+        let wet = clamp(&mut problems, "wet", self.wet(), 0.0, 1.0);
+
+        let effect = match *self {
+            Effect::Reverb { ref preset, .. } => Effect::Reverb {
+                preset: preset.clone(),
+                wet,
+            },
+            Effect::Echo {
+                delay,
+                lr_delay,
+                damping,
+                feedback,
+                spread,
+                ..
+            } => Effect::Echo {
+                delay: clamp(&mut problems, "delay", delay, 0.0, 0.207),
+                lr_delay: clamp(&mut problems, "lr_delay", lr_delay, 0.0, 0.404),
+                damping: clamp(&mut problems, "damping", damping, 0.0, 0.99),
+                feedback: clamp(&mut problems, "feedback", feedback, 0.0, 1.0),
+                spread: clamp(&mut problems, "spread", spread, -1.0, 1.0),
+                wet,
+            },
+            Effect::Chorus {
+                rate,
+                depth,
+                feedback,
+                delay,
+                ..
+            } => Effect::Chorus {
+                rate: clamp(&mut problems, "rate", rate, 0.0, 10.0),
+                depth: clamp(&mut problems, "depth", depth, 0.0, 1.0),
+                feedback: clamp(&mut problems, "feedback", feedback, -1.0, 1.0),
+                delay: clamp(&mut problems, "delay", delay, 0.0, 0.016),
+                wet,
+            },
+            Effect::Distortion {
+                edge,
+                gain,
+                lowpass_cutoff,
+                ..
+            } => Effect::Distortion {
+                edge: clamp(&mut problems, "edge", edge, 0.0, 1.0),
+                gain: clamp(&mut problems, "gain", gain, 0.01, 1.0),
+                lowpass_cutoff: clamp(
+                    &mut problems,
+                    "lowpass_cutoff",
+                    lowpass_cutoff,
+                    80.0,
+                    24000.0,
+                ),
+                wet,
+            },
+        };
+
+        (effect, problems)
+    }
+}
+
+fn get_default_reverb_preset() -> String {
+    "none".to_string()
+}
+
+/// A reverb defined by a theme, sounds use it by its name like a preset. It starts out
+/// from `preset` and sets the parameters given on top, with the names and ranges of EFX.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CustomReverb {
+    #[serde(default = "get_default_reverb_preset")]
+    pub preset: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diffusion: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain_hf: Option<f32>,
+    /// Seconds (0.1 - 20.0) the reverb takes to die away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decay_time: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decay_hf_ratio: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reflections_gain: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reflections_delay: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub late_reverb_gain: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub late_reverb_delay: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub air_absorption_gain_hf: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_rolloff_factor: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decay_hf_limit: Option<bool>,
+}
+
+impl CustomReverb {
+    /// The reverb with its parameters clamped to what EFX accepts, along with the ones
+    /// that were out of range
+    pub fn clamped(&self) -> (CustomReverb, Vec<String>) {
+        let mut problems = Vec::new();
+        let mut check = |name: &str, value: Option<f32>, min: f32, max: f32| {
+            value.map(|value| clamp(&mut problems, name, value, min, max))
+        };
+
+        let reverb = CustomReverb {
+            preset: self.preset.clone(),
+            density: check("density", self.density, 0.0, 1.0),
+            diffusion: check("diffusion", self.diffusion, 0.0, 1.0),
+            gain: check("gain", self.gain, 0.0, 1.0),
+            gain_hf: check("gain_hf", self.gain_hf, 0.0, 1.0),
+            decay_time: check("decay_time", self.decay_time, 0.1, 20.0),
+            decay_hf_ratio: check("decay_hf_ratio", self.decay_hf_ratio, 0.1, 2.0),
+            reflections_gain: check("reflections_gain", self.reflections_gain, 0.0, 3.16),
+            reflections_delay: check("reflections_delay", self.reflections_delay, 0.0, 0.3),
+            late_reverb_gain: check("late_reverb_gain", self.late_reverb_gain, 0.0, 10.0),
+            late_reverb_delay: check("late_reverb_delay", self.late_reverb_delay, 0.0, 0.1),
+            air_absorption_gain_hf: check(
+                "air_absorption_gain_hf",
+                self.air_absorption_gain_hf,
+                0.892,
+                1.0,
+            ),
+            room_rolloff_factor: check("room_rolloff_factor", self.room_rolloff_factor, 0.0, 10.0),
+            decay_hf_limit: self.decay_hf_limit,
+        };
+
+        (reverb, problems)
+    }
+}
+
+/// Gains (0.0 - 1.0) of the low, mid and high frequencies of everything played, 1.0
+/// leaves a band as it is. Filters can only cut, so none of the bands can be boosted.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Equalizer {
+    pub low: f32,
+    pub mid: f32,
+    pub high: f32,
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Equalizer {
+            low: 1.0,
+            mid: 1.0,
+            high: 1.0,
+        }
+    }
+}
+
+impl Equalizer {
+    pub fn is_flat(&self) -> bool {
+        *self == Equalizer::default()
+    }
+}
+
+/// What a backend can do, so clients can hide controls that would have no effect
+#[derive(Clone, Serialize)]
+pub struct BackendCapabilities {
+    /// Reverb presets and low/high pass filters
+    pub effects: bool,
+    /// Presets `reverb` can be set to, empty without effects
+    pub reverb_presets: Vec<String>,
+    /// Positions are placed around a listener that can move, instead of only panned
+    pub positional: bool,
+    /// The input can be monitored, e.g. for ducking
+    pub input_monitor: bool,
+}
 
 pub trait AudioEntityData: Sized {
     type Backend: AudioBackend;
@@ -14,17 +357,213 @@ pub trait AudioEntityData: Sized {
     fn set_lowpass(&mut self, amount: f32) -> Result<(), AudioBackendError>;
     fn set_highpass(&mut self, amount: f32) -> Result<(), AudioBackendError>;
     fn set_reverb(&mut self, reverb: &str) -> Result<(), AudioBackendError>;
+    /// Sends the sound through these effects on top of its reverb, replacing the ones
+    /// set before. Backends without effects ignore them, like the reverb.
+    fn set_effects(&mut self, effects: &[Effect]) -> Result<(), AudioBackendError>;
+    /// Stereo balance from -1.0 (left) to 1.0 (right)
+    fn set_pan(&mut self, pan: f32) -> Result<(), AudioBackendError>;
+    /// Places the sound at (x, y, z), the listener starts out at the origin facing -z.
+    /// Relative positions are taken from the listener's point of view and move with it.
+    fn set_position(
+        &mut self,
+        position: (f32, f32, f32),
+        relative: bool,
+    ) -> Result<(), AudioBackendError>;
     fn get_position(&mut self) -> f32;
+    /// Seconds into the sample, also while paused, 0 if it isn't playing
+    fn get_position_seconds(&mut self) -> f32;
+    /// Length of the sample in seconds
+    fn get_length(&mut self) -> f32;
+    /// Jumps to `seconds` into the sample, a paused sample stays paused
+    fn seek(&mut self, backend: &mut Self::Backend, seconds: f32) -> Result<(), AudioBackendError>;
+    /// Called regularly while playing, e.g. to keep feeding a streamed file
+    fn update(&mut self) -> Result<(), AudioBackendError>;
+    /// Gets everything ready to play before it is needed, so starting has as little
+    /// latency as possible
+    fn arm(&mut self, backend: &mut Self::Backend) -> Result<(), AudioBackendError>;
+    /// Releases what `arm` acquired, unless the sound is playing
+    fn disarm(&mut self, backend: &mut Self::Backend) -> Result<(), AudioBackendError>;
+    /// The file the entity was loaded from and whether in stereo, so it can be decoded
+    /// ahead of switching the output device
+    fn source_file(&self) -> Option<(PathBuf, bool)>;
+    /// Moves the entity to the output device the backend switched to, its file is taken
+    /// from `decoded` if it is there. If the engine has it `playing`, it plays on from
+    /// where it was, even if the old device stopped it.
+    fn reopen(
+        &mut self,
+        backend: &mut Self::Backend,
+        playing: bool,
+        decoded: &mut DecodedFiles,
+    ) -> Result<(), AudioBackendError>;
+    /// Whether the last `play` found no free source, it has to be played again once one
+    /// is released
+    fn is_waiting_for_source(&self) -> bool;
+    /// Another entity of the same samples, sharing them where the backend can. It starts
+    /// out stopped and without any of the settings of this one.
+    fn duplicate(&self, backend: &mut Self::Backend) -> Result<Self, AudioBackendError>;
 }
 
 pub trait AudioBackend: Sized {
     type EntityData: AudioEntityData<Backend = Self>;
 
-    fn init() -> Self;
-    fn load_file(&mut self, path: &PathBuf) -> Result<Self::EntityData, AudioBackendError>;
+    /// `output_rate` is the rate to play at instead of the one the backend picks, buffers
+    /// at other rates are converted to it with `resampler`. Backends with a pool of
+    /// sources grow it up to `max_sources`.
+    fn init(output_rate: Option<i32>, resampler: Resampler, max_sources: usize) -> Self;
+    /// Hands the backend the engine's clock before anything is loaded. Backends playing
+    /// on a device keep its time and ignore it.
+    fn set_clock(&mut self, _clock: Clock) {}
+    /// Stereo files are downmixed to mono unless `stereo` is set. Stereo entities can't be
+    /// positioned or panned.
+    fn load_file(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+    ) -> Result<Self::EntityData, AudioBackendError>;
+    /// The decoding part of `load_file`, it doesn't touch the device so it can run on a
+    /// worker thread. `load_decoded` creates the entity from the result.
+    fn decode_file(path: &PathBuf, stereo: bool) -> Result<DecodedFile, AudioBackendError> {
+        let mut loader = loader::get_loader_for_file(path)?;
+
+        if stereo {
+            let (samples, sample_rate, channels) = loader.load_stereo(path)?;
+            return Ok(DecodedFile::Samples {
+                samples,
+                sample_rate,
+                channels,
+            });
+        }
+
+        let (samples, sample_rate) = loader.load(path)?;
+        Ok(DecodedFile::Samples {
+            samples,
+            sample_rate,
+            channels: 1,
+        })
+    }
+
+    /// Like `load_file`, with what `decode_file` decoded from `path`
+    fn load_decoded(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+        decoded: DecodedFile,
+    ) -> Result<Self::EntityData, AudioBackendError>;
+    /// Creates an entity from generated mono samples instead of a file
+    fn load_samples(
+        &mut self,
+        samples: Vec<i16>,
+        sample_rate: i32,
+    ) -> Result<Self::EntityData, AudioBackendError>;
     fn set_volume(&mut self, volume: f32);
+    /// Applies the EQ to everything played, on top of the filters of each sound
+    fn set_eq(&mut self, eq: Equalizer) -> Result<(), AudioBackendError>;
+    /// Moves the listener, sounds that have a position are heard from there
+    fn set_listener_position(&mut self, position: (f32, f32, f32))
+        -> Result<(), AudioBackendError>;
+    /// Turns the listener to face `at`, with `up` pointing upwards
+    fn set_listener_orientation(
+        &mut self,
+        at: (f32, f32, f32),
+        up: (f32, f32, f32),
+    ) -> Result<(), AudioBackendError>;
+    /// Makes the custom reverbs of a theme known, replacing those of the previous one.
+    /// Sounds pick them up with their next run.
+    fn set_custom_reverbs(&mut self, reverbs: &HashMap<String, CustomReverb>);
     fn get_output_devices(&mut self) -> Vec<String>;
 
     fn get_current_output_device(&mut self) -> i32;
-    fn set_current_output_device(&mut self, id: i32);
+    /// Switches to another output device, entities loaded before have to be reopened
+    /// to be heard on it
+    fn set_current_output_device(&mut self, id: i32) -> Result<(), AudioBackendError>;
+    /// Rate of the output and the buffers that were resampled to it
+    fn get_sample_rates(&mut self) -> SampleRateStatus;
+    /// Backends without a limited number of sources have no pool
+    fn get_source_pool(&mut self) -> Option<SourcePool>;
+    /// Whether the output device is still there, e.g. hasn't been unplugged
+    fn is_device_alive(&mut self) -> bool;
+    /// Opens the output again after the device was lost, entities loaded before have to
+    /// be reopened just like after switching devices
+    fn reconnect(&mut self) -> Result<(), AudioBackendError>;
+    fn get_capabilities(&mut self) -> BackendCapabilities;
+
+    fn start_input_monitor(&mut self) -> Result<(), AudioBackendError>;
+    /// Peak level (0.0 - 1.0) of the input captured since the last call
+    fn get_input_level(&mut self) -> f32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn effect(value: serde_json::Value) -> Effect {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn effects_in_range_stay_as_they_are() {
+        let echo = effect(json!({ "type": "echo", "delay": 0.1, "feedback": 0.5, "wet": 0.8 }));
+        let (clamped, problems) = echo.clamped();
+
+        assert_eq!(clamped, echo);
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn effects_out_of_range_are_clamped() {
+        let chorus =
+            effect(json!({ "type": "chorus", "rate": 12.0, "feedback": -2.0, "wet": 1.5 }));
+        let (clamped, problems) = chorus.clamped();
+
+        assert_eq!(
+            clamped,
+            effect(json!({ "type": "chorus", "rate": 10.0, "feedback": -1.0, "wet": 1.0 }))
+        );
+        assert_eq!(problems.len(), 3);
+        assert!(problems
+            .iter()
+            .any(|problem| problem.starts_with("rate 12")));
+    }
+
+    #[test]
+    fn distortion_limits() {
+        let distortion = effect(json!({
+            "type": "distortion",
+            "gain": 0.0,
+            "lowpass_cutoff": 30000.0
+        }));
+        let (clamped, problems) = distortion.clamped();
+
+        assert_eq!(
+            clamped,
+            effect(json!({
+                "type": "distortion",
+                "gain": 0.01,
+                "lowpass_cutoff": 24000.0
+            }))
+        );
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[test]
+    fn reverbs_are_clamped() {
+        let reverb: CustomReverb = serde_json::from_value(json!({
+            "preset": "cave",
+            "decay_time": 30.0,
+            "density": 0.5,
+            "air_absorption_gain_hf": 0.5,
+            "decay_hf_limit": true
+        }))
+        .unwrap();
+        let (clamped, problems) = reverb.clamped();
+
+        assert_eq!(clamped.preset, "cave");
+        assert_eq!(clamped.decay_time, Some(20.0));
+        assert_eq!(clamped.density, Some(0.5));
+        assert_eq!(clamped.air_absorption_gain_hf, Some(0.892));
+        assert_eq!(clamped.decay_hf_limit, Some(true));
+        assert_eq!(clamped.gain, None);
+        assert_eq!(problems.len(), 2);
+    }
 }
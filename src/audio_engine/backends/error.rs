@@ -9,6 +9,15 @@ pub enum AudioBackendError {
   #[fail(display = "AudioBackend Operation on empty source!")]
   NoSource,
 
+  #[fail(display = "AudioBackend Not supported by this backend: {}", _0)]
+  Unsupported(String),
+
+  #[fail(display = "AudioBackend Unknown output device {}", _0)]
+  UnknownDevice(i32),
+
+  #[fail(display = "AudioBackend No output device present!")]
+  NoDevice,
+
   #[fail(display = "AudioBackend FileLoader Error: {}", _0)]
   AudioFileLoaderError(AudioFileLoaderError),
 }
@@ -1,3 +1,5 @@
 pub mod alto;
 pub mod base;
 pub mod error;
+pub mod null;
+pub mod rodio;
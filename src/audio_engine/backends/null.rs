@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::audio_engine::backends::alto::REVERB_PRESETS;
+use crate::audio_engine::backends::base::{
+    AudioBackend, AudioEntityData, BackendCapabilities, CustomReverb, DecodedFile, DecodedFiles,
+    Effect, Equalizer, SourcePool,
+};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::clock::Clock;
+use crate::audio_engine::loader;
+use crate::audio_engine::resampler::{RateConverter, Resampler, SampleRateStatus};
+
+/// Plays nothing, sounds just take as long as their samples would. Lets the engine run on
+/// headless machines and in tests.
+pub struct NullEntityData {
+    length: f32,
+    pitch: f32,
+    // Seconds of the sample played before the last pause or pitch change
+    offset: f32,
+    // Time of the clock when the sample was last started
+    started: Option<u64>,
+    has_source: bool,
+    clock: Clock,
+}
+
+impl NullEntityData {
+    fn elapsed(&self) -> f32 {
+        let running = match self.started {
+            Some(started) => (self.clock.elapsed_ms() - started) as f32 / 1000.0,
+            None => 0.0,
+        };
+
+        self.offset + running * self.pitch
+    }
+}
+
+impl AudioEntityData for NullEntityData {
+    type Backend = NullBackend;
+
+    fn pause(&mut self) {
+        self.offset = self.elapsed();
+        self.started = None;
+    }
+
+    fn play(&mut self, _backend: &mut Self::Backend) {
+        // Start over if the previous run played to the end
+        if self.has_source && self.elapsed() >= self.length {
+            self.offset = 0.0;
+            self.started = None;
+        }
+
+        if self.started.is_none() {
+            self.started = Some(self.clock.elapsed_ms());
+        }
+        self.has_source = true;
+    }
+
+    fn stop(&mut self, _backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        self.offset = 0.0;
+        self.started = None;
+        self.has_source = false;
+
+        Ok(())
+    }
+
+    fn is_playing(&mut self) -> bool {
+        self.started.is_some() && self.elapsed() < self.length
+    }
+
+    fn get_position(&mut self) -> f32 {
+        if !self.is_playing() {
+            return 0.0;
+        }
+
+        self.elapsed() / self.length
+    }
+
+    fn get_position_seconds(&mut self) -> f32 {
+        match self.started {
+            Some(_) if self.is_playing() => self.elapsed(),
+            // Played to the end
+            Some(_) => 0.0,
+            // Paused, or stopped which starts over
+            None => self.offset,
+        }
+    }
+
+    fn get_length(&mut self) -> f32 {
+        self.length
+    }
+
+    fn seek(
+        &mut self,
+        _backend: &mut Self::Backend,
+        seconds: f32,
+    ) -> Result<(), AudioBackendError> {
+        if !self.has_source {
+            return Err(AudioBackendError::NoSource);
+        }
+
+        self.offset = seconds.max(0.0).min(self.length);
+        self.started = self.started.map(|_| self.clock.elapsed_ms());
+
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn arm(&mut self, _backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn disarm(&mut self, _backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn source_file(&self) -> Option<(PathBuf, bool)> {
+        None
+    }
+
+    fn reopen(
+        &mut self,
+        _backend: &mut Self::Backend,
+        _playing: bool,
+        _decoded: &mut DecodedFiles,
+    ) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn is_waiting_for_source(&self) -> bool {
+        false
+    }
+
+    fn duplicate(&self, _backend: &mut Self::Backend) -> Result<Self, AudioBackendError> {
+        Ok(NullEntityData {
+            length: self.length,
+            pitch: 1.0,
+            offset: 0.0,
+            started: None,
+            has_source: false,
+            clock: self.clock.clone(),
+        })
+    }
+
+    fn set_volume(&mut self, _volume: f32) -> Result<(), AudioBackendError> {
+        if self.has_source {
+            Ok(())
+        } else {
+            Err(AudioBackendError::NoSource)
+        }
+    }
+
+    fn set_pitch(&mut self, pitch: f32) -> Result<(), AudioBackendError> {
+        if !self.has_source {
+            return Err(AudioBackendError::NoSource);
+        }
+
+        // The position so far was played at the old pitch
+        self.offset = self.elapsed();
+        self.started = self.started.map(|_| self.clock.elapsed_ms());
+        self.pitch = pitch;
+
+        Ok(())
+    }
+
+    fn set_lowpass(&mut self, _amount: f32) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_highpass(&mut self, _amount: f32) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_reverb(&mut self, _reverb: &str) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_effects(&mut self, _effects: &[Effect]) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_pan(&mut self, _pan: f32) -> Result<(), AudioBackendError> {
+        if self.has_source {
+            Ok(())
+        } else {
+            Err(AudioBackendError::NoSource)
+        }
+    }
+
+    fn set_position(
+        &mut self,
+        _position: (f32, f32, f32),
+        _relative: bool,
+    ) -> Result<(), AudioBackendError> {
+        if self.has_source {
+            Ok(())
+        } else {
+            Err(AudioBackendError::NoSource)
+        }
+    }
+}
+
+pub struct NullBackend {
+    clock: Clock,
+    // Only counts the buffers that would be resampled, nothing is played anyway
+    rates: RateConverter,
+}
+
+impl AudioBackend for NullBackend {
+    type EntityData = NullEntityData;
+
+    fn init(output_rate: Option<i32>, resampler: Resampler, _max_sources: usize) -> Self {
+        info!("Using null output, nothing will be audible");
+        NullBackend {
+            clock: Clock::new(),
+            rates: RateConverter::new(output_rate, resampler),
+        }
+    }
+
+    fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// The length is all that matters here, files are always loaded as mono
+    fn load_file(
+        &mut self,
+        path: &PathBuf,
+        _stereo: bool,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        let (samples, sample_rate) = loader::get_loader_for_file(path)?.load(path)?;
+        self.load_samples(samples, sample_rate)
+    }
+
+    /// Stereo files are mixed down, nothing is played anyway
+    fn decode_file(path: &PathBuf, _stereo: bool) -> Result<DecodedFile, AudioBackendError> {
+        let (samples, sample_rate) = loader::get_loader_for_file(path)?.load(path)?;
+        Ok(DecodedFile::Samples {
+            samples,
+            sample_rate,
+            channels: 1,
+        })
+    }
+
+    fn load_decoded(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+        decoded: DecodedFile,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        match decoded {
+            DecodedFile::Samples {
+                samples,
+                sample_rate,
+                ..
+            } => self.load_samples(samples, sample_rate),
+            DecodedFile::Stream => self.load_file(path, stereo),
+        }
+    }
+
+    fn load_samples(
+        &mut self,
+        samples: Vec<i16>,
+        sample_rate: i32,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        let length = samples.len() as f32 / sample_rate as f32;
+
+        info!("Loaded {} samples at rate {}", samples.len(), sample_rate);
+        self.rates.target_rate(sample_rate);
+
+        Ok(Self::EntityData {
+            length,
+            pitch: 1.0,
+            offset: 0.0,
+            started: None,
+            has_source: false,
+            clock: self.clock.clone(),
+        })
+    }
+
+    fn set_volume(&mut self, _volume: f32) {}
+
+    fn set_eq(&mut self, _eq: Equalizer) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_listener_position(
+        &mut self,
+        _position: (f32, f32, f32),
+    ) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_listener_orientation(
+        &mut self,
+        _at: (f32, f32, f32),
+        _up: (f32, f32, f32),
+    ) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_custom_reverbs(&mut self, _reverbs: &HashMap<String, CustomReverb>) {}
+
+    fn get_output_devices(&mut self) -> Vec<String> {
+        vec!["null".to_owned()]
+    }
+
+    fn get_current_output_device(&mut self) -> i32 {
+        0
+    }
+
+    fn set_current_output_device(&mut self, id: i32) -> Result<(), AudioBackendError> {
+        match id {
+            0 => Ok(()),
+            _ => Err(AudioBackendError::UnknownDevice(id)),
+        }
+    }
+
+    fn get_sample_rates(&mut self) -> SampleRateStatus {
+        self.rates.status()
+    }
+
+    fn is_device_alive(&mut self) -> bool {
+        true
+    }
+
+    fn reconnect(&mut self) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn get_capabilities(&mut self) -> BackendCapabilities {
+        // Accepts everything and ignores it, like a backend that can do it all
+        BackendCapabilities {
+            effects: true,
+            reverb_presets: REVERB_PRESETS
+                .iter()
+                .map(|preset| preset.to_string())
+                .collect(),
+            positional: true,
+            input_monitor: false,
+        }
+    }
+
+    fn get_source_pool(&mut self) -> Option<SourcePool> {
+        None
+    }
+
+    fn start_input_monitor(&mut self) -> Result<(), AudioBackendError> {
+        Err(AudioBackendError::Unsupported(
+            "input monitoring".to_owned(),
+        ))
+    }
+
+    fn get_input_level(&mut self) -> f32 {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playback_follows_the_clock() {
+        let mut backend = NullBackend::init(None, Resampler::default(), 1);
+        let clock = Clock::new();
+        backend.set_clock(clock.clone());
+
+        // One second at 1 kHz
+        let mut entity = backend.load_samples(vec![0; 1000], 1000).unwrap();
+        entity.play(&mut backend);
+        assert!(entity.is_playing());
+        assert_eq!(entity.get_position_seconds(), 0.0);
+
+        clock.advance(250);
+        assert_eq!(entity.get_position_seconds(), 0.25);
+        entity.pause();
+        clock.advance(500);
+        assert_eq!(entity.get_position_seconds(), 0.25);
+
+        entity.play(&mut backend);
+        clock.advance(750);
+        assert!(!entity.is_playing());
+    }
+}
@@ -0,0 +1,592 @@
+use rodio;
+use rodio::buffer::SamplesBuffer;
+use rodio::source::ChannelVolume;
+use rodio::{DeviceTrait, Sink, Source};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::audio_engine::backends::base::{
+    AudioBackend, AudioEntityData, BackendCapabilities, CustomReverb, DecodedFile, DecodedFiles,
+    Effect, Equalizer, SourcePool,
+};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::loader;
+use crate::audio_engine::resampler::{RateConverter, Resampler, SampleRateStatus};
+
+/// The master volume and the sinks it applies to. Sinks only have a volume of their own,
+/// so the master volume is multiplied into each of them, including those of one-shots the
+/// engine never sets a volume for again.
+struct Output {
+    volume: Cell<f32>,
+    sinks: RefCell<Vec<(Weak<Sink>, f32)>>,
+}
+
+impl Output {
+    fn new() -> Self {
+        Self {
+            volume: Cell::new(1.0),
+            sinks: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Sets the sink's own volume, starting to track it if it is new
+    fn set_sink_volume(&self, sink: &Rc<Sink>, volume: f32) {
+        sink.set_volume(volume * self.volume.get());
+
+        let mut sinks = self.sinks.borrow_mut();
+        // Forget the sinks that are gone
+        sinks.retain(|(known, _)| known.upgrade().is_some());
+        match sinks.iter_mut().find(|(known, _)| {
+            known
+                .upgrade()
+                .map_or(false, |known| Rc::ptr_eq(&known, sink))
+        }) {
+            Some(entry) => entry.1 = volume,
+            None => sinks.push((Rc::downgrade(sink), volume)),
+        }
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+
+        self.sinks
+            .borrow_mut()
+            .retain(|(sink, own)| match sink.upgrade() {
+                Some(sink) => {
+                    sink.set_volume(own * volume);
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+/// Playback through cpal/rodio, for systems without OpenAL. Filters and reverb are not
+/// available with this backend and are silently skipped.
+pub struct RodioEntityData {
+    samples: Arc<Vec<i16>>,
+    sample_rate: u32,
+    // Samples are interleaved if there is more than one
+    channels: u16,
+    sink: Option<Rc<Sink>>,
+    device: Option<Rc<rodio::Device>>,
+    output: Rc<Output>,
+    volume: f32,
+    pitch: f32,
+    pan: f32,
+    // Seconds of the sample played before the current sink was (re)started
+    offset: f32,
+    started: Option<Instant>,
+    length: f32,
+}
+
+impl RodioEntityData {
+    fn elapsed(&self) -> f32 {
+        let running = match self.started {
+            Some(started) => {
+                let elapsed = started.elapsed();
+                elapsed.as_secs() as f32 + elapsed.subsec_millis() as f32 / 1000.0
+            }
+            None => 0.0,
+        };
+
+        self.offset + running * self.pitch
+    }
+
+    /// Sinks can't change the speed or channel volumes of queued sources, so changing
+    /// those means starting a new sink at the current position
+    fn start_sink(&mut self, offset: f32) {
+        let device = match self.device {
+            Some(ref device) => device.clone(),
+            None => return,
+        };
+
+        let channels = usize::from(self.channels);
+        let start =
+            ((offset * self.sample_rate as f32) as usize * channels).min(self.samples.len());
+        let source = SamplesBuffer::new(
+            self.channels,
+            self.sample_rate,
+            self.samples[start..].to_vec(),
+        )
+        .speed(self.pitch);
+
+        let sink = Rc::new(Sink::new(&device));
+        self.output.set_sink_volume(&sink, self.volume);
+
+        // ChannelVolume mixes all channels into one, stereo samples aren't panned
+        if channels == 1 {
+            let pan = self.pan.max(-1.0).min(1.0);
+            sink.append(ChannelVolume::new(
+                source,
+                vec![(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)],
+            ));
+        } else {
+            sink.append(source);
+        }
+
+        self.sink = Some(sink);
+        self.offset = offset;
+        self.started = Some(Instant::now());
+    }
+
+    fn restart_sink(&mut self, offset: f32) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+
+        if self.started.is_some() {
+            self.start_sink(offset);
+        } else {
+            // Paused, the new sink is started when resuming
+            self.offset = offset;
+        }
+    }
+}
+
+impl AudioEntityData for RodioEntityData {
+    type Backend = RodioBackend;
+
+    fn pause(&mut self) {
+        if let Some(ref sink) = self.sink {
+            sink.pause();
+        }
+
+        self.offset = self.elapsed();
+        self.started = None;
+    }
+
+    fn stop(&mut self, _backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+
+        self.device = None;
+        self.offset = 0.0;
+        self.started = None;
+
+        Ok(())
+    }
+
+    fn play(&mut self, backend: &mut Self::Backend) {
+        if let Some(ref sink) = self.sink {
+            if !sink.empty() {
+                // Resume a paused sink
+                if self.started.is_none() {
+                    sink.play();
+                    self.started = Some(Instant::now());
+                }
+                return;
+            }
+
+            // Played to the end, start over
+            self.offset = 0.0;
+        }
+
+        self.device = Some(backend.device.clone());
+        self.start_sink(self.offset);
+    }
+
+    fn is_playing(&mut self) -> bool {
+        match self.sink {
+            Some(ref sink) => !sink.is_paused() && !sink.empty(),
+            None => false,
+        }
+    }
+
+    fn get_position(&mut self) -> f32 {
+        if !self.is_playing() {
+            return 0.0;
+        }
+
+        self.elapsed() / self.length
+    }
+
+    fn get_position_seconds(&mut self) -> f32 {
+        match self.sink {
+            Some(ref sink) if !sink.empty() => self.elapsed().min(self.length),
+            _ => 0.0,
+        }
+    }
+
+    fn get_length(&mut self) -> f32 {
+        self.length
+    }
+
+    fn seek(
+        &mut self,
+        _backend: &mut Self::Backend,
+        seconds: f32,
+    ) -> Result<(), AudioBackendError> {
+        if self.sink.is_none() {
+            return Err(AudioBackendError::NoSource);
+        }
+
+        self.restart_sink(seconds.max(0.0).min(self.length));
+
+        Ok(())
+    }
+
+    /// Without OpenAL only the direction of the position is kept, as pan. There is no
+    /// listener to move either, so all positions are relative.
+    fn set_position(
+        &mut self,
+        position: (f32, f32, f32),
+        _relative: bool,
+    ) -> Result<(), AudioBackendError> {
+        let (x, _, z) = position;
+        let distance = (x * x + z * z).sqrt();
+
+        self.set_pan(if distance > 0.0 { x / distance } else { 0.0 })
+    }
+
+    fn update(&mut self) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    /// Sinks are cheap to create, there is nothing to prepare
+    fn arm(&mut self, _backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn disarm(&mut self, _backend: &mut Self::Backend) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    /// Sinks can't seek, so sounds keep playing on the old device until they end
+    fn source_file(&self) -> Option<(PathBuf, bool)> {
+        None
+    }
+
+    fn reopen(
+        &mut self,
+        _backend: &mut Self::Backend,
+        _playing: bool,
+        _decoded: &mut DecodedFiles,
+    ) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    /// Every sound gets a sink of its own
+    fn is_waiting_for_source(&self) -> bool {
+        false
+    }
+
+    fn duplicate(&self, _backend: &mut Self::Backend) -> Result<Self, AudioBackendError> {
+        Ok(RodioEntityData {
+            samples: self.samples.clone(),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            sink: None,
+            device: None,
+            output: self.output.clone(),
+            volume: 1.0,
+            pitch: 1.0,
+            pan: 0.0,
+            offset: 0.0,
+            started: None,
+            length: self.length,
+        })
+    }
+
+    fn set_volume(&mut self, volume: f32) -> Result<(), AudioBackendError> {
+        if let Some(ref sink) = self.sink {
+            self.volume = volume;
+            self.output.set_sink_volume(sink, volume);
+            Ok(())
+        } else {
+            Err(AudioBackendError::NoSource)
+        }
+    }
+
+    fn set_pitch(&mut self, pitch: f32) -> Result<(), AudioBackendError> {
+        if self.sink.is_none() {
+            return Err(AudioBackendError::NoSource);
+        }
+
+        if pitch != self.pitch {
+            // The position so far was played at the old pitch
+            let offset = self.elapsed();
+            self.pitch = pitch;
+            self.restart_sink(offset);
+        }
+
+        Ok(())
+    }
+
+    fn set_lowpass(&mut self, _amount: f32) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_highpass(&mut self, _amount: f32) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_reverb(&mut self, _reverb: &str) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_effects(&mut self, _effects: &[Effect]) -> Result<(), AudioBackendError> {
+        Ok(())
+    }
+
+    fn set_pan(&mut self, pan: f32) -> Result<(), AudioBackendError> {
+        if self.sink.is_none() {
+            return Err(AudioBackendError::NoSource);
+        }
+
+        if pan != self.pan {
+            let offset = self.elapsed();
+            self.pan = pan;
+            self.restart_sink(offset);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct RodioBackend {
+    device: Rc<rodio::Device>,
+    device_id: i32,
+    output: Rc<Output>,
+    // Given when starting, takes precedence over the rate of the device
+    output_rate: Option<i32>,
+    rates: RateConverter,
+}
+
+impl RodioBackend {
+    fn load_channels(
+        &mut self,
+        samples: Vec<i16>,
+        sample_rate: i32,
+        channels: usize,
+    ) -> Result<RodioEntityData, AudioBackendError> {
+        let length = (samples.len() / channels) as f32 / sample_rate as f32;
+
+        info!(
+            "Loaded {} samples ({} channel(s)) at rate {}",
+            samples.len(),
+            channels,
+            sample_rate
+        );
+
+        let (samples, sample_rate) = self.rates.convert(samples, sample_rate, channels);
+        Ok(RodioEntityData {
+            samples: Arc::new(samples),
+            sample_rate: sample_rate as u32,
+            channels: channels as u16,
+            sink: None,
+            device: None,
+            output: self.output.clone(),
+            volume: 1.0,
+            pitch: 1.0,
+            pan: 0.0,
+            offset: 0.0,
+            started: None,
+            length,
+        })
+    }
+}
+
+/// Rate the device plays at by default, if it tells
+fn device_rate(device: &rodio::Device) -> Option<i32> {
+    device
+        .default_output_format()
+        .ok()
+        .map(|format| format.sample_rate.0 as i32)
+}
+
+impl AudioBackend for RodioBackend {
+    type EntityData = RodioEntityData;
+
+    fn init(output_rate: Option<i32>, resampler: Resampler, _max_sources: usize) -> Self {
+        let device = match rodio::default_output_device() {
+            Some(device) => device,
+            None => panic!("No audio output device present!"),
+        };
+
+        info!(
+            "Using output: {}",
+            device.name().unwrap_or_else(|_| "unknown".to_owned())
+        );
+
+        let rates = RateConverter::new(output_rate.or_else(|| device_rate(&device)), resampler);
+
+        RodioBackend {
+            device: Rc::new(device),
+            device_id: 0,
+            output: Rc::new(Output::new()),
+            output_rate,
+            rates,
+        }
+    }
+
+    fn load_file(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        let mut loader = loader::get_loader_for_file(path)?;
+
+        if stereo {
+            let (samples, sample_rate, channels) = loader.load_stereo(path)?;
+            return self.load_channels(samples, sample_rate, channels);
+        }
+
+        let (samples, sample_rate) = loader.load(path)?;
+        self.load_samples(samples, sample_rate)
+    }
+
+    fn load_decoded(
+        &mut self,
+        path: &PathBuf,
+        stereo: bool,
+        decoded: DecodedFile,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        match decoded {
+            DecodedFile::Samples {
+                samples,
+                sample_rate,
+                channels,
+            } => self.load_channels(samples, sample_rate, channels),
+            DecodedFile::Stream => self.load_file(path, stereo),
+        }
+    }
+
+    fn load_samples(
+        &mut self,
+        samples: Vec<i16>,
+        sample_rate: i32,
+    ) -> Result<Self::EntityData, AudioBackendError> {
+        self.load_channels(samples, sample_rate, 1)
+    }
+
+    /// Applies to every sink right away, including those of one-shots
+    fn set_volume(&mut self, volume: f32) {
+        self.output.set_volume(volume);
+    }
+
+    fn set_eq(&mut self, eq: Equalizer) -> Result<(), AudioBackendError> {
+        if eq.is_flat() {
+            Ok(())
+        } else {
+            Err(AudioBackendError::Unsupported("equalizer".to_owned()))
+        }
+    }
+
+    fn set_listener_position(
+        &mut self,
+        _position: (f32, f32, f32),
+    ) -> Result<(), AudioBackendError> {
+        Err(AudioBackendError::Unsupported(
+            "moving the listener".to_owned(),
+        ))
+    }
+
+    fn set_listener_orientation(
+        &mut self,
+        _at: (f32, f32, f32),
+        _up: (f32, f32, f32),
+    ) -> Result<(), AudioBackendError> {
+        Err(AudioBackendError::Unsupported(
+            "turning the listener".to_owned(),
+        ))
+    }
+
+    fn set_custom_reverbs(&mut self, _reverbs: &HashMap<String, CustomReverb>) {}
+
+    fn get_output_devices(&mut self) -> Vec<String> {
+        match rodio::output_devices() {
+            Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+            Err(e) => {
+                warn!("Failed to enumerate output devices: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn get_current_output_device(&mut self) -> i32 {
+        self.device_id
+    }
+
+    /// Only sounds started after switching play on the new device
+    fn set_current_output_device(&mut self, id: i32) -> Result<(), AudioBackendError> {
+        let device = rodio::output_devices()
+            .ok()
+            .and_then(|mut devices| devices.nth(id as usize));
+
+        match device {
+            Some(device) => {
+                info!(
+                    "Switching output to: {}",
+                    device.name().unwrap_or_else(|_| "unknown".to_owned())
+                );
+                self.rates
+                    .set_output_rate(self.output_rate.or_else(|| device_rate(&device)));
+                self.device = Rc::new(device);
+                self.device_id = id;
+                Ok(())
+            }
+            None => Err(AudioBackendError::UnknownDevice(id)),
+        }
+    }
+
+    fn get_sample_rates(&mut self) -> SampleRateStatus {
+        self.rates.status()
+    }
+
+    /// A device that is gone can't tell its format anymore
+    fn is_device_alive(&mut self) -> bool {
+        device_rate(&self.device).is_some()
+    }
+
+    /// Falls back to the default device, sounds that played on the lost one are gone
+    fn reconnect(&mut self) -> Result<(), AudioBackendError> {
+        let device = match rodio::default_output_device() {
+            Some(device) => device,
+            None => return Err(AudioBackendError::NoDevice),
+        };
+
+        let name = device.name().unwrap_or_else(|_| "unknown".to_owned());
+        info!("Reconnecting output to: {}", name);
+
+        self.device_id = rodio::output_devices()
+            .ok()
+            .and_then(|mut devices| devices.position(|d| d.name().ok() == Some(name.clone())))
+            .unwrap_or(0) as i32;
+        self.rates
+            .set_output_rate(self.output_rate.or_else(|| device_rate(&device)));
+        self.device = Rc::new(device);
+
+        Ok(())
+    }
+
+    fn get_capabilities(&mut self) -> BackendCapabilities {
+        BackendCapabilities {
+            effects: false,
+            reverb_presets: Vec::new(),
+            // Positions only end up as pan
+            positional: false,
+            input_monitor: false,
+        }
+    }
+
+    fn get_source_pool(&mut self) -> Option<SourcePool> {
+        None
+    }
+
+    fn start_input_monitor(&mut self) -> Result<(), AudioBackendError> {
+        Err(AudioBackendError::Unsupported(
+            "input monitoring".to_owned(),
+        ))
+    }
+
+    fn get_input_level(&mut self) -> f32 {
+        0.0
+    }
+}
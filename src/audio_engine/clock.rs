@@ -0,0 +1,24 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The engine's time, it only moves when the engine is updated. Backends that merely
+/// simulate playback (the null backend) take their time from it instead of the wall
+/// clock, so the same updates always give the same result.
+#[derive(Clone, Debug, Default)]
+pub struct Clock(Arc<AtomicU64>);
+
+impl Clock {
+    /// Starts at 0, clones share the same time
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Milliseconds the clock has been advanced in total
+    pub fn elapsed_ms(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.0.fetch_add(ms, Ordering::SeqCst);
+    }
+}
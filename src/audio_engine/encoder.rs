@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use audiopus::coder::Encoder;
+use audiopus::{Application, Channels, SampleRate};
+use ogg::{PacketWriteEndInfo, PacketWriter};
+
+use crate::audio_engine::loader;
+use crate::audio_engine::loader::error::AudioFileLoaderError;
+use crate::audio_engine::resampler::resample;
+
+// Opus only takes a handful of rates, 48kHz is the one every decoder plays back at
+const OPUS_SAMPLE_RATE: i32 = 48000;
+
+// 20ms frames, the size the opus documentation recommends for general audio
+const FRAME_SAMPLES: usize = 960;
+
+// Samples the decoder drops at the start, the encoder's lookahead at 48kHz
+const PRE_SKIP: u16 = 312;
+
+// Large enough for any packet of a single 20ms frame
+const MAX_PACKET_BYTES: usize = 4000;
+
+const STREAM_SERIAL: u32 = 1;
+
+fn encode_error<E: ToString>(path: &PathBuf, error: E) -> AudioFileLoaderError {
+    AudioFileLoaderError::FileLoadError(path.to_string_lossy().into_owned(), error.to_string())
+}
+
+/// Identification header as laid out in RFC 7845, section 5.1
+fn opus_head(input_rate: i32) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // Version
+    head.push(1); // Channels
+    head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+    head.extend_from_slice(&(input_rate as u32).to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+    head.push(0); // Channel mapping family, mono or stereo without a mapping table
+    head
+}
+
+/// Comment header without any comments, RFC 7845 section 5.2
+fn opus_tags() -> Vec<u8> {
+    let vendor = b"sinfonia";
+
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    tags
+}
+
+/// Decodes the file and encodes it again as a mono Ogg Opus stream, which all current
+/// browsers can play in an `<audio>` element
+pub fn encode_preview(path: &PathBuf) -> Result<Vec<u8>, AudioFileLoaderError> {
+    let (samples, rate) = loader::get_loader_for_file(path)?.load(path)?;
+    let samples = resample(&samples, rate, OPUS_SAMPLE_RATE);
+
+    let encoder = Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Audio)
+        .map_err(|e| encode_error(path, e))?;
+
+    let mut writer = PacketWriter::new(Vec::new());
+    let write = |writer: &mut PacketWriter<Vec<u8>>, packet: Vec<u8>, end, granule| {
+        writer
+            .write_packet(packet.into_boxed_slice(), STREAM_SERIAL, end, granule)
+            .map_err(|e| encode_error(path, e))
+    };
+
+    // Both headers have to be on pages of their own
+    write(&mut writer, opus_head(rate), PacketWriteEndInfo::EndPage, 0)?;
+    write(&mut writer, opus_tags(), PacketWriteEndInfo::EndPage, 0)?;
+
+    let frames = (samples.len() + FRAME_SAMPLES - 1) / FRAME_SAMPLES;
+    let mut packet = vec![0u8; MAX_PACKET_BYTES];
+
+    // Empty files still get a frame, streams without audio packets are invalid
+    for index in 0..frames.max(1) {
+        let start = (index * FRAME_SAMPLES).min(samples.len());
+        let end = (start + FRAME_SAMPLES).min(samples.len());
+
+        // The last frame is padded with silence, the granule position cuts it off again
+        let mut frame = samples[start..end].to_vec();
+        frame.resize(FRAME_SAMPLES, 0);
+
+        let length = encoder
+            .encode(&frame, &mut packet)
+            .map_err(|e| encode_error(path, e))?;
+
+        let (info, granule) = if index + 1 >= frames {
+            (PacketWriteEndInfo::EndStream, samples.len())
+        } else {
+            (PacketWriteEndInfo::NormalPacket, end)
+        };
+
+        write(
+            &mut writer,
+            packet[..length].to_vec(),
+            info,
+            u64::from(PRE_SKIP) + granule as u64,
+        )?;
+    }
+
+    Ok(writer.into_inner())
+}
@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use crate::audio_engine::backends::base::{AudioBackend, AudioEntityData, DecodedFiles};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::engine::AudioController;
+
+// Milliseconds to crossfade from one auditioned sample to the next
+const AUDITION_CROSSFADE_MS: u64 = 1000;
+
+struct AuditionSample<O: AudioEntityData> {
+    path: String,
+    object: O,
+    /// The stored gain of the sample if the audition is normalized, else 1.0
+    gain: f32,
+    elapsed: u64,
+}
+
+/// Plays the first seconds of a list of samples one after the other, crossfading between them
+pub struct Audition<O: AudioEntityData> {
+    /// Samples still to come as (library path, full path, gain)
+    queue: VecDeque<(String, PathBuf, f32)>,
+    /// Milliseconds each sample plays before crossfading into the next one
+    length: u64,
+    current: Option<AuditionSample<O>>,
+    /// The sample fading out, its elapsed time counts from the start of the fade
+    previous: Option<AuditionSample<O>>,
+}
+
+impl<O: AudioEntityData> Audition<O> {
+    pub fn new(samples: Vec<(String, PathBuf, f32)>, seconds: f32) -> Self {
+        Self {
+            queue: samples.into(),
+            length: (seconds * 1000.0) as u64,
+            current: None,
+            previous: None,
+        }
+    }
+
+    /// Library path of the sample playing right now
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_ref().map(|current| current.path.as_str())
+    }
+
+    /// Crossfades into the next sample that can be loaded, false if there is none left
+    pub fn next(&mut self, backend: &mut O::Backend) -> Result<bool, AudioBackendError> {
+        if let Some(mut previous) = self.previous.take() {
+            previous.object.stop(backend)?;
+        }
+
+        self.previous = self.current.take();
+        if let Some(ref mut previous) = self.previous {
+            previous.elapsed = 0;
+        }
+
+        while let Some((path, full_path, gain)) = self.queue.pop_front() {
+            match backend.load_file(&full_path, false) {
+                Ok(mut object) => {
+                    object.play(backend);
+                    object.set_volume(0.0)?;
+                    info!("Auditioning '{}'", path);
+
+                    self.current = Some(AuditionSample {
+                        path,
+                        object,
+                        gain,
+                        elapsed: 0,
+                    });
+                    return Ok(true);
+                }
+                Err(e) => warn!("Skipping '{}' in audition: {}", path, e),
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Advances the fades, returns false once the last sample is done
+    pub fn update(
+        &mut self,
+        backend: &mut O::Backend,
+        delta: u64,
+    ) -> Result<bool, AudioBackendError> {
+        let faded_out = match self.previous {
+            Some(ref mut previous) => {
+                previous.elapsed += delta;
+                let gain = 1.0 - previous.elapsed as f32 / AUDITION_CROSSFADE_MS as f32;
+
+                if gain > 0.0 && previous.object.is_playing() {
+                    previous.object.update()?;
+                    previous.object.set_volume(gain * previous.gain)?;
+                    false
+                } else {
+                    true
+                }
+            }
+            None => false,
+        };
+
+        if faded_out {
+            self.previous.take().unwrap().object.stop(backend)?;
+        }
+
+        let done = match self.current {
+            Some(ref mut current) => {
+                current.elapsed += delta;
+                let gain = current.elapsed as f32 / AUDITION_CROSSFADE_MS as f32;
+
+                current.object.update()?;
+                current.object.set_volume(gain.min(1.0) * current.gain)?;
+
+                current.elapsed >= self.length || !current.object.is_playing()
+            }
+            None => return Ok(self.previous.is_some()),
+        };
+
+        if done {
+            // With nothing left the last sample fades out like any other
+            self.next(backend)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Sum of the volumes the samples play at, they don't change between the updates
+    pub fn load(&self) -> f32 {
+        let crossfade = AUDITION_CROSSFADE_MS as f32;
+        let current = self.current.as_ref().map_or(0.0, |current| {
+            (current.elapsed as f32 / crossfade).min(1.0) * current.gain
+        });
+        let previous = self.previous.as_ref().map_or(0.0, |previous| {
+            (1.0 - previous.elapsed as f32 / crossfade).max(0.0) * previous.gain
+        });
+
+        current + previous
+    }
+
+    /// The samples playing right now
+    pub fn objects(&self) -> impl Iterator<Item = &O> {
+        self.current
+            .iter()
+            .chain(self.previous.iter())
+            .map(|sample| &sample.object)
+    }
+
+    pub fn reopen(
+        &mut self,
+        backend: &mut O::Backend,
+        decoded: &mut DecodedFiles,
+    ) -> Result<(), AudioBackendError> {
+        for sample in self.current.iter_mut().chain(self.previous.iter_mut()) {
+            sample.object.reopen(backend, true, decoded)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self, backend: &mut O::Backend) -> Result<(), AudioBackendError> {
+        self.queue.clear();
+
+        for mut sample in self.current.take().into_iter().chain(self.previous.take()) {
+            sample.object.stop(backend)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    pub(in crate::audio_engine::engine) fn update_audition(&mut self, delta: u64) {
+        let running = match self.audition {
+            Some(ref mut audition) => audition.update(&mut self.backend, delta),
+            None => return,
+        };
+
+        match running {
+            Ok(true) => {}
+            Ok(false) => {
+                info!("Audition finished");
+                self.audition = None;
+            }
+            Err(e) => {
+                warn!("Stopping audition: {}", e);
+                self.stop_audition();
+            }
+        }
+    }
+
+    pub(in crate::audio_engine::engine) fn stop_audition(&mut self) {
+        if let Some(mut audition) = self.audition.take() {
+            if let Err(e) = audition.stop(&mut self.backend) {
+                warn!("Failed to stop audition: {}", e);
+            }
+        }
+    }
+}
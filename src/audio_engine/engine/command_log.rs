@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::audio_engine::messages::Command;
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: u64,
+    command: &'a Command,
+}
+
+/// Command read back from a command log
+#[derive(Deserialize)]
+pub struct LoggedCommand {
+    /// Milliseconds since the engine started
+    pub time: u64,
+    pub command: Command,
+}
+
+/// Records every command that changes what is played, one JSON object per line, so a
+/// session can be replayed later on
+pub struct CommandLog {
+    file: File,
+    started: Instant,
+}
+
+impl CommandLog {
+    /// Starts a new log, replacing the one of a previous run
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: File::create(path)?,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, command: &Command) {
+        if !command.is_replayable() {
+            return;
+        }
+
+        let elapsed = self.started.elapsed();
+        let entry = Entry {
+            time: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+            command,
+        };
+
+        // Written line by line, so the log is complete up to a crash
+        let result = serde_json::to_vec(&entry)
+            .map_err(io::Error::from)
+            .and_then(|line| {
+                self.file.write_all(&line)?;
+                self.file.write_all(b"\n")?;
+                self.file.flush()
+            });
+
+        if let Err(e) = result {
+            warn!("Failed to write command log: {}", e);
+        }
+    }
+}
+
+pub fn read_command_log(path: &Path) -> Result<Vec<LoggedCommand>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(i, line)| {
+            let line = line.map_err(|e| e.to_string())?;
+            serde_json::from_str(&line).map_err(|e| format!("line {}: {}", i + 1, e))
+        })
+        .collect()
+}
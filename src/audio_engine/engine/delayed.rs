@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+use crate::audio_engine::backends::base::AudioBackend;
+use crate::audio_engine::engine::AudioController;
+use crate::audio_engine::messages::{Command, DelayedCommandStatus, Request, Response};
+
+/// A command waiting to be run at a point in real time, so it also runs on time while
+/// the theme is paused, the time is scaled or the engine falls behind
+pub struct DelayedCommand {
+    pub id: u64,
+    pub command: Command,
+    /// When the command runs
+    pub due: Instant,
+}
+
+impl DelayedCommand {
+    pub fn new(id: u64, command: Command, delay: u64) -> Self {
+        Self {
+            id,
+            command,
+            due: Instant::now() + Duration::from_millis(delay),
+        }
+    }
+
+    pub fn status(&self) -> DelayedCommandStatus {
+        let now = Instant::now();
+        let left = if self.due > now {
+            self.due - now
+        } else {
+            Duration::from_secs(0)
+        };
+
+        DelayedCommandStatus {
+            id: self.id,
+            due_in: left.as_secs() * 1000 + u64::from(left.subsec_millis()),
+            command: serde_json::to_value(&self.command).unwrap_or_default(),
+        }
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    /// Runs the delayed commands that are due, nobody waits for their responses so
+    /// errors are only logged
+    pub(in crate::audio_engine::engine) fn update_delayed_commands(&mut self) {
+        let now = Instant::now();
+        let mut due = Vec::new();
+
+        let mut i = 0;
+        while i < self.delayed_commands.len() {
+            if self.delayed_commands[i].due <= now {
+                due.push(self.delayed_commands.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        for delayed in due {
+            info!("Running delayed command {}", delayed.id);
+
+            let (request, response) = Request::new(delayed.command);
+            self.reply = Some(request.reply);
+            let result = self.handle_command(request.command);
+            self.reply.take();
+
+            if let Err(e) = result {
+                warn!("Delayed command {} failed: {}", delayed.id, e);
+            } else if let Ok(Response::Error(error)) = response.try_recv() {
+                warn!("Delayed command {} failed: {}", delayed.id, error.message);
+            }
+        }
+    }
+}
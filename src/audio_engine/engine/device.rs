@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+use std::thread;
+
+use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
+
+use crate::audio_engine::backends::base::{AudioBackend, DecodedFile, DecodedFiles};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::engine::AudioController;
+use crate::audio_engine::messages::{ApiError, ErrorCode, Response};
+
+// How often reconnecting is tried while it is gone
+const RECONNECT_MS: u64 = 2000;
+
+/// Notices when the output device goes away, e.g. an unplugged USB interface or a
+/// Bluetooth speaker out of range, and reconnects once there is a device again
+#[derive(Default)]
+pub struct DeviceMonitor {
+    /// Milliseconds until the next reconnect
+    countdown: u64,
+    pub lost: bool,
+    attempts: u32,
+}
+
+/// A switch to another output device, waiting for the files of the loaded sounds to be
+/// decoded. The sounds play on the old device meanwhile.
+pub struct DeviceSwitch {
+    id: i32,
+    reply: Option<Sender<Response>>,
+    decoded: Receiver<DecodedFiles>,
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    /// Decodes the files of everything loaded on a worker thread, the device is switched
+    /// and the client answered once they are done
+    pub(in crate::audio_engine::engine) fn switch_device(&mut self, id: i32) {
+        let files = self.source_files();
+        info!(
+            "Decoding {} file(s) before switching to output device {} ...",
+            files.len(),
+            id
+        );
+
+        let decode: fn(&PathBuf, bool) -> Result<DecodedFile, AudioBackendError> = T::decode_file;
+        let (sender, receiver) = bounded(1);
+        thread::spawn(move || {
+            let decoded = files
+                .into_iter()
+                .filter_map(|(path, stereo)| match decode(&path, stereo) {
+                    Ok(file) => Some(((path, stereo), file)),
+                    // Left to the switch, which fails on it like loading would
+                    Err(e) => {
+                        warn!("Failed to decode '{}': {}", path.to_string_lossy(), e);
+                        None
+                    }
+                })
+                .collect();
+            let _ = sender.send(decoded);
+        });
+
+        let switch = DeviceSwitch {
+            id,
+            reply: self.reply.take(),
+            decoded: receiver,
+        };
+        if let Some(superseded) = self.device_switch.replace(switch) {
+            if let Some(reply) = superseded.reply {
+                let _ = reply.send(Response::Error(ApiError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("Superseded by the switch to output device {}", id),
+                )));
+            }
+        }
+    }
+
+    pub(in crate::audio_engine::engine) fn update_device_switch(&mut self) {
+        let mut decoded = match self.device_switch {
+            Some(ref switch) => match switch.decoded.try_recv() {
+                Ok(decoded) => decoded,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => DecodedFiles::new(),
+            },
+            None => return,
+        };
+        let switch = self.device_switch.take().unwrap();
+
+        let response = match self.backend.set_current_output_device(switch.id) {
+            Ok(()) => {
+                self.reopen_objects(&mut decoded);
+                Response::Success
+            }
+            Err(e) => {
+                error!("Failed to switch to output device {}: {}", switch.id, e);
+                Response::Error(ApiError::from(&e))
+            }
+        };
+
+        if let Some(reply) = switch.reply {
+            let _ = reply.send(response);
+        }
+    }
+
+    /// Returns whether there is an output device to play on. Sounds aren't updated while
+    /// it is gone, they are moved to the device reconnected to. The device is checked on
+    /// every update: a lost device stops the sounds, which would otherwise count as done.
+    pub(in crate::audio_engine::engine) fn update_device(&mut self, delta: u64) -> bool {
+        let monitor = &mut self.device_monitor;
+        if !monitor.lost {
+            if self.backend.is_device_alive() {
+                return true;
+            }
+
+            self.device_monitor.lost = true;
+            self.version.bump();
+            self.events.push(
+                "device_lost",
+                "The output device is gone, reconnecting".to_string(),
+            );
+        } else if monitor.countdown > delta {
+            monitor.countdown -= delta;
+            return false;
+        }
+
+        self.device_monitor.countdown = RECONNECT_MS;
+        self.device_monitor.attempts += 1;
+
+        match self.backend.reconnect() {
+            Ok(()) => {
+                // The device is gone, so there is nothing to play on while decoding
+                self.reopen_objects(&mut DecodedFiles::new());
+                self.events.push(
+                    "device_reconnected",
+                    format!(
+                        "Reconnected to output device {} after {} attempt(s)",
+                        self.backend.get_current_output_device(),
+                        self.device_monitor.attempts
+                    ),
+                );
+                self.device_monitor = DeviceMonitor::default();
+                self.version.bump();
+                true
+            }
+            Err(e) => {
+                debug!("Failed to reconnect the output device: {}", e);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+
+use crate::audio_engine::backends::base::AudioBackend;
+use crate::audio_engine::engine::AudioController;
+use crate::audio_engine::messages::{ApiError, Command, Response};
+use crate::samplesdb::remote;
+
+/// A command waiting for the remote samples it needs, it runs again once they are downloaded
+pub struct PendingDownload {
+    command: Command,
+    reply: Option<Sender<Response>>,
+    /// Gets the URLs that failed to download once all are done
+    done: Receiver<Vec<(String, String)>>,
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    /// Downloads the samples on a worker thread and runs `command` again once they are there,
+    /// answering the client then
+    pub(in crate::audio_engine::engine) fn download_first(
+        &mut self,
+        downloads: Vec<(String, PathBuf)>,
+        command: Command,
+    ) {
+        info!("Downloading {} remote samples first ...", downloads.len());
+        self.pending_downloads.push(PendingDownload {
+            command,
+            reply: self.reply.take(),
+            done: remote::download_all(downloads),
+        });
+    }
+
+    pub(in crate::audio_engine::engine) fn update_downloads(&mut self) {
+        let mut i = 0;
+        while i < self.pending_downloads.len() {
+            let failed = match self.pending_downloads[i].done.try_recv() {
+                Ok(failed) => failed,
+                Err(TryRecvError::Empty) => {
+                    i += 1;
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => Vec::new(),
+            };
+            let pending = self.pending_downloads.remove(i);
+
+            for (url, message) in failed {
+                self.samplesdb
+                    .lock()
+                    .unwrap()
+                    .record_failed_download(url, message);
+            }
+
+            // Failed samples are missing now instead of being downloaded again
+            self.reply = pending.reply;
+            let result = self.handle_command(pending.command);
+            let reply = self.reply.take();
+
+            if let Err(e) = result {
+                error!("Failed to handle command after downloading: {}", e);
+                if let Some(reply) = reply {
+                    let _ = reply.send(Response::Error(ApiError::from(&e)));
+                }
+            }
+
+            self.samplesdb.lock().unwrap().forget_failed_downloads();
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// Time it takes to fully duck once the microphone goes hot
+const ATTACK_MS: f32 = 100.0;
+// Time it takes to fully recover once the hold time has passed
+const RELEASE_MS: f32 = 1000.0;
+
+#[derive(Debug, Clone)]
+pub struct DuckingConfig {
+    /// Input peak level (0.0 - 1.0) above which the microphone counts as hot
+    pub threshold: f32,
+    /// How much the ambience is lowered while ducking (0.5 lowers it to half the volume)
+    pub amount: f32,
+    /// Milliseconds to stay ducked after the input level dropped below the threshold
+    pub hold: u64,
+}
+
+/// Lowers the ambience bus while someone is talking into the capture device, music and
+/// triggered sounds keep their volume
+pub struct Ducker {
+    config: DuckingConfig,
+    gain: f32,
+    hold_remaining: u64,
+}
+
+impl Ducker {
+    pub fn new(config: DuckingConfig) -> Self {
+        Self {
+            config,
+            gain: 1.0,
+            hold_remaining: 0,
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn is_ducking(&self) -> bool {
+        self.gain < 1.0
+    }
+
+    /// Feeds the current input level into the ducker
+    pub fn update(&mut self, level: f32, delta: u64) {
+        if level >= self.config.threshold {
+            self.hold_remaining = self.config.hold;
+        } else {
+            self.hold_remaining = self.hold_remaining.saturating_sub(delta);
+        }
+
+        let ducked_gain = 1.0 - self.config.amount.max(0.0).min(1.0);
+        let previous = self.gain;
+
+        if self.hold_remaining > 0 {
+            let step = (1.0 - ducked_gain) * delta as f32 / ATTACK_MS;
+            self.gain = (self.gain - step).max(ducked_gain);
+        } else {
+            let step = (1.0 - ducked_gain) * delta as f32 / RELEASE_MS;
+            self.gain = (self.gain + step).min(1.0);
+        }
+
+        if previous >= 1.0 && self.gain < 1.0 {
+            debug!("Input level {} above threshold, ducking ambience", level);
+        } else if previous < 1.0 && self.gain >= 1.0 {
+            debug!("Input is quiet again, ambience fully recovered");
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::audio_engine::backends::error::AudioBackendError;
+use crate::samplesdb::SamplesDBError;
 use failure::Fail;
 
 #[derive(Fail, Debug)]
@@ -8,6 +9,9 @@ pub enum AudioEngineError {
 
   #[fail(display = "AudioEngine Sample not found at path {}", _0)]
   SampleNotFound(String),
+
+  #[fail(display = "AudioEngine SamplesDB Error: {}", _0)]
+  SamplesDBError(SamplesDBError),
 }
 
 impl From<AudioBackendError> for AudioEngineError {
@@ -15,3 +19,9 @@ impl From<AudioBackendError> for AudioEngineError {
     Self::AudioBackendError(e)
   }
 }
+
+impl From<SamplesDBError> for AudioEngineError {
+  fn from(e: SamplesDBError) -> Self {
+    Self::SamplesDBError(e)
+  }
+}
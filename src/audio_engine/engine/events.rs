@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use crate::audio_engine::backends::base::{AudioBackend, SourcePool};
+use crate::audio_engine::engine::AudioController;
+use crate::audio_engine::messages::EngineEvent;
+
+// Events kept for clients that fall behind, older ones are dropped
+const MAX_EVENTS: usize = 100;
+
+/// The most recent events, numbered so clients can ask for the ones they haven't seen
+#[derive(Default)]
+pub struct EventLog {
+    events: VecDeque<EngineEvent>,
+    last_id: u64,
+}
+
+impl EventLog {
+    pub fn push(&mut self, kind: &str, message: String) {
+        warn!("{}", message);
+
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+
+        self.last_id += 1;
+        self.events.push_back(EngineEvent {
+            id: self.last_id,
+            kind: kind.to_string(),
+            message,
+        });
+    }
+
+    pub fn since(&self, id: u64) -> Vec<EngineEvent> {
+        self.events
+            .iter()
+            .filter(|event| event.id > id)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Warns once the backend's sources get scarce, and whenever a sound got none
+pub struct SourcePoolMonitor {
+    /// Share (0.0 - 1.0) of the sources in use above which to warn
+    threshold: f32,
+    /// Whether the last check was above the threshold, so crossing it warns only once
+    above: bool,
+    exhausted: u64,
+}
+
+impl SourcePoolMonitor {
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            above: false,
+            exhausted: 0,
+        }
+    }
+
+    fn check(&mut self, pool: SourcePool, events: &mut EventLog) {
+        if pool.exhausted > self.exhausted {
+            events.push(
+                "source_pool_exhausted",
+                format!(
+                    "{} sound(s) found no free source, all {} sources are in use",
+                    pool.exhausted - self.exhausted,
+                    pool.total
+                ),
+            );
+            self.exhausted = pool.exhausted;
+        }
+
+        // The pool grows on demand, so it is only scarce close to its limit
+        let usage = if pool.limit > 0 {
+            pool.used as f32 / pool.limit as f32
+        } else {
+            1.0
+        };
+
+        if usage >= self.threshold && !self.above {
+            events.push(
+                "source_pool_high",
+                format!("{} of up to {} sources are in use", pool.used, pool.limit),
+            );
+        }
+        self.above = usage >= self.threshold;
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    pub(in crate::audio_engine::engine) fn update_source_pool(&mut self) {
+        if let Some(pool) = self.backend.get_source_pool() {
+            self.pool_monitor.check(pool, &mut self.events);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// When the audio thread last went through its loop, shared with the API so it can tell
+/// a stuck engine without waiting for it to answer a request
+#[derive(Clone, Default)]
+pub struct Heartbeat(Arc<AtomicU64>);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        self.0.store(now_ms(), Ordering::Relaxed);
+    }
+
+    /// Milliseconds since the last beat, `None` if the engine hasn't started yet
+    pub fn age(&self) -> Option<u64> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            last => Some(now_ms().saturating_sub(last)),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::audio_engine::backends::base::AudioBackend;
+use crate::audio_engine::engine::audition::Audition;
+use crate::audio_engine::engine::playlist::PlaylistPlayer;
+use crate::audio_engine::engine::{AudioController, AudioEntityState};
+use crate::audio_engine::messages::LimiterStatus;
+
+// Time it takes to turn the output all the way down once the mix gets too loud
+const ATTACK_MS: f32 = 50.0;
+// Time it takes to recover once there is headroom again
+const RELEASE_MS: f32 = 1500.0;
+
+/// Turns the output down while the volumes of everything playing add up to more than
+/// `ceiling`, so sounds stacking up don't clip
+pub struct Limiter {
+    pub enabled: bool,
+    /// Sum of the volumes the mix may reach before it is turned down
+    pub ceiling: f32,
+    gain: f32,
+    /// Sum of the volumes at the last update
+    load: f32,
+}
+
+impl Limiter {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ceiling: 1.0,
+            gain: 1.0,
+            load: 0.0,
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn status(&self) -> LimiterStatus {
+        LimiterStatus {
+            enabled: self.enabled,
+            ceiling: self.ceiling,
+            load: self.load,
+            gain: self.gain,
+        }
+    }
+
+    /// Feeds the current load into the limiter, returns true if the gain changed
+    pub fn update(&mut self, load: f32, delta: u64) -> bool {
+        self.load = load;
+
+        let target = if self.enabled && load > self.ceiling {
+            self.ceiling / load
+        } else {
+            1.0
+        };
+        let previous = self.gain;
+
+        if target < self.gain {
+            self.gain = (self.gain - delta as f32 / ATTACK_MS).max(target);
+        } else {
+            self.gain = (self.gain + delta as f32 / RELEASE_MS).min(target);
+        }
+
+        if previous >= 1.0 && self.gain < 1.0 {
+            debug!("Mix at {} above ceiling {}, limiting", load, self.ceiling);
+        } else if previous < 1.0 && self.gain >= 1.0 {
+            debug!("Mix has headroom again, limiter released");
+        }
+
+        (self.gain - previous).abs() > std::f32::EPSILON
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    pub(in crate::audio_engine::engine) fn update_limiter(&mut self, delta: u64) {
+        let sounds: f32 = self
+            .sound_handles
+            .values()
+            .filter(|handle| handle.is_in_state(&AudioEntityState::Playing))
+            .map(|handle| handle.gain)
+            .sum();
+        let playlists: f32 = self.playlists.iter().map(PlaylistPlayer::load).sum();
+        // Test tones and beeps are silent while a sound is soloed
+        let one_shots: f32 = if self.solo.is_some() {
+            0.0
+        } else {
+            self.one_shots.iter().map(|(_, volume)| volume).sum()
+        };
+        let audition = self.audition.as_ref().map_or(0.0, Audition::load);
+        let load = sounds + playlists + one_shots + audition;
+
+        if self.limiter.update(load, delta) {
+            self.apply_output_volume();
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::audio_engine::backends::base::AudioBackend;
+use crate::audio_engine::engine::AudioController;
+use crate::audio_engine::messages::{LockoutStatus, TriggerAction};
+
+/// Holds back triggers for a while, e.g. so nobody steps on a narration beat
+pub struct Lockout {
+    /// Milliseconds (real time) until triggers go through again
+    left: u64,
+    /// Queue triggers to release them afterwards instead of rejecting them
+    pub queue: bool,
+    queued: Vec<(String, TriggerAction)>,
+}
+
+impl Lockout {
+    pub fn new(duration: u64, queue: bool) -> Self {
+        Self {
+            left: duration,
+            queue,
+            queued: Vec::new(),
+        }
+    }
+
+    /// Starts over with another duration, triggers queued so far stay queued
+    pub fn extend(&mut self, duration: u64, queue: bool) {
+        self.left = duration;
+        self.queue = queue;
+    }
+
+    pub fn hold(&mut self, sound: String, action: TriggerAction) {
+        self.queued.push((sound, action));
+    }
+
+    pub fn status(&self) -> LockoutStatus {
+        LockoutStatus {
+            left: self.left,
+            queue: self.queue,
+            queued: self.queued.iter().map(|(sound, _)| sound.clone()).collect(),
+        }
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    pub(in crate::audio_engine::engine) fn update_lockout(&mut self, delta: u64) {
+        self.release_trigger();
+
+        let over = match self.lockout {
+            Some(ref mut lockout) => {
+                lockout.left = lockout.left.saturating_sub(delta);
+                lockout.left == 0
+            }
+            None => false,
+        };
+
+        if over {
+            self.end_lockout();
+        }
+    }
+
+    /// Lets triggers through again. Queued ones are released in the order they came in, one
+    /// per tick so each takes effect before the next, two toggles of a sound would cancel
+    /// out otherwise.
+    pub(in crate::audio_engine::engine) fn end_lockout(&mut self) {
+        let lockout = match self.lockout.take() {
+            Some(lockout) => lockout,
+            None => return,
+        };
+
+        info!(
+            "Lockout ended, releasing {} trigger(s)",
+            lockout.queued.len()
+        );
+
+        self.released_triggers.extend(lockout.queued);
+        self.version.bump();
+    }
+
+    fn release_trigger(&mut self) {
+        let (sound, action) = match self.released_triggers.pop_front() {
+            Some(trigger) => trigger,
+            None => return,
+        };
+
+        match self.sound_handles.get_mut(&sound) {
+            Some(handle) => {
+                handle.trigger(action);
+                self.version.bump();
+            }
+            // The theme may have changed in the meantime
+            None => warn!("Dropping queued trigger of unknown sound '{}'", sound),
+        }
+    }
+}
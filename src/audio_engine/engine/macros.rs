@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+
+use crate::audio_engine::backends::base::AudioBackend;
+use crate::audio_engine::engine::AudioController;
+use crate::audio_engine::messages::TriggerAction;
+use crate::theme::MacroAction;
+
+/// A theme macro that is currently being executed
+pub struct RunningMacro {
+    pub name: String,
+    actions: VecDeque<MacroAction>,
+    wait: u64,
+}
+
+impl RunningMacro {
+    pub fn new(name: String, actions: Vec<MacroAction>) -> Self {
+        Self {
+            name,
+            actions: actions.into(),
+            wait: 0,
+        }
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    fn run_macro_action(&mut self, macro_name: &str, action: MacroAction) {
+        match action {
+            MacroAction::Trigger { sound } => match self.sound_handles.get_mut(&sound) {
+                Some(handle) => {
+                    info!("Macro '{}' triggers sound '{}'", macro_name, sound);
+                    handle.trigger(TriggerAction::Toggle);
+                }
+                None => warn!("Macro '{}' triggers unknown sound '{}'", macro_name, sound),
+            },
+
+            MacroAction::SetVolume { sound, volume } => match self.sound_handles.get_mut(&sound) {
+                Some(handle) => {
+                    info!(
+                        "Macro '{}' sets volume of sound '{}' to {}",
+                        macro_name, sound, volume
+                    );
+                    handle.sound.volume = (volume, volume);
+                    handle.parameters.max_volume = volume;
+                }
+                None => warn!(
+                    "Macro '{}' sets volume of unknown sound '{}'",
+                    macro_name, sound
+                ),
+            },
+
+            // Waits are handled by the caller
+            MacroAction::Wait { .. } => {}
+        }
+    }
+
+    /// Advances all running macros, executing every action that is due
+    pub(in crate::audio_engine::engine) fn update_macros(&mut self, delta: u64) {
+        let mut running = std::mem::replace(&mut self.running_macros, Vec::new());
+
+        for running_macro in &mut running {
+            running_macro.wait = running_macro.wait.saturating_sub(delta);
+
+            while running_macro.wait == 0 {
+                match running_macro.actions.pop_front() {
+                    Some(MacroAction::Wait { seconds }) => {
+                        running_macro.wait = (seconds * 1000.0) as u64;
+                    }
+                    Some(action) => {
+                        self.run_macro_action(&running_macro.name, action);
+                        self.version.bump();
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        running.retain(|running_macro| {
+            let done = running_macro.actions.is_empty() && running_macro.wait == 0;
+            if done {
+                info!("Macro '{}' finished", running_macro.name);
+                self.version.bump();
+            }
+
+            !done
+        });
+
+        // Macros started while we were busy are kept as well
+        running.append(&mut self.running_macros);
+        self.running_macros = running;
+    }
+}
@@ -1,57 +1,172 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Duration;
 
-use crate::audio_engine::backends::base::AudioBackend;
+use crossbeam_channel::RecvTimeoutError;
+use serde_json::Value;
+
+use crate::audio_engine::backends::alto::REVERB_PRESETS;
+use crate::audio_engine::backends::base::{AudioBackend, AudioEntityData, Equalizer};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::engine::audition::Audition;
+use crate::audio_engine::engine::delayed::DelayedCommand;
 use crate::audio_engine::engine::error::AudioEngineError;
+use crate::audio_engine::engine::lockout::Lockout;
+use crate::audio_engine::engine::macros::RunningMacro;
+use crate::audio_engine::engine::playlist::PlaylistPlayer;
+use crate::audio_engine::engine::preload::ThemePreload;
+use crate::audio_engine::engine::scenes;
 use crate::audio_engine::engine::AudioEntity;
-use crate::audio_engine::engine::{AudioController, AudioEntityState};
-use crate::audio_engine::messages::{Command, Response};
-use crate::theme::Theme;
+use crate::audio_engine::engine::{
+    AudioController, AudioEntityState, FadeAction, FadeDirection, LoadState, DEFAULT_CROSSFADE_MS,
+    MIN_PITCH_SCALE, THEME_FADE_MS,
+};
+use crate::audio_engine::generator;
+use crate::audio_engine::loader;
+use crate::audio_engine::loader::base::SampleFormat;
+use crate::audio_engine::loader::check::WarningCache;
+use crate::audio_engine::messages::{
+    ApiError, Command, DecodeWarning, ErrorCode, LibrarySample, MissingSampleBehavior,
+    OutputChannel, OverlayTrack, Response, SampleDetails, SoundChanges, SoundStatus, TriggerAction,
+};
+use crate::samplesdb::{Sample, SamplesDBError};
+use crate::settings::Settings;
+use crate::theme::{Playlist, Sound, SoundType, Theme, Waveform};
+
+// Seconds each sample plays during an audition if the request doesn't say
+const AUDITION_SECONDS: f32 = 5.0;
+
+// Length of the generated buffer noise sounds loop over
+const NOISE_SECONDS: f32 = 10.0;
 
 // TODO This information should come from our loaders
 
 macro_rules! send_response {
     ($self: ident) => {
-        $self
-            .sender
-            .send(Response::Success)
-            .expect("Failed to communicate with API!");
+        send_response!($self, Response::Success);
     };
 
     ($self: ident, $message: expr) => {
-        $self
-            .sender
-            .send($message)
-            .expect("Failed to communicate with API!");
+        if let Some(ref reply) = $self.reply {
+            if reply.send($message).is_err() {
+                debug!("Client went away before receiving its response");
+            }
+        }
     };
 }
 
 macro_rules! send_error {
-    ($self: ident, $message: expr) => {
-        $self
-            .sender
-            .send(Response::Error {
-                message: $message.to_string(),
-            })
-            .expect("Failed to communicate with API!");
+    ($self: ident, $error: expr) => {
+        send_response!($self, Response::Error($error.into()));
     };
 }
 
-impl<'a, T: AudioBackend> AudioController<'a, T> {
+/// Formats of generated sounds and placeholders
+const GENERATED_FORMAT: SampleFormat = SampleFormat {
+    sample_rate: generator::SAMPLE_RATE,
+    channels: 1,
+};
+
+/// The format is only informational, failing to read it doesn't keep the file from loading
+pub(in crate::audio_engine::engine) fn sample_format(path: &PathBuf) -> Option<SampleFormat> {
+    match loader::get_loader_for_file(path).and_then(|mut l| l.format(path)) {
+        Ok(format) => Some(format),
+        Err(e) => {
+            warn!(
+                "Failed to read format of '{}': {}",
+                path.to_string_lossy(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Failing to check a file doesn't keep it from loading either
+pub(in crate::audio_engine::engine) fn decode_warnings(
+    cache: &WarningCache,
+    sound: &str,
+    file: &str,
+    path: &PathBuf,
+) -> Vec<DecodeWarning> {
+    let messages = cache.decode_warnings(path).unwrap_or_else(|e| {
+        warn!("Failed to check '{}': {}", path.to_string_lossy(), e);
+        Vec::new()
+    });
+
+    messages
+        .into_iter()
+        .map(|message| {
+            warn!("Sample '{}' of sound '{}': {}", file, sound, message);
+            DecodeWarning {
+                sound: sound.to_string(),
+                sample: file.to_string(),
+                message,
+            }
+        })
+        .collect()
+}
+
+fn library_entry(sample: &Sample) -> LibrarySample {
+    LibrarySample {
+        id: sample.id,
+        path: sample.path.clone(),
+        tags: sample.tags.clone(),
+        details: SampleDetails {
+            duration: sample.duration,
+            sample_rate: sample.format.map(|format| format.sample_rate),
+            channels: sample.format.map(|format| format.channels),
+            metadata: sample.metadata.clone(),
+        },
+    }
+}
+
+/// The engine draws from these ranges whenever the sound starts, an inverted one panics
+fn check_ranges(sound: &Sound) -> Result<(), String> {
+    let (from, to) = sound.start_offset;
+    if !(0.0 <= from && from <= to) {
+        return Err(format!(
+            "start_offset must be a range of seconds from 0 up, got [{}, {}]",
+            from, to
+        ));
+    }
+
+    let (from, to) = sound.pan;
+    if sound.pan_enabled && !(-1.0 <= from && from <= to && to <= 1.0) {
+        return Err(format!(
+            "pan must be a range between -1.0 and 1.0, got [{}, {}]",
+            from, to
+        ));
+    }
+
+    let (from, to) = sound.variation;
+    if sound.variation_enabled && !(-1.0 <= from && from <= to && to <= 1.0) {
+        return Err(format!(
+            "variation must be a range between -1.0 and 1.0, got [{}, {}]",
+            from, to
+        ));
+    }
+
+    Ok(())
+}
+
+impl<T: AudioBackend> AudioController<T> {
     fn handle_pause(&mut self) -> Result<(), AudioEngineError> {
         if self.theme_loaded {
-            for handle in &mut self.sound_handles.values_mut() {
-                if handle.is_in_state(&AudioEntityState::Playing) {
-                    handle.pause(true);
-                }
+            if self.pause_fade > 0 && self.playing && !self.fade_status {
+                // The sounds are paused once the fade out is done
+                self.start_fade(FadeDirection::Out, self.pause_fade, Some(FadeAction::Pause));
+                info!("Pausing ...");
+            } else {
+                self.pause_handles();
+                self.playing = false;
+                info!("Paused!");
             }
 
-            self.playing = false;
             send_response!(self);
-            info!("Paused!");
         } else {
             debug!("No theme loaded, not pausing ...");
-            send_error!(self, "No theme loaded!");
+            send_error!(self, ApiError::no_theme_loaded());
         }
 
         Ok(())
@@ -59,9 +174,26 @@ impl<'a, T: AudioBackend> AudioController<'a, T> {
 
     fn handle_play(&mut self) -> Result<(), AudioEngineError> {
         if self.theme_loaded {
-            for handle in &mut self.sound_handles.values_mut() {
-                if handle.is_in_state(&AudioEntityState::Playing) {
-                    handle.pause(false);
+            if let Some(FadeAction::Pause) = self.fade_action {
+                // Still fading out for a pause, just fade back in from where we are
+                self.fade_action = None;
+                self.fade_direction = FadeDirection::In;
+            } else if !self.playing {
+                if self.pause_fade > 0 && !self.fade_status {
+                    self.start_fade(FadeDirection::In, self.pause_fade, None);
+                    self.apply_output_volume();
+                }
+
+                for handle in &mut self.sound_handles.values_mut() {
+                    if handle.is_in_state(&AudioEntityState::Playing) {
+                        handle.pause(&mut self.backend, false);
+                    }
+
+                    handle.resume();
+                }
+
+                for player in &mut self.playlists {
+                    player.pause(&mut self.backend, false);
                 }
             }
 
@@ -71,193 +203,2346 @@ impl<'a, T: AudioBackend> AudioController<'a, T> {
             info!("Playing!");
         } else {
             debug!("No theme loaded, not playing ...");
-            send_error!(self, "No theme loaded!");
+            send_error!(self, ApiError::no_theme_loaded());
         }
 
         Ok(())
     }
 
-    fn handle_preview_sound(&mut self, sound: String) -> Result<(), AudioEngineError> {
-        if let Some(handle) = self.sound_handles.get_mut(&sound) {
-            handle.is_preview = true;
-            handle.switch_state(AudioEntityState::Preview);
-            info!("Starting preview of sound '{}'!", sound);
+    fn handle_preview_sound(&mut self, sound: String, raw: bool) -> Result<(), AudioEngineError> {
+        let already_previewing = match self.sound_handles.get(&sound) {
+            Some(handle) => handle.is_preview,
+            None => {
+                debug!("handle_preview_sound(): No such sound {}", sound);
+                send_error!(self, ApiError::unknown_sound(&sound));
+                return Ok(());
+            }
+        };
 
+        // Restarting a running preview doesn't take up another source
+        if already_previewing || self.can_start_preview() {
+            self.start_preview(&sound, raw);
+            send_response!(self);
+        } else if self.queue_previews {
+            if !self
+                .preview_queue
+                .iter()
+                .any(|(queued, _)| *queued == sound)
+            {
+                info!("Queueing preview of sound '{}'", sound);
+                self.preview_queue.push_back((sound, raw));
+            }
             send_response!(self);
         } else {
-            debug!("handle_preview_sound(): No such sound {}", sound);
-            send_error!(self, "No such sound {}");
+            send_error!(
+                self,
+                ApiError::new(
+                    ErrorCode::TooManyPreviews,
+                    format!(
+                        "Already playing {} previews, wait for one to finish!",
+                        self.max_previews
+                    ),
+                )
+                .with_sound(&sound)
+            );
         }
 
         Ok(())
     }
 
-    fn handle_load_theme(&mut self, theme: Theme) -> Result<(), AudioEngineError> {
+    pub(in crate::audio_engine::engine) fn load_sound_object(
+        &mut self,
+        sound: &Sound,
+    ) -> Result<(T::EntityData, Option<SampleFormat>, Vec<DecodeWarning>), ApiError> {
+        let samples = match sound.sound_type {
+            SoundType::File => None,
+            SoundType::Noise => Some(generator::noise(sound.color, NOISE_SECONDS, 0.5)),
+            SoundType::Tone => Some(generator::synth(
+                sound.waveform,
+                sound.frequency,
+                sound.duration,
+                0.5,
+            )),
+        };
+
+        if let Some(samples) = samples {
+            return self
+                .backend
+                .load_samples(samples, generator::SAMPLE_RATE)
+                .map(|object| (object, Some(GENERATED_FORMAT), Vec::new()))
+                .map_err(|e| ApiError::from(&e).with_sound(&sound.name));
+        }
+
+        let sample_id = match self.samplesdb.lock().unwrap().resolve_sample(&sound.file) {
+            Ok(Some(id)) => id,
+            Ok(None) => return Err(ApiError::sample_not_found(&sound.file).with_sound(&sound.name)),
+            Err(e) => return Err(ApiError::from(&e).with_sound(&sound.name)),
+        };
+        let full_path = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .full_path_of_sample(sample_id);
+
+        info!("Loading file {} ...", &full_path.to_str().unwrap());
+
+        let object = self
+            .backend
+            .load_file(&full_path, sound.stereo)
+            .map_err(|e| {
+                ApiError::from(&e)
+                    .with_sound(&sound.name)
+                    .with_sample(&sound.file)
+            })?;
+
+        Ok((
+            object,
+            sample_format(&full_path),
+            decode_warnings(&self.warning_cache, &sound.name, &sound.file, &full_path),
+        ))
+    }
+
+    fn handle_load_theme(
+        &mut self,
+        theme: Theme,
+        on_missing: MissingSampleBehavior,
+        strict: bool,
+    ) -> Result<(), AudioEngineError> {
+        // Remote samples are downloaded on a worker, the theme loads once they are there
+        let downloads = self.samplesdb.lock().unwrap().pending_downloads(
+            theme
+                .sounds
+                .iter()
+                .map(|sound| &sound.file)
+                .chain(theme.playlists.iter().flat_map(|playlist| &playlist.tracks)),
+        );
+        if !downloads.is_empty() {
+            let command = Command::LoadTheme {
+                theme,
+                on_missing,
+                strict,
+            };
+            self.download_first(downloads, command);
+            return Ok(());
+        }
+
         let mut handles = HashMap::new();
+        let mut skipped = Vec::new();
+        let mut placeholders = Vec::new();
+        let mut warnings = Vec::new();
+        let mut preloaded = self.take_preloaded(&theme.name);
+        let theme_json = match serde_json::to_value(&theme) {
+            Ok(theme_json) => theme_json,
+            Err(e) => {
+                error!("Failed to serialize theme '{}': {}", theme.name, e);
+                send_error!(
+                    self,
+                    ApiError::new(
+                        ErrorCode::InvalidValue,
+                        format!("Failed to read theme '{}': {}", theme.name, e)
+                    )
+                );
+                return Ok(());
+            }
+        };
+
+        // A tone that can't be synthesized is a mistake in the theme, not a missing sample
+        let invalid_tone = theme
+            .sounds
+            .iter()
+            .find_map(|sound| match sound.sound_type {
+                SoundType::Tone => generator::check_tone(sound.frequency, sound.duration)
+                    .err()
+                    .map(|e| ApiError::new(ErrorCode::InvalidValue, e).with_sound(&sound.name)),
+                _ => None,
+            });
+        if let Some(error) = invalid_tone {
+            warn!("Not loading theme '{}': {}", theme.name, error);
+            send_error!(self, error);
+            return Ok(());
+        }
+
+        // Checked here too, clients don't have to lint a theme before loading it
+        let invalid_range = theme.sounds.iter().find_map(|sound| {
+            check_ranges(sound)
+                .err()
+                .map(|e| ApiError::new(ErrorCode::InvalidValue, e).with_sound(&sound.name))
+        });
+        if let Some(error) = invalid_range {
+            warn!("Not loading theme '{}': {}", theme.name, error);
+            send_error!(self, error);
+            return Ok(());
+        }
+
         for sound in theme.sounds {
-            let sample_id = match self.samplesdb.sample_id_by_path(&sound.file) {
-                Some(id) => id,
-                None => {
-                    send_error!(self, format!("No such sound {}", sound.file));
-                    return Err(AudioEngineError::SampleNotFound(sound.file.clone()));
+            let loaded = match preloaded.remove(&sound.name) {
+                Some(preloaded) if preloaded.matches(&sound) => {
+                    Ok((preloaded.object, preloaded.format, preloaded.warnings))
                 }
+                _ => self.load_sound_object(&sound),
             };
-            let full_path = self.samplesdb.full_path_of_sample(sample_id);
 
-            info!("Loading file {} ...", &full_path.to_str().unwrap());
+            let (object, format, placeholder) = match loaded {
+                Ok((object, format, sound_warnings)) => {
+                    if strict && !sound_warnings.is_empty() {
+                        warn!("Not loading theme '{}' in strict mode", theme.name);
+                        send_error!(self, &sound_warnings[0]);
+                        return Ok(());
+                    }
 
-            let object = self.backend.load_file(&full_path).or_else(|e| {
-                send_response!(self);
-                Err(e)
-            })?;
+                    warnings.extend(sound_warnings);
+                    (object, format, false)
+                }
+                Err(error) => {
+                    let samples = match on_missing {
+                        MissingSampleBehavior::Strict => {
+                            warn!("Not loading theme '{}': {}", theme.name, error);
+                            send_error!(self, error);
+                            return Ok(());
+                        }
+                        MissingSampleBehavior::Skip => {
+                            warn!("Skipping sound '{}': {}", sound.name, error);
+                            skipped.push(sound.name);
+                            continue;
+                        }
+                        MissingSampleBehavior::Silence => generator::silence(1.0),
+                        MissingSampleBehavior::Beep => generator::placeholder_beep(),
+                    };
+
+                    warn!("Using a placeholder for sound '{}': {}", sound.name, error);
+                    placeholders.push(sound.name.clone());
+
+                    match self.backend.load_samples(samples, generator::SAMPLE_RATE) {
+                        Ok(object) => (object, Some(GENERATED_FORMAT), true),
+                        Err(e) => {
+                            send_error!(self, &e);
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+
+            let mut entity = AudioEntity::<T::EntityData>::new(object, sound);
+            entity.placeholder = placeholder;
+            entity.format = format;
+            entity.trim = self
+                .samplesdb
+                .lock()
+                .unwrap()
+                .sound_trim(&theme.name, &entity.sound.name);
+            entity.sample_gain = self.sample_gain(entity.sound.sound_type, &entity.sound.file);
+            entity.pitch_scale = self.pitch_scale;
+            entity.group_volume = self.group_volume(&entity.sound);
+
+            handles.insert(entity.sound.name.clone(), entity);
+        }
+
+        // A newer theme supersedes one that is still waiting to be switched to, the fade
+        // simply carries on towards the newer one. The client that loaded it has its
+        // answer already, so it learns about it from the events.
+        let superseded = match self.next_sound_handles.take() {
+            Some(mut pending) => {
+                for (_, mut handle) in pending.drain() {
+                    handle.stop(&mut self.backend)?;
+                }
+
+                self.events.push(
+                    "theme_superseded",
+                    format!(
+                        "Theme '{}' superseded by '{}' before it started",
+                        self.theme.as_ref().map_or("", String::as_str),
+                        theme.name
+                    ),
+                );
+                self.theme.clone()
+            }
+            None => None,
+        };
+
+        match self.load_state {
+            LoadState::Idle => self.load_state = LoadState::Loading,
+            // Turn around and fade out from wherever the fade in got to
+            LoadState::FadingIn => {
+                self.fade_direction = FadeDirection::Out;
+                self.fade_duration = THEME_FADE_MS;
+                self.fade_action = Some(FadeAction::SwitchTheme);
+                self.load_state = LoadState::FadingOut;
+            }
+            LoadState::Loading | LoadState::FadingOut => {}
+        }
 
-            handles.insert(
-                sound.name.clone(),
-                AudioEntity::<T::EntityData>::new(object, sound),
+        // The sounds and playlists the first scene doesn't play don't start at all
+        let scene = scenes::initial_scene(&theme);
+        let mut playlists: Vec<PlaylistPlayer<T::EntityData>> = theme
+            .playlists
+            .into_iter()
+            .map(PlaylistPlayer::new)
+            .collect();
+        if let Some(ref scene) = scene {
+            info!("Theme '{}' starts in scene '{}'", theme.name, scene);
+            scenes::start_in_scene(
+                &theme.scenes,
+                scene,
+                &mut handles,
+                &mut playlists,
+                &mut self.backend,
             );
         }
 
         self.next_sound_handles = Some(handles);
+        self.next_playlists = Some(playlists);
+
+        self.remember_last_theme(&theme.name);
 
         self.theme = Some(theme.name);
+        self.theme_json = Some(theme_json);
         self.theme_loaded = true;
 
-        send_response!(self);
+        self.macros = theme.macros;
+        self.running_macros.clear();
+
+        self.scenes = theme.scenes;
+        self.scene = scene;
+        self.solo = None;
+        self.update_one_shot_volumes();
+
+        self.backend.set_custom_reverbs(&theme.reverbs);
+        self.reverbs = theme.reverbs.keys().cloned().collect();
+
+        send_response!(
+            self,
+            Response::LoadTheme {
+                success: true,
+                skipped,
+                placeholders,
+                superseded,
+                warnings,
+            }
+        );
 
         info!("Theme loaded!");
 
         Ok(())
     }
 
-    fn handle_trigger(&mut self, sound: String) -> Result<(), AudioEngineError> {
-        if let Some(handle) = self.sound_handles.get_mut(&sound) {
-            info!("handle_trigger(): Received trigger for sound '{}'!", sound);
-            handle.is_triggered = !handle.is_triggered;
-
-            send_response!(self);
-        } else {
+    fn handle_trigger(
+        &mut self,
+        sound: String,
+        action: TriggerAction,
+    ) -> Result<(), AudioEngineError> {
+        if !self.sound_handles.contains_key(&sound) {
             error!(
                 "handle_trigger(): Received trigger for unknown sound '{}'!",
                 sound
             );
-            send_error!(self, format!("Unknown sound '{}'!", sound));
+            send_error!(self, ApiError::unknown_sound(&sound));
+            return Ok(());
+        }
+
+        match self.lockout {
+            Some(ref mut lockout) if lockout.queue => {
+                info!(
+                    "Holding back trigger of sound '{}' until the lockout ends",
+                    sound
+                );
+                lockout.hold(sound, action);
+                send_response!(self);
+            }
+            Some(_) => {
+                send_error!(
+                    self,
+                    ApiError::new(ErrorCode::LockedOut, "Triggers are locked out right now!")
+                        .with_sound(&sound)
+                );
+            }
+            None => {
+                info!(
+                    "handle_trigger(): Received trigger ({:?}) for sound '{}'!",
+                    action, sound
+                );
+                self.sound_handles.get_mut(&sound).unwrap().trigger(action);
+
+                send_response!(self);
+            }
         }
 
         Ok(())
     }
 
-    fn handle_get_status(&mut self) -> Result<(), AudioEngineError> {
-        let mut playing: Vec<String> = Vec::new();
-        let mut playing_next: HashMap<String, u64> = HashMap::new();
-        let mut previewing: Vec<String> = Vec::new();
+    fn handle_set_scene(&mut self, name: String) -> Result<(), AudioEngineError> {
+        let sounds = match self.scenes.get(&name) {
+            Some(sounds) => sounds.clone(),
+            None => {
+                debug!("handle_set_scene(): No such scene {}", name);
+                send_error!(
+                    self,
+                    ApiError::new(ErrorCode::NotFound, format!("Unknown scene '{}'!", name))
+                );
+                return Ok(());
+            }
+        };
 
-        for (name, handle) in &self.sound_handles {
-            if handle.is_in_state(&AudioEntityState::Playing) {
-                playing.push(name.to_string());
-            } else if handle.is_in_state(&AudioEntityState::WaitingForStart) {
-                playing_next.insert(name.to_string(), handle.parameters.next_play.as_secs());
+        info!("Switching to scene '{}'", name);
+        self.apply_scene(&sounds);
+        self.scene = Some(name);
+
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_fire_trigger(
+        &mut self,
+        trigger: String,
+        action: TriggerAction,
+    ) -> Result<(), AudioEngineError> {
+        let mut sounds: Vec<String> = self
+            .sound_handles
+            .values()
+            .filter(|handle| handle.sound.trigger.as_ref() == Some(&trigger))
+            .map(|handle| handle.sound.name.clone())
+            .collect();
+        sounds.sort();
+
+        if sounds.is_empty() {
+            send_error!(
+                self,
+                ApiError::new(
+                    ErrorCode::NotFound,
+                    format!("No sound listens to trigger '{}'!", trigger),
+                )
+            );
+            return Ok(());
+        }
+
+        match self.lockout {
+            Some(ref mut lockout) if lockout.queue => {
+                info!("Holding back trigger '{}' until the lockout ends", trigger);
+                for sound in &sounds {
+                    lockout.hold(sound.clone(), action);
+                }
+            }
+            Some(_) => {
+                send_error!(
+                    self,
+                    ApiError::new(ErrorCode::LockedOut, "Triggers are locked out right now!")
+                );
+                return Ok(());
+            }
+            None => {
+                info!(
+                    "Firing trigger '{}' ({:?}) for {} sound(s)",
+                    trigger,
+                    action,
+                    sounds.len()
+                );
+                for sound in &sounds {
+                    self.sound_handles.get_mut(sound).unwrap().trigger(action);
+                }
             }
+        }
 
-            if handle.is_preview {
-                previewing.push(name.to_string());
+        send_response!(self, Response::FireTrigger { sounds });
+
+        Ok(())
+    }
+
+    fn handle_set_lockout(&mut self, duration: u64, queue: bool) -> Result<(), AudioEngineError> {
+        if duration == 0 {
+            self.end_lockout();
+        } else {
+            info!(
+                "Locking out triggers for {} ms, {}",
+                duration,
+                if queue {
+                    "queueing them"
+                } else {
+                    "rejecting them"
+                }
+            );
+
+            match self.lockout {
+                Some(ref mut lockout) => lockout.extend(duration, queue),
+                None => self.lockout = Some(Lockout::new(duration, queue)),
             }
         }
 
-        send_response!(
-            self,
-            Response::Status {
-                playing: self.playing,
-                theme_loaded: self.theme_loaded,
-                theme: self.theme.clone(),
-                sounds_playing: playing,
-                sounds_playing_next: playing_next,
-                previewing: previewing
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_set_limiter(
+        &mut self,
+        enabled: bool,
+        ceiling: Option<f32>,
+    ) -> Result<(), AudioEngineError> {
+        if let Some(ceiling) = ceiling {
+            if !ceiling.is_finite() || ceiling <= 0.0 {
+                send_error!(
+                    self,
+                    ApiError::new(ErrorCode::InvalidValue, "The ceiling must be positive!")
+                );
+                return Ok(());
             }
+
+            self.limiter.ceiling = ceiling;
+        }
+
+        info!(
+            "Limiter {} at ceiling {}",
+            if enabled { "enabled" } else { "disabled" },
+            self.limiter.ceiling
         );
+        self.limiter.enabled = enabled;
+        send_response!(self);
 
         Ok(())
     }
 
-    fn handle_get_sound_library(&mut self) -> Result<(), AudioEngineError> {
-        let mut lib: Vec<String> = Vec::new();
-        for entry in self.samplesdb.samples() {
-            lib.push(entry.path.clone())
+    /// Checks the changed parameters of a sound before any of them is applied
+    fn validate_changes(&self, sound: &str, changes: &SoundChanges) -> Result<(), ApiError> {
+        let invalid = if changes
+            .volume
+            .map_or(false, |(min, max)| min < 0.0 || min > max)
+        {
+            Some("volume")
+        } else if changes.repeat_delay.map_or(false, |(min, max)| min > max) {
+            Some("repeat_delay")
+        } else if changes.loop_delay.map_or(false, |(min, max)| min > max) {
+            Some("loop_delay")
+        } else if changes
+            .pitch
+            .map_or(false, |(min, max)| min <= 0.0 || min > max)
+        {
+            Some("pitch")
+        } else {
+            None
+        };
+
+        if let Some(field) = invalid {
+            return Err(ApiError::new(
+                ErrorCode::InvalidValue,
+                format!("Invalid range for {}!", field),
+            )
+            .with_sound(sound));
         }
 
-        let samples = self
-            .samplesdb
-            .samples()
-            .map(|sample| {
-                (
-                    sample.path.clone(),
-                    sample.tags.iter().map(|&tag| tag.name.clone()).collect(),
+        if let Some(ref reverb) = changes.reverb {
+            if !REVERB_PRESETS.contains(&reverb.as_str()) && !self.reverbs.contains(reverb) {
+                return Err(ApiError::new(
+                    ErrorCode::InvalidValue,
+                    format!("Unknown reverb preset '{}'!", reverb),
                 )
-            })
-            .collect();
-
-        send_response!(self, Response::SoundLibrary { samples });
+                .with_sound(sound));
+            }
+        }
 
         Ok(())
     }
 
-    fn handle_volume(&mut self, value: f32) -> Result<(), AudioEngineError> {
-        self.backend.set_volume(value);
-        self.master_volume = value;
+    fn handle_update_sound(
+        &mut self,
+        sound: String,
+        changes: SoundChanges,
+    ) -> Result<(), AudioEngineError> {
+        if !self.sound_handles.contains_key(&sound) {
+            debug!("handle_update_sound(): No such sound {}", sound);
+            send_error!(self, ApiError::unknown_sound(&sound));
+            return Ok(());
+        }
+
+        if let Err(error) = self.validate_changes(&sound, &changes) {
+            send_error!(self, error);
+            return Ok(());
+        }
+
+        info!("Updating parameters of sound '{}': {:?}", sound, changes);
+        self.sound_handles
+            .get_mut(&sound)
+            .unwrap()
+            .apply_changes(changes);
+
         send_response!(self);
 
         Ok(())
     }
 
-    fn handle_get_driver_list(&mut self) -> Result<(), AudioEngineError> {
-        let drivers = self
-            .backend
-            .get_output_devices()
-            .into_iter()
-            .enumerate()
-            .collect();
+    /// Swaps the file of a sound, `changes` are only applied along with it if the file
+    /// could be swapped
+    fn handle_replace_sound_file(
+        &mut self,
+        sound: String,
+        file: String,
+        crossfade: Option<u64>,
+        strict: bool,
+        changes: SoundChanges,
+    ) -> Result<(), AudioEngineError> {
+        if !self.sound_handles.contains_key(&sound) {
+            debug!("handle_replace_sound_file(): No such sound {}", sound);
+            send_error!(self, ApiError::unknown_sound(&sound));
+            return Ok(());
+        }
 
-        send_response!(self, Response::DriverList { drivers });
+        if let Err(error) = self.validate_changes(&sound, &changes) {
+            send_error!(self, error);
+            return Ok(());
+        }
+
+        let downloads = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .pending_downloads(Some(&file));
+        if !downloads.is_empty() {
+            let command = Command::ReplaceSoundFile {
+                sound,
+                file,
+                crossfade,
+                strict,
+                changes,
+            };
+            self.download_first(downloads, command);
+            return Ok(());
+        }
+
+        let sample_id = match self.samplesdb.lock().unwrap().resolve_sample(&file) {
+            Ok(Some(id)) => id,
+            Ok(None) => {
+                send_error!(self, ApiError::sample_not_found(&file).with_sound(&sound));
+                return Ok(());
+            }
+            Err(e) => {
+                send_error!(self, ApiError::from(&e).with_sound(&sound));
+                return Ok(());
+            }
+        };
+        let full_path = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .full_path_of_sample(sample_id);
+
+        let warnings = decode_warnings(&self.warning_cache, &sound, &file, &full_path);
+        if strict && !warnings.is_empty() {
+            send_error!(self, &warnings[0]);
+            return Ok(());
+        }
+
+        info!("Replacing file of sound '{}' with {} ...", sound, file);
+
+        let stereo = self.sound_handles[&sound].sound.stereo;
+        let object = match self.backend.load_file(&full_path, stereo) {
+            Ok(object) => object,
+            Err(e) => {
+                send_error!(self, ApiError::from(&e).with_sound(&sound));
+                return Ok(());
+            }
+        };
+
+        let sample_gain = self.samplesdb.lock().unwrap().sample_gain(sample_id);
+        let handle = self.sound_handles.get_mut(&sound).unwrap();
+        handle.format = sample_format(&full_path);
+        handle.sample_gain = sample_gain;
+        let replaced = handle.replace_object(
+            &mut self.backend,
+            object,
+            file,
+            crossfade
+                .or(self.settings.crossfade)
+                .unwrap_or(DEFAULT_CROSSFADE_MS),
+        );
+
+        if let Err(e) = replaced {
+            warn!("Failed to replace file of sound '{}': {}", sound, e);
+            send_error!(self, ApiError::from(&e).with_sound(&sound));
+            return Ok(());
+        }
+
+        if !changes.is_empty() {
+            info!("Updating parameters of sound '{}': {:?}", sound, changes);
+            handle.apply_changes(changes);
+        }
+
+        send_response!(self, Response::ReplaceSoundFile { warnings });
 
         Ok(())
     }
 
-    fn handle_get_driver(&mut self) -> Result<(), AudioEngineError> {
-        let id = self.backend.get_current_output_device();
-        send_response!(self, Response::Driver { id });
+    fn handle_set_sound_counters(
+        &mut self,
+        sound: String,
+        loops: Option<u32>,
+        repeats: Option<u32>,
+    ) -> Result<(), AudioEngineError> {
+        if let Some(handle) = self.sound_handles.get_mut(&sound) {
+            if let Some(loops) = loops {
+                // An explicit number of loops ends looping forever
+                handle.sound.loop_forever = false;
+                handle.parameters.loops = loops;
+            }
+
+            if let Some(repeats) = repeats {
+                handle.parameters.repeats = repeats;
+            }
+
+            info!(
+                "Sound '{}' has {} loops and {} repeats left",
+                sound, handle.parameters.loops, handle.parameters.repeats
+            );
+
+            send_response!(self);
+        } else {
+            debug!("handle_set_sound_counters(): No such sound {}", sound);
+            send_error!(self, ApiError::unknown_sound(&sound));
+        }
 
         Ok(())
     }
 
-    fn handle_set_driver(&mut self, id: i32) -> Result<(), AudioEngineError> {
-        self.backend.set_current_output_device(id);
+    fn handle_stop_sound_at_loop_end(&mut self, sound: String) -> Result<(), AudioEngineError> {
+        if let Some(handle) = self.sound_handles.get_mut(&sound) {
+            info!("Sound '{}' will stop at the end of its loop", sound);
+            handle.parameters.stop_at_loop_end = true;
 
-        send_response!(self);
+            send_response!(self);
+        } else {
+            debug!("handle_stop_sound_at_loop_end(): No such sound {}", sound);
+            send_error!(self, ApiError::unknown_sound(&sound));
+        }
 
         Ok(())
     }
 
-    pub(in crate::audio_engine::engine) fn run_message_queue(
-        &mut self,
-    ) -> Result<bool, AudioEngineError> {
-        let timeout = Duration::from_millis(50);
-
-        if let Ok(msg) = self.receiver.recv_timeout(timeout) {
-            match msg {
-                Command::Quit => return Ok(true),
-                Command::Pause => self.handle_pause()?,
-                Command::Play => self.handle_play()?,
-                Command::PreviewSound { sound } => self.handle_preview_sound(sound)?,
-                Command::LoadTheme { theme } => self.handle_load_theme(theme)?,
-                Command::Trigger { sound } => self.handle_trigger(sound)?,
-                Command::GetStatus => self.handle_get_status()?,
-                Command::GetSoundLibrary => self.handle_get_sound_library()?,
-                Command::SetVolume { value } => self.handle_volume(value)?,
-                Command::GetDriverList => self.handle_get_driver_list()?,
-                Command::GetDriver => self.handle_get_driver()?,
-                Command::SetDriver { id } => self.handle_set_driver(id)?,
+    fn handle_stop_sound(&mut self, sound: String) -> Result<(), AudioEngineError> {
+        let result = match self.sound_handles.get_mut(&sound) {
+            Some(handle) => {
+                info!("Stopping sound '{}'", sound);
+                handle.stop_now(&mut self.backend)
+            }
+            None => {
+                debug!("handle_stop_sound(): No such sound {}", sound);
+                send_error!(self, ApiError::unknown_sound(&sound));
+                return Ok(());
+            }
+        };
+
+        match result {
+            Ok(()) => send_response!(self),
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    fn handle_run_macro(&mut self, name: String) -> Result<(), AudioEngineError> {
+        let actions = match self.macros.get(&name) {
+            Some(actions) => actions.clone(),
+            None => {
+                debug!("handle_run_macro(): No such macro {}", name);
+                send_error!(
+                    self,
+                    ApiError::new(
+                        ErrorCode::UnknownMacro,
+                        format!("Unknown macro '{}'!", name)
+                    )
+                );
+                return Ok(());
             }
         };
 
+        info!("Running macro '{}'", name);
+        self.running_macros.push(RunningMacro::new(name, actions));
+
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_skip_track(&mut self, playlist: String, back: bool) -> Result<(), AudioEngineError> {
+        let result = match self
+            .playlists
+            .iter_mut()
+            .find(|p| p.playlist.name == playlist)
+        {
+            Some(player) => player.skip(&mut self.backend, !back, 0),
+            None => {
+                debug!("handle_skip_track(): No such playlist {}", playlist);
+                send_error!(self, ApiError::unknown_playlist(&playlist));
+                return Ok(());
+            }
+        };
+
+        match result {
+            Ok(()) => send_response!(self),
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    fn handle_seek_track(
+        &mut self,
+        playlist: String,
+        position: f32,
+    ) -> Result<(), AudioEngineError> {
+        if position < 0.0 {
+            send_error!(
+                self,
+                ApiError::new(ErrorCode::InvalidValue, "Position must not be negative!")
+            );
+            return Ok(());
+        }
+
+        let result = match self
+            .playlists
+            .iter_mut()
+            .find(|p| p.playlist.name == playlist)
+        {
+            Some(player) => player.seek(&mut self.backend, position),
+            None => {
+                debug!("handle_seek_track(): No such playlist {}", playlist);
+                send_error!(self, ApiError::unknown_playlist(&playlist));
+                return Ok(());
+            }
+        };
+
+        match result {
+            Ok(()) => send_response!(self),
+            Err(error) => send_error!(self, error),
+        }
+
+        Ok(())
+    }
+
+    fn handle_get_sound_status(&mut self, sound: String) -> Result<(), AudioEngineError> {
+        match self.sound_handles.get_mut(&sound) {
+            Some(handle) => {
+                let status = handle.status();
+                send_response!(self, Response::SoundStatus { status });
+            }
+            None => {
+                debug!("handle_get_sound_status(): No such sound {}", sound);
+                send_error!(self, ApiError::unknown_sound(&sound));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_get_sound_position(&mut self, sound: String) -> Result<(), AudioEngineError> {
+        let handle = match self.sound_handles.get_mut(&sound) {
+            Some(handle) => handle,
+            None => {
+                debug!("handle_get_sound_position(): No such sound {}", sound);
+                send_error!(self, ApiError::unknown_sound(&sound));
+                return Ok(());
+            }
+        };
+
+        let response = Response::SoundPosition {
+            position: handle.object.get_position_seconds(),
+            length: handle.object.get_length(),
+            playing: handle.is_in_state(&AudioEntityState::Playing),
+        };
+        send_response!(self, response);
+
+        Ok(())
+    }
+
+    fn handle_seek_sound(&mut self, sound: String, position: f32) -> Result<(), AudioEngineError> {
+        let handle = match self.sound_handles.get_mut(&sound) {
+            Some(handle) => handle,
+            None => {
+                debug!("handle_seek_sound(): No such sound {}", sound);
+                send_error!(self, ApiError::unknown_sound(&sound));
+                return Ok(());
+            }
+        };
+
+        if position < 0.0 || !handle.is_in_state(&AudioEntityState::Playing) {
+            send_error!(
+                self,
+                ApiError::new(
+                    ErrorCode::InvalidValue,
+                    "Only playing sounds can be seeked, to a position of at least 0!"
+                )
+                .with_sound(&sound)
+            );
+            return Ok(());
+        }
+
+        match handle.object.seek(&mut self.backend, position) {
+            Ok(()) => {
+                info!("Sound '{}' seeked to {:.1}s", sound, position);
+                send_response!(self);
+            }
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    fn handle_delay_command(
+        &mut self,
+        delay: u64,
+        command: Command,
+    ) -> Result<(), AudioEngineError> {
+        if !command.is_delayable() {
+            send_error!(
+                self,
+                ApiError::new(ErrorCode::InvalidValue, "This command can't be delayed!")
+            );
+            return Ok(());
+        }
+
+        let id = self.next_delayed_id;
+        self.next_delayed_id += 1;
+
+        info!("Delaying command {} by {}ms", id, delay);
+        self.delayed_commands
+            .push(DelayedCommand::new(id, command, delay));
+
+        send_response!(self, Response::DelayedCommand { id });
+
+        Ok(())
+    }
+
+    fn handle_cancel_delayed_command(&mut self, id: u64) -> Result<(), AudioEngineError> {
+        match self
+            .delayed_commands
+            .iter()
+            .position(|delayed| delayed.id == id)
+        {
+            Some(index) => {
+                info!("Cancelled delayed command {}", id);
+                self.delayed_commands.remove(index);
+                send_response!(self);
+            }
+            None => send_error!(
+                self,
+                ApiError::new(ErrorCode::NotFound, format!("No delayed command {}!", id))
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn handle_get_delayed_commands(&mut self) -> Result<(), AudioEngineError> {
+        let mut commands: Vec<_> = self
+            .delayed_commands
+            .iter()
+            .map(DelayedCommand::status)
+            .collect();
+        commands.sort_by_key(|command| command.due_in);
+
+        send_response!(self, Response::DelayedCommands { commands });
+
+        Ok(())
+    }
+
+    fn handle_set_sound_trim(&mut self, sound: String, gain: f32) -> Result<(), AudioEngineError> {
+        let theme = match self.theme {
+            Some(ref theme) => theme.clone(),
+            None => {
+                send_error!(self, ApiError::no_theme_loaded());
+                return Ok(());
+            }
+        };
+
+        if !gain.is_finite() || gain < 0.0 {
+            send_error!(
+                self,
+                ApiError::new(ErrorCode::InvalidValue, "Trim must not be negative!")
+                    .with_sound(&sound)
+            );
+            return Ok(());
+        }
+
+        if !self.sound_handles.contains_key(&sound) {
+            debug!("handle_set_sound_trim(): No such sound {}", sound);
+            send_error!(self, ApiError::unknown_sound(&sound));
+            return Ok(());
+        }
+
+        // Only applied once stored, the mix shouldn't differ from what loads next time
+        if let Err(e) = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .set_sound_trim(&theme, &sound, gain)
+        {
+            warn!("Failed to store trim of sound '{}': {}", sound, e);
+            send_error!(self, &e);
+            return Ok(());
+        }
+
+        if let Some(handle) = self.sound_handles.get_mut(&sound) {
+            handle.trim = gain;
+        }
+
+        info!("Trimmed sound '{}' of theme '{}' to {}", sound, theme, gain);
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_set_listener_position(
+        &mut self,
+        position: (f32, f32, f32),
+    ) -> Result<(), AudioEngineError> {
+        if let Err(e) = self.backend.set_listener_position(position) {
+            send_error!(self, &e);
+            return Ok(());
+        }
+
+        info!("Moved listener to {:?}", position);
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_set_listener_orientation(
+        &mut self,
+        at: (f32, f32, f32),
+        up: (f32, f32, f32),
+    ) -> Result<(), AudioEngineError> {
+        if let Err(e) = self.backend.set_listener_orientation(at, up) {
+            send_error!(self, &e);
+            return Ok(());
+        }
+
+        info!("Turned listener to face {:?}, up {:?}", at, up);
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_set_sound_position(
+        &mut self,
+        sound: String,
+        position: (f32, f32, f32),
+    ) -> Result<(), AudioEngineError> {
+        let handle = match self.sound_handles.get_mut(&sound) {
+            Some(handle) => handle,
+            None => {
+                debug!("handle_set_sound_position(): No such sound {}", sound);
+                send_error!(self, ApiError::unknown_sound(&sound));
+                return Ok(());
+            }
+        };
+
+        // Sounds that aren't playing are placed when they start
+        if handle.is_in_state(&AudioEntityState::Playing) {
+            let relative = handle.is_relative();
+            if let Err(e) = handle.object.set_position(position, relative) {
+                send_error!(self, &e);
+                return Ok(());
+            }
+        }
+
+        // Placing the sound by hand takes it off its path
+        handle.position = Some(position);
+        handle.follow_path = false;
+
+        info!("Moved sound '{}' to {:?}", sound, position);
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_set_sound_muted(
+        &mut self,
+        sound: String,
+        flag: bool,
+    ) -> Result<(), AudioEngineError> {
+        if let Some(handle) = self.sound_handles.get_mut(&sound) {
+            info!(
+                "{} sound '{}'",
+                if flag { "Muting" } else { "Unmuting" },
+                sound
+            );
+            handle.muted = flag;
+            handle.sound.muted = flag;
+
+            send_response!(self);
+        } else {
+            debug!("handle_set_sound_muted(): No such sound {}", sound);
+            send_error!(self, ApiError::unknown_sound(&sound));
+        }
+
+        Ok(())
+    }
+
+    fn handle_solo_sound(&mut self, sound: String) -> Result<(), AudioEngineError> {
+        if !self.sound_handles.contains_key(&sound) {
+            debug!("handle_solo_sound(): No such sound {}", sound);
+            send_error!(self, ApiError::unknown_sound(&sound));
+            return Ok(());
+        }
+
+        info!("Soloing sound '{}'", sound);
+        for (name, handle) in self.sound_handles.iter_mut() {
+            handle.solo_muted = *name != sound;
+            handle.soloed = *name == sound;
+        }
+        self.solo = Some(sound);
+        self.update_one_shot_volumes();
+
+        send_response!(self);
+
+        Ok(())
+    }
+
+    /// Test tones and beeps are silenced like everything else while a sound is soloed
+    fn update_one_shot_volumes(&mut self) {
+        let soloed = self.solo.is_some();
+
+        for (object, volume) in &mut self.one_shots {
+            let gain = if soloed { 0.0 } else { *volume };
+            if let Err(e) = object.set_volume(gain) {
+                warn!("Failed to set the volume of a beep: {}", e);
+            }
+        }
+    }
+
+    /// Lets all sounds be heard again, sounds muted by hand stay muted
+    fn handle_clear_solo(&mut self) -> Result<(), AudioEngineError> {
+        if let Some(sound) = self.solo.take() {
+            info!("Clearing solo of sound '{}'", sound);
+        }
+
+        for handle in self.sound_handles.values_mut() {
+            handle.solo_muted = false;
+            handle.soloed = false;
+        }
+        self.update_one_shot_volumes();
+
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_arm_sound(&mut self, sound: String, armed: bool) -> Result<(), AudioEngineError> {
+        let handle = match self.sound_handles.get_mut(&sound) {
+            Some(handle) => handle,
+            None => {
+                debug!("handle_arm_sound(): No such sound {}", sound);
+                send_error!(self, ApiError::unknown_sound(&sound));
+                return Ok(());
+            }
+        };
+
+        if armed && !handle.sound.waits_for_trigger() {
+            send_error!(
+                self,
+                ApiError::new(
+                    ErrorCode::InvalidValue,
+                    "Only sounds waiting for a trigger can be armed!"
+                )
+                .with_sound(&sound)
+            );
+            return Ok(());
+        }
+
+        handle.armed = armed;
+
+        // Sounds that are playing right now are armed once they wait for a trigger again
+        let result = if !armed {
+            handle.object.disarm(&mut self.backend)
+        } else if handle.is_in_state(&AudioEntityState::WaitingForTrigger) {
+            handle.object.arm(&mut self.backend)
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = result {
+            handle.armed = false;
+            send_error!(self, &e);
+            return Ok(());
+        }
+
+        info!(
+            "Sound '{}' {}",
+            sound,
+            if armed { "armed" } else { "disarmed" }
+        );
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_set_time_scale(&mut self, value: f32, pitch: bool) -> Result<(), AudioEngineError> {
+        // Pausing is what stops the time, a scale of 0 would stop it without saying so
+        if !value.is_finite() || value <= 0.0 {
+            send_error!(
+                self,
+                ApiError::new(ErrorCode::InvalidValue, "Time scale must be above 0!")
+            );
+            return Ok(());
+        }
+
+        self.time_scale = value;
+        self.pitch_scale = if pitch {
+            value.max(MIN_PITCH_SCALE)
+        } else {
+            1.0
+        };
+
+        let handles = self.sound_handles.values_mut().chain(
+            self.next_sound_handles
+                .iter_mut()
+                .flat_map(|h| h.values_mut()),
+        );
+        // A sound whose pitch can't be set keeps playing at the time scale all the same
+        for handle in handles {
+            if let Err(e) = handle.set_pitch_scale(self.pitch_scale) {
+                warn!(
+                    "Failed to set pitch of sound '{}': {}",
+                    handle.sound.name, e
+                );
+            }
+        }
+
+        info!(
+            "Time scale set to {} (pitch scale {})",
+            value, self.pitch_scale
+        );
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_get_status(&mut self) -> Result<(), AudioEngineError> {
+        let mut playing: Vec<String> = Vec::new();
+        let mut playing_next: HashMap<String, u64> = HashMap::new();
+        let mut previewing: Vec<String> = Vec::new();
+        let mut sounds: HashMap<String, SoundStatus> = HashMap::new();
+
+        for (name, handle) in &mut self.sound_handles {
+            sounds.insert(name.to_string(), handle.status());
+
+            if handle.is_in_state(&AudioEntityState::Playing) {
+                playing.push(name.to_string());
+            } else if handle.is_in_state(&AudioEntityState::WaitingForStart) {
+                playing_next.insert(name.to_string(), handle.parameters.next_play.as_secs());
+            }
+
+            if handle.is_preview {
+                previewing.push(name.to_string());
+            }
+        }
+
+        send_response!(
+            self,
+            Response::Status {
+                playing: self.playing,
+                theme_loaded: self.theme_loaded,
+                theme: self.theme.clone(),
+                sounds_playing: playing,
+                sounds_playing_next: playing_next,
+                previewing: previewing,
+                load_state: self.load_state,
+                auditioning: self
+                    .audition
+                    .as_ref()
+                    .and_then(|audition| audition.current().map(str::to_owned)),
+                preview_queue: self
+                    .preview_queue
+                    .iter()
+                    .map(|(sound, _)| sound.clone())
+                    .collect(),
+                clients: Vec::new(),
+                last_change_by: None,
+                ducking: self.ducker.as_ref().map_or(false, |d| d.is_ducking()),
+                sounds,
+                time_scale: self.time_scale,
+                group_volumes: self.group_volumes.clone(),
+                version: self.version.get(),
+                macros_running: self.running_macros.iter().map(|m| m.name.clone()).collect(),
+                playlists: self.playlists.iter_mut().map(|p| p.status()).collect(),
+                lockout: self.lockout.as_ref().map(Lockout::status),
+                preload: self.preload.as_ref().map(ThemePreload::status),
+                scene: self.scene.clone(),
+                solo: self.solo.clone(),
+                device_lost: self.device_monitor.lost,
+                source_pool: self.backend.get_source_pool(),
+                waiting_for_source: self.source_queue.clone(),
+                eq: self.eq,
+                limiter: self.limiter.status(),
+            }
+        );
+
+        Ok(())
+    }
+
+    fn handle_get_overlay(&mut self) -> Result<(), AudioEngineError> {
+        let mut music = None;
+        let mut ambience: Vec<String> = Vec::new();
+
+        for (name, handle) in &self.sound_handles {
+            if !self.playing || !handle.is_in_state(&AudioEntityState::Playing) {
+                continue;
+            }
+
+            if handle.sound.music {
+                if music.is_none() {
+                    let metadata = self
+                        .samplesdb
+                        .lock()
+                        .unwrap()
+                        .samples()
+                        .find(|sample| sample.path == handle.sound.file)
+                        .map(|sample| sample.metadata.clone())
+                        .unwrap_or_default();
+
+                    music = Some(OverlayTrack {
+                        sound: name.to_string(),
+                        title: metadata.title,
+                        artist: metadata.artist,
+                    });
+                }
+            } else if handle.is_ambience() {
+                ambience.push(name.to_string());
+            }
+        }
+
+        // Without a music sound playing, the track of a playlist is shown instead
+        if music.is_none() && self.playing {
+            let track = self.playlists.iter().find_map(|player| {
+                player
+                    .current_track()
+                    .map(|track| (player.playlist.name.clone(), track.to_string()))
+            });
+
+            if let Some((playlist, track)) = track {
+                let metadata = self
+                    .samplesdb
+                    .lock()
+                    .unwrap()
+                    .samples()
+                    .find(|sample| sample.path == track)
+                    .map(|sample| sample.metadata.clone())
+                    .unwrap_or_default();
+
+                music = Some(OverlayTrack {
+                    sound: playlist,
+                    title: metadata.title,
+                    artist: metadata.artist,
+                });
+            }
+        }
+
+        ambience.sort();
+
+        send_response!(
+            self,
+            Response::Overlay {
+                theme: self.theme.clone(),
+                music,
+                ambience,
+            }
+        );
+
+        Ok(())
+    }
+
+    fn play_one_shot(
+        &mut self,
+        samples: Vec<i16>,
+        pan: f32,
+        volume: f32,
+    ) -> Result<(), AudioEngineError> {
+        let mut object = match self.backend.load_samples(samples, generator::SAMPLE_RATE) {
+            Ok(object) => object,
+            Err(e) => {
+                send_error!(self, &e);
+                return Ok(());
+            }
+        };
+
+        // Silenced like everything else while a sound is soloed
+        let gain = if self.solo.is_some() { 0.0 } else { volume };
+
+        object.play(&mut self.backend);
+        if let Err(e) = object.set_pan(pan).and_then(|_| object.set_volume(gain)) {
+            send_error!(self, &e);
+            object.stop(&mut self.backend)?;
+            return Ok(());
+        }
+
+        self.one_shots.push((object, volume));
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_play_test_tone(&mut self, channel: OutputChannel) -> Result<(), AudioEngineError> {
+        let pan = match channel {
+            OutputChannel::Left => -1.0,
+            OutputChannel::Right => 1.0,
+            OutputChannel::All => 0.0,
+        };
+
+        info!("Playing test tone on channel {:?}", channel);
+        self.play_one_shot(generator::tone(440.0, 1.0, 0.5), pan, 1.0)
+    }
+
+    fn handle_play_beep(
+        &mut self,
+        waveform: Waveform,
+        frequency: f32,
+        duration: f32,
+        volume: f32,
+    ) -> Result<(), AudioEngineError> {
+        if let Err(e) = generator::check_tone(frequency, duration) {
+            send_error!(self, ApiError::new(ErrorCode::InvalidValue, e));
+            return Ok(());
+        }
+
+        debug!("Beep: {:?} at {} Hz for {}s", waveform, frequency, duration);
+        let samples = generator::synth(waveform, frequency, duration, 1.0);
+        self.play_one_shot(samples, 0.0, volume)
+    }
+
+    fn remember_last_theme(&mut self, name: &str) {
+        let update = Settings {
+            last_theme: Some(name.to_string()),
+            ..Settings::default()
+        };
+
+        if let Err(e) = self.samplesdb.lock().unwrap().save_settings(&update) {
+            warn!("Failed to remember last theme: {}", e);
+        }
+
+        self.settings.merge(update);
+    }
+
+    fn handle_get_settings(&mut self) -> Result<(), AudioEngineError> {
+        send_response!(
+            self,
+            Response::Settings {
+                settings: self.settings.clone(),
+            }
+        );
+
+        Ok(())
+    }
+
+    fn handle_update_settings(&mut self, settings: Settings) -> Result<(), AudioEngineError> {
+        if let Err(e) = self.samplesdb.lock().unwrap().save_settings(&settings) {
+            warn!("Failed to store settings: {}", e);
+            send_error!(self, &e);
+            return Ok(());
+        }
+
+        self.settings.merge(settings);
+        info!("Updated settings: {:?}", self.settings);
+        send_response!(
+            self,
+            Response::Settings {
+                settings: self.settings.clone(),
+            }
+        );
+
+        Ok(())
+    }
+
+    fn handle_save_theme(&mut self, name: String, theme: Value) -> Result<(), AudioEngineError> {
+        match self.samplesdb.lock().unwrap().save_theme(&name, &theme) {
+            Ok(()) => send_response!(self),
+            Err(e) => {
+                warn!("Failed to store theme '{}': {}", name, e);
+                send_error!(self, &e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_get_stored_themes(&mut self) -> Result<(), AudioEngineError> {
+        match self.samplesdb.lock().unwrap().themes() {
+            Ok(themes) => send_response!(self, Response::ThemeList { themes }),
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    fn stored_theme(&self, name: &str) -> Result<Value, ApiError> {
+        match self.samplesdb.lock().unwrap().theme(name) {
+            Ok(Some(theme)) => Ok(theme),
+            Ok(None) => Err(ApiError::new(
+                ErrorCode::NotFound,
+                format!("No stored theme '{}'!", name),
+            )),
+            Err(e) => Err((&e).into()),
+        }
+    }
+
+    fn handle_get_stored_theme(&mut self, name: String) -> Result<(), AudioEngineError> {
+        match self.stored_theme(&name) {
+            Ok(theme) => send_response!(self, Response::StoredTheme { theme }),
+            Err(error) => send_error!(self, error),
+        }
+
+        Ok(())
+    }
+
+    /// The loaded theme with the changes made since it was loaded, so loading it again
+    /// doesn't undo them
+    fn live_theme_json(&self, theme: &Value) -> Result<Value, serde_json::Error> {
+        let mut theme = theme.clone();
+
+        // While switching themes the handles still belong to the previous one
+        if self.next_sound_handles.is_some() {
+            return Ok(theme);
+        }
+
+        if let Some(Value::Array(sounds)) = theme.get_mut("sounds") {
+            for sound in sounds.iter_mut() {
+                let handle = match sound
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .and_then(|name| self.sound_handles.get(name))
+                {
+                    Some(handle) => handle,
+                    // Sounds skipped while loading stay the way they were sent
+                    None => continue,
+                };
+
+                let mut live = serde_json::to_value(&handle.sound)?;
+                if let Value::Object(fields) = &mut live {
+                    // Scenes switch sounds off at runtime, keep whether the theme enables them
+                    if let Some(enabled) = sound.get("enabled") {
+                        fields.insert("enabled".to_string(), enabled.clone());
+                    }
+
+                    // Sounds placed by hand keep where they were placed
+                    if !handle.follow_path {
+                        fields.insert(
+                            "position".to_string(),
+                            serde_json::to_value(handle.position)?,
+                        );
+                    }
+                }
+                *sound = live;
+            }
+        }
+
+        if let Value::Object(fields) = &mut theme {
+            let playlists: Vec<&Playlist> = self
+                .playlists
+                .iter()
+                .map(|player| &player.playlist)
+                .collect();
+            fields.insert("playlists".to_string(), serde_json::to_value(playlists)?);
+            fields.insert("macros".to_string(), serde_json::to_value(&self.macros)?);
+            fields.insert("scenes".to_string(), serde_json::to_value(&self.scenes)?);
+            fields.insert(
+                "initial_scene".to_string(),
+                serde_json::to_value(&self.scene)?,
+            );
+        }
+
+        Ok(theme)
+    }
+
+    fn handle_get_theme(&mut self) -> Result<(), AudioEngineError> {
+        let theme = match &self.theme_json {
+            Some(theme) => self.live_theme_json(theme),
+            None => {
+                send_error!(self, ApiError::no_theme_loaded());
+                return Ok(());
+            }
+        };
+
+        match theme {
+            Ok(theme) => send_response!(self, Response::Theme { theme }),
+            Err(e) => {
+                error!("Failed to serialize the loaded theme: {}", e);
+                send_error!(
+                    self,
+                    ApiError::new(
+                        ErrorCode::Internal,
+                        format!("Failed to serialize the loaded theme: {}", e)
+                    )
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_load_stored_theme(
+        &mut self,
+        name: String,
+        on_missing: MissingSampleBehavior,
+        strict: bool,
+    ) -> Result<(), AudioEngineError> {
+        let theme = self.stored_theme(&name).and_then(|theme| {
+            serde_json::from_value(theme).map_err(|e| ApiError::new(ErrorCode::InvalidValue, e))
+        });
+
+        match theme {
+            Ok(theme) => self.handle_load_theme(theme, on_missing, strict)?,
+            Err(error) => send_error!(self, error),
+        }
+
+        Ok(())
+    }
+
+    fn handle_preload_theme(&mut self, name: String) -> Result<(), AudioEngineError> {
+        let theme = self.stored_theme(&name).and_then(|theme| {
+            serde_json::from_value::<Theme>(theme)
+                .map_err(|e| ApiError::new(ErrorCode::InvalidValue, e))
+        });
+
+        let theme = match theme {
+            Ok(theme) => theme,
+            Err(error) => {
+                send_error!(self, error);
+                return Ok(());
+            }
+        };
+
+        // Generated sounds are quick to create, only files are worth loading ahead. Files
+        // that can't be found now are tried again along with the theme.
+        let mut sounds = Vec::new();
+        let mut missing = Vec::new();
+        for sound in theme.sounds {
+            if sound.sound_type != SoundType::File {
+                continue;
+            }
+
+            let mut samplesdb = self.samplesdb.lock().unwrap();
+            match samplesdb.resolve_sample(&sound.file) {
+                Ok(Some(id)) => sounds.push((sound, samplesdb.full_path_of_sample(id))),
+                _ => missing.push(sound.name),
+            }
+        }
+
+        let count = sounds.len() + missing.len();
+        info!("Preloading {} sound(s) of theme '{}'", count, theme.name);
+
+        // Only one theme is preloaded at a time, one preloaded before is dropped
+        self.preload = Some(ThemePreload::new(
+            theme.name,
+            sounds,
+            missing,
+            T::decode_file,
+            self.warning_cache.clone(),
+        ));
+        send_response!(self, Response::PreloadTheme { sounds: count });
+
+        Ok(())
+    }
+
+    fn handle_get_client_setting(
+        &mut self,
+        client: String,
+        key: String,
+    ) -> Result<(), AudioEngineError> {
+        match self.samplesdb.lock().unwrap().client_setting(&client, &key) {
+            Ok(value) => send_response!(self, Response::ClientSetting { value }),
+            Err(e) => {
+                warn!(
+                    "Failed to read setting '{}' of client '{}': {}",
+                    key, client, e
+                );
+                send_error!(self, &e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_set_client_setting(
+        &mut self,
+        client: String,
+        key: String,
+        value: String,
+    ) -> Result<(), AudioEngineError> {
+        if let Err(e) = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .set_client_setting(&client, &key, &value)
+        {
+            warn!(
+                "Failed to store setting '{}' of client '{}': {}",
+                key, client, e
+            );
+            send_error!(self, &e);
+            return Ok(());
+        }
+
+        debug!("Stored setting '{}' of client '{}'", key, client);
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_get_sound_library(&mut self) -> Result<(), AudioEngineError> {
+        let mut lib: Vec<String> = Vec::new();
+        for entry in self.samplesdb.lock().unwrap().samples() {
+            lib.push(entry.path.clone())
+        }
+
+        let samples = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .samples()
+            .map(library_entry)
+            .collect();
+
+        send_response!(self, Response::SoundLibrary { samples });
+
+        Ok(())
+    }
+
+    fn handle_get_library_report(&mut self) -> Result<(), AudioEngineError> {
+        let report = self.samplesdb.lock().unwrap().report().clone();
+        send_response!(self, Response::LibraryReport { report });
+
+        Ok(())
+    }
+
+    fn handle_start_audition(
+        &mut self,
+        tag: Option<String>,
+        query: Option<String>,
+        seconds: Option<f32>,
+        normalized: bool,
+    ) -> Result<(), AudioEngineError> {
+        let seconds = seconds.unwrap_or(AUDITION_SECONDS);
+        if seconds < 1.0 {
+            send_error!(
+                self,
+                ApiError::new(
+                    ErrorCode::InvalidValue,
+                    "Auditioned samples have to play at least a second!"
+                )
+            );
+            return Ok(());
+        }
+
+        if tag.is_none() && query.is_none() {
+            send_error!(
+                self,
+                ApiError::new(
+                    ErrorCode::InvalidRequest,
+                    "Either a tag or a query is required!"
+                )
+            );
+            return Ok(());
+        }
+
+        let samples: Vec<(String, PathBuf, f32)> = {
+            let samplesdb = self.samplesdb.lock().unwrap();
+            let mut samples = match (tag, query) {
+                (Some(tag), _) => samplesdb.samples_by_tag(&tag),
+                (None, Some(query)) => samplesdb.search(&query),
+                (None, None) => Vec::new(),
+            };
+            samples.sort_by(|a, b| a.path.cmp(&b.path));
+
+            samples
+                .into_iter()
+                .map(|sample| {
+                    let gain = if normalized {
+                        samplesdb.sample_gain(sample.id)
+                    } else {
+                        1.0
+                    };
+                    (
+                        sample.path.clone(),
+                        samplesdb.full_path_of_sample(sample.id),
+                        gain,
+                    )
+                })
+                .collect()
+        };
+
+        if samples.is_empty() {
+            send_error!(
+                self,
+                ApiError::new(ErrorCode::NotFound, "No samples to audition!")
+            );
+            return Ok(());
+        }
+
+        let paths = samples.iter().map(|(path, _, _)| path.clone()).collect();
+
+        self.stop_audition();
+        let mut audition = Audition::new(samples, seconds);
+        if let Err(e) = audition.next(&mut self.backend) {
+            send_error!(self, &e);
+            return Ok(());
+        }
+        self.audition = Some(audition);
+
+        send_response!(self, Response::Audition { samples: paths });
+
+        Ok(())
+    }
+
+    fn handle_next_audition(&mut self) -> Result<(), AudioEngineError> {
+        match self.audition {
+            Some(ref mut audition) => match audition.next(&mut self.backend) {
+                Ok(_) => send_response!(self),
+                Err(e) => send_error!(self, &e),
+            },
+            None => send_error!(
+                self,
+                ApiError::new(ErrorCode::NotFound, "No audition running!")
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn handle_stop_audition(&mut self) -> Result<(), AudioEngineError> {
+        self.stop_audition();
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_reload_settings(&mut self) -> Result<(), AudioEngineError> {
+        // Settings are only read on startup otherwise
+        match self.samplesdb.lock().unwrap().settings() {
+            Ok(settings) => {
+                self.settings = settings;
+                send_response!(self);
+            }
+            Err(e) => {
+                warn!("Failed to read restored settings: {}", e);
+                send_error!(self, &e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_get_tags(&mut self) -> Result<(), AudioEngineError> {
+        let tags = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .tags()
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        send_response!(self, Response::Tags { tags });
+
+        Ok(())
+    }
+
+    fn handle_get_samples_by_tag(&mut self, tag: String) -> Result<(), AudioEngineError> {
+        let samples = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .samples_by_tag(&tag)
+            .into_iter()
+            .map(library_entry)
+            .collect();
+
+        send_response!(self, Response::SoundLibrary { samples });
+
+        Ok(())
+    }
+
+    fn handle_add_sample_tag(&mut self, sample: i64, tag: String) -> Result<(), AudioEngineError> {
+        match self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .add_tag_to_sample(sample, &tag)
+        {
+            Ok(()) => send_response!(self),
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    fn handle_remove_sample_tag(
+        &mut self,
+        sample: i64,
+        tag: String,
+    ) -> Result<(), AudioEngineError> {
+        match self.samplesdb.lock().unwrap().remove_tag(sample, &tag) {
+            Ok(()) => send_response!(self),
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    /// Normalization gain of the sample a sound plays, generated sounds aren't normalized
+    pub(in crate::audio_engine::engine) fn sample_gain(
+        &mut self,
+        sound_type: SoundType,
+        file: &str,
+    ) -> f32 {
+        if sound_type != SoundType::File {
+            return 1.0;
+        }
+
+        let samplesdb = self.samplesdb.lock().unwrap();
+        samplesdb
+            .find_sample(file)
+            .map_or(1.0, |id| samplesdb.sample_gain(id))
+    }
+
+    /// Sounds pick up changed gains of their samples right away
+    fn refresh_sample_gains(&mut self) {
+        let sounds: Vec<(String, SoundType, String)> = self
+            .sound_handles
+            .values()
+            .chain(self.next_sound_handles.iter().flat_map(HashMap::values))
+            .filter(|handle| !handle.placeholder)
+            .map(|handle| {
+                (
+                    handle.sound.name.clone(),
+                    handle.sound.sound_type,
+                    handle.sound.file.clone(),
+                )
+            })
+            .collect();
+
+        for (name, sound_type, file) in sounds {
+            let gain = self.sample_gain(sound_type, &file);
+
+            for handles in Some(&mut self.sound_handles)
+                .into_iter()
+                .chain(self.next_sound_handles.as_mut())
+            {
+                if let Some(handle) = handles.get_mut(&name) {
+                    if handle.sound.file == file {
+                        handle.sample_gain = gain;
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_refresh_sample_gains(&mut self) -> Result<(), AudioEngineError> {
+        self.refresh_sample_gains();
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_reset_sample_gain(&mut self, sample: i64) -> Result<(), AudioEngineError> {
+        let result = self.samplesdb.lock().unwrap().set_sample_gain(sample, 1.0);
+        match result {
+            Ok(()) => {
+                self.refresh_sample_gains();
+                send_response!(self);
+            }
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    fn handle_get_sample_path(&mut self, sample: i64) -> Result<(), AudioEngineError> {
+        if self.samplesdb.lock().unwrap().sample(sample).is_none() {
+            send_error!(self, &SamplesDBError::UnknownSample(sample));
+            return Ok(());
+        }
+
+        let path = self.samplesdb.lock().unwrap().full_path_of_sample(sample);
+        send_response!(self, Response::SamplePath { path });
+
+        Ok(())
+    }
+
+    fn handle_get_waveform(&mut self, sample: i64) -> Result<(), AudioEngineError> {
+        match self.samplesdb.lock().unwrap().waveform(sample) {
+            Ok(waveform) => send_response!(self, Response::Waveform { waveform }),
+            Err(e) => send_error!(self, &e),
+        }
+
+        Ok(())
+    }
+
+    fn handle_search_library(&mut self, query: String) -> Result<(), AudioEngineError> {
+        let samples = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .search(&query)
+            .into_iter()
+            .map(library_entry)
+            .collect();
+
+        send_response!(self, Response::SoundLibrary { samples });
+
+        Ok(())
+    }
+
+    fn handle_volume(&mut self, value: f32) -> Result<(), AudioEngineError> {
+        self.master_volume = value;
+        self.apply_output_volume();
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_set_eq(&mut self, eq: Equalizer) -> Result<(), AudioEngineError> {
+        let bands = [eq.low, eq.mid, eq.high];
+        if bands.iter().any(|gain| *gain < 0.0 || *gain > 1.0) {
+            send_error!(
+                self,
+                ApiError::new(
+                    ErrorCode::InvalidValue,
+                    "EQ gains must be between 0.0 and 1.0!"
+                )
+            );
+            return Ok(());
+        }
+
+        if let Err(e) = self.backend.set_eq(eq) {
+            send_error!(self, ApiError::from(&e));
+            return Ok(());
+        }
+
+        self.eq = eq;
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn group_volume(&self, sound: &Sound) -> f32 {
+        sound
+            .group
+            .as_ref()
+            .and_then(|group| self.group_volumes.get(group))
+            .cloned()
+            .unwrap_or(1.0)
+    }
+
+    fn handle_set_group_volume(
+        &mut self,
+        group: String,
+        value: f32,
+    ) -> Result<(), AudioEngineError> {
+        if value < 0.0 {
+            send_error!(
+                self,
+                ApiError::new(ErrorCode::InvalidValue, "Volume must not be negative!")
+            );
+            return Ok(());
+        }
+
+        // Sounds of the theme that is about to fade in are part of the group as well
+        let handles = self.sound_handles.values_mut().chain(
+            self.next_sound_handles
+                .iter_mut()
+                .flat_map(|h| h.values_mut()),
+        );
+        for handle in handles {
+            if handle.sound.group.as_ref() == Some(&group) {
+                handle.group_volume = value;
+            }
+        }
+
+        info!("Volume of group '{}' set to {}", group, value);
+        self.group_volumes.insert(group, value);
+        send_response!(self);
+
+        Ok(())
+    }
+
+    fn handle_get_driver_list(&mut self) -> Result<(), AudioEngineError> {
+        let drivers = self
+            .backend
+            .get_output_devices()
+            .into_iter()
+            .enumerate()
+            .collect();
+
+        send_response!(self, Response::DriverList { drivers });
+
+        Ok(())
+    }
+
+    fn handle_get_driver(&mut self) -> Result<(), AudioEngineError> {
+        let id = self.backend.get_current_output_device();
+        let sample_rates = self.backend.get_sample_rates();
+        send_response!(self, Response::Driver { id, sample_rates });
+
+        Ok(())
+    }
+
+    fn handle_get_health(&mut self) -> Result<(), AudioEngineError> {
+        let database_error = self
+            .samplesdb
+            .lock()
+            .unwrap()
+            .ping()
+            .err()
+            .map(|e| e.to_string());
+        if let Some(ref error) = database_error {
+            error!("Samples database can't be reached: {}", error);
+        }
+
+        let response = Response::Health {
+            device_alive: self.backend.is_device_alive(),
+            database_error,
+            source_pool: self.backend.get_source_pool(),
+        };
+        send_response!(self, response);
+
+        Ok(())
+    }
+
+    fn handle_get_engine_config(&mut self) -> Result<(), AudioEngineError> {
+        let device = self.backend.get_current_output_device();
+        let sample_rates = self.backend.get_sample_rates();
+        let response = Response::EngineConfig {
+            capabilities: self.backend.get_capabilities(),
+            device: self
+                .backend
+                .get_output_devices()
+                .get(device as usize)
+                .cloned(),
+            output_rate: sample_rates.output_rate,
+            resampler: sample_rates.resampler,
+            source_pool: self.backend.get_source_pool(),
+            pause_fade: self.pause_fade,
+            theme_fade: THEME_FADE_MS,
+            default_crossfade: DEFAULT_CROSSFADE_MS,
+            max_previews: self.max_previews,
+            queue_previews: self.queue_previews,
+            ducking: self.ducker.is_some(),
+            command_log: self.command_log.is_some(),
+            tick: self.tick,
+        };
+        send_response!(self, response);
+
+        Ok(())
+    }
+
+    fn handle_get_events(&mut self, since: u64) -> Result<(), AudioEngineError> {
+        let events = self.events.since(since);
+        send_response!(self, Response::Events { events });
+
+        Ok(())
+    }
+
+    fn handle_set_driver(&mut self, id: i32) -> Result<(), AudioEngineError> {
+        if id < 0 || id as usize >= self.backend.get_output_devices().len() {
+            send_error!(self, &AudioBackendError::UnknownDevice(id));
+            return Ok(());
+        }
+
+        // Answered once the switch is done
+        self.switch_device(id);
+
+        Ok(())
+    }
+
+    pub(in crate::audio_engine::engine) fn run_message_queue(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<bool, AudioEngineError> {
+        let request = match self.receiver.recv_timeout(timeout) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout) => return Ok(false),
+            // Nobody is left to send a request, not even the one to quit
+            Err(RecvTimeoutError::Disconnected) => {
+                info!("All senders are gone, stopping");
+                return Ok(true);
+            }
+        };
+
+        if let Some(ref mut log) = self.command_log {
+            log.record(&request.command);
+        }
+
+        // Responses of the handlers go to whoever sent this request
+        self.reply = Some(request.reply);
+        let result = self.handle_command(request.command);
+
+        // A failing command must neither leave its client waiting nor stop the engine
+        let quit = match result {
+            Ok(quit) => quit,
+            Err(e) => {
+                error!("Failed to handle command: {}", e);
+                send_error!(self, &e);
+                false
+            }
+        };
+        self.reply.take();
+
+        Ok(quit)
+    }
+
+    /// Runs a command, responding to `self.reply`. Returns whether the engine should quit.
+    pub(in crate::audio_engine::engine) fn handle_command(
+        &mut self,
+        command: Command,
+    ) -> Result<bool, AudioEngineError> {
+        // Scoped commands count once they are let through
+        match command {
+            Command::Scoped { .. } => {}
+            ref command if command.is_replayable() => self.version.bump(),
+            _ => {}
+        }
+
+        match command {
+            Command::Quit => return Ok(true),
+            Command::Pause => self.handle_pause()?,
+            Command::Play => self.handle_play()?,
+            Command::PreviewSound { sound, raw } => self.handle_preview_sound(sound, raw)?,
+            Command::LoadTheme {
+                theme,
+                on_missing,
+                strict,
+            } => self.handle_load_theme(theme, on_missing, strict)?,
+            Command::SaveTheme { name, theme } => self.handle_save_theme(name, theme)?,
+            Command::GetStoredThemes => self.handle_get_stored_themes()?,
+            Command::GetStoredTheme { name } => self.handle_get_stored_theme(name)?,
+            Command::GetTheme => self.handle_get_theme()?,
+            Command::LoadStoredTheme {
+                name,
+                on_missing,
+                strict,
+            } => self.handle_load_stored_theme(name, on_missing, strict)?,
+            Command::PreloadTheme { name } => self.handle_preload_theme(name)?,
+            Command::Trigger { sound } => self.handle_trigger(sound, TriggerAction::Toggle)?,
+            Command::TriggerOn { sound } => self.handle_trigger(sound, TriggerAction::On)?,
+            Command::TriggerOff { sound } => self.handle_trigger(sound, TriggerAction::Off)?,
+            Command::TriggerOnce { sound } => self.handle_trigger(sound, TriggerAction::Once)?,
+            Command::SetScene { name } => self.handle_set_scene(name)?,
+            Command::FireTrigger { trigger, action } => {
+                self.handle_fire_trigger(trigger, action)?
+            }
+            Command::GetSoundStatus { sound } => self.handle_get_sound_status(sound)?,
+            Command::SetLockout { duration, queue } => self.handle_set_lockout(duration, queue)?,
+            Command::SetLimiter { enabled, ceiling } => {
+                self.handle_set_limiter(enabled, ceiling)?
+            }
+            Command::GetStatus => self.handle_get_status()?,
+            Command::GetOverlay => self.handle_get_overlay()?,
+            Command::GetSoundLibrary => self.handle_get_sound_library()?,
+            Command::GetLibraryReport => self.handle_get_library_report()?,
+            Command::StartAudition {
+                tag,
+                query,
+                seconds,
+                normalized,
+            } => self.handle_start_audition(tag, query, seconds, normalized)?,
+            Command::NextAudition => self.handle_next_audition()?,
+            Command::StopAudition => self.handle_stop_audition()?,
+            Command::GetTags => self.handle_get_tags()?,
+            Command::GetSamplesByTag { tag } => self.handle_get_samples_by_tag(tag)?,
+            Command::AddSampleTag { sample, tag } => self.handle_add_sample_tag(sample, tag)?,
+            Command::RemoveSampleTag { sample, tag } => {
+                self.handle_remove_sample_tag(sample, tag)?
+            }
+            Command::RefreshSampleGains => self.handle_refresh_sample_gains()?,
+            Command::ResetSampleGain { sample } => self.handle_reset_sample_gain(sample)?,
+            Command::SkipTrack { playlist, back } => self.handle_skip_track(playlist, back)?,
+            Command::SeekTrack { playlist, position } => {
+                self.handle_seek_track(playlist, position)?
+            }
+            Command::SearchLibrary { query } => self.handle_search_library(query)?,
+            Command::ReplaceSoundFile {
+                sound,
+                file,
+                crossfade,
+                strict,
+                changes,
+            } => self.handle_replace_sound_file(sound, file, crossfade, strict, changes)?,
+            Command::UpdateSound { sound, changes } => self.handle_update_sound(sound, changes)?,
+            Command::SetListenerPosition { position } => {
+                self.handle_set_listener_position(position)?
+            }
+            Command::SetListenerOrientation { at, up } => {
+                self.handle_set_listener_orientation(at, up)?
+            }
+            Command::SetSoundPosition { sound, position } => {
+                self.handle_set_sound_position(sound, position)?
+            }
+            Command::ArmSound { sound, armed } => self.handle_arm_sound(sound, armed)?,
+            Command::SetSoundMuted { sound, flag } => self.handle_set_sound_muted(sound, flag)?,
+            Command::SoloSound { sound } => self.handle_solo_sound(sound)?,
+            Command::ClearSolo => self.handle_clear_solo()?,
+            Command::SetSoundCounters {
+                sound,
+                loops,
+                repeats,
+            } => self.handle_set_sound_counters(sound, loops, repeats)?,
+            Command::RunMacro { name } => self.handle_run_macro(name)?,
+            Command::SetSoundTrim { sound, gain } => self.handle_set_sound_trim(sound, gain)?,
+            Command::PlayTestTone { channel } => self.handle_play_test_tone(channel)?,
+            Command::PlayBeep {
+                waveform,
+                frequency,
+                duration,
+                volume,
+            } => self.handle_play_beep(waveform, frequency, duration, volume)?,
+            Command::GetSettings => self.handle_get_settings()?,
+            Command::ReloadSettings => self.handle_reload_settings()?,
+            Command::UpdateSettings { settings } => self.handle_update_settings(settings)?,
+            Command::GetClientSetting { client, key } => {
+                self.handle_get_client_setting(client, key)?
+            }
+            Command::SetClientSetting { client, key, value } => {
+                self.handle_set_client_setting(client, key, value)?
+            }
+            Command::SetTimeScale { value, pitch } => self.handle_set_time_scale(value, pitch)?,
+            Command::StopSoundAtLoopEnd { sound } => self.handle_stop_sound_at_loop_end(sound)?,
+            Command::StopSound { sound } => self.handle_stop_sound(sound)?,
+            Command::SetVolume { value } => self.handle_volume(value)?,
+            Command::SetEq { low, mid, high } => {
+                self.handle_set_eq(Equalizer { low, mid, high })?
+            }
+            Command::SetGroupVolume { group, value } => {
+                self.handle_set_group_volume(group, value)?
+            }
+            Command::GetDriverList => self.handle_get_driver_list()?,
+            Command::GetDriver => self.handle_get_driver()?,
+            Command::GetEvents { since } => self.handle_get_events(since)?,
+            Command::GetSamplePath { sample } => self.handle_get_sample_path(sample)?,
+            Command::GetWaveform { sample } => self.handle_get_waveform(sample)?,
+            Command::GetHealth => self.handle_get_health()?,
+            Command::GetEngineConfig => self.handle_get_engine_config()?,
+            Command::SetDriver { id } => self.handle_set_driver(id)?,
+            Command::GetSoundPosition { sound } => self.handle_get_sound_position(sound)?,
+            Command::SeekSound { sound, position } => self.handle_seek_sound(sound, position)?,
+            Command::DelayCommand { delay, command } => {
+                self.handle_delay_command(delay, *command)?
+            }
+            Command::CancelDelayedCommand { id } => self.handle_cancel_delayed_command(id)?,
+            Command::GetDelayedCommands => self.handle_get_delayed_commands()?,
+            Command::Scoped { themes, command } => {
+                // Checked right before running, no other theme can be loaded in between
+                let allowed = self
+                    .theme
+                    .as_ref()
+                    .map_or(false, |theme| themes.contains(theme));
+
+                if allowed {
+                    return self.handle_command(*command);
+                }
+                send_error!(
+                    self,
+                    ApiError::new(
+                        ErrorCode::Forbidden,
+                        "This token may not control this theme!"
+                    )
+                );
+            }
+        }
+
         Ok(false)
     }
 }
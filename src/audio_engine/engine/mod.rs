@@ -1,19 +1,117 @@
+mod audition;
+pub mod command_log;
+mod delayed;
+mod device;
+mod downloads;
+pub mod ducking;
 pub mod error;
+mod events;
+pub mod heartbeat;
+mod limiter;
+mod lockout;
+mod macros;
 mod messaging;
-
+mod playlist;
+mod preload;
+mod scenes;
+mod scheduler;
+mod sources;
+pub mod state_version;
+
+use crossbeam_channel::{Receiver, Sender};
 use rand::distributions::range::SampleRange;
 use rand::{thread_rng, Rng};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::sync::mpsc::{Receiver, Sender};
-use std::time::{Duration, SystemTime};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use crate::audio_engine::backends::base::{AudioBackend, AudioEntityData};
+use crate::audio_engine::backends::base::{AudioBackend, AudioEntityData, DecodedFiles, Equalizer};
 use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::clock::Clock;
+use crate::audio_engine::engine::audition::Audition;
+use crate::audio_engine::engine::command_log::CommandLog;
+use crate::audio_engine::engine::delayed::DelayedCommand;
+use crate::audio_engine::engine::device::{DeviceMonitor, DeviceSwitch};
+use crate::audio_engine::engine::downloads::PendingDownload;
+use crate::audio_engine::engine::ducking::{Ducker, DuckingConfig};
 use crate::audio_engine::engine::error::AudioEngineError;
-use crate::audio_engine::messages::{Command, Response};
-use crate::samplesdb::{Sample, SamplesDB, Tag};
-use crate::theme::Sound;
+use crate::audio_engine::engine::events::{EventLog, SourcePoolMonitor};
+use crate::audio_engine::engine::heartbeat::Heartbeat;
+use crate::audio_engine::engine::limiter::Limiter;
+use crate::audio_engine::engine::lockout::Lockout;
+use crate::audio_engine::engine::macros::RunningMacro;
+use crate::audio_engine::engine::playlist::PlaylistPlayer;
+use crate::audio_engine::engine::preload::ThemePreload;
+use crate::audio_engine::engine::scheduler::Scheduler;
+use crate::audio_engine::engine::state_version::StateVersion;
+use crate::audio_engine::loader::base::SampleFormat;
+use crate::audio_engine::loader::check::WarningCache;
+use crate::audio_engine::messages::{Request, Response, SoundChanges, SoundStatus, TriggerAction};
+use crate::audio_engine::resampler::Resampler;
+use crate::samplesdb::{Sample, SharedSamplesDB, Tag};
+use crate::settings::Settings;
+use crate::theme::{MacroAction, PauseBehavior, PositionMode, Sound};
+
+// Default duration of the crossfade when a sound's file is replaced while it is playing
+pub const DEFAULT_CROSSFADE_MS: u64 = 2000;
+
+// Duration of each half of the fade when switching themes
+const THEME_FADE_MS: u64 = 500;
+
+// Gain of sounds whose group is ducked by another sound that is playing
+const SIDECHAIN_GAIN: f32 = 0.3;
+// Time it takes to duck a group once a sound ducking it starts, and to recover after it stopped
+const SIDECHAIN_ATTACK_MS: f32 = 200.0;
+const SIDECHAIN_RELEASE_MS: f32 = 1500.0;
+
+// Time it takes sounds to fade in or out when switching scenes
+pub(in crate::audio_engine::engine) const SCENE_FADE_MS: f32 = 2000.0;
+
+// Ticks the engine fell behind on by more than this, e.g. while loading a large theme,
+// are dropped instead of being run all at once
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+// OpenAL doesn't accept a pitch of 0, slowing down the time only lowers the pitch this far
+const MIN_PITCH_SCALE: f32 = 0.1;
+
+/// Moves a sidechain gain towards the ducked or the full level
+pub(in crate::audio_engine::engine) fn sidechain_gain(gain: f32, ducked: bool, delta: u64) -> f32 {
+    if ducked {
+        let step = (1.0 - SIDECHAIN_GAIN) * delta as f32 / SIDECHAIN_ATTACK_MS;
+        (gain - step).max(SIDECHAIN_GAIN)
+    } else {
+        let step = (1.0 - SIDECHAIN_GAIN) * delta as f32 / SIDECHAIN_RELEASE_MS;
+        (gain + step).min(1.0)
+    }
+}
+
+pub struct EngineConfig {
+    pub ducking: Option<DuckingConfig>,
+    /// Milliseconds to fade out/in when pausing and resuming, 0 cuts immediately
+    pub pause_fade: u64,
+    /// Previews that may play at once, 0 for no limit
+    pub max_previews: usize,
+    /// Queue previews beyond the limit instead of rejecting them
+    pub queue_previews: bool,
+    /// File to record the commands to, for replaying the session later on
+    pub command_log: Option<PathBuf>,
+    /// Rate to play at instead of the one the backend picks, buffers at other rates are
+    /// resampled to it
+    pub output_rate: Option<i32>,
+    /// How buffers are converted to the output rate
+    pub resampler: Resampler,
+    /// Share (0.0 - 1.0) of the backend's sources in use above which a warning is sent
+    pub source_pool_warning: f32,
+    /// Sources the backend's pool may grow to
+    pub max_sources: usize,
+    /// Milliseconds the engine moves everything forward by at a time
+    pub tick: u64,
+    /// Beaten by the audio thread on every pass through its loop
+    pub heartbeat: Heartbeat,
+    /// Increased by the engine whenever its state changes
+    pub version: StateVersion,
+}
 
 fn get_random_value<T: PartialOrd + SampleRange + fmt::Display>(val: (T, T)) -> T {
     if val.0 == val.1 {
@@ -24,20 +122,90 @@ fn get_random_value<T: PartialOrd + SampleRange + fmt::Display>(val: (T, T)) ->
     }
 }
 
-pub struct AudioController<'a, T: AudioBackend> {
+pub struct AudioController<T: AudioBackend> {
     backend: T,
-    receiver: Receiver<Command>,
-    sender: Sender<Response>,
+    clock: Clock,
+    receiver: Receiver<Request>,
+    /// Where responses to the request currently being handled go
+    reply: Option<Sender<Response>>,
     sound_handles: HashMap<String, AudioEntity<T::EntityData>>,
     next_sound_handles: Option<HashMap<String, AudioEntity<T::EntityData>>>,
     fade_status: bool,
-    fade_volume: f32,
+    /// Gain (0.0 - 1.0) of the global fade, applied on top of the master volume
+    fade_gain: f32,
     fade_direction: FadeDirection,
+    fade_duration: u64,
+    fade_action: Option<FadeAction>,
+    load_state: LoadState,
     master_volume: f32,
+    eq: Equalizer,
     playing: bool,
     theme_loaded: bool,
     theme: Option<String>,
-    samplesdb: SamplesDB<'a>,
+    /// The loaded theme the way it was sent, what changed since is put on top when
+    /// clients fetch it again
+    theme_json: Option<serde_json::Value>,
+    samplesdb: SharedSamplesDB,
+    ducker: Option<Ducker>,
+    limiter: Limiter,
+    pause_fade: u64,
+    macros: HashMap<String, Vec<MacroAction>>,
+    running_macros: Vec<RunningMacro>,
+    scheduler: Scheduler,
+    playlists: Vec<PlaylistPlayer<T::EntityData>>,
+    /// Playlists of the theme that is about to be switched to
+    next_playlists: Option<Vec<PlaylistPlayer<T::EntityData>>>,
+    /// Test tones and beeps, with the volume they play at
+    one_shots: Vec<(T::EntityData, f32)>,
+    time_scale: f32,
+    /// Scaled milliseconds left over from the last update, so slowed down time doesn't
+    /// lose the fractions of each tick
+    scaled_remainder: f32,
+    pitch_scale: f32,
+    tick: u64,
+    /// Volume of each mixer group, groups not in here play at 1.0
+    group_volumes: HashMap<String, f32>,
+    settings: Settings,
+    max_previews: usize,
+    queue_previews: bool,
+    /// Previews waiting for a running one to finish, with whether they are raw
+    preview_queue: VecDeque<(String, bool)>,
+    audition: Option<Audition<T::EntityData>>,
+    command_log: Option<CommandLog>,
+    delayed_commands: Vec<DelayedCommand>,
+    next_delayed_id: u64,
+    pending_downloads: Vec<PendingDownload>,
+    events: EventLog,
+    pool_monitor: SourcePoolMonitor,
+    /// Sounds waiting for a source, in the order they found none
+    source_queue: Vec<String>,
+    device_monitor: DeviceMonitor,
+    device_switch: Option<DeviceSwitch>,
+    heartbeat: Heartbeat,
+    version: StateVersion,
+    lockout: Option<Lockout>,
+    /// Triggers queued during the last lockout that haven't been passed on yet
+    released_triggers: VecDeque<(String, TriggerAction)>,
+    preload: Option<ThemePreload<T::EntityData>>,
+    warning_cache: WarningCache,
+    /// Scenes of the loaded theme and the sounds in each of them
+    scenes: HashMap<String, Vec<String>>,
+    scene: Option<String>,
+    /// Sound all others are silenced for while mixing
+    solo: Option<String>,
+    /// Names of the custom reverbs of the loaded theme
+    reverbs: Vec<String>,
+}
+
+/// Progress of switching to a newly loaded theme
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadState {
+    Idle,
+    /// The new theme is loaded and waits for another fade (e.g. pausing) to finish
+    Loading,
+    FadingOut,
+    FadingIn,
 }
 
 enum FadeDirection {
@@ -45,96 +213,476 @@ enum FadeDirection {
     In,
 }
 
-impl<'a, T: AudioBackend> AudioController<'a, T> {
+/// What to do once the master volume has been faded out completely
+enum FadeAction {
+    SwitchTheme,
+    Pause,
+}
+
+impl<T: AudioBackend> AudioController<T> {
     pub fn new(
-        receiver: Receiver<Command>,
-        sender: Sender<Response>,
-        samplesdb: SamplesDB<'a>,
+        receiver: Receiver<Request>,
+        samplesdb: SharedSamplesDB,
+        config: EngineConfig,
     ) -> Result<Self, AudioEngineError> {
-        let backend = T::init();
+        let mut backend = T::init(config.output_rate, config.resampler, config.max_sources);
+        let clock = Clock::new();
+        backend.set_clock(clock.clone());
+
+        let ducker = match config.ducking {
+            Some(config) => match backend.start_input_monitor() {
+                Ok(()) => Some(Ducker::new(config)),
+                Err(e) => {
+                    error!("Failed to start input monitor, ducking disabled: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let settings = samplesdb.lock().unwrap().settings().unwrap_or_else(|e| {
+            error!("Failed to read settings, using defaults: {}", e);
+            Settings::default()
+        });
+
+        if let Some(volume) = settings.volume {
+            backend.set_volume(volume);
+        }
+
+        let eq = match settings.eq {
+            Some(eq) => match backend.set_eq(eq) {
+                Ok(()) => eq,
+                Err(e) => {
+                    warn!("Failed to apply the master EQ, leaving it flat: {}", e);
+                    Equalizer::default()
+                }
+            },
+            None => Equalizer::default(),
+        };
+
+        let command_log = config.command_log.as_ref().and_then(|path| {
+            CommandLog::create(path)
+                .map_err(|e| {
+                    error!(
+                        "Failed to create command log '{}', not recording: {}",
+                        path.to_string_lossy(),
+                        e
+                    )
+                })
+                .ok()
+        });
 
         Ok(AudioController {
             backend,
+            clock,
             receiver,
-            sender,
+            reply: None,
             sound_handles: HashMap::new(),
             next_sound_handles: None,
             fade_status: false,
             fade_direction: FadeDirection::Out,
-            fade_volume: 0.0,
-            master_volume: 1.0,
+            fade_duration: THEME_FADE_MS,
+            fade_action: None,
+            load_state: LoadState::Idle,
+            fade_gain: 1.0,
+            master_volume: settings.volume.unwrap_or(1.0),
+            eq,
             playing: false,
             theme_loaded: false,
             theme: None,
+            theme_json: None,
             samplesdb,
+            ducker,
+            limiter: Limiter::new(settings.limiter.unwrap_or(false)),
+            pause_fade: config.pause_fade,
+            macros: HashMap::new(),
+            running_macros: Vec::new(),
+            scheduler: Scheduler::default(),
+            playlists: Vec::new(),
+            next_playlists: None,
+            one_shots: Vec::new(),
+            time_scale: 1.0,
+            scaled_remainder: 0.0,
+            pitch_scale: 1.0,
+            tick: config.tick.max(1),
+            group_volumes: HashMap::new(),
+            settings,
+            max_previews: config.max_previews,
+            queue_previews: config.queue_previews,
+            preview_queue: VecDeque::new(),
+            audition: None,
+            command_log,
+            delayed_commands: Vec::new(),
+            next_delayed_id: 1,
+            pending_downloads: Vec::new(),
+            events: EventLog::default(),
+            pool_monitor: SourcePoolMonitor::new(config.source_pool_warning),
+            source_queue: Vec::new(),
+            device_monitor: DeviceMonitor::default(),
+            device_switch: None,
+            heartbeat: config.heartbeat,
+            version: config.version,
+            lockout: None,
+            released_triggers: VecDeque::new(),
+            preload: None,
+            warning_cache: WarningCache::default(),
+            scenes: HashMap::new(),
+            scene: None,
+            solo: None,
+            reverbs: Vec::new(),
         })
     }
 
+    fn start_fade(&mut self, direction: FadeDirection, duration: u64, action: Option<FadeAction>) {
+        self.fade_gain = match direction {
+            FadeDirection::Out => 1.0,
+            FadeDirection::In => 0.0,
+        };
+
+        self.fade_status = true;
+        self.fade_direction = direction;
+        self.fade_duration = duration;
+        self.fade_action = action;
+    }
+
+    /// Files of everything loaded so far, to decode them for another output device
+    fn source_files(&self) -> HashSet<(PathBuf, bool)> {
+        let handles = self.sound_handles.values().chain(
+            self.next_sound_handles
+                .iter()
+                .flat_map(|handles| handles.values()),
+        );
+
+        handles
+            .flat_map(|handle| handle.objects())
+            .chain(self.playlists.iter().flat_map(|player| player.objects()))
+            .chain(self.one_shots.iter().map(|(object, _)| object))
+            .chain(self.audition.iter().flat_map(|audition| audition.objects()))
+            .chain(self.preload.iter().flat_map(|preload| preload.objects()))
+            .filter_map(|object| object.source_file())
+            .collect()
+    }
+
+    /// Moves everything loaded so far to the output device the backend switched to, taking
+    /// the files from `decoded` where they are. Objects that fail to move are left on the
+    /// old device.
+    fn reopen_objects(&mut self, decoded: &mut DecodedFiles) {
+        let backend = &mut self.backend;
+        let handles = self
+            .sound_handles
+            .iter_mut()
+            .chain(self.next_sound_handles.iter_mut().flatten());
+
+        for (name, handle) in handles {
+            let paused = !self.playing && !handle.is_preview;
+            if let Err(e) = handle.reopen(backend, paused, decoded) {
+                warn!("Failed to move sound '{}' to the new device: {}", name, e);
+            }
+        }
+
+        for player in &mut self.playlists {
+            if let Err(e) = player.reopen(backend, !self.playing, decoded) {
+                warn!(
+                    "Failed to move playlist '{}' to the new device: {}",
+                    player.playlist.name, e
+                );
+            }
+        }
+
+        for (object, _) in &mut self.one_shots {
+            if let Err(e) = object.reopen(backend, true, decoded) {
+                warn!("Failed to move a beep to the new device: {}", e);
+            }
+        }
+
+        if let Some(ref mut audition) = self.audition {
+            if let Err(e) = audition.reopen(backend, decoded) {
+                warn!("Failed to move the audition to the new device: {}", e);
+            }
+        }
+
+        if let Some(ref mut preload) = self.preload {
+            preload.reopen(backend, decoded);
+        }
+    }
+
+    fn finish_fade_out(&mut self) -> Result<(), AudioEngineError> {
+        match self.fade_action.take() {
+            Some(FadeAction::SwitchTheme) => {
+                for (_, mut handle) in self.sound_handles.drain() {
+                    handle.stop(&mut self.backend)?;
+                }
+                // Queued previews refer to sounds of the old theme
+                self.preview_queue.clear();
+
+                let mut handles = self.next_sound_handles.take().unwrap();
+                for (key, handle) in handles.drain() {
+                    self.sound_handles.insert(key, handle);
+                }
+                self.scheduler
+                    .set_sounds(self.sound_handles.values().map(|handle| &handle.sound));
+
+                for mut player in self.playlists.drain(..) {
+                    player.stop(&mut self.backend)?;
+                }
+                if let Some(playlists) = self.next_playlists.take() {
+                    self.playlists = playlists;
+                }
+
+                self.fade_direction = FadeDirection::In;
+                self.load_state = LoadState::FadingIn;
+            }
+            Some(FadeAction::Pause) => {
+                self.pause_handles();
+                self.playing = false;
+                info!("Paused!");
+
+                // Restore the volume right away, previews should still be audible
+                self.fade_status = false;
+                self.fade_gain = 1.0;
+                self.apply_output_volume();
+            }
+            None => self.fade_status = false,
+        }
+        self.version.bump();
+
+        Ok(())
+    }
+
+    fn pause_handles(&mut self) {
+        for handle in &mut self.sound_handles.values_mut() {
+            if handle.is_in_state(&AudioEntityState::Playing) {
+                handle.pause(&mut self.backend, true);
+            }
+        }
+
+        for player in &mut self.playlists {
+            player.pause(&mut self.backend, true);
+        }
+    }
+
+    fn running_previews(&self) -> usize {
+        self.sound_handles
+            .values()
+            .filter(|handle| handle.is_preview)
+            .count()
+    }
+
+    fn can_start_preview(&self) -> bool {
+        self.max_previews == 0 || self.running_previews() < self.max_previews
+    }
+
+    fn start_preview(&mut self, sound: &str, raw: bool) {
+        if let Some(handle) = self.sound_handles.get_mut(sound) {
+            handle.is_preview = true;
+            handle.raw_preview = raw;
+            handle.switch_state(AudioEntityState::Preview);
+            info!("Starting preview of sound '{}'!", sound);
+        }
+    }
+
+    /// Starts queued previews as far as the limit allows
+    fn update_preview_queue(&mut self) {
+        while !self.preview_queue.is_empty() && self.can_start_preview() {
+            let (sound, raw) = self.preview_queue.pop_front().unwrap();
+            self.start_preview(&sound, raw);
+        }
+    }
+
+    /// Marks every sound whose group is ducked by another playing sound
+    fn update_sidechain(&mut self) {
+        let ducking: Vec<(String, Vec<String>)> = self
+            .sound_handles
+            .values()
+            .filter(|h| !h.sound.ducks.is_empty() && h.is_in_state(&AudioEntityState::Playing))
+            .map(|h| (h.sound.name.clone(), h.sound.ducks.clone()))
+            .collect();
+
+        for handle in self.sound_handles.values_mut() {
+            handle.ducked = match handle.sound.group {
+                Some(ref group) => ducking
+                    .iter()
+                    .any(|(name, groups)| *name != handle.sound.name && groups.contains(group)),
+                None => false,
+            };
+        }
+
+        for player in &mut self.playlists {
+            player.ducked = match player.playlist.group {
+                Some(ref group) => ducking.iter().any(|(_, groups)| groups.contains(group)),
+                None => false,
+            };
+        }
+    }
+
+    /// The output volume is staged as master volume × global fade × limiter, so each of
+    /// them can change without overwriting the others
+    fn apply_output_volume(&mut self) {
+        let volume = self.master_volume * self.fade_gain * self.limiter.gain();
+        self.backend.set_volume(volume);
+    }
+
+    /// The time the engine has been moved forward by, see `Clock`
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Moves everything forward in fixed ticks, handling requests as they come in
+    /// between them
     pub fn run(&mut self) -> Result<(), AudioEngineError> {
         let mut quit = false;
 
-        let clock = SystemTime::now();
-        let mut last_update: u64 = clock.elapsed().unwrap().as_millis() as u64;
+        let tick = Duration::from_millis(self.tick);
+        let mut next_tick = Instant::now() + tick;
 
         while !quit {
-            quit = match self.run_message_queue() {
-                Ok(flag) => flag,
-                Err(e) => {
-                    error!("Error running message queue: {}", e);
-                    false
-                }
-            };
+            self.heartbeat.beat();
 
-            let time_elapsed = clock.elapsed().unwrap().as_millis() as u64 - last_update;
-
-            for handle in &mut self.sound_handles.values_mut() {
-                if handle.is_preview || self.playing && handle.sound.enabled {
-                    handle.update(&mut self.backend, time_elapsed)?;
-                }
-            }
-
-            // Handle global fade-in/fade-out
-            // This usually happens when a new theme is loaded while another one
-            // is already playing.
-            if self.next_sound_handles.is_some() || self.fade_status {
-                if !self.fade_status {
-                    self.fade_status = true;
-                    self.fade_direction = FadeDirection::Out;
-                    self.fade_volume = self.master_volume;
-                }
-
-                match self.fade_direction {
-                    FadeDirection::Out => {
-                        self.fade_volume -= 0.1;
-                        if self.fade_volume <= 0.0 {
-                            self.fade_direction = FadeDirection::In;
-                            self.fade_volume = 0.0;
-                            for (_, mut handle) in self.sound_handles.drain() {
-                                handle.stop(&mut self.backend)?;
-                            }
-                            let mut handles = self.next_sound_handles.take().unwrap();
-                            for (key, handle) in handles.drain() {
-                                self.sound_handles.insert(key, handle);
-                            }
-                        }
+            let now = Instant::now();
+            if now < next_tick {
+                quit = self.handle_messages(next_tick - now);
+                continue;
+            }
+
+            self.update(self.tick)?;
+            next_tick += tick;
+
+            if now > next_tick + MAX_LAG {
+                warn!(
+                    "Engine fell behind by {} ms, skipping ahead",
+                    (now - next_tick).as_millis()
+                );
+                next_tick = now + tick;
+            }
+        }
+
+        info!("AudioEngine stopped");
+
+        Ok(())
+    }
+
+    /// Handles the next request, waiting up to `timeout` for one. Returns whether the
+    /// engine was asked to quit.
+    pub fn handle_messages(&mut self, timeout: Duration) -> bool {
+        match self.run_message_queue(timeout) {
+            Ok(flag) => flag,
+            Err(e) => {
+                error!("Error running message queue: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Moves all sounds, macros and fades forward by the given milliseconds
+    pub fn update(&mut self, real_time_elapsed: u64) -> Result<(), AudioEngineError> {
+        self.clock.advance(real_time_elapsed);
+
+        // All countdowns and fades run on the scaled time
+        let scaled = real_time_elapsed as f32 * self.time_scale + self.scaled_remainder;
+        let time_elapsed = scaled as u64;
+        self.scaled_remainder = scaled - time_elapsed as f32;
+
+        // Everything holds still until there is a device to play on again
+        if !self.update_device(real_time_elapsed) {
+            return Ok(());
+        }
+
+        self.update_sidechain();
+        self.update_source_queue();
+
+        for handle in &mut self.sound_handles.values_mut() {
+            if handle.is_waiting_for_source() {
+                continue;
+            }
+
+            if handle.is_preview || self.playing && handle.sound.enabled {
+                handle.update(&mut self.backend, time_elapsed)?;
+            } else if !self.playing && handle.sound.enabled {
+                handle.update_paused(time_elapsed);
+            }
+        }
+
+        self.update_limiter(real_time_elapsed);
+        self.update_scenes();
+
+        if self.playing {
+            self.update_macros(time_elapsed);
+            self.update_schedules(time_elapsed);
+            self.update_playlists(time_elapsed);
+        }
+
+        self.update_preview_queue();
+        self.update_audition(real_time_elapsed);
+        self.update_delayed_commands();
+        self.update_downloads();
+        self.update_device_switch();
+        self.update_lockout(real_time_elapsed);
+        self.update_preload();
+        self.update_source_pool();
+
+        // Free the sources of finished test tones and beeps
+        let mut i = 0;
+        while i < self.one_shots.len() {
+            if self.one_shots[i].0.is_playing() {
+                i += 1;
+            } else {
+                self.one_shots.remove(i).0.stop(&mut self.backend)?;
+            }
+        }
+
+        // Lower the ambience while someone is talking into the microphone
+        if let Some(ref mut ducker) = self.ducker {
+            let level = self.backend.get_input_level();
+            ducker.update(level, real_time_elapsed);
+
+            let gain = ducker.gain();
+            for handle in self.sound_handles.values_mut() {
+                handle.talk_gain = if handle.is_ambience() { gain } else { 1.0 };
+            }
+        }
+
+        // Handle global fade-in/fade-out
+        // This happens when a new theme is loaded while another one
+        // is already playing and when pausing or resuming.
+        if self.load_state == LoadState::Loading && !self.fade_status {
+            self.start_fade(
+                FadeDirection::Out,
+                THEME_FADE_MS,
+                Some(FadeAction::SwitchTheme),
+            );
+            self.load_state = LoadState::FadingOut;
+            self.version.bump();
+        }
+
+        if self.fade_status {
+            let step = time_elapsed as f32 / self.fade_duration.max(1) as f32;
+
+            match self.fade_direction {
+                FadeDirection::Out => {
+                    self.fade_gain -= step;
+                    if self.fade_gain <= 0.0 {
+                        self.fade_gain = 0.0;
+                        self.finish_fade_out()?;
                     }
-                    FadeDirection::In => {
-                        self.fade_volume += 0.1;
+                }
+                FadeDirection::In => {
+                    self.fade_gain += step;
 
-                        if self.fade_volume >= self.master_volume {
-                            self.fade_status = false;
+                    if self.fade_gain >= 1.0 {
+                        self.fade_gain = 1.0;
+                        self.fade_status = false;
+
+                        if self.load_state == LoadState::FadingIn {
+                            self.load_state = LoadState::Idle;
+                            self.version.bump();
                         }
                     }
                 }
-
-                self.backend.set_volume(self.fade_volume);
             }
 
-            last_update = clock.elapsed().unwrap().as_millis() as u64;
+            self.apply_output_volume();
         }
 
-        info!("AudioEngine stopped");
-
         Ok(())
     }
 }
@@ -168,15 +716,86 @@ pub struct AudioEntity<O: AudioEntityData> {
     pub parameters: AudioEntityParameters,
     pub is_triggered: bool,
     pub is_preview: bool,
+    pub crossfade: Option<AudioEntityCrossfade<O>>,
+    /// Venue specific gain applied on top of the theme's volume
+    pub trim: f32,
+    /// Applied on top of the sound's pitch while the time is scaled
+    pub pitch_scale: f32,
+    /// Volume of the sound's mixer group
+    pub group_volume: f32,
+    /// Another sound that ducks the group of this one is playing
+    pub ducked: bool,
+    /// Envelope following `ducked`, applied on top of the group volume
+    pub sidechain_gain: f32,
+    /// Gain of the ambience while someone is talking into the microphone, see Ducker
+    pub talk_gain: f32,
+    /// Where the sound is placed, in the room or relative to the listener depending on its
+    /// position mode
+    pub position: Option<(f32, f32, f32)>,
+    /// The sound moves along its path, until it is placed somewhere else by hand
+    pub follow_path: bool,
+    /// Milliseconds the sound has moved along its path
+    pub path_elapsed: u64,
+    /// The sound holds on to what it needs to play while it waits for its trigger
+    pub armed: bool,
+    /// Times the sound started playing, not counting previews
+    pub plays: u32,
+    /// Gain normalizing the sample, applied on top of the volume
+    pub sample_gain: f32,
+    /// The object plays a generated placeholder because the sample couldn't be loaded
+    pub placeholder: bool,
+    /// Format the object was decoded from, if it could be read
+    pub format: Option<SampleFormat>,
+    /// The preview plays at full scale instead of the volume it would have in the mix
+    pub raw_preview: bool,
+    /// Started by a one-off trigger, waits for the next trigger again after a single play
+    pub play_once: bool,
+    /// Gain (0.0 - 1.0) of fading in or out when switching scenes
+    pub scene_gain: f32,
+    /// Fading out because the scene switched to one the sound isn't part of
+    pub leaving_scene: bool,
+    /// Disabled because the scene doesn't play it, it is enabled again by a scene that does
+    pub left_scene: bool,
+    /// Silenced by hand while mixing, the sound keeps running
+    pub muted: bool,
+    /// Silenced because another sound is soloed
+    pub solo_muted: bool,
+    /// Soloed, it is heard even if it was muted by hand
+    pub soloed: bool,
+    /// Found no free source to start on, it waits in the engine's queue for one
+    pub waiting_for_source: bool,
+    /// Volume the object was set to with the last update while playing
+    pub gain: f32,
+}
+
+/// The previous object of an entity whose file has been replaced while playing.
+/// It keeps playing and fades out while the new object fades in.
+pub struct AudioEntityCrossfade<O: AudioEntityData> {
+    pub object: O,
+    pub volume: f32,
+    pub elapsed: u64,
+    pub duration: u64,
+}
+
+impl<O: AudioEntityData> AudioEntityCrossfade<O> {
+    /// Gain of the incoming object, the outgoing one is faded with the inverse
+    pub fn gain(&self) -> f32 {
+        (self.elapsed as f32 / self.duration as f32).min(1.0)
+    }
 }
 
 pub struct AudioEntityParameters {
     pub state: AudioEntityState,
     pub next_play: Duration,
+    pub next_play_delay: (u64, u64),
     pub repeats: u32,
     pub loops: u32,
     pub fade_in: f32,
+    /// Position (0.0 - 1.0) the current run started at, the fade in starts from there
+    pub start_position: f32,
     pub max_volume: f32,
+    pub pitch: f32,
+    pub stop_at_loop_end: bool,
 }
 
 impl AudioEntityParameters {
@@ -184,22 +803,219 @@ impl AudioEntityParameters {
         Self {
             state: AudioEntityState::Virgin,
             next_play: Duration::new(0, 0),
+            next_play_delay: (0, 0),
             repeats: 0,
             loops: 1,
             fade_in: 0.0,
+            start_position: 0.0,
             max_volume: 1.0,
+            pitch: 1.0,
+            stop_at_loop_end: false,
         }
     }
 }
 
+/// Position on a closed path after moving along it for `elapsed` milliseconds
+fn path_position(path: &[(f32, f32, f32)], step: u64, elapsed: u64) -> (f32, f32, f32) {
+    if path.len() == 1 || step == 0 {
+        return path[0];
+    }
+
+    let index = (elapsed / step) as usize % path.len();
+    let (from, to) = (path[index], path[(index + 1) % path.len()]);
+    let t = (elapsed % step) as f32 / step as f32;
+
+    (
+        from.0 + (to.0 - from.0) * t,
+        from.1 + (to.1 - from.1) * t,
+        from.2 + (to.2 - from.2) * t,
+    )
+}
+
 impl<O: AudioEntityData> AudioEntity<O> {
     pub fn new(object: O, sound: Sound) -> Self {
         Self {
+            muted: sound.muted,
+            position: sound.position.or_else(|| sound.path.first().cloned()),
+            follow_path: !sound.path.is_empty(),
+            path_elapsed: 0,
+            armed: false,
+            plays: 0,
+            sample_gain: 1.0,
             sound,
             object,
             parameters: AudioEntityParameters::new(),
             is_triggered: false,
             is_preview: false,
+            crossfade: None,
+            trim: 1.0,
+            pitch_scale: 1.0,
+            group_volume: 1.0,
+            ducked: false,
+            sidechain_gain: 1.0,
+            talk_gain: 1.0,
+            placeholder: false,
+            format: None,
+            raw_preview: false,
+            play_once: false,
+            scene_gain: 1.0,
+            leaving_scene: false,
+            left_scene: false,
+            solo_muted: false,
+            soloed: false,
+            waiting_for_source: false,
+            gain: 0.0,
+        }
+    }
+
+    /// Stops the sound right away as if it had finished, sounds waiting for a trigger wait
+    /// for the next one
+    pub fn stop_now(&mut self, backend: &mut O::Backend) -> Result<(), AudioBackendError> {
+        self.stop(backend)?;
+
+        self.parameters.repeats = 0;
+        self.parameters.loops = 0;
+        self.parameters.stop_at_loop_end = false;
+        self.is_triggered = false;
+        self.play_once = false;
+        self.is_preview = false;
+        self.raw_preview = false;
+        self.waiting_for_source = false;
+
+        if self.sound.waits_for_trigger() {
+            self.switch_state(AudioEntityState::Reset);
+        } else {
+            self.switch_state(AudioEntityState::Dead);
+        }
+
+        Ok(())
+    }
+
+    /// Enables the sound again, fading it in if it was disabled
+    pub fn enter_scene(&mut self) {
+        // Sounds disabled by the theme or by hand stay disabled
+        if self.left_scene {
+            self.sound.enabled = true;
+            self.left_scene = false;
+            self.scene_gain = 0.0;
+        }
+        self.leaving_scene = false;
+    }
+
+    /// Disables the sound because the scene doesn't play it
+    pub fn leave_scene(&mut self, backend: &mut O::Backend) -> Result<(), AudioBackendError> {
+        self.leaving_scene = false;
+        if !self.sound.enabled {
+            return Ok(());
+        }
+
+        self.disable(backend)?;
+        self.left_scene = true;
+
+        Ok(())
+    }
+
+    /// Stops the sound and leaves it out until it is enabled again
+    pub fn disable(&mut self, backend: &mut O::Backend) -> Result<(), AudioBackendError> {
+        self.stop(backend)?;
+        self.sound.enabled = false;
+        self.leaving_scene = false;
+        self.switch_state(AudioEntityState::Virgin);
+
+        Ok(())
+    }
+
+    fn update_scene_gain(&mut self, delta: u64) {
+        let step = delta as f32 / SCENE_FADE_MS;
+        self.scene_gain = if self.leaving_scene {
+            (self.scene_gain - step).max(0.0)
+        } else {
+            (self.scene_gain + step).min(1.0)
+        };
+    }
+
+    /// Whether the sound waits for a trigger to start, rather than running
+    fn is_waiting(&self) -> bool {
+        match self.parameters.state {
+            AudioEntityState::Virgin
+            | AudioEntityState::Reset
+            | AudioEntityState::WaitingForTrigger => true,
+            _ => false,
+        }
+    }
+
+    /// A set trigger starts a waiting sound and stops a running one after its current
+    /// play, on, off and once only set it if that's where the sound is headed
+    pub fn trigger(&mut self, action: TriggerAction) {
+        let waiting = self.is_waiting();
+
+        match action {
+            TriggerAction::Toggle => self.is_triggered = !self.is_triggered,
+            TriggerAction::On => {
+                self.is_triggered = waiting;
+                self.play_once = false;
+            }
+            TriggerAction::Off => {
+                self.is_triggered = !waiting;
+                self.play_once = false;
+            }
+            TriggerAction::Once => {
+                if waiting {
+                    self.is_triggered = true;
+                    self.play_once = true;
+                }
+            }
+        }
+    }
+
+    /// Takes over the changed parameters, the volume applies right away while everything
+    /// else is picked up when the sound starts its next run
+    pub fn apply_changes(&mut self, changes: SoundChanges) {
+        if let Some(volume) = changes.volume {
+            self.sound.volume = volume;
+            self.parameters.max_volume = get_random_value(volume);
+        }
+        if let Some(repeat_delay) = changes.repeat_delay {
+            self.sound.repeat_delay = repeat_delay;
+        }
+        if let Some(loop_delay) = changes.loop_delay {
+            self.sound.loop_delay = loop_delay;
+        }
+        if let Some(reverb) = changes.reverb {
+            self.sound.reverb = reverb;
+        }
+        if let Some(pitch) = changes.pitch {
+            self.sound.pitch = pitch;
+            self.sound.pitch_enabled = true;
+        }
+    }
+
+    fn mute_gain(&self) -> f32 {
+        if self.solo_muted || (self.muted && !self.soloed) {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    pub fn status(&mut self) -> SoundStatus {
+        SoundStatus {
+            state: self.parameters.state.to_string(),
+            loops: self.parameters.loops,
+            repeats: self.parameters.repeats,
+            loop_forever: self.sound.loop_forever,
+            stopping: self.parameters.stop_at_loop_end,
+            trim: self.trim,
+            placeholder: self.placeholder,
+            format: self.format,
+            armed: self.armed,
+            plays: self.plays,
+            position: self.object.get_position_seconds(),
+            length: self.object.get_length(),
+            waits_for_trigger: self.sound.waits_for_trigger(),
+            waiting: self.is_waiting(),
+            triggered: self.is_triggered,
+            muted: self.muted,
         }
     }
 
@@ -212,8 +1028,83 @@ impl<O: AudioEntityData> AudioEntity<O> {
         self.parameters.state == *state
     }
 
-    pub fn pause(&mut self, _flag: bool) {
-        self.object.pause();
+    /// Part of the ambience bus, the sounds setting the scene: neither music nor sounds
+    /// that wait for a trigger
+    pub fn is_ambience(&self) -> bool {
+        !self.sound.music && !self.sound.waits_for_trigger() && !self.is_preview
+    }
+
+    pub fn is_waiting_for_source(&self) -> bool {
+        self.waiting_for_source && self.is_in_state(&AudioEntityState::Starting)
+    }
+
+    /// Whether the position of the sound moves along with the listener
+    pub fn is_relative(&self) -> bool {
+        self.sound.position_mode == PositionMode::Listener
+    }
+
+    /// Schedules the next run after a random delay (in milliseconds) from the given range
+    fn schedule(&mut self, delay: (u64, u64)) {
+        self.parameters.next_play_delay = delay;
+        self.parameters.next_play = Duration::from_millis(get_random_value(delay));
+    }
+
+    fn count_down(&mut self, delta: u64) {
+        self.parameters.next_play = self
+            .parameters
+            .next_play
+            .checked_sub(Duration::from_millis(delta))
+            .unwrap_or_else(|| Duration::new(0, 0));
+    }
+
+    /// Called instead of `update` while the theme is paused
+    pub fn update_paused(&mut self, delta: u64) {
+        if self.sound.on_pause == PauseBehavior::KeepRunning
+            && self.is_in_state(&AudioEntityState::WaitingForStart)
+        {
+            self.count_down(delta);
+        }
+    }
+
+    /// Called when the theme resumes playing after a pause
+    pub fn resume(&mut self) {
+        if self.sound.on_pause == PauseBehavior::Reset
+            && self.is_in_state(&AudioEntityState::WaitingForStart)
+        {
+            let delay = self.parameters.next_play_delay;
+            self.schedule(delay);
+            debug!(
+                "Sound '{}' rescheduled to play in {}s after pause",
+                self.sound.name,
+                self.parameters.next_play.as_secs()
+            );
+        }
+    }
+
+    pub fn set_pitch_scale(&mut self, scale: f32) -> Result<(), AudioBackendError> {
+        self.pitch_scale = scale;
+
+        if self.is_in_state(&AudioEntityState::Playing) {
+            self.object.set_pitch(self.parameters.pitch * scale)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn pause(&mut self, backend: &mut O::Backend, flag: bool) {
+        if let Some(ref mut crossfade) = self.crossfade {
+            if flag {
+                crossfade.object.pause();
+            } else {
+                crossfade.object.play(backend);
+            }
+        }
+
+        if flag {
+            self.object.pause();
+        } else {
+            self.object.play(backend);
+        }
     }
 
     pub fn play(&mut self, backend: &mut O::Backend) {
@@ -221,18 +1112,186 @@ impl<O: AudioEntityData> AudioEntity<O> {
     }
 
     pub fn stop(&mut self, backend: &mut O::Backend) -> Result<(), AudioBackendError> {
+        if let Some(mut crossfade) = self.crossfade.take() {
+            crossfade.object.stop(backend)?;
+        }
+
         Ok(self.object.stop(backend)?)
     }
 
+    /// The object along with the one it crossfades from
+    pub fn objects(&self) -> impl Iterator<Item = &O> {
+        self.crossfade
+            .iter()
+            .map(|crossfade| &crossfade.object)
+            .chain(Some(&self.object))
+    }
+
+    /// Moves the entity to the new output device, it plays on if it was playing. Sounds of
+    /// a `paused` theme are paused again right away.
+    pub fn reopen(
+        &mut self,
+        backend: &mut O::Backend,
+        paused: bool,
+        decoded: &mut DecodedFiles,
+    ) -> Result<(), AudioBackendError> {
+        let playing = self.is_in_state(&AudioEntityState::Playing);
+
+        if let Some(ref mut crossfade) = self.crossfade {
+            crossfade.object.reopen(backend, playing, decoded)?;
+            if playing && paused {
+                crossfade.object.pause();
+            }
+        }
+
+        self.object.reopen(backend, playing, decoded)?;
+        if playing && paused {
+            self.object.pause();
+        }
+
+        Ok(())
+    }
+
+    /// Swaps the sample played by this entity. If the entity is currently playing,
+    /// the old sample fades out while the new one starts fading in.
+    pub fn replace_object(
+        &mut self,
+        backend: &mut O::Backend,
+        object: O,
+        file: String,
+        crossfade: u64,
+    ) -> Result<(), AudioBackendError> {
+        // A crossfade that is still running is cut short
+        if let Some(mut previous) = self.crossfade.take() {
+            previous.object.stop(backend)?;
+        }
+
+        let mut old_object = std::mem::replace(&mut self.object, object);
+        self.sound.file = file;
+
+        if self.is_in_state(&AudioEntityState::Playing) && crossfade > 0 {
+            self.crossfade = Some(AudioEntityCrossfade {
+                object: old_object,
+                volume: self.parameters.max_volume * self.trim,
+                elapsed: 0,
+                duration: crossfade,
+            });
+
+            self.switch_state(AudioEntityState::Starting);
+        } else {
+            old_object.stop(backend)?;
+        }
+
+        Ok(())
+    }
+
+    fn update_crossfade(
+        &mut self,
+        backend: &mut O::Backend,
+        delta: u64,
+    ) -> Result<(), AudioBackendError> {
+        let finished = match self.crossfade {
+            Some(ref mut crossfade) => {
+                crossfade.elapsed += delta;
+                let gain = crossfade.gain();
+
+                crossfade.object.update()?;
+
+                if crossfade.object.is_playing() {
+                    crossfade
+                        .object
+                        .set_volume(crossfade.volume * (1.0 - gain))?;
+                }
+
+                gain >= 1.0 || !crossfade.object.is_playing()
+            }
+            None => false,
+        };
+
+        if finished {
+            debug!("Sound '{}' finished crossfading", self.sound.name);
+            self.crossfade.take().unwrap().object.stop(backend)?;
+        }
+
+        Ok(())
+    }
+
+    /// Milliseconds left of the run if it goes straight into the next loop and is close
+    /// enough to its end to overlap with it
+    fn loop_seam_ahead(&mut self) -> Option<u64> {
+        if self.sound.loop_crossfade_ms == 0 || self.crossfade.is_some() || self.is_preview {
+            return None;
+        }
+
+        // Runs that stop, repeat or wait before looping have no seam to hide
+        let loops = self.parameters.loops > 0 || self.sound.loop_forever;
+        let ends = (self.sound.trigger.is_some() && self.is_triggered)
+            || self.play_once
+            || self.parameters.stop_at_loop_end;
+        if !loops
+            || ends
+            || self.parameters.repeats > 0
+            || self.sound.loop_delay.1 > 0
+            || self.sound.play_probability < 1.0
+        {
+            return None;
+        }
+
+        let length = self.object.get_length();
+        let left = (1.0 - self.object.get_position()) * length * 1000.0;
+
+        // Short samples fade for at most half of their length
+        if left <= (self.sound.loop_crossfade_ms as f32).min(length * 500.0) {
+            Some(left as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Starts the next loop on a duplicate of the object, this run fades out over what is
+    /// left of it while the next one fades in
+    fn crossfade_loop(
+        &mut self,
+        backend: &mut O::Backend,
+        left: u64,
+    ) -> Result<(), AudioBackendError> {
+        let object = self.object.duplicate(backend)?;
+        let previous = std::mem::replace(&mut self.object, object);
+
+        debug!("Sound '{}' crossfading into its next loop", self.sound.name);
+        self.crossfade = Some(AudioEntityCrossfade {
+            object: previous,
+            volume: self.gain,
+            elapsed: 0,
+            duration: left.max(1),
+        });
+
+        if !self.sound.loop_forever {
+            self.parameters.loops -= 1;
+        }
+
+        self.schedule(self.sound.loop_delay);
+        self.switch_state(AudioEntityState::PrepareRun);
+
+        Ok(())
+    }
+
+    fn update_sidechain(&mut self, delta: u64) {
+        self.sidechain_gain = sidechain_gain(self.sidechain_gain, self.ducked, delta);
+    }
+
     pub fn update(&mut self, backend: &mut O::Backend, delta: u64) -> Result<(), AudioEngineError> {
+        self.update_crossfade(backend, delta)?;
+        self.update_sidechain(delta);
+        self.update_scene_gain(delta);
+
         match self.parameters.state {
             // Initial state every new sound is in
             AudioEntityState::Virgin => {
-                self.parameters.next_play =
-                    Duration::from_millis(get_random_value(self.sound.loop_delay));
+                self.schedule(self.sound.loop_delay);
                 self.parameters.loops = get_random_value(self.sound.loop_count);
 
-                if self.sound.trigger.is_some() && !self.is_preview {
+                if self.sound.waits_for_trigger() && !self.is_preview {
                     self.switch_state(AudioEntityState::WaitingForTrigger);
                 } else {
                     self.switch_state(AudioEntityState::PrepareRun);
@@ -245,12 +1304,21 @@ impl<O: AudioEntityData> AudioEntity<O> {
 
             AudioEntityState::Reset => {
                 self.stop(backend)?;
+                self.play_once = false;
 
                 self.switch_state(AudioEntityState::Virgin);
             }
 
             // The sound is waiting for an external trigger to start
             AudioEntityState::WaitingForTrigger => {
+                // Playing released the source, get it ready again for the next trigger
+                if self.armed {
+                    if let Err(e) = self.object.arm(backend) {
+                        warn!("Failed to arm sound '{}': {}", self.sound.name, e);
+                        self.armed = false;
+                    }
+                }
+
                 if self.is_triggered {
                     self.switch_state(AudioEntityState::WaitingForStart);
                     self.is_triggered = false;
@@ -276,41 +1344,87 @@ impl<O: AudioEntityData> AudioEntity<O> {
             // Wait for specified delay between loops
             AudioEntityState::WaitingForStart => {
                 if self.parameters.next_play > Duration::new(0, 0) {
-                    self.parameters.next_play = match self
-                        .parameters
-                        .next_play
-                        .checked_sub(Duration::from_millis(delta))
-                    {
-                        Some(d) => d,
-                        None => Duration::new(0, 0),
-                    };
+                    self.count_down(delta);
                 }
 
                 if self.parameters.next_play == Duration::new(0, 0) {
-                    self.switch_state(AudioEntityState::Starting);
+                    // Rare sounds skip some of their runs, as if they had played silently
+                    if !self.is_preview
+                        && !thread_rng()
+                            .gen_bool(self.sound.play_probability.max(0.0).min(1.0) as f64)
+                    {
+                        debug!("Sound '{}' skipped this run", self.sound.name);
+                        self.switch_state(AudioEntityState::Repeat);
+                    } else {
+                        self.switch_state(AudioEntityState::Starting);
+                    }
                 }
             }
 
             // Start playing the sound
             AudioEntityState::Starting => {
                 self.play(backend);
+
+                // Tries again once the engine lets it have one of the next free sources
+                if self.object.is_waiting_for_source() {
+                    if !self.waiting_for_source {
+                        info!("Sound {} is waiting for a free source", self.sound.name);
+                    }
+                    self.waiting_for_source = true;
+                    return Ok(());
+                }
+                self.waiting_for_source = false;
+
+                // Long loops start from another spot every run
+                self.parameters.start_position = 0.0;
+                let offset = get_random_value(self.sound.start_offset);
+                let length = self.object.get_length();
+                if offset > 0.0 && length > 0.0 {
+                    self.object.seek(backend, offset % length)?;
+                    self.parameters.start_position = self.object.get_position();
+                }
+
+                if !self.is_preview {
+                    self.plays += 1;
+                }
                 self.parameters.max_volume = get_random_value(self.sound.volume);
 
-                let mut pitch = -1.0;
-                if self.sound.pitch_enabled {
-                    pitch = get_random_value(self.sound.pitch);
+                self.parameters.pitch = if self.sound.pitch_enabled {
+                    get_random_value(self.sound.pitch)
+                } else {
+                    1.0
+                };
+
+                let pitch = self.parameters.pitch * self.pitch_scale;
+                if self.sound.pitch_enabled || self.pitch_scale != 1.0 {
                     self.object.set_pitch(pitch)?;
                 }
 
+                // Tilt the tone of every run a bit, negative values are darker, positive brighter
+                let mut tilt = 0.0;
+                if self.sound.variation_enabled {
+                    tilt = get_random_value(self.sound.variation);
+                }
+
                 let mut lowpass = -1.0;
-                if self.sound.lowpass_enabled {
-                    lowpass = get_random_value(self.sound.lowpass);
+                if self.sound.lowpass_enabled || self.sound.variation_enabled {
+                    lowpass = if self.sound.lowpass_enabled {
+                        get_random_value(self.sound.lowpass)
+                    } else {
+                        0.0
+                    };
+                    lowpass = (lowpass - f32::min(tilt, 0.0)).min(1.0);
                     self.object.set_lowpass(lowpass)?;
                 }
 
                 let mut highpass = -1.0;
-                if self.sound.highpass_enabled {
-                    highpass = get_random_value(self.sound.highpass);
+                if self.sound.highpass_enabled || self.sound.variation_enabled {
+                    highpass = if self.sound.highpass_enabled {
+                        get_random_value(self.sound.highpass)
+                    } else {
+                        0.0
+                    };
+                    highpass = (highpass + f32::max(tilt, 0.0)).min(1.0);
                     self.object.set_highpass(highpass)?;
                 }
 
@@ -321,11 +1435,26 @@ impl<O: AudioEntityData> AudioEntity<O> {
                     self.object.set_volume(0.0)?;
                 }
 
+                // Fades in from silence while the previous object fades out
+                if self.crossfade.is_some() {
+                    self.object.set_volume(0.0)?;
+                }
+
                 self.object.set_reverb(self.sound.reverb.as_ref())?;
+                self.object.set_effects(&self.sound.effects)?;
+
+                // Each run lands somewhere else in the stereo field, e.g. for a flock of birds
+                let mut pan = 0.0;
+                if let Some(position) = self.position {
+                    self.object.set_position(position, self.is_relative())?;
+                } else if self.sound.pan_enabled {
+                    pan = get_random_value(self.sound.pan);
+                    self.object.set_pan(pan)?;
+                }
 
                 info!(
-                    "Going to play {} at volume {}, pitch {}, lowpass {}, highpass {}, with reverb {}, fade in until {}",
-                    self.sound.name, self.parameters.max_volume, pitch, lowpass, highpass, self.sound.reverb, self.parameters.fade_in
+                    "Going to play {} at volume {}, pitch {}, lowpass {}, highpass {}, pan {}, with reverb {}, fade in until {}",
+                    self.sound.name, self.parameters.max_volume, pitch, lowpass, highpass, pan, self.sound.reverb, self.parameters.fade_in
                 );
 
                 self.switch_state(AudioEntityState::Playing);
@@ -333,20 +1462,55 @@ impl<O: AudioEntityData> AudioEntity<O> {
 
             // Wait until the sound is done
             AudioEntityState::Playing => {
-                let volume = if self.sound.fade_in_enabled
-                    && self.object.get_position() < self.parameters.fade_in
-                {
-                    (1.0 - (self.parameters.fade_in - self.object.get_position())
-                        / self.parameters.fade_in)
+                let position = self.object.get_position() - self.parameters.start_position;
+                let volume = if self.sound.fade_in_enabled && position < self.parameters.fade_in {
+                    (1.0 - (self.parameters.fade_in - position.max(0.0)) / self.parameters.fade_in)
                         * self.parameters.max_volume
                 } else {
                     self.parameters.max_volume
                 };
 
+                // The volume the sound is set up to have in the mix
+                let volume = match self.crossfade {
+                    Some(ref crossfade) => volume * crossfade.gain(),
+                    None => volume,
+                } * self.trim
+                    * self.sample_gain
+                    * self.group_volume;
+
+                // Previews are heard at that volume, whatever muting, the scene or ducking
+                // do to the sound right now. Raw previews play at full scale.
+                let volume = if !self.is_preview {
+                    volume
+                        * self.sidechain_gain
+                        * self.talk_gain
+                        * self.scene_gain
+                        * self.mute_gain()
+                } else if self.raw_preview {
+                    1.0
+                } else {
+                    volume
+                };
+
+                self.object.update()?;
                 self.object.set_volume(volume)?;
+                self.gain = volume;
+
+                if self.follow_path {
+                    self.path_elapsed += delta;
+                    let position =
+                        path_position(&self.sound.path, self.sound.path_step, self.path_elapsed);
+                    self.position = Some(position);
+                    self.object.set_position(position, self.is_relative())?;
+                }
+
+                if let Some(left) = self.loop_seam_ahead() {
+                    self.crossfade_loop(backend, left)?;
+                    return Ok(());
+                }
 
                 if !self.object.is_playing() {
-                    if self.sound.trigger.is_some() && self.is_triggered {
+                    if (self.sound.trigger.is_some() && self.is_triggered) || self.play_once {
                         info!("Sound {} cancelled!", self.sound.name);
                         self.stop(backend)?;
 
@@ -362,8 +1526,7 @@ impl<O: AudioEntityData> AudioEntity<O> {
             AudioEntityState::Repeat => {
                 if self.parameters.repeats > 0 {
                     self.parameters.repeats -= 1;
-                    self.parameters.next_play =
-                        Duration::from_millis(get_random_value(self.sound.repeat_delay));
+                    self.schedule(self.sound.repeat_delay);
                     info!("Repeats are {}", self.parameters.repeats);
 
                     self.switch_state(AudioEntityState::WaitingForStart);
@@ -371,6 +1534,7 @@ impl<O: AudioEntityData> AudioEntity<O> {
                     // If this run was a preview, exit preview mode and switch back to being a virgin
                     if self.is_preview {
                         self.is_preview = false;
+                        self.raw_preview = false;
                         self.switch_state(AudioEntityState::Virgin);
                     } else {
                         // Otherwise go back to looping
@@ -384,13 +1548,16 @@ impl<O: AudioEntityData> AudioEntity<O> {
                 // Stop the sound for now to free up resources
                 self.stop(backend)?;
 
-                if self.parameters.loops > 0 || self.sound.loop_forever {
+                if self.parameters.stop_at_loop_end {
+                    info!("Sound {} stopped at the end of its loop", self.sound.name);
+                    self.parameters.stop_at_loop_end = false;
+                    self.switch_state(AudioEntityState::Finished);
+                } else if self.parameters.loops > 0 || self.sound.loop_forever {
                     if !self.sound.loop_forever {
                         self.parameters.loops -= 1;
                     }
 
-                    self.parameters.next_play =
-                        Duration::from_millis(get_random_value(self.sound.loop_delay));
+                    self.schedule(self.sound.loop_delay);
 
                     self.switch_state(AudioEntityState::PrepareRun);
                 } else {
@@ -402,7 +1569,7 @@ impl<O: AudioEntityData> AudioEntity<O> {
             AudioEntityState::Finished => {
                 info!("Sound {} finished!", self.sound.name);
 
-                if self.sound.trigger.is_some() {
+                if self.sound.waits_for_trigger() {
                     self.switch_state(AudioEntityState::Reset);
                 } else {
                     self.switch_state(AudioEntityState::Dead);
@@ -418,16 +1585,20 @@ impl<O: AudioEntityData> AudioEntity<O> {
 }
 
 pub fn start_audio_controller<T: AudioBackend>(
-    receiver: Receiver<Command>,
-    sender: Sender<Response>,
-    samplesdb: SamplesDB,
+    receiver: Receiver<Request>,
+    samplesdb: SharedSamplesDB,
+    config: EngineConfig,
 ) -> Result<(), AudioEngineError> {
-    let mut audio_ctrl: AudioController<T> = AudioController::new(receiver, sender, samplesdb)?;
+    let mut audio_ctrl: AudioController<T> = AudioController::new(receiver, samplesdb, config)?;
 
     match audio_ctrl.run() {
-        Ok(()) => info!("AudioController exited ok"),
-        Err(e) => error!("Error while running AudioController: {}", e),
-    };
-
-    Ok(())
+        Ok(()) => {
+            info!("AudioController exited ok");
+            Ok(())
+        }
+        Err(e) => {
+            error!("Error while running AudioController: {}", e);
+            Err(e)
+        }
+    }
 }
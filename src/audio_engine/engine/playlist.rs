@@ -0,0 +1,550 @@
+use std::path::PathBuf;
+use std::thread;
+
+use crossbeam_channel::{bounded, Receiver, TryRecvError};
+use rand::{thread_rng, Rng};
+
+use crate::audio_engine::backends::base::{
+    AudioBackend, AudioEntityData, DecodedFile, DecodedFiles,
+};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::engine::{
+    sidechain_gain, AudioController, AudioEntityCrossfade, SCENE_FADE_MS,
+};
+use crate::audio_engine::messages::{ApiError, ErrorCode, PlaylistStatus};
+use crate::theme::{Playlist, PlaylistOrder, SoundType};
+
+// Time to wait before trying the next track when one couldn't be loaded
+const TRACK_RETRY_MS: u64 = 1000;
+
+#[derive(PartialEq, Debug)]
+pub enum PlaylistState {
+    /// The next track starts after this many milliseconds
+    Waiting(u64),
+    /// The next track is being decoded on a worker thread
+    Loading,
+    Playing,
+    /// The last track has played and the playlist doesn't repeat
+    Finished,
+}
+
+/// A playlist of the loaded theme, playing one track after another
+pub struct PlaylistPlayer<O: AudioEntityData> {
+    pub playlist: Playlist,
+    state: PlaylistState,
+    /// Indices of the tracks in the order they are played
+    order: Vec<usize>,
+    /// Position of the current track in `order`
+    position: usize,
+    current: Option<O>,
+    /// Gain normalizing the current track
+    gain: f32,
+    /// The previous track, fading out while the current one fades in
+    crossfade: Option<AudioEntityCrossfade<O>>,
+    /// Fades in and out when switching scenes, like the sounds
+    scene_gain: f32,
+    /// Fading out because the scene switched to one the playlist isn't part of
+    leaving_scene: bool,
+    /// Stopped because the scene doesn't play it, it starts over with a scene that does
+    left_scene: bool,
+    /// Volume of the playlist's mixer group
+    group_volume: f32,
+    /// Another sound that ducks the group of the playlist is playing
+    pub ducked: bool,
+    /// Envelope following `ducked`, like the one of the sounds
+    sidechain_gain: f32,
+    /// Silenced because a sound is soloed
+    solo_muted: bool,
+    /// Sum of the volumes the tracks playing were set to with the last update
+    load: f32,
+    /// Gets the file of the next track once the worker decoded it
+    loading: Option<Receiver<(PathBuf, Result<DecodedFile, AudioBackendError>)>>,
+}
+
+impl<O: AudioEntityData> PlaylistPlayer<O> {
+    pub fn new(playlist: Playlist) -> Self {
+        let mut player = Self {
+            playlist,
+            state: PlaylistState::Waiting(0),
+            order: Vec::new(),
+            position: 0,
+            current: None,
+            gain: 1.0,
+            crossfade: None,
+            scene_gain: 1.0,
+            leaving_scene: false,
+            left_scene: false,
+            group_volume: 1.0,
+            ducked: false,
+            sidechain_gain: 1.0,
+            solo_muted: false,
+            load: 0.0,
+            loading: None,
+        };
+
+        player.shuffle();
+        player
+    }
+
+    /// Puts the tracks in the order they are played, shuffled playlists get a new order
+    /// every time they start over
+    fn shuffle(&mut self) {
+        self.order = (0..self.playlist.tracks.len()).collect();
+
+        if self.playlist.order == PlaylistOrder::Shuffle {
+            thread_rng().shuffle(&mut self.order);
+        }
+    }
+
+    fn track(&self) -> Option<&str> {
+        self.order
+            .get(self.position)
+            .map(|&index| self.playlist.tracks[index].as_str())
+    }
+
+    /// Gain of the mixer on top of the playlist's volume: its group, ducking, solo and
+    /// the scene fades
+    fn mix_gain(&self) -> f32 {
+        if self.solo_muted {
+            return 0.0;
+        }
+
+        self.group_volume * self.sidechain_gain * self.scene_gain
+    }
+
+    /// How much the playlist adds to the mix, for the limiter
+    pub fn load(&self) -> f32 {
+        if self.left_scene {
+            0.0
+        } else {
+            self.load
+        }
+    }
+
+    fn has_next(&self) -> bool {
+        self.playlist.repeat || self.position + 1 < self.order.len()
+    }
+
+    pub fn status(&mut self) -> PlaylistStatus {
+        let position = match self.current {
+            Some(ref mut object) => object.get_position_seconds(),
+            None => 0.0,
+        };
+
+        PlaylistStatus {
+            name: self.playlist.name.clone(),
+            state: match self.state {
+                PlaylistState::Waiting(_) => "waiting",
+                PlaylistState::Loading => "loading",
+                PlaylistState::Playing => "playing",
+                PlaylistState::Finished => "finished",
+            }
+            .to_string(),
+            track: self.track().map(str::to_owned),
+            index: self.position,
+            tracks: self.order.len(),
+            position,
+        }
+    }
+
+    /// The track playing right now, if any
+    pub fn current_track(&self) -> Option<&str> {
+        match self.state {
+            PlaylistState::Playing => self.track(),
+            _ => None,
+        }
+    }
+
+    pub fn pause(&mut self, backend: &mut O::Backend, flag: bool) {
+        if let Some(ref mut crossfade) = self.crossfade {
+            if flag {
+                crossfade.object.pause();
+            } else {
+                crossfade.object.play(backend);
+            }
+        }
+
+        if let Some(ref mut object) = self.current {
+            if flag {
+                object.pause();
+            } else {
+                object.play(backend);
+            }
+        }
+    }
+
+    pub fn stop(&mut self, backend: &mut O::Backend) -> Result<(), AudioBackendError> {
+        self.loading = None;
+
+        if let Some(mut crossfade) = self.crossfade.take() {
+            crossfade.object.stop(backend)?;
+        }
+
+        if let Some(mut object) = self.current.take() {
+            object.stop(backend)?;
+        }
+        self.load = 0.0;
+
+        Ok(())
+    }
+
+    pub fn enter_scene(&mut self) {
+        if self.left_scene {
+            self.left_scene = false;
+            self.scene_gain = 0.0;
+            self.state = PlaylistState::Waiting(0);
+        }
+        self.leaving_scene = false;
+    }
+
+    /// Fades out before stopping unless `now` is set
+    pub fn leave_scene(
+        &mut self,
+        backend: &mut O::Backend,
+        now: bool,
+    ) -> Result<(), AudioBackendError> {
+        if self.left_scene {
+            return Ok(());
+        }
+
+        if !now {
+            self.leaving_scene = true;
+            return Ok(());
+        }
+
+        self.leaving_scene = false;
+        self.left_scene = true;
+        self.stop(backend)
+    }
+
+    /// Stops the playlist once it faded out after leaving the scene
+    fn update_scene(
+        &mut self,
+        backend: &mut O::Backend,
+        delta: u64,
+    ) -> Result<(), AudioBackendError> {
+        let step = delta as f32 / SCENE_FADE_MS;
+        self.scene_gain = if self.leaving_scene {
+            (self.scene_gain - step).max(0.0)
+        } else {
+            (self.scene_gain + step).min(1.0)
+        };
+
+        if self.leaving_scene && self.scene_gain <= 0.0 {
+            self.leave_scene(backend, true)?;
+        }
+
+        Ok(())
+    }
+
+    /// The current track along with the one it crossfades from
+    pub fn objects(&self) -> impl Iterator<Item = &O> {
+        self.crossfade
+            .iter()
+            .map(|crossfade| &crossfade.object)
+            .chain(self.current.iter())
+    }
+
+    /// Moves the tracks to the new output device, they are paused again if the theme is
+    /// `paused`
+    pub fn reopen(
+        &mut self,
+        backend: &mut O::Backend,
+        paused: bool,
+        decoded: &mut DecodedFiles,
+    ) -> Result<(), AudioBackendError> {
+        if let Some(ref mut crossfade) = self.crossfade {
+            crossfade.object.reopen(backend, true, decoded)?;
+        }
+
+        if let Some(ref mut object) = self.current {
+            let playing = self.state == PlaylistState::Playing;
+            object.reopen(backend, playing, decoded)?;
+        }
+
+        if paused {
+            self.pause(backend, true);
+        }
+
+        Ok(())
+    }
+
+    /// Moves to the next or previous track, which starts after `wait` milliseconds. The
+    /// current track fades out if the playlist crossfades, otherwise it stops right away.
+    pub fn skip(
+        &mut self,
+        backend: &mut O::Backend,
+        forward: bool,
+        wait: u64,
+    ) -> Result<(), AudioBackendError> {
+        if forward {
+            if self.position + 1 < self.order.len() {
+                self.position += 1;
+            } else if self.playlist.repeat {
+                self.shuffle();
+                self.position = 0;
+            } else {
+                info!("Playlist '{}' finished", self.playlist.name);
+                self.stop(backend)?;
+                self.state = PlaylistState::Finished;
+                return Ok(());
+            }
+        } else {
+            // The first track just starts over
+            self.position = self.position.saturating_sub(1);
+        }
+
+        // A track still being decoded is dropped
+        self.loading = None;
+
+        if let Some(mut previous) = self.crossfade.take() {
+            previous.object.stop(backend)?;
+        }
+
+        if let Some(mut object) = self.current.take() {
+            if self.playlist.crossfade > 0 && object.is_playing() {
+                self.crossfade = Some(AudioEntityCrossfade {
+                    object,
+                    volume: self.playlist.volume * self.gain,
+                    elapsed: 0,
+                    duration: self.playlist.crossfade,
+                });
+            } else {
+                object.stop(backend)?;
+            }
+        }
+
+        self.state = PlaylistState::Waiting(wait);
+
+        Ok(())
+    }
+
+    pub fn seek(&mut self, backend: &mut O::Backend, seconds: f32) -> Result<(), ApiError> {
+        match self.current {
+            Some(ref mut object) if self.state == PlaylistState::Playing => object
+                .seek(backend, seconds)
+                .map_err(|e| ApiError::from(&e)),
+            _ => Err(ApiError::new(
+                ErrorCode::InvalidValue,
+                format!("Playlist '{}' isn't playing a track!", self.playlist.name),
+            )),
+        }
+    }
+
+    fn update_crossfade(
+        &mut self,
+        backend: &mut O::Backend,
+        delta: u64,
+    ) -> Result<(), AudioBackendError> {
+        let mix_gain = self.mix_gain();
+        let finished = match self.crossfade {
+            Some(ref mut crossfade) => {
+                crossfade.elapsed += delta;
+                let gain = crossfade.gain();
+
+                crossfade.object.update()?;
+
+                if crossfade.object.is_playing() {
+                    let volume = crossfade.volume * mix_gain * (1.0 - gain);
+                    crossfade.object.set_volume(volume)?;
+                    self.load += volume;
+                }
+
+                gain >= 1.0 || !crossfade.object.is_playing()
+            }
+            None => false,
+        };
+
+        if finished {
+            self.crossfade.take().unwrap().object.stop(backend)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the fades and the current track forward, counts down the wait for the next one
+    fn update(&mut self, backend: &mut O::Backend, delta: u64) -> Result<(), AudioBackendError> {
+        self.load = 0.0;
+        self.update_scene(backend, delta)?;
+        self.update_crossfade(backend, delta)?;
+
+        match self.state {
+            PlaylistState::Waiting(wait) => {
+                self.state = PlaylistState::Waiting(wait.saturating_sub(delta))
+            }
+            PlaylistState::Playing => self.update_track(backend)?,
+            PlaylistState::Loading | PlaylistState::Finished => {}
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the current track going and moves on once it ended, or once it is close
+    /// enough to its end to crossfade into the next one
+    fn update_track(&mut self, backend: &mut O::Backend) -> Result<(), AudioBackendError> {
+        let fade_in = self.crossfade.as_ref().map_or(1.0, |c| c.gain());
+        let volume = self.playlist.volume * self.gain * self.mix_gain() * fade_in;
+
+        let remaining = match self.current {
+            Some(ref mut object) => {
+                object.update()?;
+
+                if object.is_playing() {
+                    object.set_volume(volume)?;
+                    self.load += volume;
+                    let length = object.get_length();
+                    let left = (1.0 - object.get_position()) * length;
+                    Some(((left * 1000.0) as u64, (length * 1000.0) as u64))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        match remaining {
+            None => {
+                let gap = self.playlist.gap;
+                self.skip(backend, true, gap)
+            }
+            // Short tracks fade for at most half of their length
+            Some((left, length))
+                if left <= self.playlist.crossfade.min(length / 2) && self.has_next() =>
+            {
+                self.skip(backend, true, 0)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    /// Decodes the next track on a worker thread, it starts once it is decoded
+    fn load_track(&mut self, index: usize) {
+        let file = match self.playlists[index].track() {
+            Some(file) => file.to_string(),
+            None => {
+                self.playlists[index].state = PlaylistState::Finished;
+                return;
+            }
+        };
+
+        let resolved = self.samplesdb.lock().unwrap().resolve_sample(&file);
+        let path = match resolved {
+            Ok(Some(id)) => self.samplesdb.lock().unwrap().full_path_of_sample(id),
+            Ok(None) => return self.skip_track(index, &file, &ApiError::sample_not_found(&file)),
+            Err(e) => return self.skip_track(index, &file, &ApiError::from(&e)),
+        };
+
+        let stereo = self.playlists[index].playlist.stereo;
+        let decode: fn(&PathBuf, bool) -> Result<DecodedFile, AudioBackendError> = T::decode_file;
+        let (sender, receiver) = bounded(1);
+        thread::spawn(move || {
+            let decoded = decode(&path, stereo);
+            // Nobody is waiting anymore once the playlist moved on
+            let _ = sender.send((path, decoded));
+        });
+
+        let player = &mut self.playlists[index];
+        player.loading = Some(receiver);
+        player.state = PlaylistState::Loading;
+    }
+
+    /// Starts the track once the worker decoded it
+    fn start_track(&mut self, index: usize) {
+        let received = match self.playlists[index].loading {
+            Some(ref loading) => loading.try_recv(),
+            None => Err(TryRecvError::Disconnected),
+        };
+        let (path, decoded) = match received {
+            Ok(received) => received,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                self.playlists[index].loading = None;
+                self.playlists[index].state = PlaylistState::Waiting(0);
+                return;
+            }
+        };
+        self.playlists[index].loading = None;
+
+        let file = self.playlists[index]
+            .track()
+            .unwrap_or_default()
+            .to_string();
+        let stereo = self.playlists[index].playlist.stereo;
+        let loaded = decoded.and_then(|decoded| self.backend.load_decoded(&path, stereo, decoded));
+
+        let mut object = match loaded {
+            Ok(object) => object,
+            Err(e) => return self.skip_track(index, &file, &ApiError::from(&e).with_sample(&file)),
+        };
+
+        let gain = self.sample_gain(SoundType::File, &file);
+        let player = &mut self.playlists[index];
+
+        object.play(&mut self.backend);
+        // Fades in from silence while the previous track fades out
+        let fade_in = player.crossfade.as_ref().map_or(1.0, |c| c.gain());
+        let volume = player.playlist.volume * gain * player.mix_gain() * fade_in;
+        if let Err(e) = object.set_volume(volume) {
+            warn!("Failed to set the volume of track '{}': {}", file, e);
+        }
+
+        info!("Playlist '{}' playing '{}'", player.playlist.name, file);
+        player.gain = gain;
+        player.current = Some(object);
+        player.state = PlaylistState::Playing;
+        self.version.bump();
+    }
+
+    fn skip_track(&mut self, index: usize, file: &str, error: &ApiError) {
+        warn!("Skipping track '{}': {}", file, error.message);
+        if let Err(e) = self.playlists[index].skip(&mut self.backend, true, TRACK_RETRY_MS) {
+            warn!("Failed to move on from track '{}': {}", file, e);
+        }
+    }
+
+    /// Moves all playlists forward, starting the tracks that are due. A playlist that fails
+    /// is reported and left as it is, the others keep playing.
+    pub(in crate::audio_engine::engine) fn update_playlists(&mut self, delta: u64) {
+        let soloed = self.solo.is_some();
+        let mut due = Vec::new();
+        let mut loaded = Vec::new();
+
+        for (index, player) in self.playlists.iter_mut().enumerate() {
+            if player.left_scene {
+                continue;
+            }
+
+            player.group_volume = player
+                .playlist
+                .group
+                .as_ref()
+                .and_then(|group| self.group_volumes.get(group))
+                .cloned()
+                .unwrap_or(1.0);
+            player.sidechain_gain = sidechain_gain(player.sidechain_gain, player.ducked, delta);
+            player.solo_muted = soloed;
+
+            if let Err(e) = player.update(&mut self.backend, delta) {
+                warn!(
+                    "Failed to update playlist '{}': {}",
+                    player.playlist.name, e
+                );
+            }
+
+            match player.state {
+                PlaylistState::Waiting(0) => due.push(index),
+                PlaylistState::Loading => loaded.push(index),
+                _ => {}
+            }
+        }
+
+        for index in loaded {
+            self.start_track(index);
+        }
+        for index in due {
+            self.load_track(index);
+        }
+    }
+}
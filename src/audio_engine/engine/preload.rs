@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::thread;
+
+use crossbeam_channel::{unbounded, Receiver};
+
+use crate::audio_engine::backends::base::{
+    AudioBackend, AudioEntityData, DecodedFile, DecodedFiles,
+};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::engine::messaging::{decode_warnings, sample_format};
+use crate::audio_engine::engine::AudioController;
+use crate::audio_engine::loader::base::SampleFormat;
+use crate::audio_engine::loader::check::WarningCache;
+use crate::audio_engine::messages::{DecodeWarning, PreloadStatus};
+use crate::theme::Sound;
+
+/// A sound of a stored theme, loaded before the theme itself
+pub struct PreloadedSound<O: AudioEntityData> {
+    /// What it was loaded from, the stored theme may have changed by the time it is loaded
+    file: String,
+    stereo: bool,
+    pub object: O,
+    pub format: Option<SampleFormat>,
+    pub warnings: Vec<DecodeWarning>,
+}
+
+impl<O: AudioEntityData> PreloadedSound<O> {
+    pub fn matches(&self, sound: &Sound) -> bool {
+        self.file == sound.file && self.stereo == sound.stereo
+    }
+}
+
+/// A file of the theme as decoded by the worker, waiting to be uploaded
+struct DecodedSound {
+    sound: Sound,
+    path: PathBuf,
+    decoded: Result<DecodedFile, AudioBackendError>,
+    format: Option<SampleFormat>,
+    warnings: Vec<DecodeWarning>,
+}
+
+/// Decodes the files of a stored theme on a worker thread, the engine uploads one of them
+/// per update, so switching to the theme later on doesn't have to
+pub struct ThemePreload<O: AudioEntityData> {
+    theme: String,
+    decoded: Receiver<DecodedSound>,
+    loaded: HashMap<String, PreloadedSound<O>>,
+    /// Sounds that couldn't be loaded, they are tried again when the theme is loaded
+    failed: Vec<String>,
+    total: usize,
+}
+
+impl<O: AudioEntityData> ThemePreload<O> {
+    /// Starts decoding `sounds` from their paths with `decode`, `failed` are the sounds of
+    /// the theme whose files couldn't be found
+    pub fn new(
+        theme: String,
+        sounds: Vec<(Sound, PathBuf)>,
+        failed: Vec<String>,
+        decode: fn(&PathBuf, bool) -> Result<DecodedFile, AudioBackendError>,
+        warning_cache: WarningCache,
+    ) -> Self {
+        let total = sounds.len() + failed.len();
+        let (sender, receiver) = unbounded();
+
+        thread::spawn(move || {
+            for (sound, path) in sounds {
+                let decoded = DecodedSound {
+                    decoded: decode(&path, sound.stereo),
+                    format: sample_format(&path),
+                    warnings: decode_warnings(&warning_cache, &sound.name, &sound.file, &path),
+                    sound,
+                    path,
+                };
+
+                // Nobody is waiting anymore once another theme is preloaded
+                if sender.send(decoded).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            theme,
+            decoded: receiver,
+            loaded: HashMap::new(),
+            failed,
+            total,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.loaded.len() + self.failed.len() >= self.total
+    }
+
+    /// Uploads the sounds loaded so far to the new device, the ones that fail are loaded
+    /// again along with the theme
+    pub fn objects(&self) -> impl Iterator<Item = &O> {
+        self.loaded.values().map(|sound| &sound.object)
+    }
+
+    pub fn reopen(&mut self, backend: &mut O::Backend, decoded: &mut DecodedFiles) {
+        let mut failed = Vec::new();
+
+        for (name, sound) in self.loaded.iter_mut() {
+            if let Err(e) = sound.object.reopen(backend, false, decoded) {
+                warn!("Failed to move preloaded sound '{}': {}", name, e);
+                failed.push(name.clone());
+            }
+        }
+
+        for name in failed {
+            self.loaded.remove(&name);
+            self.failed.push(name);
+        }
+    }
+
+    pub fn status(&self) -> PreloadStatus {
+        PreloadStatus {
+            theme: self.theme.clone(),
+            loaded: self.loaded.len(),
+            failed: self.failed.clone(),
+            total: self.total,
+        }
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    pub(in crate::audio_engine::engine) fn update_preload(&mut self) {
+        let decoded = match self.preload {
+            Some(ref preload) => match preload.decoded.try_recv() {
+                Ok(decoded) => decoded,
+                Err(_) => return,
+            },
+            None => return,
+        };
+
+        let DecodedSound {
+            sound,
+            path,
+            decoded,
+            format,
+            warnings,
+        } = decoded;
+        let result =
+            decoded.and_then(|decoded| self.backend.load_decoded(&path, sound.stereo, decoded));
+        let preload = self.preload.as_mut().unwrap();
+
+        match result {
+            Ok(object) => {
+                preload.loaded.insert(
+                    sound.name,
+                    PreloadedSound {
+                        file: sound.file,
+                        stereo: sound.stereo,
+                        object,
+                        format,
+                        warnings,
+                    },
+                );
+            }
+            Err(e) => {
+                warn!("Failed to preload sound '{}': {}", sound.name, e);
+                preload.failed.push(sound.name);
+            }
+        }
+
+        if preload.is_done() {
+            info!(
+                "Preloaded {} of {} sound(s) of theme '{}'",
+                preload.loaded.len(),
+                preload.total,
+                preload.theme
+            );
+        }
+    }
+
+    /// Hands out the sounds preloaded for this theme, sounds still pending are left to
+    /// be loaded along with the theme
+    pub(in crate::audio_engine::engine) fn take_preloaded(
+        &mut self,
+        theme: &str,
+    ) -> HashMap<String, PreloadedSound<T::EntityData>> {
+        let preloaded = self
+            .preload
+            .as_ref()
+            .map_or(false, |preload| preload.theme == theme);
+
+        if preloaded {
+            self.preload.take().unwrap().loaded
+        } else {
+            HashMap::new()
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::audio_engine::backends::base::{AudioBackend, AudioEntityData};
+use crate::audio_engine::engine::playlist::PlaylistPlayer;
+use crate::audio_engine::engine::{AudioController, AudioEntity};
+use crate::theme::Theme;
+
+/// Scene a theme starts out in, its `initial_scene` or else the first one by name
+pub fn initial_scene(theme: &Theme) -> Option<String> {
+    match theme.initial_scene {
+        Some(ref scene) if theme.scenes.contains_key(scene) => Some(scene.clone()),
+        Some(ref scene) => {
+            warn!("Theme '{}' has no initial scene '{}'", theme.name, scene);
+            None
+        }
+        None => theme.scenes.keys().min().cloned(),
+    }
+}
+
+/// Leaves out the sounds and playlists of a theme that is about to start which the scene
+/// doesn't play, so they don't start in the first place
+pub fn start_in_scene<O: AudioEntityData>(
+    scenes: &HashMap<String, Vec<String>>,
+    scene: &str,
+    handles: &mut HashMap<String, AudioEntity<O>>,
+    playlists: &mut [PlaylistPlayer<O>],
+    backend: &mut O::Backend,
+) {
+    let in_scenes: HashSet<&String> = scenes.values().flatten().collect();
+    let members = &scenes[scene];
+    let left_out = |name: &String| in_scenes.contains(name) && !members.contains(name);
+
+    for (name, handle) in handles.iter_mut().filter(|(name, _)| left_out(*name)) {
+        if let Err(e) = handle.leave_scene(backend) {
+            warn!("Failed to leave out sound '{}': {}", name, e);
+        }
+    }
+
+    for player in playlists
+        .iter_mut()
+        .filter(|player| left_out(&player.playlist.name))
+    {
+        if let Err(e) = player.leave_scene(backend, true) {
+            warn!(
+                "Failed to leave out playlist '{}': {}",
+                player.playlist.name, e
+            );
+        }
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    /// Fades in the sounds and playlists of the scene and fades out those of the other
+    /// scenes, the ones that aren't part of any scene keep playing. While paused the switch
+    /// is immediate.
+    pub(in crate::audio_engine::engine) fn apply_scene(&mut self, members: &[String]) {
+        let in_scenes: HashSet<&String> = self.scenes.values().flatten().collect();
+
+        for (name, handle) in self.sound_handles.iter_mut() {
+            if !in_scenes.contains(name) {
+                continue;
+            }
+
+            if members.contains(name) {
+                handle.enter_scene();
+            } else if self.playing {
+                handle.leaving_scene = handle.sound.enabled;
+            } else if let Err(e) = handle.leave_scene(&mut self.backend) {
+                warn!("Failed to stop sound '{}' leaving the scene: {}", name, e);
+            }
+        }
+
+        for player in self.playlists.iter_mut() {
+            let name = player.playlist.name.clone();
+            if !in_scenes.contains(&name) {
+                continue;
+            }
+
+            if members.contains(&name) {
+                player.enter_scene();
+            } else if let Err(e) = player.leave_scene(&mut self.backend, !self.playing) {
+                warn!(
+                    "Failed to stop playlist '{}' leaving the scene: {}",
+                    name, e
+                );
+            }
+        }
+    }
+
+    /// Disables the sounds that faded out completely after leaving the scene
+    pub(in crate::audio_engine::engine) fn update_scenes(&mut self) {
+        for (name, handle) in self.sound_handles.iter_mut() {
+            if handle.leaving_scene && handle.scene_gain <= 0.0 {
+                if let Err(e) = handle.leave_scene(&mut self.backend) {
+                    warn!("Failed to stop sound '{}' leaving the scene: {}", name, e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Local};
+
+use crate::audio_engine::backends::base::AudioBackend;
+use crate::audio_engine::engine::{get_random_value, AudioController, AudioEntityState};
+use crate::schedule::{in_window, Schedule, ScheduleKind};
+use crate::theme::Sound;
+
+struct ScheduledEntry {
+    sound: String,
+    schedule: Schedule,
+    /// Milliseconds until an interval schedule is due
+    countdown: u64,
+    /// Minute (since the epoch) a cron schedule was last due in, so it fires only once
+    last_minute: Option<i64>,
+}
+
+impl ScheduledEntry {
+    fn new(sound: &str, schedule: &Schedule) -> Self {
+        let mut entry = Self {
+            sound: sound.to_string(),
+            schedule: schedule.clone(),
+            countdown: 0,
+            last_minute: None,
+        };
+
+        entry.reschedule();
+        entry
+    }
+
+    fn reschedule(&mut self) {
+        if let ScheduleKind::Every { interval, .. } = self.schedule.kind {
+            self.countdown = get_random_value(interval);
+        }
+    }
+
+    fn update(&mut self, delta: u64, now: &DateTime<Local>) -> bool {
+        match self.schedule.kind {
+            ScheduleKind::Every { window, .. } => {
+                self.countdown = self.countdown.saturating_sub(delta);
+                if self.countdown > 0 {
+                    return false;
+                }
+
+                // Runs that come due outside of the window are skipped
+                self.reschedule();
+                window.map_or(true, |window| in_window(window, now))
+            }
+            ScheduleKind::Cron(ref cron) => {
+                let minute = now.timestamp() / 60;
+                if self.last_minute == Some(minute) || !cron.matches(now) {
+                    return false;
+                }
+
+                self.last_minute = Some(minute);
+                true
+            }
+        }
+    }
+}
+
+/// Starts sounds with a schedule whenever one of their schedules is due
+#[derive(Default)]
+pub struct Scheduler {
+    entries: Vec<ScheduledEntry>,
+}
+
+impl Scheduler {
+    /// Replaces all entries with the schedules of these sounds, e.g. after switching themes
+    pub fn set_sounds<'a, I: Iterator<Item = &'a Sound>>(&mut self, sounds: I) {
+        self.entries = sounds
+            .flat_map(|sound| {
+                sound
+                    .schedule
+                    .iter()
+                    .map(move |schedule| ScheduledEntry::new(&sound.name, schedule))
+            })
+            .collect();
+
+        if !self.entries.is_empty() {
+            info!("Scheduled {} sound(s)", self.entries.len());
+        }
+    }
+
+    /// Moves interval schedules forward by `delta` milliseconds of theme time and checks
+    /// cron schedules against the wall clock. Returns the sounds that are due.
+    pub fn update(&mut self, delta: u64, now: &DateTime<Local>) -> Vec<String> {
+        let mut due = Vec::new();
+
+        for entry in &mut self.entries {
+            if entry.update(delta, now) && !due.contains(&entry.sound) {
+                debug!(
+                    "Schedule '{}' of sound '{}' is due",
+                    entry.schedule, entry.sound
+                );
+                due.push(entry.sound.clone());
+            }
+        }
+
+        due
+    }
+}
+
+impl<T: AudioBackend> AudioController<T> {
+    /// Triggers the sounds whose schedule is due, sounds that are still playing from their
+    /// last run are left alone
+    pub(in crate::audio_engine::engine) fn update_schedules(&mut self, delta: u64) {
+        for sound in self.scheduler.update(delta, &Local::now()) {
+            match self.sound_handles.get_mut(&sound) {
+                Some(handle) if handle.is_in_state(&AudioEntityState::WaitingForTrigger) => {
+                    info!("Starting scheduled sound '{}'", sound);
+                    handle.is_triggered = true;
+                    self.version.bump();
+                }
+                Some(_) => debug!("Scheduled sound '{}' is still playing, skipping", sound),
+                None => {}
+            }
+        }
+    }
+}
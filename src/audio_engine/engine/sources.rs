@@ -0,0 +1,37 @@
+use crate::audio_engine::backends::base::AudioBackend;
+use crate::audio_engine::engine::AudioController;
+
+impl<T: AudioBackend> AudioController<T> {
+    /// Lets the sounds that have waited longest for a source try again, as many as the
+    /// backend has sources to spare. Sounds keep their place until they got one.
+    pub(in crate::audio_engine::engine) fn update_source_queue(&mut self) {
+        let handles = &mut self.sound_handles;
+        let queue = &mut self.source_queue;
+
+        // Sounds that started or were stopped in the meantime leave the queue
+        queue.retain(|sound| {
+            handles
+                .get(sound)
+                .map_or(false, |handle| handle.is_waiting_for_source())
+        });
+
+        let mut arrived: Vec<String> = handles
+            .iter()
+            .filter(|(name, handle)| handle.is_waiting_for_source() && !queue.contains(*name))
+            .map(|(name, _)| name.clone())
+            .collect();
+        arrived.sort();
+        queue.extend(arrived);
+
+        let spare = match self.backend.get_source_pool() {
+            Some(pool) => pool.limit.saturating_sub(pool.used),
+            None => queue.len(),
+        };
+
+        for sound in queue.iter().take(spare) {
+            if let Some(handle) = handles.get_mut(sound) {
+                handle.waiting_for_source = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Goes up with every change to the engine's state, shared with the API so it can put it
+/// into what it pushes to clients. A client that sees it skip a number missed an update.
+#[derive(Clone, Default)]
+pub struct StateVersion(Arc<AtomicU64>);
+
+impl StateVersion {
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,130 @@
+use rand::{thread_rng, Rng};
+use std::f32::consts::PI;
+
+use crate::theme::{NoiseColor, Waveform};
+
+// Sample rate of all generated buffers
+pub const SAMPLE_RATE: i32 = 44100;
+
+/// A buffer of digital silence
+pub fn silence(seconds: f32) -> Vec<i16> {
+    vec![0; (seconds * SAMPLE_RATE as f32) as usize]
+}
+
+/// A sine tone at the given frequency, `amplitude` ranges from 0.0 to 1.0
+pub fn tone(frequency: f32, seconds: f32, amplitude: f32) -> Vec<i16> {
+    let length = (seconds * SAMPLE_RATE as f32) as usize;
+    let amplitude = amplitude.max(0.0).min(1.0) * std::i16::MAX as f32;
+
+    (0..length)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            ((2.0 * PI * frequency * t).sin() * amplitude) as i16
+        })
+        .collect()
+}
+
+// Fade in/out time of synthesized tones, avoids clicks at their start and end
+const ENVELOPE_SECONDS: f32 = 0.01;
+// Longest tone that is synthesized, beeps and cues are short and the whole tone is kept
+// in memory
+pub const MAX_TONE_SECONDS: f32 = 10.0;
+
+/// Checks the frequency and duration of a tone before it is synthesized, the error says
+/// what is wrong
+pub fn check_tone(frequency: f32, seconds: f32) -> Result<(), String> {
+    if frequency > 0.0 && seconds > 0.0 && seconds <= MAX_TONE_SECONDS {
+        Ok(())
+    } else {
+        Err(format!(
+            "Frequency must be above 0 and duration between 0 and {} seconds!",
+            MAX_TONE_SECONDS
+        ))
+    }
+}
+
+/// A tone of the given waveform with a short fade in and out, `amplitude` ranges from
+/// 0.0 to 1.0
+pub fn synth(waveform: Waveform, frequency: f32, seconds: f32, amplitude: f32) -> Vec<i16> {
+    let length = (seconds * SAMPLE_RATE as f32) as usize;
+    let envelope_length = (ENVELOPE_SECONDS * SAMPLE_RATE as f32).max(1.0);
+    let amplitude = amplitude.max(0.0).min(1.0) * std::i16::MAX as f32;
+
+    (0..length)
+        .map(|i| {
+            let phase = (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin();
+            let value = match waveform {
+                Waveform::Sine => phase,
+                Waveform::Square => phase.signum(),
+            };
+
+            let envelope = (i as f32 / envelope_length)
+                .min((length - i) as f32 / envelope_length)
+                .min(1.0);
+
+            (value * envelope * amplitude) as i16
+        })
+        .collect()
+}
+
+/// Noise of the given color, `amplitude` ranges from 0.0 to 1.0
+pub fn noise(color: NoiseColor, seconds: f32, amplitude: f32) -> Vec<i16> {
+    let length = (seconds * SAMPLE_RATE as f32) as usize;
+    let amplitude = amplitude.max(0.0).min(1.0) * std::i16::MAX as f32;
+    let mut rng = thread_rng();
+
+    // Filter state of the pink (Paul Kellet's refined method) and brown noise filters
+    let mut b = [0.0f32; 7];
+    let mut last = 0.0f32;
+
+    (0..length)
+        .map(|_| {
+            let white: f32 = rng.gen_range(-1.0, 1.0);
+
+            let value = match color {
+                NoiseColor::White => white,
+                NoiseColor::Pink => {
+                    b[0] = 0.99886 * b[0] + white * 0.055_517_9;
+                    b[1] = 0.99332 * b[1] + white * 0.075_075_9;
+                    b[2] = 0.96900 * b[2] + white * 0.153_852;
+                    b[3] = 0.86650 * b[3] + white * 0.310_485_6;
+                    b[4] = 0.55000 * b[4] + white * 0.532_952_2;
+                    b[5] = -0.7616 * b[5] - white * 0.016_898;
+                    let pink = b.iter().sum::<f32>() + white * 0.5362;
+                    b[6] = white * 0.115_926;
+                    pink * 0.11
+                }
+                NoiseColor::Brown => {
+                    last = (last + 0.02 * white) / 1.02;
+                    last * 3.5
+                }
+            };
+
+            (value.max(-1.0).min(1.0) * amplitude) as i16
+        })
+        .collect()
+}
+
+/// A short, quiet beep followed by silence. Used as placeholder for missing samples so
+/// the hole in the soundscape can be heard during a sound check.
+pub fn placeholder_beep() -> Vec<i16> {
+    let mut samples = tone(880.0, 0.2, 0.2);
+    samples.extend(silence(0.8));
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tones_are_checked() {
+        assert!(check_tone(880.0, 0.2).is_ok());
+        assert!(check_tone(880.0, MAX_TONE_SECONDS).is_ok());
+        assert!(check_tone(880.0, 0.0).is_err());
+        assert!(check_tone(880.0, -1.0).is_err());
+        assert!(check_tone(880.0, MAX_TONE_SECONDS + 0.1).is_err());
+        assert!(check_tone(880.0, std::f32::NAN).is_err());
+        assert!(check_tone(0.0, 0.2).is_err());
+    }
+}
@@ -2,6 +2,102 @@ use std::path::PathBuf;
 
 use crate::audio_engine::loader::error::AudioFileLoaderError;
 
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SampleMetadata {
+  pub title: Option<String>,
+  pub artist: Option<String>,
+  pub album: Option<String>,
+  pub genre: Option<String>,
+  pub comment: Option<String>,
+}
+
+/// Format of the samples decoded from a file
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SampleFormat {
+  pub sample_rate: i32,
+  /// Channels of the file, they are mixed down to mono for playback
+  pub channels: u32,
+}
+
+/// A file decoded piece by piece, for files too long to keep in memory as a whole
+pub trait AudioStream {
+  fn sample_rate(&self) -> i32;
+  /// Channels of the samples `read` returns, 2 for stereo files that are kept stereo
+  fn channels(&self) -> usize;
+  /// Decodes up to `frames` frames (a sample for each channel, interleaved), nothing is
+  /// returned at the end of the file
+  fn read(&mut self, frames: usize) -> Result<Vec<i16>, AudioFileLoaderError>;
+  /// Starts over at the beginning of the file
+  fn rewind(&mut self) -> Result<(), AudioFileLoaderError>;
+  /// Continues at `frame` without decoding what comes before, as far as the format allows.
+  /// Past the end of the file nothing is returned anymore.
+  fn seek(&mut self, frame: usize) -> Result<(), AudioFileLoaderError>;
+}
+
+// Frames decoded at once while skipping
+const SKIP_CHUNK_FRAMES: usize = 4096;
+
+/// Decodes and drops the next `frames` frames, for the part of a seek the format can't jump
+pub fn skip_frames<S: AudioStream + ?Sized>(
+  stream: &mut S,
+  frames: usize,
+) -> Result<(), AudioFileLoaderError> {
+  let mut skip = frames;
+  while skip > 0 {
+    let samples = stream.read(skip.min(SKIP_CHUNK_FRAMES))?;
+    if samples.is_empty() {
+      break;
+    }
+
+    skip = skip.saturating_sub(samples.len() / stream.channels());
+  }
+
+  Ok(())
+}
+
 pub trait AudioFileLoader {
   fn load(&mut self, path: &PathBuf) -> Result<(Vec<i16>, i32), AudioFileLoaderError>;
+  /// Like `load`, but stereo files keep both channels, interleaved. Returns the number of
+  /// channels as well, files that aren't stereo are mixed down to mono as usual.
+  fn load_stereo(&mut self, path: &PathBuf)
+    -> Result<(Vec<i16>, i32, usize), AudioFileLoaderError>;
+  fn stream(
+    &mut self,
+    path: &PathBuf,
+    keep_stereo: bool,
+  ) -> Result<Box<dyn AudioStream>, AudioFileLoaderError>;
+  fn metadata(&mut self, path: &PathBuf) -> Result<SampleMetadata, AudioFileLoaderError>;
+  /// Read from the header, without decoding the file
+  fn format(&mut self, path: &PathBuf) -> Result<SampleFormat, AudioFileLoaderError>;
+  /// Length in seconds, read without decoding the whole file
+  fn duration(&mut self, path: &PathBuf) -> Result<f32, AudioFileLoaderError>;
+  /// Track gain in dB of the file's ReplayGain tag, None if it has none
+  fn replay_gain(&mut self, path: &PathBuf) -> Result<Option<f32>, AudioFileLoaderError>;
+}
+
+/// Reads the value of a REPLAYGAIN_TRACK_GAIN tag, e.g. "-6.54 dB"
+pub fn parse_replay_gain(value: &str) -> Option<f32> {
+  value
+    .trim()
+    .trim_end_matches("dB")
+    .trim_end_matches("db")
+    .trim()
+    .parse()
+    .ok()
+    .filter(|gain: &f32| gain.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn replay_gain_tags_are_parsed() {
+    assert_eq!(parse_replay_gain("-6.54 dB"), Some(-6.54));
+    assert_eq!(parse_replay_gain("+2.10 dB"), Some(2.1));
+    assert_eq!(parse_replay_gain(" 0.5db"), Some(0.5));
+    assert_eq!(parse_replay_gain("-3"), Some(-3.0));
+    assert_eq!(parse_replay_gain("loud"), None);
+    assert_eq!(parse_replay_gain("NaN dB"), None);
+  }
 }
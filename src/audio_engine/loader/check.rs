@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::audio_engine::loader::error::AudioFileLoaderError;
+use crate::audio_engine::loader::get_loader_for_file;
+
+// Sample rates files are usually recorded at, anything else hints at a broken header
+const COMMON_SAMPLE_RATES: [i32; 9] =
+    [8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000];
+
+// Share of samples at full scale above which a file counts as clipped
+const CLIPPED_SHARE: f32 = 0.001;
+
+// Longer files aren't decoded a second time just to look for clipping
+const CLIP_CHECK_MAX_SECONDS: f32 = 60.0;
+
+// Number of (min, max) pairs a waveform is reduced to, enough for a full-width display
+const WAVEFORM_PEAKS: usize = 1000;
+
+/// Looks for problems that don't keep a file from playing, but likely make it sound wrong
+pub fn decode_warnings(path: &PathBuf) -> Result<Vec<String>, AudioFileLoaderError> {
+    let mut loader = get_loader_for_file(path)?;
+    let format = loader.format(path)?;
+    let mut warnings = Vec::new();
+
+    if !COMMON_SAMPLE_RATES.contains(&format.sample_rate) {
+        warnings.push(format!("unusual sample rate of {} Hz", format.sample_rate));
+    }
+
+    // Only stereo is mixed down to mono, other channel layouts play interleaved
+    if format.channels > 2 {
+        warnings.push(format!(
+            "{} channels, only mono and stereo files are mixed down",
+            format.channels
+        ));
+    }
+
+    if loader.duration(path)? <= CLIP_CHECK_MAX_SECONDS {
+        let (samples, _) = loader.load(path)?;
+        let clipped = samples
+            .iter()
+            .filter(|s| **s == i16::max_value() || **s == i16::min_value())
+            .count();
+
+        if !samples.is_empty() && clipped as f32 / samples.len() as f32 > CLIPPED_SHARE {
+            warnings.push(format!(
+                "{} of {} samples are clipped",
+                clipped,
+                samples.len()
+            ));
+        }
+    }
+
+    Ok(warnings)
+}
+
+/// Warnings `decode_warnings` found, by path. They are kept until the file is modified, so
+/// loading a theme again doesn't decode its files a second time. Clones share the cache,
+/// workers can fill it as well.
+#[derive(Clone, Default)]
+pub struct WarningCache {
+    checked: Arc<Mutex<HashMap<PathBuf, (SystemTime, Vec<String>)>>>,
+}
+
+impl WarningCache {
+    pub fn decode_warnings(&self, path: &PathBuf) -> Result<Vec<String>, AudioFileLoaderError> {
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        if let Some(modified) = modified {
+            if let Some((checked, warnings)) = self.checked.lock().unwrap().get(path) {
+                if *checked == modified {
+                    return Ok(warnings.clone());
+                }
+            }
+        }
+
+        let warnings = decode_warnings(path)?;
+        if let Some(modified) = modified {
+            self.checked
+                .lock()
+                .unwrap()
+                .insert(path.clone(), (modified, warnings.clone()));
+        }
+
+        Ok(warnings)
+    }
+}
+
+/// Peak and RMS level of a file, both linear from 0.0 (silence) to 1.0 (full scale)
+pub struct Levels {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+pub fn measure_levels(path: &PathBuf) -> Result<Levels, AudioFileLoaderError> {
+    let (samples, _) = get_loader_for_file(path)?.load(path)?;
+    let full_scale = f32::from(i16::max_value());
+
+    let mut peak: f32 = 0.0;
+    let mut sum_of_squares = 0.0f64;
+    for sample in &samples {
+        let value = f32::from(*sample) / full_scale;
+        peak = peak.max(value.abs());
+        sum_of_squares += f64::from(value * value);
+    }
+
+    let rms = if samples.is_empty() {
+        0.0
+    } else {
+        (sum_of_squares / samples.len() as f64).sqrt() as f32
+    };
+
+    Ok(Levels {
+        peak: peak.min(1.0),
+        rms,
+    })
+}
+
+/// A file reduced to the lowest and highest sample of evenly sized chunks, for drawing
+/// its waveform
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peaks {
+    /// Length in seconds
+    pub duration: f32,
+    /// (min, max) of each chunk, from -1.0 to 1.0
+    pub peaks: Vec<(f32, f32)>,
+}
+
+pub fn compute_peaks(path: &PathBuf) -> Result<Peaks, AudioFileLoaderError> {
+    let (samples, rate) = get_loader_for_file(path)?.load(path)?;
+    let full_scale = f32::from(i16::max_value());
+    // Four decimals are plenty for a drawing and keep the JSON small
+    let round = |value: i16| (f32::from(value) / full_scale * 10000.0).round() / 10000.0;
+
+    let chunk = (samples.len() + WAVEFORM_PEAKS - 1) / WAVEFORM_PEAKS;
+    let peaks = samples
+        .chunks(chunk.max(1))
+        .map(|chunk| {
+            let min = chunk.iter().min().cloned().unwrap_or(0);
+            let max = chunk.iter().max().cloned().unwrap_or(0);
+            (round(min).max(-1.0), round(max).min(1.0))
+        })
+        .collect();
+
+    Ok(Peaks {
+        duration: if rate > 0 {
+            samples.len() as f32 / rate as f32
+        } else {
+            0.0
+        },
+        peaks,
+    })
+}
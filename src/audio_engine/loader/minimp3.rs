@@ -1,15 +1,260 @@
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::mem;
 use std::path::PathBuf;
 
 use minimp3::{Decoder, Error as MiniMP3Error, Frame};
 
-use crate::audio_engine::loader::base::AudioFileLoader;
+use crate::audio_engine::loader::base::{
+    parse_replay_gain, skip_frames, AudioFileLoader, AudioStream, SampleFormat, SampleMetadata,
+};
 use crate::audio_engine::loader::error::AudioFileLoaderError;
 use crate::utils::convert_to_mono;
 
+// Frames decoded ahead of a seek, the audio of a frame can reach back into the ones before
+const SEEK_PREROLL_FRAMES: usize = 2;
+
+// Bitrates in kbit/s by the index in the frame header
+const BITRATES_V1_L1: [usize; 15] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const BITRATES_V1_L2: [usize; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const BITRATES_V1_L3: [usize; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const BITRATES_V2_L1: [usize; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const BITRATES_V2_L23: [usize; 15] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160,
+];
+
 pub struct MiniMP3Loader;
 
+/// Bytes of the ID3v2 tag in front of the first frame, 0 if there is none
+fn tag_length(header: &[u8; 10]) -> u64 {
+    if &header[..3] != b"ID3" {
+        return 0;
+    }
+
+    // Sizes are stored in 7 bits per byte
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, &byte| (size << 7) | (byte & 0x7F) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+
+    10 + size + footer
+}
+
+/// Samples per channel and length in bytes of the frame with this header, None if it isn't
+/// a frame header or the frame doesn't tell its length (free format)
+fn frame_size(header: [u8; 4]) -> Option<(usize, usize)> {
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    // 3 is MPEG 1, 2 MPEG 2 and 0 MPEG 2.5
+    let version = (header[1] >> 3) & 0x03;
+    // 3 is layer I, 2 layer II and 1 layer III
+    let layer = (header[1] >> 1) & 0x03;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let rate_index = ((header[2] >> 2) & 0x03) as usize;
+    let padding = ((header[2] >> 1) & 0x01) as usize;
+
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3
+    {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let bitrates = match (mpeg1, layer) {
+        (true, 3) => BITRATES_V1_L1,
+        (true, 2) => BITRATES_V1_L2,
+        (true, _) => BITRATES_V1_L3,
+        (false, 3) => BITRATES_V2_L1,
+        (false, _) => BITRATES_V2_L23,
+    };
+    let bitrate = bitrates[bitrate_index] * 1000;
+    let sample_rate = [44100, 48000, 32000][rate_index]
+        >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+
+    Some(match layer {
+        3 => (384, (12 * bitrate / sample_rate + padding) * 4),
+        1 if !mpeg1 => (576, 72 * bitrate / sample_rate + padding),
+        _ => (1152, 144 * bitrate / sample_rate + padding),
+    })
+}
+
+pub struct MiniMP3Stream {
+    path: PathBuf,
+    decoder: Decoder<File>,
+    sample_rate: i32,
+    keep_stereo: bool,
+    // Channels of the samples handed out, set by the first frame
+    channels: usize,
+    // Samples of the last decoded frame that didn't fit into the previous read
+    pending: Vec<i16>,
+}
+
+impl MiniMP3Stream {
+    fn open(path: &PathBuf, keep_stereo: bool) -> Result<Self, AudioFileLoaderError> {
+        let file = File::open(path).map_err(|e| {
+            AudioFileLoaderError::FileLoadError(
+                path.to_string_lossy().into_owned(),
+                e.description().to_string(),
+            )
+        })?;
+
+        let mut stream = MiniMP3Stream {
+            path: path.clone(),
+            decoder: Decoder::new(file),
+            sample_rate: 0,
+            keep_stereo,
+            channels: 0,
+            pending: Vec::new(),
+        };
+
+        // The sample rate and channels are only known after decoding the first frame
+        match stream.next_frame()? {
+            Some(samples) => stream.pending = samples,
+            None => {
+                return Err(AudioFileLoaderError::FileLoadError(
+                    path.to_string_lossy().into_owned(),
+                    "File contains no audio".to_owned(),
+                ))
+            }
+        }
+
+        Ok(stream)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Vec<i16>>, AudioFileLoaderError> {
+        match self.decoder.next_frame() {
+            Ok(Frame {
+                data,
+                sample_rate,
+                channels,
+                ..
+            }) => {
+                self.sample_rate = sample_rate;
+                if self.channels == 0 {
+                    self.channels = if channels == 2 && self.keep_stereo {
+                        2
+                    } else {
+                        1
+                    };
+                }
+
+                // Frames can switch between mono and stereo, the stream sticks to the
+                // channels of the first one
+                match (channels, self.channels) {
+                    (2, 1) => Ok(Some(convert_to_mono(data))),
+                    (1, 2) => Ok(Some(data.iter().flat_map(|&s| vec![s, s]).collect())),
+                    _ => Ok(Some(data)),
+                }
+            }
+            Err(MiniMP3Error::Eof) => Ok(None),
+            Err(e) => Err(AudioFileLoaderError::FileLoadError(
+                self.path.to_string_lossy().into_owned(),
+                e.description().to_string(),
+            )),
+        }
+    }
+}
+
+impl AudioStream for MiniMP3Stream {
+    fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, frames: usize) -> Result<Vec<i16>, AudioFileLoaderError> {
+        let samples = frames * self.channels;
+        while self.pending.len() < samples {
+            match self.next_frame()? {
+                Some(mut frame) => self.pending.append(&mut frame),
+                None => break,
+            }
+        }
+
+        let rest = if self.pending.len() > samples {
+            self.pending.split_off(samples)
+        } else {
+            Vec::new()
+        };
+
+        Ok(mem::replace(&mut self.pending, rest))
+    }
+
+    /// The decoder buffers ahead, so rather than seeking start over with a new one
+    fn rewind(&mut self) -> Result<(), AudioFileLoaderError> {
+        *self = Self::open(&self.path, self.keep_stereo)?;
+        Ok(())
+    }
+
+    /// Walks the frame headers up to the frame and decodes from a few frames before it with
+    /// a new decoder. Files whose frames don't tell their length are decoded up to it.
+    fn seek(&mut self, frame: usize) -> Result<(), AudioFileLoaderError> {
+        let path = self.path.clone();
+        let io_error = |e: std::io::Error| {
+            AudioFileLoaderError::FileLoadError(
+                path.to_string_lossy().into_owned(),
+                e.description().to_string(),
+            )
+        };
+
+        let mut file = File::open(&self.path).map_err(io_error)?;
+        let mut tag = [0u8; 10];
+        let mut offset = match file.read_exact(&mut tag) {
+            Ok(()) => tag_length(&tag),
+            Err(_) => 0,
+        };
+
+        // Starts of the last frames walked as (offset in bytes, frames before it)
+        let mut starts = VecDeque::new();
+        let mut skipped = 0;
+        loop {
+            starts.push_back((offset, skipped));
+            if starts.len() > SEEK_PREROLL_FRAMES + 1 {
+                starts.pop_front();
+            }
+
+            let mut header = [0u8; 4];
+            if file.seek(SeekFrom::Start(offset)).is_err() || file.read_exact(&mut header).is_err()
+            {
+                break;
+            }
+
+            match frame_size(header) {
+                Some((samples, length)) if skipped + samples <= frame => {
+                    skipped += samples;
+                    offset += length as u64;
+                }
+                // The frame holds the position, or the rest has to be decoded
+                _ => break,
+            }
+        }
+
+        let (offset, skipped) = starts.pop_front().unwrap_or((0, 0));
+        file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+        self.decoder = Decoder::new(file);
+        self.pending.clear();
+
+        skip_frames(self, frame - skipped)
+    }
+}
+
 impl AudioFileLoader for MiniMP3Loader {
     fn load(&mut self, path: &PathBuf) -> Result<(Vec<i16>, i32), AudioFileLoaderError> {
         let file = match File::open(path) {
@@ -53,4 +298,120 @@ impl AudioFileLoader for MiniMP3Loader {
 
         Ok((samples, final_sample_rate))
     }
+
+    fn load_stereo(
+        &mut self,
+        path: &PathBuf,
+    ) -> Result<(Vec<i16>, i32, usize), AudioFileLoaderError> {
+        let mut stream = MiniMP3Stream::open(path, true)?;
+
+        let mut samples = mem::replace(&mut stream.pending, Vec::new());
+        while let Some(mut frame) = stream.next_frame()? {
+            samples.append(&mut frame);
+        }
+
+        Ok((samples, stream.sample_rate, stream.channels))
+    }
+
+    fn stream(
+        &mut self,
+        path: &PathBuf,
+        keep_stereo: bool,
+    ) -> Result<Box<dyn AudioStream>, AudioFileLoaderError> {
+        Ok(Box::new(MiniMP3Stream::open(path, keep_stereo)?))
+    }
+
+    fn metadata(&mut self, path: &PathBuf) -> Result<SampleMetadata, AudioFileLoaderError> {
+        let tag = match id3::Tag::read_from_path(path) {
+            Ok(tag) => tag,
+            // Plenty of mp3 files simply don't carry an ID3 tag
+            Err(ref e) if e.kind == id3::ErrorKind::NoTag => return Ok(SampleMetadata::default()),
+            Err(e) => {
+                return Err(AudioFileLoaderError::FileLoadError(
+                    path.to_string_lossy().into_owned(),
+                    e.description().to_string(),
+                ))
+            }
+        };
+
+        Ok(SampleMetadata {
+            title: tag.title().map(str::to_owned),
+            artist: tag.artist().map(str::to_owned),
+            album: tag.album().map(str::to_owned),
+            genre: tag.genre().map(str::to_owned),
+            comment: tag.comments().next().map(|c| c.text.clone()),
+        })
+    }
+
+    fn format(&mut self, path: &PathBuf) -> Result<SampleFormat, AudioFileLoaderError> {
+        let file = File::open(path).map_err(|e| {
+            AudioFileLoaderError::FileLoadError(
+                path.to_string_lossy().into_owned(),
+                e.description().to_string(),
+            )
+        })?;
+
+        match Decoder::new(file).next_frame() {
+            Ok(Frame {
+                sample_rate,
+                channels,
+                ..
+            }) => Ok(SampleFormat {
+                sample_rate,
+                channels: channels as u32,
+            }),
+            Err(e) => Err(AudioFileLoaderError::FileLoadError(
+                path.to_string_lossy().into_owned(),
+                e.description().to_string(),
+            )),
+        }
+    }
+
+    fn duration(&mut self, path: &PathBuf) -> Result<f32, AudioFileLoaderError> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(AudioFileLoaderError::FileLoadError(
+                    path.to_string_lossy().into_owned(),
+                    e.description().to_string(),
+                ))
+            }
+        };
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+        // Decoding the whole file is too slow for scanning a library, estimate the length
+        // from the bitrate of the first frame instead
+        match Decoder::new(file).next_frame() {
+            Ok(Frame { bitrate, .. }) if bitrate > 0 => {
+                Ok(size as f32 * 8.0 / (bitrate as f32 * 1000.0))
+            }
+            Ok(_) => Ok(0.0),
+            Err(e) => Err(AudioFileLoaderError::FileLoadError(
+                path.to_string_lossy().into_owned(),
+                e.description().to_string(),
+            )),
+        }
+    }
+
+    /// Taggers store it in a user defined text frame (TXXX)
+    fn replay_gain(&mut self, path: &PathBuf) -> Result<Option<f32>, AudioFileLoaderError> {
+        let tag = match id3::Tag::read_from_path(path) {
+            Ok(tag) => tag,
+            Err(ref e) if e.kind == id3::ErrorKind::NoTag => return Ok(None),
+            Err(e) => {
+                return Err(AudioFileLoaderError::FileLoadError(
+                    path.to_string_lossy().into_owned(),
+                    e.description().to_string(),
+                ))
+            }
+        };
+
+        Ok(tag
+            .extended_texts()
+            .find(|text| {
+                text.description
+                    .eq_ignore_ascii_case("REPLAYGAIN_TRACK_GAIN")
+            })
+            .and_then(|text| parse_replay_gain(&text.value)))
+    }
 }
@@ -1,6 +1,8 @@
 pub mod base;
+pub mod check;
 pub mod error;
 mod minimp3;
+mod opus;
 mod sndfile;
 
 use std::ffi::OsStr;
@@ -8,6 +10,7 @@ use std::path::PathBuf;
 
 use crate::audio_engine::loader::base::AudioFileLoader;
 use crate::audio_engine::loader::minimp3::MiniMP3Loader;
+use crate::audio_engine::loader::opus::OpusLoader;
 use crate::audio_engine::loader::sndfile::SndFileLoader;
 
 use crate::audio_engine::loader::error::AudioFileLoaderError;
@@ -19,6 +22,7 @@ pub fn get_loader_for_file(
     match ext {
         Some("mp3") => Ok(Box::new(MiniMP3Loader {})),
         Some("wav") | Some("ogg") => Ok(Box::new(SndFileLoader {})),
+        Some("opus") => Ok(Box::new(OpusLoader {})),
 
         _ => {
             error!("No loader installed for extension {}", ext.unwrap());
@@ -0,0 +1,351 @@
+use std::fs::File;
+use std::mem;
+use std::path::PathBuf;
+
+use audiopus::coder::Decoder;
+use audiopus::{Channels, SampleRate};
+use ogg::PacketReader;
+
+use crate::audio_engine::loader::base::{
+    parse_replay_gain, AudioFileLoader, AudioStream, SampleFormat, SampleMetadata,
+};
+use crate::audio_engine::loader::error::AudioFileLoaderError;
+use crate::utils::convert_to_mono;
+
+// Opus always decodes at 48kHz, whatever the rate of the original recording was
+const OPUS_SAMPLE_RATE: i32 = 48000;
+
+// Samples per channel of the longest possible opus packet, 120ms
+const MAX_PACKET_SAMPLES: usize = 5760;
+
+// Samples per channel decoded ahead of a seek, 80ms, a decoder starting in the middle of a
+// stream needs them to settle
+const SEEK_PREROLL_SAMPLES: u64 = 3840;
+
+pub struct OpusLoader;
+
+fn load_error<E: ToString>(path: &PathBuf, error: E) -> AudioFileLoaderError {
+    AudioFileLoaderError::FileLoadError(path.to_string_lossy().into_owned(), error.to_string())
+}
+
+fn next_packet(
+    reader: &mut PacketReader<File>,
+    path: &PathBuf,
+) -> Result<Option<ogg::Packet>, AudioFileLoaderError> {
+    reader.read_packet().map_err(|e| load_error(path, e))
+}
+
+/// Fields of the identification header every opus stream starts with
+struct OpusHeader {
+    channels: usize,
+    // Samples the encoder put in front of the audio, to be dropped after decoding
+    pre_skip: usize,
+}
+
+/// Opens the file and reads the identification header, the next packet is the comment
+/// header
+fn open(path: &PathBuf) -> Result<(PacketReader<File>, OpusHeader), AudioFileLoaderError> {
+    let file = File::open(path).map_err(|e| load_error(path, e))?;
+    let mut reader = PacketReader::new(file);
+
+    let header = match next_packet(&mut reader, path)? {
+        Some(ref packet) if packet.data.len() >= 19 && packet.data.starts_with(b"OpusHead") => {
+            OpusHeader {
+                channels: packet.data[9] as usize,
+                pre_skip: u16::from_le_bytes([packet.data[10], packet.data[11]]) as usize,
+            }
+        }
+        _ => return Err(load_error(path, "Not an opus file")),
+    };
+
+    // Surround files need a multistream decoder
+    if header.channels == 0 || header.channels > 2 {
+        return Err(load_error(
+            path,
+            format!("Unsupported number of channels {}", header.channels),
+        ));
+    }
+
+    Ok((reader, header))
+}
+
+/// Parses the vorbis comments ('KEY=value') of the comment header
+fn read_comments(data: &[u8]) -> Vec<(String, String)> {
+    fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+        data.get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
+
+    let mut comments = Vec::new();
+    if !data.starts_with(b"OpusTags") {
+        return comments;
+    }
+
+    // Skip the vendor string
+    let mut offset = match read_u32(data, 8) {
+        Some(length) => 12 + length,
+        None => return comments,
+    };
+    let count = read_u32(data, offset).unwrap_or(0);
+    offset += 4;
+
+    for _ in 0..count {
+        let length = match read_u32(data, offset) {
+            Some(length) => length,
+            None => break,
+        };
+        let comment = match data.get(offset + 4..offset + 4 + length) {
+            Some(comment) => String::from_utf8_lossy(comment),
+            None => break,
+        };
+        offset += 4 + length;
+
+        let mut parts = comment.splitn(2, '=');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            comments.push((key.to_uppercase(), value.trim().to_owned()));
+        }
+    }
+
+    comments
+}
+
+/// The vorbis comments of the file, from the header following the identification header
+fn file_comments(path: &PathBuf) -> Result<Vec<(String, String)>, AudioFileLoaderError> {
+    let (mut reader, _) = open(path)?;
+
+    Ok(match next_packet(&mut reader, path)? {
+        Some(packet) => read_comments(&packet.data),
+        None => Vec::new(),
+    })
+}
+
+pub struct OpusStream {
+    path: PathBuf,
+    reader: PacketReader<File>,
+    decoder: Decoder,
+    channels: usize,
+    keep_stereo: bool,
+    // Pre-skip samples (per channel) left to drop
+    skip: usize,
+    // Samples of the last decoded packet that didn't fit into the previous read
+    pending: Vec<i16>,
+}
+
+impl OpusStream {
+    fn open(path: &PathBuf, keep_stereo: bool) -> Result<Self, AudioFileLoaderError> {
+        let (mut reader, header) = open(path)?;
+
+        // The comment header carries no audio
+        next_packet(&mut reader, path)?;
+
+        let channels = if header.channels == 1 {
+            Channels::Mono
+        } else {
+            Channels::Stereo
+        };
+        let decoder =
+            Decoder::new(SampleRate::Hz48000, channels).map_err(|e| load_error(path, e))?;
+
+        Ok(OpusStream {
+            path: path.clone(),
+            reader,
+            decoder,
+            channels: header.channels,
+            keep_stereo,
+            skip: header.pre_skip,
+            pending: Vec::new(),
+        })
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Vec<i16>>, AudioFileLoaderError> {
+        match next_packet(&mut self.reader, &self.path)? {
+            Some(packet) => Ok(Some(self.decode_packet(&packet)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_packet(&mut self, packet: &ogg::Packet) -> Result<Vec<i16>, AudioFileLoaderError> {
+        let mut samples = vec![0i16; MAX_PACKET_SAMPLES * self.channels];
+        let decoded = self
+            .decoder
+            .decode(Some(&packet.data[..]), &mut samples[..], false)
+            .map_err(|e| load_error(&self.path, e))?;
+        samples.truncate(decoded * self.channels);
+
+        if self.channels == 2 && !self.keep_stereo {
+            samples = convert_to_mono(samples);
+        }
+
+        let channels = self.output_channels();
+        let skip = self.skip.min(samples.len() / channels);
+        samples.drain(..skip * channels);
+        self.skip -= skip;
+
+        Ok(samples)
+    }
+
+    fn output_channels(&self) -> usize {
+        if self.channels == 2 && self.keep_stereo {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+impl AudioStream for OpusStream {
+    fn sample_rate(&self) -> i32 {
+        OPUS_SAMPLE_RATE
+    }
+
+    fn channels(&self) -> usize {
+        self.output_channels()
+    }
+
+    fn read(&mut self, frames: usize) -> Result<Vec<i16>, AudioFileLoaderError> {
+        let samples = frames * self.output_channels();
+        while self.pending.len() < samples {
+            match self.next_packet()? {
+                Some(mut packet) => self.pending.append(&mut packet),
+                None => break,
+            }
+        }
+
+        let rest = if self.pending.len() > samples {
+            self.pending.split_off(samples)
+        } else {
+            Vec::new()
+        };
+
+        Ok(mem::replace(&mut self.pending, rest))
+    }
+
+    fn rewind(&mut self) -> Result<(), AudioFileLoaderError> {
+        *self = Self::open(&self.path, self.keep_stereo)?;
+        Ok(())
+    }
+
+    /// Skips the pages before the frame without decoding them, the decoder starts a little
+    /// ahead of it to settle
+    fn seek(&mut self, frame: usize) -> Result<(), AudioFileLoaderError> {
+        self.rewind()?;
+
+        // Granule positions count the pre-skip samples as well
+        let goal = (frame + self.skip) as u64;
+        let start = goal.saturating_sub(SEEK_PREROLL_SAMPLES);
+
+        // A page only tells where its last packet ends, so this is where the first packet
+        // of the next page starts
+        let mut position = 0;
+        let packet = loop {
+            match next_packet(&mut self.reader, &self.path)? {
+                Some(ref packet) if packet.absgp_page() < start => {
+                    position = packet.absgp_page()
+                }
+                Some(packet) => break packet,
+                // Past the end of the file
+                None => return Ok(()),
+            }
+        };
+
+        self.skip = (goal - position) as usize;
+        self.pending = self.decode_packet(&packet)?;
+
+        Ok(())
+    }
+}
+
+fn decode(
+    path: &PathBuf,
+    keep_stereo: bool,
+) -> Result<(Vec<i16>, i32, usize), AudioFileLoaderError> {
+    let mut stream = OpusStream::open(path, keep_stereo)?;
+
+    let mut samples = Vec::new();
+    while let Some(mut packet) = stream.next_packet()? {
+        samples.append(&mut packet);
+    }
+
+    Ok((samples, OPUS_SAMPLE_RATE, stream.output_channels()))
+}
+
+impl AudioFileLoader for OpusLoader {
+    fn load(&mut self, path: &PathBuf) -> Result<(Vec<i16>, i32), AudioFileLoaderError> {
+        let (samples, sample_rate, _) = decode(path, false)?;
+        Ok((samples, sample_rate))
+    }
+
+    fn load_stereo(
+        &mut self,
+        path: &PathBuf,
+    ) -> Result<(Vec<i16>, i32, usize), AudioFileLoaderError> {
+        decode(path, true)
+    }
+
+    fn stream(
+        &mut self,
+        path: &PathBuf,
+        keep_stereo: bool,
+    ) -> Result<Box<dyn AudioStream>, AudioFileLoaderError> {
+        Ok(Box::new(OpusStream::open(path, keep_stereo)?))
+    }
+
+    fn metadata(&mut self, path: &PathBuf) -> Result<SampleMetadata, AudioFileLoaderError> {
+        let comments = file_comments(path)?;
+        let find = |key: &str| {
+            comments
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        Ok(SampleMetadata {
+            title: find("TITLE"),
+            artist: find("ARTIST"),
+            album: find("ALBUM"),
+            genre: find("GENRE"),
+            comment: find("COMMENT").or_else(|| find("DESCRIPTION")),
+        })
+    }
+
+    fn format(&mut self, path: &PathBuf) -> Result<SampleFormat, AudioFileLoaderError> {
+        let (_, header) = open(path)?;
+
+        Ok(SampleFormat {
+            sample_rate: OPUS_SAMPLE_RATE,
+            channels: header.channels as u32,
+        })
+    }
+
+    /// The granule position of the last page counts the samples of the whole file
+    fn duration(&mut self, path: &PathBuf) -> Result<f32, AudioFileLoaderError> {
+        let (mut reader, header) = open(path)?;
+
+        let mut granule = 0;
+        while let Some(packet) = next_packet(&mut reader, path)? {
+            granule = packet.absgp_page();
+        }
+
+        Ok(granule.saturating_sub(header.pre_skip as u64) as f32 / OPUS_SAMPLE_RATE as f32)
+    }
+
+    /// Opus files usually carry an R128 gain instead, in 1/256 dB towards -23 LUFS. That is
+    /// 5 dB quieter than the reference of ReplayGain.
+    fn replay_gain(&mut self, path: &PathBuf) -> Result<Option<f32>, AudioFileLoaderError> {
+        let comments = file_comments(path)?;
+        let find = |key: &str| {
+            comments
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.as_str())
+        };
+
+        Ok(find("REPLAYGAIN_TRACK_GAIN")
+            .and_then(parse_replay_gain)
+            .or_else(|| {
+                find("R128_TRACK_GAIN")
+                    .and_then(|value| value.parse::<i16>().ok())
+                    .map(|gain| f32::from(gain) / 256.0 + 5.0)
+            }))
+    }
+}
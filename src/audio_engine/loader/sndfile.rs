@@ -1,34 +1,33 @@
 use sndfile_sys;
 
 use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
 use std::path::PathBuf;
 use std::ptr;
 
-use crate::audio_engine::loader::base::AudioFileLoader;
+use crate::audio_engine::loader::base::{
+    AudioFileLoader, AudioStream, SampleFormat, SampleMetadata,
+};
 use crate::audio_engine::loader::error::AudioFileLoaderError;
 use crate::utils::convert_to_mono;
 
+// Whence for sf_seek, as in stdio
+const SEEK_SET: c_int = 0;
+
 pub struct SndFileLoader;
 
 //#[link(name = "libsndfile")]
 //extern "C" {}
 
-impl AudioFileLoader for SndFileLoader {
-    fn load(&mut self, path: &PathBuf) -> Result<(Vec<i16>, i32), AudioFileLoaderError> {
-        let mut info = Box::new(sndfile_sys::SF_INFO {
-            frames: 0,
-            samplerate: 0,
-            channels: 0,
-            format: 0,
-            sections: 0,
-            seekable: 0,
-        });
-
+impl SndFileLoader {
+    fn open(
+        path: &PathBuf,
+        info: &mut sndfile_sys::SF_INFO,
+    ) -> Result<*mut sndfile_sys::SNDFILE, AudioFileLoaderError> {
         let path_c = CString::new(path.to_str().unwrap()).unwrap();
-        let tmp_sndfile =
-            unsafe { sndfile_sys::sf_open(path_c.into_raw(), sndfile_sys::SFM_READ, &mut *info) };
+        let sndfile = unsafe { sndfile_sys::sf_open(path_c.as_ptr(), sndfile_sys::SFM_READ, info) };
 
-        if tmp_sndfile.is_null() {
+        if sndfile.is_null() {
             return Err(AudioFileLoaderError::FileLoadError(
                 path.to_string_lossy().into_owned(),
                 unsafe {
@@ -40,6 +39,16 @@ impl AudioFileLoader for SndFileLoader {
             ));
         }
 
+        Ok(sndfile)
+    }
+
+    fn decode(
+        path: &PathBuf,
+        keep_stereo: bool,
+    ) -> Result<(Vec<i16>, i32, usize), AudioFileLoaderError> {
+        let mut info = Self::empty_info();
+        let tmp_sndfile = Self::open(path, &mut *info)?;
+
         let len = info.channels as i64 * info.frames;
         let mut samples = vec![0i16; len as usize];
         unsafe {
@@ -48,13 +57,205 @@ impl AudioFileLoader for SndFileLoader {
                 samples.as_mut_slice().as_mut_ptr(),
                 len as i64,
             );
+            sndfile_sys::sf_close(tmp_sndfile);
         }
 
         // If we get a stereo file, convert it to mono
-        if info.channels == 2 {
+        if info.channels == 2 && !keep_stereo {
             samples = convert_to_mono(samples);
         }
 
-        Ok((samples, info.samplerate))
+        let channels = if info.channels == 2 && keep_stereo {
+            2
+        } else {
+            1
+        };
+
+        Ok((samples, info.samplerate, channels))
+    }
+
+    fn empty_info() -> Box<sndfile_sys::SF_INFO> {
+        Box::new(sndfile_sys::SF_INFO {
+            frames: 0,
+            samplerate: 0,
+            channels: 0,
+            format: 0,
+            sections: 0,
+            seekable: 0,
+        })
+    }
+}
+
+pub struct SndFileStream {
+    sndfile: *mut sndfile_sys::SNDFILE,
+    path: PathBuf,
+    channels: usize,
+    sample_rate: i32,
+    // Length of the file in frames
+    frames: i64,
+    keep_stereo: bool,
+}
+
+impl AudioStream for SndFileStream {
+    fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        if self.channels == 2 && self.keep_stereo {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn read(&mut self, frames: usize) -> Result<Vec<i16>, AudioFileLoaderError> {
+        let mut buffer = vec![0i16; frames * self.channels];
+        let frames = unsafe {
+            sndfile_sys::sf_readf_short(self.sndfile, buffer.as_mut_ptr(), frames as i64)
+        };
+        buffer.truncate(frames.max(0) as usize * self.channels);
+
+        if self.channels == 2 && !self.keep_stereo {
+            buffer = convert_to_mono(buffer);
+        }
+
+        Ok(buffer)
+    }
+
+    fn rewind(&mut self) -> Result<(), AudioFileLoaderError> {
+        if unsafe { sndfile_sys::sf_seek(self.sndfile, 0, SEEK_SET) } < 0 {
+            return Err(AudioFileLoaderError::FileLoadError(
+                self.path.to_string_lossy().into_owned(),
+                "Failed to seek to the start of the file".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn seek(&mut self, frame: usize) -> Result<(), AudioFileLoaderError> {
+        let frame = (frame as i64).min(self.frames);
+        if unsafe { sndfile_sys::sf_seek(self.sndfile, frame, SEEK_SET) } < 0 {
+            return Err(AudioFileLoaderError::FileLoadError(
+                self.path.to_string_lossy().into_owned(),
+                format!("Failed to seek to frame {} of the file", frame),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SndFileStream {
+    fn drop(&mut self) {
+        unsafe {
+            sndfile_sys::sf_close(self.sndfile);
+        }
+    }
+}
+
+fn get_string(sndfile: *mut sndfile_sys::SNDFILE, kind: c_int) -> Option<String> {
+    let value = unsafe { sndfile_sys::sf_get_string(sndfile, kind) };
+
+    if value.is_null() {
+        return None;
+    }
+
+    let value = unsafe { CStr::from_ptr(value) }
+        .to_string_lossy()
+        .trim()
+        .to_owned();
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+impl AudioFileLoader for SndFileLoader {
+    fn load(&mut self, path: &PathBuf) -> Result<(Vec<i16>, i32), AudioFileLoaderError> {
+        let (samples, sample_rate, _) = Self::decode(path, false)?;
+        Ok((samples, sample_rate))
+    }
+
+    fn load_stereo(
+        &mut self,
+        path: &PathBuf,
+    ) -> Result<(Vec<i16>, i32, usize), AudioFileLoaderError> {
+        Self::decode(path, true)
+    }
+
+    fn stream(
+        &mut self,
+        path: &PathBuf,
+        keep_stereo: bool,
+    ) -> Result<Box<dyn AudioStream>, AudioFileLoaderError> {
+        let mut info = Self::empty_info();
+        let sndfile = Self::open(path, &mut *info)?;
+
+        Ok(Box::new(SndFileStream {
+            sndfile,
+            path: path.clone(),
+            channels: info.channels as usize,
+            sample_rate: info.samplerate,
+            frames: info.frames,
+            keep_stereo,
+        }))
+    }
+
+    fn metadata(&mut self, path: &PathBuf) -> Result<SampleMetadata, AudioFileLoaderError> {
+        let mut info = Self::empty_info();
+        let tmp_sndfile = Self::open(path, &mut *info)?;
+
+        let metadata = SampleMetadata {
+            title: get_string(tmp_sndfile, sndfile_sys::SF_STR_TITLE),
+            artist: get_string(tmp_sndfile, sndfile_sys::SF_STR_ARTIST),
+            album: get_string(tmp_sndfile, sndfile_sys::SF_STR_ALBUM),
+            genre: get_string(tmp_sndfile, sndfile_sys::SF_STR_GENRE),
+            comment: get_string(tmp_sndfile, sndfile_sys::SF_STR_COMMENT),
+        };
+
+        unsafe {
+            sndfile_sys::sf_close(tmp_sndfile);
+        }
+
+        Ok(metadata)
+    }
+
+    fn format(&mut self, path: &PathBuf) -> Result<SampleFormat, AudioFileLoaderError> {
+        let mut info = Self::empty_info();
+        let tmp_sndfile = Self::open(path, &mut *info)?;
+
+        unsafe {
+            sndfile_sys::sf_close(tmp_sndfile);
+        }
+
+        Ok(SampleFormat {
+            sample_rate: info.samplerate,
+            channels: info.channels as u32,
+        })
+    }
+
+    fn duration(&mut self, path: &PathBuf) -> Result<f32, AudioFileLoaderError> {
+        let mut info = Self::empty_info();
+        let tmp_sndfile = Self::open(path, &mut *info)?;
+
+        unsafe {
+            sndfile_sys::sf_close(tmp_sndfile);
+        }
+
+        if info.samplerate > 0 {
+            Ok(info.frames as f32 / info.samplerate as f32)
+        } else {
+            Ok(0.0)
+        }
+    }
+
+    /// libsndfile only hands out the standard strings (title, artist, ...), not the other
+    /// vorbis comments ReplayGain is stored in
+    fn replay_gain(&mut self, _path: &PathBuf) -> Result<Option<f32>, AudioFileLoaderError> {
+        Ok(None)
     }
 }
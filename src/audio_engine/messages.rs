@@ -1,12 +1,434 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
 
-use crate::theme::Theme;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+use crate::audio_engine::backends::base::{BackendCapabilities, Equalizer, SourcePool};
+use crate::audio_engine::backends::error::AudioBackendError;
+use crate::audio_engine::engine::error::AudioEngineError;
+use crate::audio_engine::engine::LoadState;
+use crate::audio_engine::loader::base::{SampleFormat, SampleMetadata};
+use crate::audio_engine::loader::check::Peaks;
+use crate::audio_engine::loader::error::AudioFileLoaderError;
+use crate::audio_engine::resampler::{Resampler, SampleRateStatus};
+use crate::samplesdb::{LibraryReport, SamplesDBError};
+use crate::settings::Settings;
+use crate::theme::{Theme, Waveform};
+
+/// Stable, machine-readable error codes clients can match on instead of the message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NoThemeLoaded,
+    UnknownSound,
+    UnknownMacro,
+    UnknownPlaylist,
+    SampleNotFound,
+    NotFound,
+    Forbidden,
+    TooManyPreviews,
+    InvalidValue,
+    InvalidRequest,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    DownloadFailed,
+    Database,
+    Backend,
+    EngineUnavailable,
+    EngineTimeout,
+    DecodeWarning,
+    LockedOut,
+    Internal,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// The sound of the theme the error refers to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+    /// The sample path or URL the error refers to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<String>,
+}
+
+impl ApiError {
+    pub fn new<M: ToString>(code: ErrorCode, message: M) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            sound: None,
+            sample: None,
+        }
+    }
+
+    pub fn with_sound(mut self, sound: &str) -> Self {
+        self.sound = Some(sound.to_string());
+        self
+    }
+
+    pub fn with_sample(mut self, sample: &str) -> Self {
+        self.sample = Some(sample.to_string());
+        self
+    }
+
+    /// The audio thread has stopped, no command will be handled anymore
+    pub fn engine_unavailable() -> Self {
+        Self::new(
+            ErrorCode::EngineUnavailable,
+            "The audio engine has stopped, check the log and restart the server!",
+        )
+    }
+
+    /// The audio thread didn't get to the command in time, it may still run it later
+    pub fn engine_timeout() -> Self {
+        Self::new(
+            ErrorCode::EngineTimeout,
+            "The audio engine didn't respond in time, try again!",
+        )
+    }
+
+    pub fn no_theme_loaded() -> Self {
+        Self::new(ErrorCode::NoThemeLoaded, "No theme loaded!")
+    }
+
+    pub fn unknown_sound(sound: &str) -> Self {
+        Self::new(
+            ErrorCode::UnknownSound,
+            format!("Unknown sound '{}'!", sound),
+        )
+        .with_sound(sound)
+    }
+
+    pub fn unknown_playlist(playlist: &str) -> Self {
+        Self::new(
+            ErrorCode::UnknownPlaylist,
+            format!("Unknown playlist '{}'!", playlist),
+        )
+    }
+
+    pub fn sample_not_found(sample: &str) -> Self {
+        Self::new(
+            ErrorCode::SampleNotFound,
+            format!("No such sample '{}'!", sample),
+        )
+        .with_sample(sample)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<&SamplesDBError> for ApiError {
+    fn from(e: &SamplesDBError) -> Self {
+        match e {
+            SamplesDBError::DownloadError(url, _) => {
+                Self::new(ErrorCode::DownloadFailed, e).with_sample(url)
+            }
+            SamplesDBError::UnsupportedRemoteFile(url) => {
+                Self::new(ErrorCode::InvalidValue, e).with_sample(url)
+            }
+            SamplesDBError::UnknownSample(_)
+            | SamplesDBError::UnknownTag(_)
+            | SamplesDBError::NoWaveform(_) => Self::new(ErrorCode::NotFound, e),
+            SamplesDBError::LoaderError(AudioFileLoaderError::UnsupportedFileFormat(..)) => {
+                Self::new(ErrorCode::UnsupportedMediaType, e)
+            }
+            SamplesDBError::LoaderError(_) => Self::new(ErrorCode::Backend, e),
+            _ => Self::new(ErrorCode::Database, e),
+        }
+    }
+}
+
+impl From<&AudioBackendError> for ApiError {
+    fn from(e: &AudioBackendError) -> Self {
+        Self::new(ErrorCode::Backend, e)
+    }
+}
+
+impl From<&AudioEngineError> for ApiError {
+    fn from(e: &AudioEngineError) -> Self {
+        match e {
+            AudioEngineError::AudioBackendError(e) => e.into(),
+            AudioEngineError::SampleNotFound(path) => Self::sample_not_found(path),
+            AudioEngineError::SamplesDBError(e) => e.into(),
+        }
+    }
+}
+
+/// Problem found in the sample of a sound that doesn't keep it from playing
+#[derive(Serialize, Debug)]
+pub struct DecodeWarning {
+    pub sound: String,
+    pub sample: String,
+    pub message: String,
+}
+
+/// In strict mode decode warnings are fatal
+impl From<&DecodeWarning> for ApiError {
+    fn from(w: &DecodeWarning) -> Self {
+        Self::new(
+            ErrorCode::DecodeWarning,
+            format!("Sample '{}': {}", w.sample, w.message),
+        )
+        .with_sound(&w.sound)
+        .with_sample(&w.sample)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SoundStatus {
+    pub state: String,
+    /// Loops left after the current one
+    pub loops: u32,
+    /// Repeats left in the current loop
+    pub repeats: u32,
+    pub loop_forever: bool,
+    /// The sound stops once its current loop is done
+    pub stopping: bool,
+    pub trim: f32,
+    /// A generated placeholder is played instead of the missing sample
+    pub placeholder: bool,
+    /// Sample rate and channels of the decoded file, before mixing down to mono
+    pub format: Option<SampleFormat>,
+    pub armed: bool,
+    /// Times the sound started playing since the theme was loaded
+    pub plays: u32,
+    /// Seconds into the sample, 0 if it isn't playing
+    pub position: f32,
+    /// Length of the sample in seconds
+    pub length: f32,
+    /// The sound has a trigger or a schedule
+    pub waits_for_trigger: bool,
+    /// Waiting for a trigger rather than running
+    pub waiting: bool,
+    /// A trigger came in that the sound hasn't acted on yet, it starts a waiting sound
+    /// and stops a running one after its current play
+    pub triggered: bool,
+    pub muted: bool,
+}
+
+/// What a trigger does to a sound
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerAction {
+    Toggle,
+    On,
+    Off,
+    Once,
+}
+
+impl Default for TriggerAction {
+    fn default() -> Self {
+        TriggerAction::Toggle
+    }
+}
+
+/// Parameters of a sound that can be changed while its theme is running, the ones left
+/// out stay as they are
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SoundChanges {
+    #[serde(default)]
+    pub volume: Option<(f32, f32)>,
+    #[serde(default)]
+    pub repeat_delay: Option<(u64, u64)>,
+    #[serde(default)]
+    pub loop_delay: Option<(u64, u64)>,
+    #[serde(default)]
+    pub reverb: Option<String>,
+    #[serde(default)]
+    pub pitch: Option<(f32, f32)>,
+}
+
+impl SoundChanges {
+    pub fn is_empty(&self) -> bool {
+        self.volume.is_none()
+            && self.repeat_delay.is_none()
+            && self.loop_delay.is_none()
+            && self.reverb.is_none()
+            && self.pitch.is_none()
+    }
+}
+
+impl Command {
+    pub fn trigger(sound: String, action: TriggerAction) -> Self {
+        match action {
+            TriggerAction::Toggle => Command::Trigger { sound },
+            TriggerAction::On => Command::TriggerOn { sound },
+            TriggerAction::Off => Command::TriggerOff { sound },
+            TriggerAction::Once => Command::TriggerOnce { sound },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DelayedCommandStatus {
+    pub id: u64,
+    /// Milliseconds until the command runs
+    pub due_in: u64,
+    pub command: serde_json::Value,
+}
+
+#[derive(Serialize)]
+pub struct LockoutStatus {
+    /// Milliseconds until triggers go through again
+    pub left: u64,
+    /// Triggers are queued instead of rejected
+    pub queue: bool,
+    /// Sounds whose triggers are released once the lockout ends, in order
+    pub queued: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct LimiterStatus {
+    pub enabled: bool,
+    pub ceiling: f32,
+    /// Sum of the gains of the sounds playing
+    pub load: f32,
+    /// Gain (0.0 - 1.0) the output is turned down by
+    pub gain: f32,
+}
+
+#[derive(Serialize)]
+pub struct PreloadStatus {
+    pub theme: String,
+    /// Sounds loaded so far, out of `total`
+    pub loaded: usize,
+    /// Sounds that couldn't be loaded ahead, they are loaded along with the theme
+    pub failed: Vec<String>,
+    pub total: usize,
+}
+
+/// Something the operator should know about, e.g. the engine running out of sources
+#[derive(Serialize, Clone)]
+pub struct EngineEvent {
+    pub id: u64,
+    /// source_pool_high or source_pool_exhausted
+    pub kind: String,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct PlaylistStatus {
+    pub name: String,
+    /// waiting (for the next track), playing or finished
+    pub state: String,
+    /// The current track, or the next one while waiting
+    pub track: Option<String>,
+    /// Position of the track in the playing order
+    pub index: usize,
+    pub tracks: usize,
+    /// Seconds into the current track
+    pub position: f32,
+}
+
+/// What to do when a sound's sample is missing or can't be loaded while loading a theme
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingSampleBehavior {
+    /// Reject the whole theme, the previous theme keeps playing
+    Strict,
+    /// Load the theme without the affected sounds
+    Skip,
+    /// Substitute the affected sounds' samples with silence
+    Silence,
+    /// Substitute the affected sounds' samples with a short beep
+    Beep,
+}
+
+impl Default for MissingSampleBehavior {
+    fn default() -> Self {
+        MissingSampleBehavior::Strict
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputChannel {
+    Left,
+    Right,
+    All,
+}
+
+impl Default for OutputChannel {
+    fn default() -> Self {
+        OutputChannel::All
+    }
+}
+
+/// Level samples are normalized to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizeMode {
+    /// The loudest sample reaches the target
+    Peak,
+    /// The RMS level reaches the target, without raising the peak above full scale
+    Loudness,
+}
+
+impl Default for NormalizeMode {
+    fn default() -> Self {
+        NormalizeMode::Peak
+    }
+}
+
+impl NormalizeMode {
+    /// Target level in dBFS if none is given
+    pub fn default_target(self) -> f32 {
+        match self {
+            NormalizeMode::Peak => -1.0,
+            NormalizeMode::Loudness => -20.0,
+        }
+    }
+}
+
+/// What the library scan read from a sample's file
+#[derive(Serialize)]
+pub struct SampleDetails {
+    /// Length in seconds, estimated for mp3 files
+    pub duration: Option<f32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<u32>,
+    /// Tags embedded in the file (title, artist, ...)
+    pub metadata: SampleMetadata,
+}
+
+/// A sample of the library, as listed by the library routes
+#[derive(Serialize)]
+pub struct LibrarySample {
+    pub id: i64,
+    pub path: String,
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub details: SampleDetails,
+}
+
+#[derive(Serialize)]
+pub struct NormalizedSample {
+    pub id: i64,
+    pub path: String,
+    /// Levels of the file itself in dBFS, before applying the gain
+    pub peak: f32,
+    pub rms: f32,
+    /// Gain applied whenever the sample is played
+    pub gain: f32,
+}
+
+#[derive(Serialize)]
+pub struct OverlayTrack {
+    pub sound: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
 
 #[derive(Serialize)]
 pub enum Response {
-    Error {
-        message: String,
-    },
+    Error(ApiError),
     Success,
     Status {
         playing: bool,
@@ -15,10 +437,55 @@ pub enum Response {
         sounds_playing: Vec<String>,
         sounds_playing_next: HashMap<String, u64>,
         previewing: Vec<String>,
+        load_state: LoadState,
+        /// Sample currently being auditioned
+        auditioning: Option<String>,
+        /// Previews waiting for a free slot, in the order they will start
+        preview_queue: Vec<String>,
+        clients: Vec<String>,
+        last_change_by: Option<String>,
+        ducking: bool,
+        sounds: HashMap<String, SoundStatus>,
+        macros_running: Vec<String>,
+        playlists: Vec<PlaylistStatus>,
+        lockout: Option<LockoutStatus>,
+        /// Stored theme whose files are being loaded ahead
+        preload: Option<PreloadStatus>,
+        /// Scene of the theme switched to last
+        scene: Option<String>,
+        /// Sound all others are silenced for
+        solo: Option<String>,
+        /// The output device is gone and the engine is trying to reconnect
+        device_lost: bool,
+        source_pool: Option<SourcePool>,
+        /// Sounds waiting for a free source, in the order they get one
+        waiting_for_source: Vec<String>,
+        /// Master EQ on the output
+        eq: Equalizer,
+        limiter: LimiterStatus,
+        time_scale: f32,
+        group_volumes: HashMap<String, f32>,
+        version: u64,
     },
 
     LoadTheme {
         success: bool,
+        /// Sounds left out because their sample couldn't be loaded
+        skipped: Vec<String>,
+        /// Sounds playing a generated placeholder because their sample couldn't be loaded
+        placeholders: Vec<String>,
+        /// Theme that was still waiting to fade in and is replaced by this one
+        superseded: Option<String>,
+        warnings: Vec<DecodeWarning>,
+    },
+    ReplaceSoundFile {
+        warnings: Vec<DecodeWarning>,
+    },
+
+    Overlay {
+        theme: Option<String>,
+        music: Option<OverlayTrack>,
+        ambience: Vec<String>,
     },
 
     Trigger {
@@ -27,7 +494,81 @@ pub enum Response {
     },
 
     SoundLibrary {
-        samples: Vec<(String, Vec<String>)>,
+        samples: Vec<LibrarySample>,
+    },
+
+    Tags {
+        tags: Vec<String>,
+    },
+
+    LibraryReport {
+        report: LibraryReport,
+    },
+
+    DelayedCommand {
+        id: u64,
+    },
+
+    SoundPosition {
+        /// Seconds into the sample
+        position: f32,
+        length: f32,
+        playing: bool,
+    },
+
+    SoundStatus {
+        status: SoundStatus,
+    },
+
+    FireTrigger {
+        /// Sounds listening to the trigger, sorted by name
+        sounds: Vec<String>,
+    },
+
+    DelayedCommands {
+        commands: Vec<DelayedCommandStatus>,
+    },
+
+    Events {
+        events: Vec<EngineEvent>,
+    },
+
+    Audition {
+        /// Library paths of the samples to be auditioned, in order
+        samples: Vec<String>,
+    },
+
+    ThemeList {
+        themes: Vec<String>,
+    },
+
+    SamplePath {
+        path: PathBuf,
+    },
+
+    Waveform {
+        waveform: Peaks,
+    },
+
+    StoredTheme {
+        theme: serde_json::Value,
+    },
+
+    Theme {
+        theme: serde_json::Value,
+    },
+
+    PreloadTheme {
+        /// Files of the theme that are going to be loaded
+        sounds: usize,
+    },
+
+    ClientSetting {
+        value: Option<String>,
+    },
+
+    Settings {
+        settings: Settings,
     },
 
     DriverList {
@@ -36,22 +577,371 @@ pub enum Response {
 
     Driver {
         id: i32,
+        sample_rates: SampleRateStatus,
+    },
+
+    Health {
+        device_alive: bool,
+        /// Why the samples database can't be reached, if it can't
+        database_error: Option<String>,
+        source_pool: Option<SourcePool>,
+    },
+
+    EngineConfig {
+        capabilities: BackendCapabilities,
+        /// Name of the output device, if the backend can tell
+        device: Option<String>,
+        output_rate: Option<i32>,
+        resampler: Resampler,
+        source_pool: Option<SourcePool>,
+        /// Milliseconds of the fades when pausing and switching themes
+        pause_fade: u64,
+        theme_fade: u64,
+        /// Milliseconds of crossfading when replacing a sound's file without asking for another
+        default_crossfade: u64,
+        max_previews: usize,
+        queue_previews: bool,
+        ducking: bool,
+        command_log: bool,
+        /// Milliseconds the engine moves forward by at a time
+        tick: u64,
     },
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub enum Command {
     Quit,
     Play,
     Pause,
     GetStatus,
+    GetOverlay,
     GetSoundLibrary,
+    GetLibraryReport,
+    StartAudition {
+        tag: Option<String>,
+        query: Option<String>,
+        seconds: Option<f32>,
+        /// Play the samples with their stored gain
+        #[serde(default)]
+        normalized: bool,
+    },
+    NextAudition,
+    StopAudition,
+    GetStoredThemes,
+    GetTags,
     GetDriver,
     GetDriverList,
+    GetHealth,
+    GetEngineConfig,
+
+    SetDriver {
+        id: i32,
+    },
+    SetVolume {
+        value: f32,
+    },
+    SetEq {
+        low: f32,
+        mid: f32,
+        high: f32,
+    },
+    SetGroupVolume {
+        group: String,
+        value: f32,
+    },
+    PreviewSound {
+        sound: String,
+        raw: bool,
+    },
+    LoadTheme {
+        theme: Theme,
+        on_missing: MissingSampleBehavior,
+        /// Decode warnings fail the load instead of being reported
+        strict: bool,
+    },
+    /// Starts a sound that waits for a trigger, or stops it after its current play if it
+    /// is running
+    Trigger {
+        sound: String,
+    },
+    /// Starts the sound unless it is running already
+    TriggerOn {
+        sound: String,
+    },
+    /// Stops the sound after its current play, unless it is waiting already
+    TriggerOff {
+        sound: String,
+    },
+    /// Plays the sound a single time, unless it is running already
+    TriggerOnce {
+        sound: String,
+    },
+    /// Switches to a scene of the loaded theme, fading the sounds of other scenes out
+    SetScene {
+        name: String,
+    },
+    /// Triggers all sounds listening to this trigger name at once
+    FireTrigger {
+        trigger: String,
+        action: TriggerAction,
+    },
+    /// Turns the limiter on or off, the ceiling stays as it is if not given
+    SetLimiter {
+        enabled: bool,
+        ceiling: Option<f32>,
+    },
+    /// Queues or rejects triggers for `duration` milliseconds, 0 lifts the lockout
+    SetLockout {
+        duration: u64,
+        queue: bool,
+    },
+    SearchLibrary {
+        query: String,
+    },
+    ReplaceSoundFile {
+        sound: String,
+        file: String,
+        crossfade: Option<u64>,
+        strict: bool,
+        /// Parameters changed along with the file, only if it could be swapped
+        #[serde(default)]
+        changes: SoundChanges,
+    },
+    /// Changes parameters of a sound of the loaded theme, they take effect with its next run
+    UpdateSound {
+        sound: String,
+        changes: SoundChanges,
+    },
+    SetListenerPosition {
+        position: (f32, f32, f32),
+    },
+    SetListenerOrientation {
+        at: (f32, f32, f32),
+        up: (f32, f32, f32),
+    },
+    SetSoundPosition {
+        sound: String,
+        position: (f32, f32, f32),
+    },
+    ArmSound {
+        sound: String,
+        armed: bool,
+    },
+    /// Silences the sound without stopping it
+    SetSoundMuted {
+        sound: String,
+        flag: bool,
+    },
+    /// Silences all other sounds until the solo is cleared
+    SoloSound {
+        sound: String,
+    },
+    ClearSolo,
+    SetSoundCounters {
+        sound: String,
+        loops: Option<u32>,
+        repeats: Option<u32>,
+    },
+    StopSoundAtLoopEnd {
+        sound: String,
+    },
+    /// Stops the sound right away, dropping the repeats and loops it had left
+    StopSound {
+        sound: String,
+    },
+    RunMacro {
+        name: String,
+    },
+    SetSoundTrim {
+        sound: String,
+        gain: f32,
+    },
+    SetTimeScale {
+        value: f32,
+        pitch: bool,
+    },
+    PlayTestTone {
+        channel: OutputChannel,
+    },
+    PlayBeep {
+        waveform: Waveform,
+        frequency: f32,
+        duration: f32,
+        volume: f32,
+    },
+    GetSettings,
+    /// Reads the settings from the database again, after a backup was restored
+    ReloadSettings,
+    UpdateSettings {
+        settings: Settings,
+    },
+    GetClientSetting {
+        client: String,
+        key: String,
+    },
+    SetClientSetting {
+        client: String,
+        key: String,
+        value: String,
+    },
+    SaveTheme {
+        name: String,
+        theme: serde_json::Value,
+    },
+    GetStoredTheme {
+        name: String,
+    },
+    /// The theme loaded last, as it was loaded
+    GetTheme,
+    LoadStoredTheme {
+        name: String,
+        on_missing: MissingSampleBehavior,
+        strict: bool,
+    },
+    /// Runs the command only if the loaded theme is one of `themes`, for scoped tokens
+    Scoped {
+        themes: Vec<String>,
+        command: Box<Command>,
+    },
+    /// Loads the files of a stored theme without playing them, so loading the theme
+    /// later on is quicker
+    PreloadTheme {
+        name: String,
+    },
+    GetSamplesByTag {
+        tag: String,
+    },
+    AddSampleTag {
+        sample: i64,
+        tag: String,
+    },
+    RemoveSampleTag {
+        sample: i64,
+        tag: String,
+    },
+    /// Sounds playing pick up the gains of their samples again, after they were normalized
+    RefreshSampleGains,
+    ResetSampleGain {
+        sample: i64,
+    },
+    SkipTrack {
+        playlist: String,
+        /// Skip to the previous track instead of the next one
+        back: bool,
+    },
+    SeekTrack {
+        playlist: String,
+        /// Seconds into the current track
+        position: f32,
+    },
+    GetSoundPosition {
+        sound: String,
+    },
+    GetSoundStatus {
+        sound: String,
+    },
+    SeekSound {
+        sound: String,
+        /// Seconds into the sample
+        position: f32,
+    },
+    /// Runs `command` once `delay` milliseconds have passed
+    DelayCommand {
+        delay: u64,
+        command: Box<Command>,
+    },
+    CancelDelayedCommand {
+        id: u64,
+    },
+    GetDelayedCommands,
+    /// Events with an id greater than `since`
+    GetEvents {
+        since: u64,
+    },
+    /// Where the file of a sample is, for clients to decode it themselves
+    GetSamplePath {
+        sample: i64,
+    },
+    GetWaveform {
+        sample: i64,
+    },
+}
+
+impl Command {
+    /// Commands that change what is played or stored, replaying queries or quitting
+    /// wouldn't reproduce anything
+    pub fn is_replayable(&self) -> bool {
+        match self {
+            Command::Quit
+            | Command::GetStatus
+            | Command::GetOverlay
+            | Command::GetSoundLibrary
+            | Command::GetLibraryReport
+            | Command::GetStoredThemes
+            | Command::GetTags
+            | Command::GetDriver
+            | Command::GetHealth
+            | Command::GetEngineConfig
+            | Command::GetDriverList
+            | Command::GetDelayedCommands
+            | Command::GetEvents { .. }
+            | Command::GetSoundPosition { .. }
+            | Command::GetSoundStatus { .. }
+            | Command::GetSamplePath { .. }
+            | Command::GetWaveform { .. }
+            | Command::SearchLibrary { .. }
+            | Command::GetSettings
+            | Command::GetClientSetting { .. }
+            | Command::GetStoredTheme { .. }
+            | Command::GetTheme
+            | Command::GetSamplesByTag { .. } => false,
+            Command::Scoped { command, .. } => command.is_replayable(),
+            _ => true,
+        }
+    }
+
+    /// Commands that control playback and may be run later on, see DelayCommand
+    pub fn is_delayable(&self) -> bool {
+        match self {
+            Command::Play
+            | Command::Pause
+            | Command::Trigger { .. }
+            | Command::TriggerOn { .. }
+            | Command::TriggerOff { .. }
+            | Command::TriggerOnce { .. }
+            | Command::FireTrigger { .. }
+            | Command::SetScene { .. }
+            | Command::RunMacro { .. }
+            | Command::LoadStoredTheme { .. }
+            | Command::SetVolume { .. }
+            | Command::SetEq { .. }
+            | Command::SetLimiter { .. }
+            | Command::SetGroupVolume { .. }
+            | Command::SetTimeScale { .. }
+            | Command::StopSoundAtLoopEnd { .. }
+            | Command::StopSound { .. }
+            | Command::SetSoundCounters { .. }
+            | Command::ArmSound { .. }
+            | Command::SkipTrack { .. }
+            | Command::SeekTrack { .. }
+            | Command::SeekSound { .. }
+            | Command::PlayBeep { .. } => true,
+            Command::Scoped { command, .. } => command.is_delayable(),
+            _ => false,
+        }
+    }
+}
+
+/// A command for the engine along with the channel the response is sent back on, so
+/// several clients can wait for their responses at the same time
+pub struct Request {
+    pub command: Command,
+    pub reply: Sender<Response>,
+}
 
-    SetDriver { id: i32 },
-    SetVolume { value: f32 },
-    PreviewSound { sound: String },
-    LoadTheme { theme: Theme },
-    Trigger { sound: String },
+impl Request {
+    pub fn new(command: Command) -> (Self, Receiver<Response>) {
+        let (reply, receiver) = unbounded();
+        (Self { command, reply }, receiver)
+    }
 }
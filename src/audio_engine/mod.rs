@@ -1,5 +1,10 @@
 pub mod backends;
+pub mod clock;
+pub mod encoder;
+pub mod generator;
 pub mod loader;
 #[macro_use]
 pub mod messages;
 pub mod engine;
+pub mod normalize;
+pub mod resampler;
@@ -0,0 +1,93 @@
+use crate::audio_engine::loader::check::{measure_levels, Levels};
+use crate::audio_engine::messages::{NormalizeMode, NormalizedSample};
+use crate::samplesdb::{SamplesDBError, SharedSamplesDB};
+
+// Normalizing never raises a sample by more than 24 dB, quieter files are mostly noise
+const MAX_NORMALIZE_GAIN: f32 = 16.0;
+
+/// Level in dBFS, silence ends up at -100 dB
+fn to_db(level: f32) -> f32 {
+    20.0 * level.max(0.000_01).log10()
+}
+
+/// The gain of a ReplayGain track gain in dB, capped like the gains of normalizing
+pub fn replay_gain_factor(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0).min(MAX_NORMALIZE_GAIN)
+}
+
+/// Gain bringing the sample to the target level in dBFS
+fn normalization_gain(levels: &Levels, mode: NormalizeMode, target: f32) -> f32 {
+    let level = match mode {
+        NormalizeMode::Peak => levels.peak,
+        NormalizeMode::Loudness => levels.rms,
+    };
+
+    // Nothing to bring up in a silent file
+    if level <= 0.0 {
+        return 1.0;
+    }
+
+    let gain = 10.0f32.powf(target / 20.0) / level;
+    let gain = match mode {
+        NormalizeMode::Peak => gain,
+        NormalizeMode::Loudness => gain.min(1.0 / levels.peak),
+    };
+
+    gain.min(MAX_NORMALIZE_GAIN)
+}
+
+/// Measures the samples (all of the library if `samples` is None) and stores a gain
+/// bringing each to `target`. Every file is decoded as a whole, so the database is only
+/// locked to look up the files and to store the gains, not while measuring. Returns the
+/// normalized samples and the paths of those that couldn't be measured.
+pub fn normalize_samples(
+    samplesdb: &SharedSamplesDB,
+    samples: Option<Vec<i64>>,
+    mode: NormalizeMode,
+    target: f32,
+) -> Result<(Vec<NormalizedSample>, Vec<String>), SamplesDBError> {
+    let files = {
+        let samplesdb = samplesdb.lock().unwrap();
+        let ids: Vec<i64> = match samples {
+            Some(ids) => ids,
+            None => samplesdb.samples().map(|sample| sample.id).collect(),
+        };
+
+        let mut files = Vec::new();
+        for id in ids {
+            let sample = samplesdb
+                .sample(id)
+                .ok_or(SamplesDBError::UnknownSample(id))?;
+            files.push((id, sample.path.clone(), samplesdb.full_path_of_sample(id)));
+        }
+        files
+    };
+
+    let mut normalized = Vec::new();
+    let mut failed = Vec::new();
+
+    for (id, path, full_path) in files {
+        let levels = match measure_levels(&full_path) {
+            Ok(levels) => levels,
+            Err(e) => {
+                warn!("Failed to measure levels of '{}': {}", path, e);
+                failed.push(path);
+                continue;
+            }
+        };
+
+        let gain = normalization_gain(&levels, mode, target);
+        samplesdb.lock().unwrap().set_sample_gain(id, gain)?;
+
+        info!("Normalized '{}' with a gain of {:.1} dB", path, to_db(gain));
+        normalized.push(NormalizedSample {
+            id,
+            path,
+            peak: to_db(levels.peak),
+            rms: to_db(levels.rms),
+            gain,
+        });
+    }
+
+    Ok((normalized, failed))
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+/// How buffers are converted to the output rate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resampler {
+    /// Cubic (Catmull-Rom) interpolation
+    Cubic,
+    /// Linear interpolation, cheaper but dulls the highs
+    Linear,
+    /// Buffers are played at their own rate, leaving the conversion to the device
+    Off,
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Resampler::Cubic
+    }
+}
+
+impl std::str::FromStr for Resampler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cubic" => Ok(Resampler::Cubic),
+            "linear" => Ok(Resampler::Linear),
+            "off" => Ok(Resampler::Off),
+            _ => Err(format!("Unknown resampler '{}'", s)),
+        }
+    }
+}
+
+impl Resampler {
+    /// Resamples interleaved samples one channel at a time
+    pub fn resample(self, samples: &[i16], channels: usize, from: i32, to: i32) -> Vec<i16> {
+        let resample_channel = |samples: &[i16]| match self {
+            Resampler::Cubic => resample(samples, from, to),
+            Resampler::Linear => resample_linear(samples, from, to),
+            Resampler::Off => samples.to_vec(),
+        };
+
+        if channels <= 1 {
+            return resample_channel(samples);
+        }
+
+        let resampled: Vec<Vec<i16>> = (0..channels)
+            .map(|channel| {
+                let samples: Vec<i16> = samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .cloned()
+                    .collect();
+                resample_channel(&samples)
+            })
+            .collect();
+
+        let length = resampled.iter().map(Vec::len).min().unwrap_or(0);
+        (0..length)
+            .flat_map(|n| resampled.iter().map(move |channel| channel[n]))
+            .collect()
+    }
+}
+
+/// Sample rate of the output, and the rates of buffers that had to be converted to it
+#[derive(Clone, Serialize)]
+pub struct SampleRateStatus {
+    /// Rate the device plays at, unknown for backends that can't tell
+    pub output_rate: Option<i32>,
+    pub resampler: Resampler,
+    pub resampled: Vec<ResampledRate>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ResampledRate {
+    pub rate: i32,
+    /// Number of buffers loaded at this rate
+    pub buffers: usize,
+}
+
+/// Converts buffers to the output rate before they are handed to the device, instead of
+/// relying on the device doing it, which some implementations get subtly wrong
+pub struct RateConverter {
+    output_rate: Option<i32>,
+    resampler: Resampler,
+    mismatches: BTreeMap<i32, usize>,
+}
+
+impl RateConverter {
+    pub fn new(output_rate: Option<i32>, resampler: Resampler) -> Self {
+        Self {
+            output_rate,
+            resampler,
+            mismatches: BTreeMap::new(),
+        }
+    }
+
+    pub fn resampler(&self) -> Resampler {
+        self.resampler
+    }
+
+    /// Buffers loaded from now on are converted to this rate, loaded ones are kept as is
+    pub fn set_output_rate(&mut self, output_rate: Option<i32>) {
+        if output_rate != self.output_rate {
+            self.output_rate = output_rate;
+            self.mismatches.clear();
+        }
+    }
+
+    /// Rate a buffer at `sample_rate` has to be converted to, if any. Buffers that need
+    /// converting are counted for the status, even if the device is left to convert them.
+    pub fn target_rate(&mut self, sample_rate: i32) -> Option<i32> {
+        let output_rate = match self.output_rate {
+            Some(rate) if rate != sample_rate && sample_rate > 0 => rate,
+            _ => return None,
+        };
+
+        let buffers = self.mismatches.entry(sample_rate).or_insert(0);
+        if *buffers == 0 {
+            warn!(
+                "Output plays at {} Hz, buffers at {} Hz are {}",
+                output_rate,
+                sample_rate,
+                if self.resampler == Resampler::Off {
+                    "left to the device to resample"
+                } else {
+                    "resampled"
+                }
+            );
+        }
+        *buffers += 1;
+
+        if self.resampler == Resampler::Off {
+            None
+        } else {
+            Some(output_rate)
+        }
+    }
+
+    /// Returns the (interleaved) samples at the output rate along with that rate, samples
+    /// are passed through if the rates match or the output rate is unknown
+    pub fn convert(
+        &mut self,
+        samples: Vec<i16>,
+        sample_rate: i32,
+        channels: usize,
+    ) -> (Vec<i16>, i32) {
+        match self.target_rate(sample_rate) {
+            Some(output_rate) => (
+                self.resampler
+                    .resample(&samples, channels, sample_rate, output_rate),
+                output_rate,
+            ),
+            None => (samples, sample_rate),
+        }
+    }
+
+    pub fn status(&self) -> SampleRateStatus {
+        SampleRateStatus {
+            output_rate: self.output_rate,
+            resampler: self.resampler,
+            resampled: self
+                .mismatches
+                .iter()
+                .map(|(&rate, &buffers)| ResampledRate { rate, buffers })
+                .collect(),
+        }
+    }
+}
+
+/// Resamples mono samples with cubic (Catmull-Rom) interpolation
+pub fn resample(samples: &[i16], from: i32, to: i32) -> Vec<i16> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let step = f64::from(from) / f64::from(to);
+    let length = (samples.len() as f64 / step) as usize;
+    let last = samples.len() as isize - 1;
+    let at = |i: isize| f64::from(samples[i.max(0).min(last) as usize]);
+
+    (0..length)
+        .map(|n| {
+            let position = n as f64 * step;
+            let i = position as isize;
+            let t = position - i as f64;
+
+            let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+            let value = p1
+                + 0.5
+                    * t
+                    * (p2 - p0
+                        + t * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3
+                            + t * (3.0 * (p1 - p2) + p3 - p0)));
+
+            value
+                .max(f64::from(std::i16::MIN))
+                .min(f64::from(std::i16::MAX)) as i16
+        })
+        .collect()
+}
+
+/// Resamples mono samples with linear interpolation
+pub fn resample_linear(samples: &[i16], from: i32, to: i32) -> Vec<i16> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let step = f64::from(from) / f64::from(to);
+    let length = (samples.len() as f64 / step) as usize;
+    let last = samples.len() - 1;
+
+    (0..length)
+        .map(|n| {
+            let position = n as f64 * step;
+            let i = (position as usize).min(last);
+            let t = position - i as f64;
+
+            let (p0, p1) = (f64::from(samples[i]), f64::from(samples[(i + 1).min(last)]));
+            (p0 + t * (p1 - p0)) as i16
+        })
+        .collect()
+}
@@ -1,19 +1,261 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 use std::task::{Context, Poll};
 
 use actix_service::{Service, Transform};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::{Error, HttpResponse};
+use actix_web::http::Method;
+use actix_web::{Error, HttpMessage, HttpResponse};
 use futures::future::{ok, Either, Ready};
 
+/// Themes and rooms a scoped token may control, all of them if it doesn't name any.
+/// Requests made with one carry it in their extensions.
+#[derive(Debug, Clone)]
+pub struct TokenScope {
+    pub themes: Vec<String>,
+    /// Ids of the rooms, the default room isn't one of them
+    pub rooms: Vec<String>,
+}
+
+impl TokenScope {
+    pub fn allows_theme(&self, theme: &str) -> bool {
+        self.themes.is_empty() || self.themes.iter().any(|t| t == theme)
+    }
+
+    /// Requests outside of /rooms/{id} go to the default room
+    fn allows_room(&self, room: Option<&str>) -> bool {
+        self.rooms.is_empty()
+            || room.map_or(false, |room| self.rooms.iter().any(|r| r == room))
+    }
+}
+
+/// A token restricted to some themes and/or rooms, given as `token=theme,room:id,...`
+#[derive(Debug)]
+pub struct ScopedToken {
+    pub token: String,
+    pub scope: TokenScope,
+}
+
+impl FromStr for ScopedToken {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let (token, entries) = match (parts.next(), parts.next()) {
+            (Some(token), Some(entries)) if !token.is_empty() => (token, entries),
+            _ => return Err(format!("Expected 'token=theme,room:id,...', got '{}'", s)),
+        };
+
+        let mut scope = TokenScope {
+            themes: Vec::new(),
+            rooms: Vec::new(),
+        };
+        for entry in entries.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            if entry.starts_with("room:") {
+                scope.rooms.push(entry["room:".len()..].to_string());
+            } else {
+                scope.themes.push(entry.to_string());
+            }
+        }
+
+        if scope.themes.is_empty() && scope.rooms.is_empty() {
+            return Err(format!("Scoped token '{}' names no theme or room", token));
+        }
+
+        Ok(ScopedToken {
+            token: token.to_string(),
+            scope,
+        })
+    }
+}
+
+/// What a token may do
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including server administration and switching the output device
+    Admin,
+    /// Everything but administration
+    Controller,
+    /// Only reading, e.g. for overlays and status displays
+    Viewer,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "controller" => Ok(Role::Controller),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(format!("Unknown role '{}'", s)),
+        }
+    }
+}
+
+impl Role {
+    fn allows(self, req: &ServiceRequest) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Controller => !is_admin_only(req),
+            Role::Viewer => req.method() == Method::GET && !is_admin_only(req),
+        }
+    }
+}
+
+/// A token with a name and role, given as `name:role:token` or in the configuration file
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NamedToken {
+    pub name: String,
+    pub role: Role,
+    pub token: String,
+}
+
+impl FromStr for NamedToken {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(role), Some(token)) if !name.is_empty() && !token.is_empty() => {
+                Ok(NamedToken {
+                    name: name.to_string(),
+                    role: role.parse()?,
+                    token: token.to_string(),
+                })
+            }
+            _ => Err(format!("Expected 'name:role:token', got '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Identity {
+    name: String,
+    role: Role,
+}
+
+/// All tokens the server accepts. The main access token always has the admin role.
+pub struct TokenStore {
+    access_token: String,
+    named: HashMap<String, Identity>,
+    scoped: HashMap<String, TokenScope>,
+}
+
+impl TokenStore {
+    pub fn new(access_token: String, named: Vec<NamedToken>, scoped: Vec<ScopedToken>) -> Self {
+        let mut store = Self {
+            access_token,
+            named: HashMap::new(),
+            scoped: scoped
+                .into_iter()
+                .map(|scoped| (scoped.token, scoped.scope))
+                .collect(),
+        };
+
+        store.set_named_tokens(named);
+        store
+    }
+
+    pub fn set_access_token(&mut self, token: String) {
+        self.access_token = token;
+    }
+
+    /// Replaces all named tokens, e.g. after reloading the configuration
+    pub fn set_named_tokens(&mut self, named: Vec<NamedToken>) {
+        self.named = named
+            .into_iter()
+            .map(|named| {
+                (
+                    named.token,
+                    Identity {
+                        name: named.name,
+                        role: named.role,
+                    },
+                )
+            })
+            .collect();
+    }
+
+    /// Scoped tokens act as controllers limited to their themes
+    fn lookup(&self, token: &str) -> Option<(Identity, Option<TokenScope>)> {
+        if token == self.access_token {
+            let identity = Identity {
+                name: "admin".to_string(),
+                role: Role::Admin,
+            };
+            return Some((identity, None));
+        }
+
+        if let Some(identity) = self.named.get(token) {
+            return Some((identity.clone(), None));
+        }
+
+        self.scoped.get(token).map(|scope| {
+            let identity = Identity {
+                name: "scoped".to_string(),
+                role: Role::Controller,
+            };
+            (identity, Some(scope.clone()))
+        })
+    }
+}
+
+/// Id of the room the request goes to, None for the default one
+fn room_id(req: &ServiceRequest) -> Option<&str> {
+    let path = req.path();
+    if !path.starts_with("/rooms/") {
+        return None;
+    }
+
+    let rest = &path["/rooms/".len()..];
+    Some(rest.find('/').map_or(rest, |i| &rest[..i]))
+}
+
+/// Path of the route, rooms have the same routes as the default one below /rooms/{id}
+fn route_path(req: &ServiceRequest) -> &str {
+    let path = req.path();
+    if path.starts_with("/rooms/") {
+        let rest = &path["/rooms/".len()..];
+        return rest.find('/').map_or("", |i| &rest[i..]);
+    }
+
+    path
+}
+
+/// Server administration is left to admin tokens
+fn is_admin_only(req: &ServiceRequest) -> bool {
+    let path = route_path(req);
+
+    path.starts_with("/admin/")
+        || path.starts_with("/driver")
+        // Normalizing decodes samples as a whole, the whole library if asked to
+        || (path.starts_with("/library/") && path.ends_with("/normalize"))
+        || (path == "/settings" && req.method() == Method::PUT)
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<&str> {
+    let token = req.head().headers().get("Authorization")?.to_str().ok()?;
+    let token_parts: Vec<&str> = token.split(' ').collect();
+
+    if token_parts.len() == 2 && token_parts[0] == "Bearer" {
+        Some(token_parts[1])
+    } else {
+        None
+    }
+}
+
 pub struct TokenAuthorization {
-    token: String,
+    tokens: Arc<RwLock<TokenStore>>,
 }
 
 impl TokenAuthorization {
-    pub fn new(token: &str) -> Self {
-        Self {
-            token: token.into(),
-        }
+    /// The store is shared so tokens can be replaced while the server is running
+    pub fn new(tokens: Arc<RwLock<TokenStore>>) -> Self {
+        Self { tokens }
     }
 }
 
@@ -32,13 +274,13 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ok(TokenAuthorizationMiddleware {
             service,
-            token: self.token.clone(),
+            tokens: self.tokens.clone(),
         })
     }
 }
 pub struct TokenAuthorizationMiddleware<S> {
     service: S,
-    token: String,
+    tokens: Arc<RwLock<TokenStore>>,
 }
 
 impl<S, B> Service for TokenAuthorizationMiddleware<S>
@@ -56,21 +298,48 @@ where
     }
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
-        let authorization = req.head().headers().get("Authorization");
-
-        match authorization {
-            Some(token) => {
-                let token_parts: Vec<&str> = token.to_str().unwrap().split(' ').collect();
-                if token_parts.len() != 2
-                    || token_parts[0] != "Bearer"
-                    || token_parts[1] != self.token
-                {
-                    Either::Right(ok(
-                        req.into_response(HttpResponse::Forbidden().finish().into_body())
-                    ))
-                } else {
-                    Either::Left(self.service.call(req))
+        // Health probes of container orchestration can't be given a token
+        if route_path(&req) == "/health" && req.method() == Method::GET {
+            return Either::Left(self.service.call(req));
+        }
+
+        let access = bearer_token(&req)
+            .and_then(|token| self.tokens.read().unwrap().lookup(token))
+            .filter(|(identity, _)| {
+                let allowed = identity.role.allows(&req);
+                if !allowed {
+                    warn!(
+                        "Token '{}' ({:?}) may not {} {}",
+                        identity.name,
+                        identity.role,
+                        req.method(),
+                        req.path()
+                    );
+                }
+                allowed
+            });
+
+        // Scoped tokens may still list the rooms to find theirs
+        let access = access.filter(|(identity, scope)| {
+            let allowed = scope.as_ref().map_or(true, |scope| {
+                route_path(&req) == "/rooms" || scope.allows_room(room_id(&req))
+            });
+            if !allowed {
+                warn!(
+                    "Token '{}' may not control the room of {}",
+                    identity.name,
+                    req.path()
+                );
+            }
+            allowed
+        });
+
+        match access {
+            Some((_, scope)) => {
+                if let Some(scope) = scope {
+                    req.extensions_mut().insert(scope);
                 }
+                Either::Left(self.service.call(req))
             }
             None => Either::Right(ok(
                 req.into_response(HttpResponse::Forbidden().finish().into_body())
@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::authorization::NamedToken;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u32 = 9090;
+const DEFAULT_TOKEN: &str = "totallynotsecure";
+const DEFAULT_THREADS: usize = 2;
+const DEFAULT_SOUND_LIBRARY: &str = "sounds";
+const DEFAULT_DATABASE: &str = "samples.db";
+const DEFAULT_BACKEND: &str = "openal";
+const DEFAULT_LOG_LEVEL: &str = "debug";
+const DEFAULT_BACKUP_INTERVAL: u64 = 60;
+const DEFAULT_BACKUP_KEEP: usize = 24;
+
+/// Server configuration, read from the TOML file given with --config. Options given on
+/// the command line take precedence over the file, unset fields use the defaults.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub host: Option<String>,
+    pub port: Option<u32>,
+    pub token: Option<String>,
+    /// Additional tokens with their own role
+    pub tokens: Option<Vec<NamedToken>>,
+    pub threads: Option<usize>,
+    pub sound_library: Option<PathBuf>,
+    /// Path to an SQLite file or a postgres:// URL
+    pub database: Option<String>,
+    /// openal, rodio or null
+    pub backend: Option<String>,
+    /// error, warn, info, debug or trace
+    pub log_level: Option<String>,
+    /// Directory to snapshot the SQLite database into regularly, no snapshots if unset
+    pub backup_dir: Option<PathBuf>,
+    /// Minutes between two snapshots
+    pub backup_interval: Option<u64>,
+    /// Number of snapshots to keep, older ones are removed
+    pub backup_keep: Option<usize>,
+}
+
+impl Config {
+    pub fn host(&self) -> String {
+        self.host
+            .clone()
+            .unwrap_or_else(|| DEFAULT_HOST.to_string())
+    }
+
+    pub fn port(&self) -> u32 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    pub fn token(&self) -> String {
+        self.token
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TOKEN.to_string())
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.unwrap_or(DEFAULT_THREADS)
+    }
+
+    pub fn sound_library(&self) -> PathBuf {
+        self.sound_library
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOUND_LIBRARY))
+    }
+
+    pub fn database(&self) -> String {
+        self.database
+            .clone()
+            .unwrap_or_else(|| DEFAULT_DATABASE.to_string())
+    }
+
+    pub fn backend(&self) -> String {
+        self.backend
+            .clone()
+            .unwrap_or_else(|| DEFAULT_BACKEND.to_string())
+    }
+
+    pub fn log_level(&self) -> String {
+        self.log_level
+            .clone()
+            .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string())
+    }
+
+    pub fn backup_interval(&self) -> u64 {
+        self.backup_interval.unwrap_or(DEFAULT_BACKUP_INTERVAL)
+    }
+
+    pub fn backup_keep(&self) -> usize {
+        self.backup_keep.unwrap_or(DEFAULT_BACKUP_KEEP)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        toml::from_str(&content).map_err(|e| e.to_string())
+    }
+
+    /// Takes over all fields that are set in `other`
+    pub fn merge(&mut self, other: Config) {
+        if other.host.is_some() {
+            self.host = other.host;
+        }
+
+        if other.port.is_some() {
+            self.port = other.port;
+        }
+
+        if other.token.is_some() {
+            self.token = other.token;
+        }
+
+        if other.tokens.is_some() {
+            self.tokens = other.tokens;
+        }
+
+        if other.threads.is_some() {
+            self.threads = other.threads;
+        }
+
+        if other.sound_library.is_some() {
+            self.sound_library = other.sound_library;
+        }
+
+        if other.database.is_some() {
+            self.database = other.database;
+        }
+
+        if other.backend.is_some() {
+            self.backend = other.backend;
+        }
+
+        if other.log_level.is_some() {
+            self.log_level = other.log_level;
+        }
+
+        if other.backup_dir.is_some() {
+            self.backup_dir = other.backup_dir;
+        }
+
+        if other.backup_interval.is_some() {
+            self.backup_interval = other.backup_interval;
+        }
+
+        if other.backup_keep.is_some() {
+            self.backup_keep = other.backup_keep;
+        }
+    }
+}
+
+/// Where the configuration came from, so it can be read again when reloading
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSource {
+    pub file: Option<PathBuf>,
+    /// Options given on the command line
+    pub overrides: Config,
+}
+
+impl ConfigSource {
+    pub fn read(&self) -> Result<Config, String> {
+        let mut config = match self.file {
+            Some(ref path) => Config::load(path)
+                .map_err(|e| format!("Failed to read '{}': {}", path.to_string_lossy(), e))?,
+            None => Config::default(),
+        };
+
+        config.merge(self.overrides.clone());
+        Ok(config)
+    }
+}
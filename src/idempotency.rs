@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{Body, ResponseBody, ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, Either, Ready};
+
+const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+/// How long retries of a request get the first response instead of running it again
+const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(60);
+
+struct CachedResponse {
+    /// Status and body, None while the first request is still running
+    response: Option<(StatusCode, Option<Bytes>)>,
+    created: Instant,
+}
+
+type ResponseCache = Arc<Mutex<HashMap<(String, String), CachedResponse>>>;
+
+/// Replays the response of POST requests that are sent again with the same `Idempotency-Key`,
+/// so retrying over a flaky connection doesn't fire a trigger twice. Retries arriving while
+/// the first request is still running get `409 Conflict`. Has to be the innermost
+/// middleware, it only handles plain response bodies.
+#[derive(Clone, Default)]
+pub struct Idempotency {
+    cache: ResponseCache,
+}
+
+impl<S> Transform<S> for Idempotency
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IdempotencyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IdempotencyMiddleware {
+            service,
+            cache: self.cache.clone(),
+        })
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: S,
+    cache: ResponseCache,
+}
+
+impl<S> Service for IdempotencyMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Either<
+        Ready<Result<Self::Response, Self::Error>>,
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let key = req
+            .head()
+            .headers()
+            .get(IDEMPOTENCY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|_| req.method() == Method::POST)
+            .map(|value| (value.to_string(), req.path().to_string()));
+
+        let key = match key {
+            Some(key) => key,
+            None => return Either::Right(Box::pin(self.service.call(req))),
+        };
+
+        {
+            // Looking the key up and marking it as pending happens under one lock, so two
+            // requests with the same key arriving at once don't both run
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, cached| cached.created.elapsed() < IDEMPOTENCY_WINDOW);
+
+            match cache.get(&key) {
+                Some(CachedResponse {
+                    response: Some((status, body)),
+                    ..
+                }) => {
+                    debug!("Replaying response for idempotency key '{}'", key.0);
+
+                    let mut response = HttpResponse::build(*status);
+                    let response = match body {
+                        Some(body) => response.content_type("application/json").body(body.clone()),
+                        None => response.finish(),
+                    };
+
+                    return Either::Left(ok(req.into_response(response)));
+                }
+                Some(CachedResponse { response: None, .. }) => {
+                    debug!("Request with idempotency key '{}' is still running", key.0);
+
+                    let response = HttpResponse::Conflict().header("Retry-After", "1").finish();
+                    return Either::Left(ok(req.into_response(response)));
+                }
+                None => {
+                    cache.insert(
+                        key.clone(),
+                        CachedResponse {
+                            response: None,
+                            created: Instant::now(),
+                        },
+                    );
+                }
+            }
+        }
+
+        let cache = self.cache.clone();
+        let response = self.service.call(req);
+
+        Either::Right(Box::pin(async move {
+            let response = match response.await {
+                Ok(response) => response,
+                Err(e) => {
+                    // Nothing happened that a retry would repeat
+                    cache.lock().unwrap().remove(&key);
+                    return Err(e);
+                }
+            };
+
+            let body = match response.response().body() {
+                ResponseBody::Body(Body::Bytes(bytes))
+                | ResponseBody::Other(Body::Bytes(bytes)) => Some(bytes.clone()),
+                _ => None,
+            };
+
+            cache.lock().unwrap().insert(
+                key,
+                CachedResponse {
+                    response: Some((response.status(), body)),
+                    created: Instant::now(),
+                },
+            );
+
+            Ok(response)
+        }))
+    }
+}
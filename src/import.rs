@@ -0,0 +1,222 @@
+use serde_json::{json, Map, Value};
+
+use crate::theme::Theme;
+
+/// Soundscape tools whose exports can be converted into themes
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// Syrinscape soundset descriptors: `{ name, elements: [{ name, type, samples, volume,
+    /// min_delay, max_delay }] }` with volumes from 0 to 100 and delays in seconds
+    Syrinscape,
+    /// Tabletop Audio SoundPad descriptors: `{ title, sounds: [{ label, file, loop, volume }] }`
+    /// with volumes from 0.0 to 1.0
+    TabletopAudio,
+}
+
+impl std::str::FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(s.to_string()))
+            .map_err(|_| format!("Unknown import format '{}'", s))
+    }
+}
+
+#[derive(Serialize)]
+pub struct ImportResult {
+    /// The converted theme in Sinfonia's theme format
+    pub theme: Value,
+    /// Features of the source that couldn't be mapped and were dropped
+    pub warnings: Vec<String>,
+}
+
+struct Importer {
+    warnings: Vec<String>,
+}
+
+impl Importer {
+    fn warn(&mut self, sound: &str, message: String) {
+        self.warnings.push(format!("{}: {}", sound, message));
+    }
+
+    /// Reports all keys of `object` that aren't in `known`
+    fn report_unknown(&mut self, sound: &str, object: &Map<String, Value>, known: &[&str]) {
+        for key in object.keys() {
+            if !known.contains(&key.as_str()) {
+                self.warn(sound, format!("'{}' is not supported and was dropped", key));
+            }
+        }
+    }
+
+    fn import_syrinscape(&mut self, source: &Value) -> Result<Value, String> {
+        let name = source["name"].as_str().unwrap_or("Imported Theme");
+        let elements = source["elements"]
+            .as_array()
+            .ok_or("Syrinscape soundset has no 'elements'")?;
+
+        let mut sounds = Vec::new();
+        for (i, element) in elements.iter().enumerate() {
+            let element = element
+                .as_object()
+                .ok_or(format!("Element {} is not an object", i))?;
+            let sound_name = element
+                .get("name")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Element {}", i + 1));
+
+            self.report_unknown(
+                &sound_name,
+                element,
+                &[
+                    "name",
+                    "type",
+                    "samples",
+                    "volume",
+                    "min_delay",
+                    "max_delay",
+                ],
+            );
+
+            let files: Vec<&str> = element
+                .get("samples")
+                .and_then(Value::as_array)
+                .map(|samples| {
+                    samples
+                        .iter()
+                        .filter_map(|s| s.get("file").and_then(Value::as_str))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let file = match files.first() {
+                Some(file) => file.to_string(),
+                None => {
+                    self.warn(&sound_name, "has no samples and was skipped".to_string());
+                    continue;
+                }
+            };
+
+            if files.len() > 1 {
+                self.warn(
+                    &sound_name,
+                    format!(
+                        "only the first of {} samples is used, a sound plays a single file",
+                        files.len()
+                    ),
+                );
+            }
+
+            let volume = element
+                .get("volume")
+                .and_then(Value::as_f64)
+                .unwrap_or(100.0)
+                / 100.0;
+            let delay =
+                |key: &str| element.get(key).and_then(Value::as_f64).unwrap_or(0.0) * 1000.0;
+            let loop_delay = (delay("min_delay") as u64, delay("max_delay") as u64);
+
+            let element_type = element
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or("loop");
+            let mut sound = json!({
+                "name": sound_name,
+                "file": file,
+                "volume": [volume, volume],
+                "trigger": null,
+                "enabled": true,
+            });
+
+            match element_type {
+                "music" => {
+                    sound["music"] = json!(true);
+                    sound["loop_forever"] = json!(true);
+                }
+                "loop" | "ambience" => sound["loop_forever"] = json!(true),
+                "oneshot" => {
+                    sound["loop_forever"] = json!(true);
+                    sound["loop_delay"] = json!(loop_delay);
+                }
+                other => {
+                    self.warn(
+                        &sound_name,
+                        format!("unknown element type '{}', imported as a loop", other),
+                    );
+                    sound["loop_forever"] = json!(true);
+                }
+            }
+
+            sounds.push(sound);
+        }
+
+        Ok(json!({ "name": name, "sounds": sounds }))
+    }
+
+    fn import_tabletop_audio(&mut self, source: &Value) -> Result<Value, String> {
+        let name = source["title"].as_str().unwrap_or("Imported Theme");
+        let entries = source["sounds"]
+            .as_array()
+            .ok_or("SoundPad has no 'sounds'")?;
+
+        let mut sounds = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let entry = entry
+                .as_object()
+                .ok_or(format!("Sound {} is not an object", i))?;
+            let sound_name = entry
+                .get("label")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Sound {}", i + 1));
+
+            self.report_unknown(&sound_name, entry, &["label", "file", "loop", "volume"]);
+
+            let file = match entry.get("file").and_then(Value::as_str) {
+                Some(file) => file,
+                None => {
+                    self.warn(&sound_name, "has no file and was skipped".to_string());
+                    continue;
+                }
+            };
+
+            let volume = entry.get("volume").and_then(Value::as_f64).unwrap_or(1.0);
+            let looping = entry.get("loop").and_then(Value::as_bool).unwrap_or(false);
+
+            // Pads that don't loop are played on demand, so they become triggered sounds
+            sounds.push(json!({
+                "name": sound_name,
+                "file": file,
+                "volume": [volume, volume],
+                "trigger": if looping { Value::Null } else { json!(sound_name) },
+                "enabled": true,
+                "loop_forever": looping,
+            }));
+        }
+
+        Ok(json!({ "name": name, "sounds": sounds }))
+    }
+}
+
+/// Converts an export of another soundscape tool into a theme, reporting everything
+/// that couldn't be mapped
+pub fn import_theme(format: ImportFormat, source: &Value) -> Result<ImportResult, String> {
+    let mut importer = Importer {
+        warnings: Vec::new(),
+    };
+
+    let theme = match format {
+        ImportFormat::Syrinscape => importer.import_syrinscape(source)?,
+        ImportFormat::TabletopAudio => importer.import_tabletop_audio(source)?,
+    };
+
+    // Make sure the result is something the engine will accept
+    serde_json::from_value::<Theme>(theme.clone())
+        .map_err(|e| format!("Converted theme is invalid: {}", e))?;
+
+    Ok(ImportResult {
+        theme,
+        warnings: importer.warnings,
+    })
+}
@@ -0,0 +1,32 @@
+#![warn(unused_extern_crates)]
+
+#[macro_use]
+extern crate rusqlite;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+
+#[macro_use]
+mod utils;
+#[macro_use]
+pub mod audio_engine;
+pub mod api;
+pub mod authorization;
+pub mod config;
+mod error;
+mod idempotency;
+pub mod import;
+pub mod lint;
+mod migrate;
+mod profiles;
+pub mod samplesdb;
+pub mod satellite;
+pub mod schedule;
+mod sessions;
+pub mod settings;
+pub mod theme;
+
+/// Scripted scenarios on the engine's own clock, for the crate's own tests and theme authors
+#[cfg(feature = "test-support")]
+pub mod testing;
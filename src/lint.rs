@@ -0,0 +1,566 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::audio_engine::backends::alto::{MAX_EFFECTS, REVERB_PRESETS};
+use crate::audio_engine::backends::base::{CustomReverb, Effect};
+use crate::audio_engine::generator;
+use crate::audio_engine::loader;
+use crate::samplesdb::is_library_path;
+use crate::samplesdb::remote::is_remote;
+use crate::theme::{Playlist, Sound, SoundType, Theme};
+
+// Looping files longer than this without any delay are most likely music beds by accident
+const LONG_FILE_SECONDS: f32 = 60.0;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub enum LintLevel {
+    /// The theme will fail to load or misbehave
+    Error,
+    /// The theme works, but probably not the way it was intended
+    Warning,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LintMessage {
+    pub level: LintLevel,
+    pub sound: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for LintMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.sound {
+            Some(ref sound) => write!(f, "{:?} [{}]: {}", self.level, sound, self.message),
+            None => write!(f, "{:?}: {}", self.level, self.message),
+        }
+    }
+}
+
+struct Linter<'a> {
+    library_path: Option<&'a Path>,
+    reverbs: &'a HashMap<String, CustomReverb>,
+    messages: Vec<LintMessage>,
+}
+
+impl Linter<'_> {
+    fn knows_reverb(&self, reverb: &str) -> bool {
+        REVERB_PRESETS.contains(&reverb) || self.reverbs.contains_key(reverb)
+    }
+
+    fn report(&mut self, level: LintLevel, sound: &Sound, message: String) {
+        self.messages.push(LintMessage {
+            level,
+            sound: Some(sound.name.clone()),
+            message,
+        });
+    }
+
+    fn check_range<T: PartialOrd + fmt::Display>(
+        &mut self,
+        sound: &Sound,
+        name: &str,
+        range: &(T, T),
+    ) {
+        if range.0 > range.1 {
+            self.report(
+                LintLevel::Error,
+                sound,
+                format!("{} range is inverted ({} > {})", name, range.0, range.1),
+            );
+        }
+    }
+
+    fn check_unused<T: PartialEq + fmt::Debug>(
+        &mut self,
+        sound: &Sound,
+        name: &str,
+        enabled: bool,
+        value: &T,
+        default: &T,
+    ) {
+        if !enabled && value != default {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                format!("{} is set to {:?} but not enabled", name, value),
+            );
+        }
+    }
+
+    /// Full path of a file in the library, None if there is no library or the path leads
+    /// out of it, so linting can't be used to probe for other files on the host
+    fn library_file(&self, file: &str) -> Option<PathBuf> {
+        match self.library_path {
+            Some(library_path) if is_library_path(file) => Some(library_path.join(file)),
+            _ => None,
+        }
+    }
+
+    /// Read from the header, the file isn't decoded
+    fn duration_of(&self, sound: &Sound) -> Option<f32> {
+        if sound.sound_type != SoundType::File || is_remote(&sound.file) {
+            return None;
+        }
+
+        let path = self.library_file(&sound.file)?;
+        loader::get_loader_for_file(&path)
+            .and_then(|mut l| l.duration(&path))
+            .ok()
+    }
+
+    fn lint_sound(&mut self, sound: &Sound) {
+        self.check_range(sound, "volume", &sound.volume);
+        self.check_range(sound, "repeat_count", &sound.repeat_count);
+        self.check_range(sound, "repeat_delay", &sound.repeat_delay);
+        self.check_range(sound, "loop_count", &sound.loop_count);
+        self.check_range(sound, "loop_delay", &sound.loop_delay);
+        self.check_range(sound, "pitch", &sound.pitch);
+        self.check_range(sound, "lowpass", &sound.lowpass);
+        self.check_range(sound, "highpass", &sound.highpass);
+        self.check_range(sound, "pan", &sound.pan);
+        self.check_range(sound, "fade_in", &sound.fade_in);
+        self.check_range(sound, "variation", &sound.variation);
+        self.check_range(sound, "start_offset", &sound.start_offset);
+
+        if sound.sound_type == SoundType::Tone
+            && generator::check_tone(sound.frequency, sound.duration).is_err()
+        {
+            self.report(
+                LintLevel::Error,
+                sound,
+                format!(
+                    "tone needs a frequency above 0 and a duration between 0 and {} seconds",
+                    generator::MAX_TONE_SECONDS
+                ),
+            );
+        }
+
+        if sound.volume.0 < 0.0 {
+            self.report(LintLevel::Error, sound, "volume is negative".to_string());
+        }
+
+        if sound.volume.1 > 1.0 {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                format!(
+                    "volume goes up to {}, which will likely clip",
+                    sound.volume.1
+                ),
+            );
+        }
+
+        if sound.start_offset.0 < 0.0 {
+            self.report(
+                LintLevel::Error,
+                sound,
+                "start_offset is negative".to_string(),
+            );
+        }
+
+        if sound.play_probability <= 0.0 || sound.play_probability > 1.0 {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                format!(
+                    "play_probability {} should be above 0.0 and at most 1.0",
+                    sound.play_probability
+                ),
+            );
+        }
+
+        if sound.pitch_enabled && sound.pitch.0 <= 0.0 {
+            self.report(LintLevel::Error, sound, "pitch must be above 0".to_string());
+        }
+
+        if sound.variation_enabled && (sound.variation.0 < -1.0 || sound.variation.1 > 1.0) {
+            self.report(
+                LintLevel::Error,
+                sound,
+                "variation must be between -1.0 and 1.0".to_string(),
+            );
+        }
+
+        for &(name, enabled, range) in &[
+            ("lowpass", sound.lowpass_enabled, sound.lowpass),
+            ("highpass", sound.highpass_enabled, sound.highpass),
+        ] {
+            if enabled && (range.0 < 0.0 || range.1 > 1.0) {
+                self.report(
+                    LintLevel::Warning,
+                    sound,
+                    format!("{} amount should be between 0.0 and 1.0", name),
+                );
+            }
+        }
+
+        if sound.pan_enabled {
+            if sound.pan.0 < -1.0 || sound.pan.1 > 1.0 {
+                self.report(
+                    LintLevel::Error,
+                    sound,
+                    "pan must be between -1.0 and 1.0".to_string(),
+                );
+            }
+
+            if sound.position.is_some() || !sound.path.is_empty() {
+                self.report(
+                    LintLevel::Warning,
+                    sound,
+                    "pan is ignored, the sound is placed by its position".to_string(),
+                );
+            }
+        }
+
+        if sound.stereo && (sound.pan_enabled || sound.position.is_some() || !sound.path.is_empty())
+        {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                "stereo sounds can't be panned or positioned".to_string(),
+            );
+        }
+
+        let (default_pitch, default_fade_in) = ((1.0, 1.0), (0.0, 0.0));
+        self.check_unused(
+            sound,
+            "pitch",
+            sound.pitch_enabled,
+            &sound.pitch,
+            &default_pitch,
+        );
+        self.check_unused(
+            sound,
+            "lowpass",
+            sound.lowpass_enabled,
+            &sound.lowpass,
+            &default_pitch,
+        );
+        self.check_unused(
+            sound,
+            "highpass",
+            sound.highpass_enabled,
+            &sound.highpass,
+            &default_pitch,
+        );
+        self.check_unused(
+            sound,
+            "pan",
+            sound.pan_enabled,
+            &sound.pan,
+            &default_fade_in,
+        );
+        self.check_unused(
+            sound,
+            "fade_in",
+            sound.fade_in_enabled,
+            &sound.fade_in,
+            &default_fade_in,
+        );
+        self.check_unused(
+            sound,
+            "variation",
+            sound.variation_enabled,
+            &sound.variation,
+            &default_fade_in,
+        );
+
+        if !self.knows_reverb(&sound.reverb) {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                format!("unknown reverb preset '{}' will be ignored", sound.reverb),
+            );
+        } else if !sound.enabled && sound.reverb != "none" {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                "reverb is configured on a disabled sound".to_string(),
+            );
+        }
+
+        if sound.effects.len() > MAX_EFFECTS {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                format!(
+                    "only the first {} of {} effects will be applied",
+                    MAX_EFFECTS,
+                    sound.effects.len()
+                ),
+            );
+        }
+
+        for effect in &sound.effects {
+            if let Effect::Reverb { ref preset, .. } = *effect {
+                if !self.knows_reverb(preset) {
+                    self.report(
+                        LintLevel::Warning,
+                        sound,
+                        format!("unknown reverb preset '{}' in effects", preset),
+                    );
+                }
+            }
+
+            for problem in effect.clamped().1 {
+                self.report(
+                    LintLevel::Warning,
+                    sound,
+                    format!("{} effect: {}", effect.name(), problem),
+                );
+            }
+        }
+
+        if sound.waits_for_trigger() && sound.loop_forever {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                "triggered sound loops forever and can only be stopped by triggering it again"
+                    .to_string(),
+            );
+        }
+
+        let loops = sound.loop_forever || sound.loop_count.1 > 0;
+        if sound.loop_crossfade_ms > 0 && (!loops || sound.loop_delay.1 > 0) {
+            self.report(
+                LintLevel::Warning,
+                sound,
+                "loop_crossfade_ms only applies to sounds looping without a delay".to_string(),
+            );
+        }
+
+        if loops && sound.loop_delay == (0, 0) {
+            if let Some(duration) = self.duration_of(sound) {
+                if duration > LONG_FILE_SECONDS {
+                    self.report(
+                        LintLevel::Warning,
+                        sound,
+                        format!(
+                            "{:.0}s long file loops without any delay between loops",
+                            duration
+                        ),
+                    );
+                }
+            }
+        }
+
+        if self.library_path.is_some()
+            && sound.sound_type == SoundType::File
+            && !is_remote(&sound.file)
+        {
+            match self.library_file(&sound.file) {
+                Some(path) if path.is_file() => {}
+                Some(_) => self.report(
+                    LintLevel::Error,
+                    sound,
+                    format!("file '{}' not found in sound library", sound.file),
+                ),
+                None => self.report(
+                    LintLevel::Error,
+                    sound,
+                    format!("file '{}' is outside of the sound library", sound.file),
+                ),
+            }
+        }
+    }
+
+    fn lint_playlist(&mut self, playlist: &Playlist) {
+        let mut messages = Vec::new();
+
+        if playlist.tracks.is_empty() {
+            messages.push((LintLevel::Warning, "playlist has no tracks".to_string()));
+        }
+
+        if playlist.gap > 0 && playlist.crossfade > 0 {
+            messages.push((
+                LintLevel::Warning,
+                "gap is ignored while the playlist crossfades".to_string(),
+            ));
+        }
+
+        if self.library_path.is_some() {
+            for track in playlist.tracks.iter().filter(|track| !is_remote(track)) {
+                match self.library_file(track) {
+                    Some(path) if path.is_file() => {}
+                    Some(_) => messages.push((
+                        LintLevel::Error,
+                        format!("track '{}' not found in sound library", track),
+                    )),
+                    None => messages.push((
+                        LintLevel::Error,
+                        format!("track '{}' is outside of the sound library", track),
+                    )),
+                }
+            }
+        }
+
+        for (level, message) in messages {
+            self.messages.push(LintMessage {
+                level,
+                sound: Some(playlist.name.clone()),
+                message,
+            });
+        }
+    }
+}
+
+/// Checks a theme for mistakes and smells. If `library_path` is given, the referenced
+/// files are checked as well.
+pub fn lint_theme(theme: &Theme, library_path: Option<&Path>) -> Vec<LintMessage> {
+    let mut linter = Linter {
+        library_path,
+        reverbs: &theme.reverbs,
+        messages: Vec::new(),
+    };
+
+    for (name, reverb) in &theme.reverbs {
+        let mut messages = reverb.clamped().1;
+
+        if REVERB_PRESETS.contains(&name.as_str()) {
+            messages.push("takes the place of the built-in preset of the same name".to_string());
+        }
+        if !REVERB_PRESETS.contains(&reverb.preset.as_str()) {
+            messages.push(format!(
+                "unknown preset '{}' will be ignored",
+                reverb.preset
+            ));
+        }
+
+        for message in messages {
+            linter.messages.push(LintMessage {
+                level: LintLevel::Warning,
+                sound: None,
+                message: format!("reverb '{}': {}", name, message),
+            });
+        }
+    }
+
+    if theme.sounds.is_empty() {
+        linter.messages.push(LintMessage {
+            level: LintLevel::Warning,
+            sound: None,
+            message: "theme does not contain any sounds".to_string(),
+        });
+    }
+
+    let mut names = HashSet::new();
+    for sound in &theme.sounds {
+        if !names.insert(&sound.name) {
+            linter.report(
+                LintLevel::Error,
+                sound,
+                "sound name is used more than once".to_string(),
+            );
+        }
+
+        linter.lint_sound(sound);
+    }
+
+    let groups: HashSet<&String> = theme
+        .sounds
+        .iter()
+        .filter_map(|s| s.group.as_ref())
+        .collect();
+    for sound in &theme.sounds {
+        for group in sound.ducks.iter().filter(|g| !groups.contains(g)) {
+            linter.report(
+                LintLevel::Warning,
+                sound,
+                format!("ducks group '{}' which contains no sounds", group),
+            );
+        }
+    }
+
+    for playlist in &theme.playlists {
+        linter.lint_playlist(playlist);
+    }
+
+    let playlists: HashSet<&String> = theme.playlists.iter().map(|p| &p.name).collect();
+    for (scene, sounds) in &theme.scenes {
+        for sound in sounds
+            .iter()
+            .filter(|s| !names.contains(s) && !playlists.contains(s))
+        {
+            linter.messages.push(LintMessage {
+                level: LintLevel::Warning,
+                sound: None,
+                message: format!(
+                    "scene '{}' contains unknown sound or playlist '{}'",
+                    scene, sound
+                ),
+            });
+        }
+    }
+
+    if let Some(ref scene) = theme.initial_scene {
+        if !theme.scenes.contains_key(scene) {
+            linter.messages.push(LintMessage {
+                level: LintLevel::Error,
+                sound: None,
+                message: format!("initial scene '{}' doesn't exist", scene),
+            });
+        }
+    }
+
+    linter.messages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn theme_with_file(file: &str) -> Theme {
+        serde_json::from_value(serde_json::json!({
+            "version": 2,
+            "name": "Files",
+            "sounds": [{
+                "name": "Sound",
+                "file": file,
+                "volume": [1.0, 1.0],
+                "enabled": true
+            }],
+            "playlists": [{ "name": "Music", "tracks": [file] }]
+        }))
+        .unwrap()
+    }
+
+    fn file_messages(file: &str) -> Vec<String> {
+        let library = env::temp_dir();
+        lint_theme(&theme_with_file(file), Some(&library))
+            .into_iter()
+            .filter(|message| message.level == LintLevel::Error)
+            .map(|message| message.message)
+            .collect()
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        assert_eq!(
+            file_messages("missing.ogg"),
+            vec![
+                "file 'missing.ogg' not found in sound library",
+                "track 'missing.ogg' not found in sound library"
+            ]
+        );
+    }
+
+    #[test]
+    fn files_outside_of_the_library_are_not_looked_up() {
+        for file in &[
+            "/etc/hostname",
+            "../etc/hostname",
+            "sounds/../../etc/hostname",
+        ] {
+            assert_eq!(
+                file_messages(file),
+                vec![
+                    format!("file '{}' is outside of the sound library", file),
+                    format!("track '{}' is outside of the sound library", file)
+                ]
+            );
+        }
+    }
+}
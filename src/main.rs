@@ -1,119 +1,865 @@
 #![warn(unused_extern_crates)]
 
-#[macro_use]
-extern crate rusqlite;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate serde_derive;
-
-#[macro_use]
-mod utils;
-#[macro_use]
-mod audio_engine;
-mod api;
-mod authorization;
-mod error;
-mod samplesdb;
-mod theme;
 
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread;
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use crossbeam_channel::{unbounded, Sender};
 use structopt::StructOpt;
 
-use api::start_web_service;
-use audio_engine::backends::alto::OpenALBackend;
-use audio_engine::engine::start_audio_controller;
-use audio_engine::messages::{Command, Response};
-use samplesdb::{SamplesDB, SamplesDBError};
+use sinfonia_server::api::{start_web_service, Room};
+use sinfonia_server::audio_engine::backends::alto::OpenALBackend;
+use sinfonia_server::audio_engine::backends::null::NullBackend;
+use sinfonia_server::audio_engine::backends::rodio::RodioBackend;
+use sinfonia_server::audio_engine::engine::command_log::read_command_log;
+use sinfonia_server::audio_engine::engine::ducking::DuckingConfig;
+use sinfonia_server::audio_engine::engine::error::AudioEngineError;
+use sinfonia_server::audio_engine::engine::heartbeat::Heartbeat;
+use sinfonia_server::audio_engine::engine::state_version::StateVersion;
+use sinfonia_server::audio_engine::engine::{start_audio_controller, EngineConfig};
+use sinfonia_server::audio_engine::messages::{
+    Command, ErrorCode, MissingSampleBehavior, Request, Response,
+};
+use sinfonia_server::audio_engine::resampler::Resampler;
+use sinfonia_server::authorization::{NamedToken, ScopedToken};
+use sinfonia_server::config::{Config, ConfigSource};
+use sinfonia_server::import::{import_theme, ImportFormat};
+use sinfonia_server::lint::{lint_theme, LintLevel};
+use sinfonia_server::samplesdb::{
+    Backup, SampleRepository, SamplesDB, SamplesDBError, SharedSamplesDB, Snapshots,
+    SqliteRepository,
+};
+use sinfonia_server::theme::Theme;
 
 /// A basic example
 #[derive(StructOpt, Debug)]
 #[structopt(name = "basic")]
 struct Opt {
-    #[structopt(short = "h", long = "host", default_value = "127.0.0.1")]
-    host: String,
+    /// TOML file with the host, port, access token, sound library, database, backend and
+    /// log level, the options given here take precedence
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    #[structopt(short = "h", long = "host")]
+    host: Option<String>,
+
+    #[structopt(short = "p", long = "port")]
+    port: Option<u32>,
+
+    /// Token with the admin role
+    #[structopt(short = "a", long = "access-token")]
+    token: Option<String>,
+
+    /// Additional token with a role, admin, controller (everything but administration
+    /// and switching the output device) or viewer (read only), e.g. 'overlay:viewer:s3cret'
+    #[structopt(long = "token", number_of_values = 1)]
+    tokens: Vec<NamedToken>,
+
+    /// Additional token that may only control some themes, e.g. 'helper=Tavern,Forest'
+    #[structopt(long = "scoped-token", number_of_values = 1)]
+    scoped_tokens: Vec<ScopedToken>,
+
+    #[structopt(short = "t", long = "threads")]
+    threads: Option<usize>,
+
+    #[structopt(short = "s", long = "sound-library", parse(from_os_str))]
+    sound_library: Option<PathBuf>,
+
+    /// Samples database, either a path to an SQLite file or a postgres:// URL
+    #[structopt(long = "database")]
+    database: Option<String>,
+
+    /// error, warn, info, debug or trace
+    #[structopt(long = "log-level")]
+    log_level: Option<String>,
+
+    /// Snapshot the SQLite database and its themes into this directory regularly, see the
+    /// restore-snapshot subcommand
+    #[structopt(long = "backup-dir", parse(from_os_str))]
+    backup_dir: Option<PathBuf>,
+
+    /// Minutes between two snapshots [default: 60]
+    #[structopt(long = "backup-interval")]
+    backup_interval: Option<u64>,
+
+    /// Number of snapshots to keep [default: 24]
+    #[structopt(long = "backup-keep")]
+    backup_keep: Option<usize>,
+
+    /// Largest accepted JSON request body in megabytes, e.g. for big generated themes
+    #[structopt(long = "max-payload", default_value = "16")]
+    max_payload: usize,
+
+    /// Milliseconds to fade out/in when pausing and resuming
+    #[structopt(long = "pause-fade", default_value = "250")]
+    pause_fade: u64,
+
+    /// Number of previews that may play at once, 0 for no limit
+    #[structopt(long = "max-previews", default_value = "3")]
+    max_previews: usize,
+
+    /// Queue previews beyond --max-previews until one finishes instead of rejecting them
+    #[structopt(long = "queue-previews")]
+    queue_previews: bool,
+
+    /// Lower the ambience while the microphone picks up someone talking
+    #[structopt(long = "duck-on-talk")]
+    duck_on_talk: bool,
+
+    /// Input level (0.0 - 1.0) above which the microphone counts as hot
+    #[structopt(long = "duck-threshold", default_value = "0.1")]
+    duck_threshold: f32,
+
+    /// How much to lower the ambience while ducking (0.0 - 1.0)
+    #[structopt(long = "duck-amount", default_value = "0.5")]
+    duck_amount: f32,
+
+    /// Milliseconds to stay ducked after the microphone went quiet
+    #[structopt(long = "duck-hold", default_value = "1000")]
+    duck_hold: u64,
+
+    /// Record every command that changes what is played to this file, see the replay
+    /// subcommand
+    #[structopt(long = "command-log", parse(from_os_str))]
+    command_log: Option<PathBuf>,
+
+    /// Additional room with its own theme and volume, controlled below /rooms/{id}. Either
+    /// 'id' or 'id:device' to play on another output device, see GET /driverlist.
+    #[structopt(long = "room", number_of_values = 1)]
+    rooms: Vec<RoomOption>,
+
+    /// Instead of serving the API, play whatever the primary instance at this URL plays,
+    /// e.g. 'http://10.0.0.2:9090'. The access token is used to connect to it.
+    #[structopt(long = "satellite")]
+    satellite: Option<String>,
+
+    /// Audio output to use, openal, rodio (for systems without OpenAL) or null (no output)
+    #[structopt(long = "backend")]
+    backend: Option<String>,
+
+    /// Sample rate in Hz to play at, e.g. 48000 for a Bluetooth speaker. Samples at other
+    /// rates are resampled before playing. Defaults to the rate the device mixes at.
+    #[structopt(long = "output-rate")]
+    output_rate: Option<i32>,
+
+    /// How samples are resampled to the output rate: cubic, linear (cheaper, for slow
+    /// machines) or off to leave it to the device
+    #[structopt(long = "resampler", default_value = "cubic")]
+    resampler: Resampler,
+
+    /// Warn on GET /events once this share (0.0 - 1.0) of the sources is in use, so sounds
+    /// don't silently stop playing when the backend runs out of them
+    #[structopt(long = "source-pool-warning", default_value = "0.9")]
+    source_pool_warning: f32,
+
+    /// Sources OpenAL's pool grows to when all of them are in use, sounds wait for one
+    /// beyond that
+    #[structopt(long = "max-sources", default_value = "256")]
+    max_sources: usize,
+
+    /// Milliseconds the engine moves sounds and fades forward by at a time, lower values
+    /// react faster at the cost of more CPU
+    #[structopt(long = "tick", default_value = "10")]
+    tick: u64,
+
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Backend {
+    OpenAL,
+    Rodio,
+    Null,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openal" => Ok(Backend::OpenAL),
+            "rodio" => Ok(Backend::Rodio),
+            "null" => Ok(Backend::Null),
+            _ => Err(format!("Unknown audio backend '{}'", s)),
+        }
+    }
+}
 
-    #[structopt(short = "p", long = "port", default_value = "9090")]
-    port: u32,
+#[derive(Debug)]
+struct RoomOption {
+    id: String,
+    device: Option<i32>,
+}
+
+impl std::str::FromStr for RoomOption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let id = parts.next().unwrap_or("").to_string();
+
+        if id.is_empty() || id.contains('/') {
+            return Err(format!("Invalid room id '{}'", id));
+        }
+
+        let device = match parts.next() {
+            Some(device) => Some(
+                device
+                    .parse()
+                    .map_err(|_| format!("Invalid output device '{}'", device))?,
+            ),
+            None => None,
+        };
+
+        Ok(RoomOption { id, device })
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Subcommand {
+    /// Check a theme file for errors and common mistakes without starting the server
+    #[structopt(name = "validate")]
+    Validate {
+        #[structopt(parse(from_os_str))]
+        theme: PathBuf,
+    },
+
+    /// Convert a soundscape export of another tool (syrinscape, tabletop_audio) into a theme
+    #[structopt(name = "import")]
+    Import {
+        #[structopt(short = "f", long = "format")]
+        format: ImportFormat,
+
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+
+    /// Write everything stored in the samples database to a file
+    #[structopt(name = "backup")]
+    Backup {
+        /// Include the sample files themselves
+        #[structopt(long = "samples")]
+        samples: bool,
+
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+
+    /// Restore a backup written by the backup subcommand or GET /admin/backup
+    #[structopt(name = "restore")]
+    Restore {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+
+    /// Overwrite the database with a snapshot taken with --backup-dir, lists the snapshots
+    /// if none is given. The server must not be running.
+    #[structopt(name = "restore-snapshot")]
+    RestoreSnapshot { name: Option<String> },
+
+    /// Issue the commands of a log written with --command-log again, with their original
+    /// timing. They run on a copy of the SQLite database, which is left as it is.
+    #[structopt(name = "replay")]
+    Replay {
+        /// Replay faster (e.g. 10) or slower (e.g. 0.5) than recorded
+        #[structopt(long = "speed", default_value = "1.0")]
+        speed: f32,
+
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+pub type ChannelSender = Sender<Request>;
+
+type EngineHandle = JoinHandle<Result<(), AudioEngineError>>;
 
-    #[structopt(short = "a", long = "access-token", default_value = "totallynotsecure")]
-    token: String,
+impl Opt {
+    fn engine_config(&self) -> EngineConfig {
+        EngineConfig {
+            ducking: if self.duck_on_talk {
+                Some(DuckingConfig {
+                    threshold: self.duck_threshold,
+                    amount: self.duck_amount,
+                    hold: self.duck_hold,
+                })
+            } else {
+                None
+            },
+            pause_fade: self.pause_fade,
+            max_previews: self.max_previews,
+            queue_previews: self.queue_previews,
+            command_log: self.command_log.clone(),
+            output_rate: self.output_rate,
+            resampler: self.resampler,
+            source_pool_warning: self.source_pool_warning,
+            max_sources: self.max_sources,
+            tick: self.tick,
+            heartbeat: Heartbeat::default(),
+            version: StateVersion::default(),
+        }
+    }
+
+    fn config_source(&self) -> ConfigSource {
+        ConfigSource {
+            file: self.config.clone(),
+            overrides: Config {
+                host: self.host.clone(),
+                port: self.port,
+                token: self.token.clone(),
+                tokens: if self.tokens.is_empty() {
+                    None
+                } else {
+                    Some(self.tokens.clone())
+                },
+                threads: self.threads,
+                sound_library: self.sound_library.clone(),
+                database: self.database.clone(),
+                backend: self.backend.clone(),
+                log_level: self.log_level.clone(),
+                backup_dir: self.backup_dir.clone(),
+                backup_interval: self.backup_interval,
+                backup_keep: self.backup_keep,
+            },
+        }
+    }
+}
+
+fn open_repository(database: &str) -> Result<Box<dyn SampleRepository>, SamplesDBError> {
+    if database.starts_with("postgres://") || database.starts_with("postgresql://") {
+        #[cfg(feature = "postgresql")]
+        return Ok(Box::new(
+            sinfonia_server::samplesdb::PostgresRepository::open(database)?,
+        ));
 
-    #[structopt(short = "t", long = "threads", default_value = "2")]
-    threads: usize,
+        #[cfg(not(feature = "postgresql"))]
+        return Err(SamplesDBError::UnsupportedDatabase(database.to_string()));
+    }
+
+    Ok(Box::new(SqliteRepository::open(Path::new(database))?))
+}
+
+/// Opens the samples database once, the engines of all rooms share it
+fn open_samplesdb(config: &Config) -> Result<SharedSamplesDB, SamplesDBError> {
+    let samplesdb = SamplesDB::open(
+        open_repository(&config.database())?,
+        &config.sound_library(),
+    )?;
+
+    Ok(Arc::new(Mutex::new(samplesdb)))
+}
 
-    #[structopt(
-        short = "s",
-        long = "sound-library",
-        default_value = "/home/fabian/tmp/sound/",
-        parse(from_os_str)
-    )]
-    sound_library: PathBuf,
+/// Starts the audio engine on its own thread. Requests are sent through the returned
+/// sender, responses come back on a channel that comes with each request.
+fn start_engine(
+    samplesdb: SharedSamplesDB,
+    backend: Backend,
+    engine_config: EngineConfig,
+) -> (ChannelSender, EngineHandle) {
+    let (sender, receiver) = unbounded();
+
+    let handle = thread::Builder::new()
+        .name("audio-engine".to_string())
+        .spawn(move || {
+            raise_thread_priority();
+
+            match backend {
+                Backend::OpenAL => {
+                    start_audio_controller::<OpenALBackend>(receiver, samplesdb, engine_config)
+                }
+                Backend::Rodio => {
+                    start_audio_controller::<RodioBackend>(receiver, samplesdb, engine_config)
+                }
+                Backend::Null => {
+                    start_audio_controller::<NullBackend>(receiver, samplesdb, engine_config)
+                }
+            }
+        })
+        .expect("Failed to start the audio engine thread");
+
+    (sender, handle)
+}
+
+/// Loads the theme that was loaded last before the server stopped, paused. Only stored
+/// themes can be loaded again, themes that were just posted are gone.
+fn load_last_theme(samplesdb: &SharedSamplesDB, sender: &ChannelSender) {
+    let name = match samplesdb.lock().unwrap().settings() {
+        Ok(settings) => match settings.last_theme {
+            Some(name) => name,
+            None => return,
+        },
+        Err(e) => {
+            warn!("Failed to read settings, not loading the last theme: {}", e);
+            return;
+        }
+    };
+
+    let (request, response) = Request::new(Command::LoadStoredTheme {
+        name: name.clone(),
+        on_missing: MissingSampleBehavior::default(),
+        strict: false,
+    });
+    if sender.send(request).is_err() {
+        return;
+    }
+
+    match response.recv() {
+        Ok(Response::Error(ref e)) if e.code == ErrorCode::NotFound => {
+            info!("Last theme '{}' isn't stored, not loading it", name)
+        }
+        Ok(Response::Error(e)) => error!("Failed to load the last theme '{}': {}", name, e),
+        Ok(_) => info!("Loaded the last theme '{}'", name),
+        Err(_) => error!("AudioController stopped while loading the last theme"),
+    }
 }
 
-pub type ChannelSender = Sender<Command>;
-pub type ResponseReceiver = Receiver<Response>;
+/// Schedules the calling thread round-robin with real-time priority, so the web workers and
+/// decoding threads can't hold up the engine's updates. This needs CAP_SYS_NICE (or an
+/// rtprio limit), without it the thread keeps the normal priority.
+#[cfg(unix)]
+fn raise_thread_priority() {
+    // Enough to get ahead of everything else, while staying below the audio server
+    const ENGINE_THREAD_PRIORITY: i32 = 10;
+
+    let result = unsafe {
+        let mut param: libc::sched_param = std::mem::zeroed();
+        param.sched_priority = ENGINE_THREAD_PRIORITY;
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_RR, &param)
+    };
+
+    if result == 0 {
+        info!("Audio engine runs with real-time priority");
+    } else {
+        warn!(
+            "Failed to raise the priority of the audio engine, it runs with normal priority: {}",
+            std::io::Error::from_raw_os_error(result)
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn raise_thread_priority() {}
+
+fn stop_engine(sender: &ChannelSender, handle: EngineHandle) {
+    // Tell AudioController to shut down, unless it has stopped on its own already
+    if sender.send(Request::new(Command::Quit).0).is_err() {
+        warn!("AudioController has already stopped");
+    }
+
+    // Wait until AudioController shuts down
+    match handle.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("AudioController failed: {}", e),
+        Err(_) => error!("AudioController panicked!"),
+    }
+}
+
+fn replay_log(
+    config: &Config,
+    backend: Backend,
+    engine_config: EngineConfig,
+    path: &Path,
+    speed: f32,
+) -> ! {
+    if speed <= 0.0 {
+        eprintln!("The speed has to be greater than 0");
+        process::exit(1);
+    }
+
+    let commands = match read_command_log(path) {
+        Ok(commands) => commands,
+        Err(e) => {
+            eprintln!(
+                "Failed to read command log '{}': {}",
+                path.to_string_lossy(),
+                e
+            );
+            process::exit(1);
+        }
+    };
+
+    // Saving themes, tagging, normalizing, ... would otherwise be done to the database
+    // once more
+    let database = config.database();
+    if database.starts_with("postgres://") || database.starts_with("postgresql://") {
+        eprintln!("Replaying needs an SQLite database to run on a copy of");
+        process::exit(1);
+    }
+    let scratch = std::env::temp_dir().join(format!("sinfonia-replay-{}.db", process::id()));
+    if let Err(e) = fs::copy(&database, &scratch) {
+        eprintln!("Failed to copy the database '{}': {}", database, e);
+        process::exit(1);
+    }
+    let config = Config {
+        database: Some(scratch.to_string_lossy().into_owned()),
+        ..config.clone()
+    };
+
+    let (sender, handle) = match open_samplesdb(&config) {
+        Ok(samplesdb) => start_engine(samplesdb, backend, engine_config),
+        Err(e) => {
+            eprintln!("Failed to open the samples database: {}", e);
+            let _ = fs::remove_file(&scratch);
+            process::exit(1);
+        }
+    };
+
+    info!(
+        "Replaying {} commands from '{}'",
+        commands.len(),
+        path.to_string_lossy()
+    );
+
+    let started = Instant::now();
+    for logged in commands {
+        let due = Duration::from_millis((logged.time as f64 / f64::from(speed)) as u64);
+        let elapsed = started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+
+        let (request, response) = Request::new(logged.command);
+        if sender.send(request).is_err() {
+            break;
+        }
+
+        match response.recv() {
+            Ok(Response::Error(e)) => warn!("Command recorded at {} ms failed: {}", logged.time, e),
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+
+    stop_engine(&sender, handle);
+    if let Err(e) = fs::remove_file(&scratch) {
+        warn!("Failed to remove '{}': {}", scratch.to_string_lossy(), e);
+    }
+    info!("Replay finished");
+    process::exit(0);
+}
+
+fn import_file(format: ImportFormat, path: &Path) -> ! {
+    let result = File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+        .and_then(|source| import_theme(format, &source));
+
+    match result {
+        Ok(result) => {
+            for warning in &result.warnings {
+                eprintln!("Warning: {}", warning);
+            }
+
+            println!("{}", serde_json::to_string_pretty(&result.theme).unwrap());
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Failed to import '{}': {}", path.to_string_lossy(), e);
+            process::exit(1);
+        }
+    }
+}
+
+fn write_backup(config: &Config, path: &Path, samples: bool) -> ! {
+    let result = open_repository(&config.database())
+        .and_then(|repository| SamplesDB::open(repository, &config.sound_library()))
+        .and_then(|samplesdb| samplesdb.backup(samples))
+        .map_err(|e| e.to_string())
+        .and_then(|backup| {
+            File::create(path)
+                .map_err(|e| e.to_string())
+                .and_then(|f| serde_json::to_writer(f, &backup).map_err(|e| e.to_string()))
+        });
+
+    match result {
+        Ok(()) => {
+            println!("Wrote backup to '{}'.", path.to_string_lossy());
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Failed to write backup '{}': {}", path.to_string_lossy(), e);
+            process::exit(1);
+        }
+    }
+}
+
+fn restore_backup(config: &Config, path: &Path) -> ! {
+    let result = File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader::<_, Backup>(f).map_err(|e| e.to_string()))
+        .and_then(|backup| {
+            open_repository(&config.database())
+                .and_then(|repository| SamplesDB::open(repository, &config.sound_library()))
+                .and_then(|mut samplesdb| samplesdb.restore(&backup))
+                .map_err(|e| e.to_string())
+        });
+
+    match result {
+        Ok(()) => {
+            println!("Restored backup '{}'.", path.to_string_lossy());
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed to restore backup '{}': {}",
+                path.to_string_lossy(),
+                e
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn snapshots(config: &Config) -> Option<(Snapshots, PathBuf)> {
+    let dir = config.backup_dir.as_ref()?;
+    let database = config.database();
+
+    if database.starts_with("postgres://") || database.starts_with("postgresql://") {
+        warn!("Snapshots are only taken of SQLite databases, back up postgres on its own");
+        return None;
+    }
+
+    Some((
+        Snapshots::new(dir, config.backup_keep()),
+        PathBuf::from(database),
+    ))
+}
+
+fn restore_snapshot(config: &Config, name: Option<&str>) -> ! {
+    let (snapshots, database) = match snapshots(config) {
+        Some(snapshots) => snapshots,
+        None => {
+            eprintln!("Snapshots need --backup-dir and an SQLite database.");
+            process::exit(1);
+        }
+    };
+
+    let name = match name {
+        Some(name) => name,
+        None => match snapshots.list() {
+            Ok(names) => {
+                for name in names {
+                    println!("{}", name);
+                }
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Failed to list snapshots: {}", e);
+                process::exit(1);
+            }
+        },
+    };
+
+    match snapshots.restore(name, &database) {
+        Ok(()) => {
+            println!("Restored snapshot '{}'.", name);
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Failed to restore snapshot '{}': {}", name, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Snapshots the database every --backup-interval minutes on its own thread
+fn start_snapshots(config: &Config) {
+    let (snapshots, database) = match snapshots(config) {
+        Some(snapshots) => snapshots,
+        None => return,
+    };
+    let interval = Duration::from_secs(config.backup_interval().max(1) * 60);
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        match snapshots.take(&database) {
+            Ok(name) => info!("Took snapshot '{}'", name),
+            Err(e) => error!("Failed to take a snapshot: {}", e),
+        }
+    });
+}
+
+fn validate_theme(path: &Path, library_path: &Path) -> ! {
+    let theme: Theme = match File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+    {
+        Ok(theme) => theme,
+        Err(e) => {
+            eprintln!("Failed to read theme '{}': {}", path.to_string_lossy(), e);
+            process::exit(1);
+        }
+    };
+
+    let messages = lint_theme(&theme, Some(library_path));
+    for message in &messages {
+        println!("{}", message);
+    }
+
+    if messages.iter().any(|m| m.level == LintLevel::Error) {
+        process::exit(1);
+    }
+
+    println!("Theme '{}' is valid.", theme.name);
+    process::exit(0);
+}
 
 #[actix_rt::main]
 async fn main() -> Result<(), SamplesDBError> {
-    std::env::set_var(
-        "RUST_LOG",
-        "sinfonia_server=debug,alto=debug,actix_web=debug",
-    );
+    let opt = Opt::from_args();
+    let source = opt.config_source();
+
+    let config = match source.read() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let backend = match config.backend().parse::<Backend>() {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let log_level = config.log_level();
+    let log_level = match log::LevelFilter::from_str(&log_level) {
+        Ok(level) => level,
+        Err(_) => {
+            eprintln!("Unknown log level '{}'", log_level);
+            process::exit(1);
+        }
+    };
+
     std::env::set_var("RUST_BACKTRACE", "full");
 
-    let opt = Opt::from_args();
+    match opt.command {
+        Some(Subcommand::Validate { ref theme }) => validate_theme(theme, &config.sound_library()),
+        Some(Subcommand::Import { format, ref file }) => import_file(format, file),
+        Some(Subcommand::Backup { samples, ref file }) => write_backup(&config, file, samples),
+        Some(Subcommand::Restore { ref file }) => restore_backup(&config, file),
+        Some(Subcommand::RestoreSnapshot { ref name }) => {
+            restore_snapshot(&config, name.as_ref().map(String::as_str))
+        }
+        Some(Subcommand::Replay { .. }) | None => {}
+    }
+
+    // The logger lets everything of ours through and only the maximum level filters, so
+    // reloading the configuration can raise it again
+    env_logger::Builder::new()
+        .parse("sinfonia_server=trace,alto=trace,actix_web=trace")
+        .init();
+    log::set_max_level(log_level);
+
+    if let Some(Subcommand::Replay { speed, ref file }) = opt.command {
+        // Replaying records nothing itself
+        let engine_config = EngineConfig {
+            command_log: None,
+            ..opt.engine_config()
+        };
+        replay_log(&config, backend, engine_config, file, speed);
+    }
 
-    env_logger::init();
     info!("Starting up!");
 
     // Start server
     info!(
         "Starting server on {}:{}, threads: {}, access token: '{}', sound library: '{}'",
-        opt.host,
-        opt.port,
-        opt.threads,
-        opt.token,
-        opt.sound_library.to_string_lossy()
+        config.host(),
+        config.port(),
+        config.threads(),
+        config.token(),
+        config.sound_library().to_string_lossy()
     );
 
-    let library_path = opt.sound_library.clone();
+    let engine_config = opt.engine_config();
+    let heartbeat = engine_config.heartbeat.clone();
+    let version = engine_config.version.clone();
+    let samplesdb = open_samplesdb(&config)?;
+    let (main_sender, handle) = start_engine(samplesdb.clone(), backend, engine_config);
+    start_snapshots(&config);
 
-    // Set up channel for REST->AudioController communication
-    let (sender, receiver) = channel();
-    let (response_sender, response_receiver) = channel();
+    // Satellites play what their primary plays
+    if opt.satellite.is_none() {
+        load_last_theme(&samplesdb, &main_sender);
+    }
 
-    let samplesdb = SamplesDB::open(Path::new("samples.db"), &library_path)?;
-    let handle = thread::spawn(|| {
-        start_audio_controller::<OpenALBackend>(receiver, response_sender, samplesdb)
-    });
-    let main_sender = sender.clone();
-
-    match start_web_service(
-        opt.host,
-        opt.port,
-        main_sender.clone(),
-        response_receiver,
-        opt.token,
-    )
-    .await
-    {
-        Ok(_) => {}
-        Err(e) => panic!("Failed to bind webserver: {}!", e),
+    // Rooms are only served by the API, satellites play what their primary plays
+    let mut rooms = Vec::new();
+    let mut room_engines = Vec::new();
+    if opt.satellite.is_none() {
+        for room in &opt.rooms {
+            let engine_config = EngineConfig {
+                command_log: None,
+                ..opt.engine_config()
+            };
+            let room_heartbeat = engine_config.heartbeat.clone();
+            let room_version = engine_config.version.clone();
+            let (sender, room_handle) = start_engine(samplesdb.clone(), backend, engine_config);
+
+            if let Some(device) = room.device {
+                let (request, response) = Request::new(Command::SetDriver { id: device });
+                if sender.send(request).is_ok() {
+                    if let Ok(Response::Error(e)) = response.recv() {
+                        error!(
+                            "Failed to switch room '{}' to output {}: {}",
+                            room.id, device, e
+                        );
+                    }
+                }
+            }
+
+            info!("Started room '{}'", room.id);
+            rooms.push(Room {
+                id: room.id.clone(),
+                sender: sender.clone(),
+                heartbeat: room_heartbeat,
+                version: room_version,
+            });
+            room_engines.push((sender, room_handle));
+        }
     }
 
-    // Tell AudioController to shut down:
-    main_sender
-        .send(Command::Quit)
-        .expect("Failed to send AudioControllerMessage::Quit to AudioController!");
+    match opt.satellite {
+        Some(ref primary) => {
+            sinfonia_server::satellite::run(primary, &config.token(), main_sender.clone()).await
+        }
+        None => {
+            match start_web_service(
+                main_sender.clone(),
+                heartbeat,
+                version,
+                samplesdb,
+                rooms,
+                config,
+                source,
+                opt.scoped_tokens,
+                opt.max_payload * 1024 * 1024,
+            )
+            .await
+            {
+                Ok(_) => {}
+                Err(e) => panic!("Failed to bind webserver: {}!", e),
+            }
+        }
+    }
 
-    // Wait until AudioController shuts down
-    handle
-        .join()
-        .expect("Waiting for the AudioController to finish has failed!");
+    for (sender, room_handle) in room_engines {
+        stop_engine(&sender, room_handle);
+    }
+    stop_engine(&main_sender, handle);
 
     Ok(())
 }
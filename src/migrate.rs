@@ -0,0 +1,265 @@
+use serde_json::{json, Map, Value};
+
+/// Version of the theme format written by this server. Themes of older versions are
+/// converted to it when they are read.
+pub const THEME_VERSION: u32 = 2;
+
+/// Themes don't have to say which version they are in, the sounds of those that don't
+/// are told apart by their fields: the original format kept its parameters in `funcs`
+fn sound_version(sound: &Map<String, Value>) -> u32 {
+    if sound.contains_key("funcs") || sound.contains_key("file_path") {
+        1
+    } else {
+        THEME_VERSION
+    }
+}
+
+/// Version the theme says it is in, if it says so
+fn declared_version(theme: &Map<String, Value>) -> Result<Option<u32>, String> {
+    let version = match theme.get("version") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(version) => version,
+    };
+
+    match version.as_u64() {
+        Some(version) if version >= 1 && version <= u64::from(THEME_VERSION) => {
+            Ok(Some(version as u32))
+        }
+        Some(version) if version > u64::from(THEME_VERSION) => Err(format!(
+            "theme format version {} is newer than the supported version {}",
+            version, THEME_VERSION
+        )),
+        _ => Err(format!("invalid theme format version {}", version)),
+    }
+}
+
+/// Brings a theme of an older version of the format up to the current one. The sounds
+/// are converted from the version the theme declares, or the one they look like if it
+/// doesn't declare any.
+pub fn migrate_theme(theme: &mut Value) -> Result<(), String> {
+    let theme = match theme {
+        Value::Object(theme) => theme,
+        // Not a theme at all, left for reading it to fail
+        _ => return Ok(()),
+    };
+    let declared = declared_version(theme)?;
+
+    if let Some(Value::Array(sounds)) = theme.get_mut("sounds") {
+        for sound in sounds.iter_mut() {
+            if let Value::Object(sound) = sound {
+                let version = declared.unwrap_or_else(|| sound_version(sound));
+                migrate_sound(sound, version)?;
+            }
+        }
+    }
+
+    theme.insert("version".to_string(), Value::from(THEME_VERSION));
+
+    Ok(())
+}
+
+/// Brings a sound of the given version of the format up to the current one, one version
+/// at a time
+pub fn migrate_sound(sound: &mut Map<String, Value>, mut version: u32) -> Result<(), String> {
+    while version < THEME_VERSION {
+        match version {
+            1 => migrate_funcs(sound)?,
+            _ => unreachable!(),
+        }
+        version += 1;
+    }
+
+    Ok(())
+}
+
+fn parameter(sound: &str, func: &str, params: &Value, key: &str) -> Result<Value, String> {
+    match params.get(key) {
+        Some(value) => Ok(value.clone()),
+        None => Err(format!("{} of sound '{}' has no '{}'", func, sound, key)),
+    }
+}
+
+/// Version 1 sounds look like `{ "file_path": "creak.wav", "needs_trigger": false,
+/// "is_disabled": false, "funcs": { "start": { "Volume": { "value": [0.1, 0.4] } },
+/// "finish": { "Loop": { "times": [0, 0] } }, "update": {} } }`. Custom reverbs and
+/// filters in Hz have no equivalent and are dropped.
+fn migrate_funcs(sound: &mut Map<String, Value>) -> Result<(), String> {
+    let name = sound
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let funcs = sound.remove("funcs").unwrap_or_else(|| json!({}));
+
+    sound.remove("id");
+    if let Some(file) = sound.remove("file_path") {
+        sound.insert("file".to_string(), file);
+    }
+
+    // Triggers were sent by sound name back then
+    let needs_trigger = sound.remove("needs_trigger").and_then(|v| v.as_bool());
+    if needs_trigger == Some(true) {
+        sound.insert("trigger".to_string(), Value::String(name.clone()));
+    }
+
+    let disabled = sound.remove("is_disabled").and_then(|v| v.as_bool());
+    sound.insert("enabled".to_string(), Value::Bool(disabled != Some(true)));
+
+    if let Some(start) = funcs.get("start").and_then(Value::as_object) {
+        for (func, params) in start {
+            let (key, value) = match func.as_str() {
+                "Volume" => ("volume", parameter(&name, func, params, "value")?),
+                "Frequency" => {
+                    sound.insert("pitch_enabled".to_string(), Value::Bool(true));
+                    ("pitch", parameter(&name, func, params, "value")?)
+                }
+                "Delay" => ("loop_delay", parameter(&name, func, params, "value")?),
+                "Repeat" => ("repeat_count", parameter(&name, func, params, "value")?),
+                _ => {
+                    warn!(
+                        "Dropping {} of sound '{}', it can't be converted",
+                        func, name
+                    );
+                    continue;
+                }
+            };
+            sound.insert(key.to_string(), value);
+        }
+    }
+
+    if let Some(params) = funcs.get("finish").and_then(|f| f.get("Loop")) {
+        let times = parameter(&name, "Loop", params, "times")?;
+        // No number of loops meant looping until the theme is stopped
+        if times == json!([0, 0]) {
+            sound.insert("loop_forever".to_string(), Value::Bool(true));
+        } else {
+            sound.insert("loop_count".to_string(), times);
+        }
+    }
+
+    if let Some(params) = funcs.get("update").and_then(|f| f.get("Fader")) {
+        let fade_in = parameter(&name, "Fader", params, "fade_in_length")?;
+        sound.insert("fade_in_enabled".to_string(), Value::Bool(true));
+        sound.insert("fade_in".to_string(), fade_in);
+
+        if params.get("fade_out_length").is_some() {
+            warn!(
+                "Dropping the fade out of sound '{}', it can't be converted",
+                name
+            );
+        }
+    }
+
+    // The volume used to be optional
+    sound
+        .entry("volume".to_string())
+        .or_insert_with(|| json!([1.0, 1.0]));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrated(mut theme: Value) -> Value {
+        migrate_theme(&mut theme).unwrap();
+        theme
+    }
+
+    #[test]
+    fn converts_version_1_sounds() {
+        let theme = migrated(json!({
+            "version": 1,
+            "name": "tavern",
+            "sounds": [{
+                "id": 3,
+                "name": "creak",
+                "file_path": "creak.wav",
+                "needs_trigger": true,
+                "is_disabled": false,
+                "funcs": {
+                    "start": { "Volume": { "value": [0.1, 0.4] } },
+                    "finish": { "Loop": { "times": [0, 0] } },
+                    "update": { "Fader": { "fade_in_length": [0.5, 0.5] } }
+                }
+            }]
+        }));
+
+        assert_eq!(theme["version"], json!(THEME_VERSION));
+        assert_eq!(
+            theme["sounds"][0],
+            json!({
+                "name": "creak",
+                "file": "creak.wav",
+                "trigger": "creak",
+                "enabled": true,
+                "volume": [0.1, 0.4],
+                "loop_forever": true,
+                "fade_in_enabled": true,
+                "fade_in": [0.5, 0.5]
+            })
+        );
+    }
+
+    #[test]
+    fn uses_the_declared_version() {
+        // A current sound that happens to have a field the original format used
+        let sound =
+            json!({ "name": "rain", "file": "rain.ogg", "file_path": "x", "volume": [0.5, 0.5] });
+        let theme = migrated(json!({ "version": 2, "name": "storm", "sounds": [sound.clone()] }));
+
+        assert_eq!(theme["sounds"][0], sound);
+    }
+
+    #[test]
+    fn guesses_the_version_of_sounds_without_one() {
+        let theme = migrated(json!({
+            "name": "storm",
+            "sounds": [
+                { "name": "rain", "file_path": "rain.ogg", "funcs": {} },
+                { "name": "wind", "file": "wind.ogg", "volume": [0.5, 0.5] }
+            ]
+        }));
+
+        assert_eq!(theme["version"], json!(THEME_VERSION));
+        assert_eq!(theme["sounds"][0]["file"], json!("rain.ogg"));
+        assert_eq!(theme["sounds"][0]["volume"], json!([1.0, 1.0]));
+        assert_eq!(theme["sounds"][1]["file"], json!("wind.ogg"));
+    }
+
+    #[test]
+    fn loop_counts_are_kept() {
+        let mut sound = json!({
+            "name": "bell",
+            "file_path": "bell.wav",
+            "funcs": { "finish": { "Loop": { "times": [2, 3] } } }
+        });
+        migrate_sound(sound.as_object_mut().unwrap(), 1).unwrap();
+
+        assert_eq!(sound["loop_count"], json!([2, 3]));
+        assert!(sound.get("loop_forever").is_none());
+        assert_eq!(sound["enabled"], json!(true));
+    }
+
+    #[test]
+    fn missing_parameters_are_reported() {
+        let mut sound = json!({
+            "name": "bell",
+            "funcs": { "start": { "Volume": {} } }
+        });
+
+        assert!(migrate_sound(sound.as_object_mut().unwrap(), 1).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut newer = json!({ "version": THEME_VERSION + 1, "name": "x", "sounds": [] });
+        let mut zero = json!({ "version": 0, "name": "x", "sounds": [] });
+        let mut text = json!({ "version": "2", "name": "x", "sounds": [] });
+
+        assert!(migrate_theme(&mut newer).is_err());
+        assert!(migrate_theme(&mut zero).is_err());
+        assert!(migrate_theme(&mut text).is_err());
+    }
+}
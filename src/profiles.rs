@@ -0,0 +1,114 @@
+use serde_json::{json, Map, Value};
+
+/// Defaults of a built-in profile, sounds referencing it only need to set what differs
+fn profile_defaults(name: &str) -> Option<Value> {
+    Some(match name {
+        // Plays once when triggered
+        "one_shot" => json!({
+            "volume": [1.0, 1.0],
+            "enabled": true,
+            "repeat_count": [0, 0],
+            "loop_count": [0, 0],
+            "loop_forever": false,
+            "fade_in_enabled": false,
+        }),
+        // Quiet background that never stops, fading in at the start of every loop
+        "ambient_bed" => json!({
+            "volume": [0.4, 0.5],
+            "enabled": true,
+            "loop_forever": true,
+            "loop_delay": [0, 0],
+            "fade_in_enabled": true,
+            "fade_in": [0.05, 0.05],
+            "on_pause": "keep_running",
+        }),
+        // Tracks with a short pause between them
+        "music" => json!({
+            "volume": [0.7, 0.7],
+            "enabled": true,
+            "loop_forever": true,
+            "loop_delay": [2000, 5000],
+            "fade_in_enabled": true,
+            "fade_in": [0.02, 0.02],
+            "music": true,
+            "group": "music",
+        }),
+        _ => return None,
+    })
+}
+
+/// Fills in the values a sound doesn't set itself from the profile it references. The
+/// profiles of the theme come first, so a theme can also replace a built-in one.
+pub fn apply_profile(
+    sound: &mut Map<String, Value>,
+    profiles: &Map<String, Value>,
+) -> Result<(), String> {
+    let name = match sound.get("profile") {
+        Some(Value::String(name)) => name.clone(),
+        Some(Value::Null) | None => return Ok(()),
+        Some(_) => return Err("profile has to be a string".to_string()),
+    };
+
+    let defaults = match profiles.get(&name) {
+        Some(defaults) => Some(defaults.clone()),
+        None => profile_defaults(&name),
+    };
+
+    match defaults {
+        Some(Value::Object(defaults)) => {
+            for (key, value) in defaults {
+                sound.entry(key).or_insert(value);
+            }
+            Ok(())
+        }
+        Some(_) => Err(format!("profile '{}' has to be an object", name)),
+        None => Err(format!("unknown profile '{}'", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sound(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(fields) => fields,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn sounds_keep_their_own_values() {
+        let mut rain = sound(json!({ "profile": "ambient_bed", "volume": [0.8, 0.8] }));
+        apply_profile(&mut rain, &Map::new()).unwrap();
+
+        assert_eq!(rain["volume"], json!([0.8, 0.8]));
+        assert_eq!(rain["loop_forever"], json!(true));
+    }
+
+    #[test]
+    fn theme_profiles_come_first() {
+        let profiles = sound(json!({
+            "music": { "volume": [0.3, 0.3] },
+            "creature": { "volume": [0.6, 0.9], "loop_delay": [5000, 20000] }
+        }));
+
+        let mut wolves = sound(json!({ "profile": "creature" }));
+        apply_profile(&mut wolves, &profiles).unwrap();
+        assert_eq!(wolves["loop_delay"], json!([5000, 20000]));
+
+        let mut lute = sound(json!({ "profile": "music" }));
+        apply_profile(&mut lute, &profiles).unwrap();
+        assert_eq!(lute["volume"], json!([0.3, 0.3]));
+        assert!(lute.get("group").is_none());
+    }
+
+    #[test]
+    fn unknown_profiles_are_errors() {
+        let mut wolves = sound(json!({ "profile": "creature" }));
+        assert!(apply_profile(&mut wolves, &Map::new()).is_err());
+
+        let profiles = sound(json!({ "creature": [0.6, 0.9] }));
+        assert!(apply_profile(&mut wolves, &profiles).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Error, Serialize, Serializer};
+
+use crate::audio_engine::loader::base::SampleMetadata;
+
+/// Everything stored in the samples database, written by GET /admin/backup and the backup
+/// subcommand and read back by the restore endpoint and subcommand
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Backup {
+  pub version: u32,
+  pub samples: Vec<BackupSample>,
+  pub themes: Vec<BackupTheme>,
+  /// Server settings as (key, JSON value)
+  pub settings: Vec<(String, String)>,
+  /// Client settings as (client, key, value)
+  pub client_settings: Vec<(String, String, String)>,
+  /// Gain trims as (theme, sound, gain)
+  pub sound_trims: Vec<(String, String, f32)>,
+}
+
+impl Backup {
+  pub const VERSION: u32 = 1;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupSample {
+  /// Path relative to the sound library
+  pub path: String,
+  pub tags: Vec<String>,
+  pub metadata: SampleMetadata,
+  /// Normalization gain, if the sample has been normalized
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub gain: Option<f32>,
+  /// Base64 encoded contents of the file, only if the backup includes the samples
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub data: Option<SampleData>,
+}
+
+/// Contents of a sample file. Backups refer to the files and only read and encode them one
+/// at a time while they are written, so the samples of the whole library never are in
+/// memory at once.
+#[derive(Debug)]
+pub enum SampleData {
+  /// Base64 encoded, as read from a backup
+  Encoded(String),
+  File(PathBuf),
+}
+
+impl SampleData {
+  pub fn contents(&self) -> Result<Vec<u8>, String> {
+    match self {
+      SampleData::Encoded(data) => base64::decode(data).map_err(|e| e.to_string()),
+      SampleData::File(path) => fs::read(path).map_err(|e| e.to_string()),
+    }
+  }
+}
+
+impl Serialize for SampleData {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self {
+      SampleData::Encoded(data) => serializer.serialize_str(data),
+      SampleData::File(path) => {
+        let contents = fs::read(path).map_err(|e| {
+          S::Error::custom(format!(
+            "Failed to read '{}': {}",
+            path.to_string_lossy(),
+            e
+          ))
+        })?;
+        serializer.serialize_str(&base64::encode(&contents))
+      }
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for SampleData {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer).map(SampleData::Encoded)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupTheme {
+  pub name: String,
+  pub theme: serde_json::Value,
+}
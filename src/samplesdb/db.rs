@@ -1,16 +1,48 @@
-use rusqlite::{Connection, NO_PARAMS};
+use futures::executor::block_on;
 use std::collections::hash_map::Values;
 use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
 use walkdir::WalkDir;
 
+use crate::audio_engine::loader;
+use crate::audio_engine::loader::base::{SampleFormat, SampleMetadata};
+use crate::audio_engine::loader::check::{compute_peaks, Peaks};
+use crate::audio_engine::normalize::replay_gain_factor;
+use crate::samplesdb::backup::{Backup, BackupSample, BackupTheme, SampleData};
 use crate::samplesdb::error::SamplesDBError;
+use crate::samplesdb::remote;
+use crate::samplesdb::repository::SampleRepository;
+use crate::settings::Settings;
 
 #[derive(Debug)]
-pub struct Sample<'a> {
+pub struct Sample {
   pub id: i64,
   pub path: String,
-  pub tags: Vec<&'a Tag>,
+  /// Names of the sample's tags
+  pub tags: Vec<String>,
+  pub metadata: SampleMetadata,
+  /// Length in seconds as read during the scan, estimated for mp3 files
+  pub duration: Option<f32>,
+  pub format: Option<SampleFormat>,
+}
+
+impl Sample {
+  /// Case-insensitive substring match against the path and the embedded metadata.
+  /// `query` is expected to be lowercase already.
+  fn matches(&self, query: &str) -> bool {
+    let metadata = &self.metadata;
+
+    std::iter::once(&self.path)
+      .chain(metadata.title.iter())
+      .chain(metadata.artist.iter())
+      .chain(metadata.album.iter())
+      .chain(metadata.genre.iter())
+      .chain(metadata.comment.iter())
+      .any(|field| field.to_lowercase().contains(query))
+  }
 }
 
 #[derive(Debug)]
@@ -19,147 +51,794 @@ pub struct Tag {
   pub name: String,
 }
 
-pub struct SamplesDB<'a> {
-  samples: HashMap<i64, Sample<'a>>,
+/// Health of the library as found by the last scan
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LibraryReport {
+  pub total: usize,
+  /// Samples that weren't in the database before the scan
+  pub new: usize,
+  /// Samples in the database whose file is gone
+  pub missing: Vec<String>,
+  /// Files none of the loaders can read
+  pub broken: Vec<String>,
+  pub untagged: usize,
+  /// Combined length in seconds, estimated for mp3 files
+  pub duration: f32,
+  /// Combined file size in bytes
+  pub disk_usage: u64,
+}
+
+/// One database shared by the engines of all rooms
+pub type SharedSamplesDB = Arc<Mutex<SamplesDB>>;
+
+/// A supported file found in the library
+struct ScannedFile {
+  /// Relative to the library
+  path: String,
+  /// None if the embedded tags can't be read
+  metadata: Option<SampleMetadata>,
+  /// Length and format, or why the file can't be read
+  details: Result<(f32, SampleFormat), String>,
+  /// The length and format were read from the file instead of taken from the database
+  details_read: bool,
+  /// Track gain in dB of the ReplayGain tag, only read along with the details
+  replay_gain: Option<f32>,
+  size: u64,
+  /// Seconds since the epoch
+  modified: i64,
+  /// Only computed if the stored peaks are missing or older than the file
+  peaks: Option<Peaks>,
+}
+
+/// Everything a walk of the library found. Reading the headers of all files takes a while
+/// for big libraries, so it is done without the database, which only has to be locked to
+/// store the result.
+pub struct LibraryScan {
+  files: Vec<ScannedFile>,
+}
+
+/// What earlier scans stored about the files of the library, by path
+pub struct StoredFiles {
+  /// Modification times of the files the stored peaks were computed from
+  waveforms: HashMap<String, i64>,
+  /// Modification time of the file along with its length and format
+  details: HashMap<String, (i64, f32, SampleFormat)>,
+}
+
+impl LibraryScan {
+  pub fn new(base_path: &Path, stored: &StoredFiles) -> Result<Self, SamplesDBError> {
+    let mut files = Vec::new();
+
+    for entry in WalkDir::new(base_path) {
+      let full_path = entry?.path().to_path_buf();
+
+      let supported = full_path.extension().map_or(false, |extension| {
+        SUPPORTED_AUDIO_FILES.iter().any(|&ext| ext == extension)
+      });
+      if !supported {
+        continue;
+      }
+
+      let path = full_path
+        .strip_prefix(base_path)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+
+      let file_metadata = fs::metadata(&full_path).ok();
+      let modified = file_metadata
+        .as_ref()
+        .and_then(|m| m.modified().ok())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs() as i64);
+
+      // Only the header (or the first frame of mp3 files) is read, not the whole file, and
+      // only if the file changed since the last scan
+      let (details, details_read) = match stored.details.get(&path) {
+        Some(&(stored_modified, duration, format)) if stored_modified == modified => {
+          (Ok((duration, format)), false)
+        }
+        _ => {
+          let details = loader::get_loader_for_file(&full_path)
+            .and_then(|mut l| Ok((l.duration(&full_path)?, l.format(&full_path)?)))
+            .map_err(|e| e.to_string());
+          (details, true)
+        }
+      };
+
+      let replay_gain = if details_read {
+        loader::get_loader_for_file(&full_path)
+          .and_then(|mut l| l.replay_gain(&full_path))
+          .unwrap_or_else(|e| {
+            warn!("Failed to read ReplayGain of '{}': {}", path, e);
+            None
+          })
+      } else {
+        None
+      };
+
+      // Decoding the whole file takes a while, so it is only done once for every change
+      let peaks = if details.is_ok() && stored.waveforms.get(&path) != Some(&modified) {
+        debug!("Computing waveform of '{}'", path);
+        compute_peaks(&full_path)
+          .map_err(|e| warn!("Failed to compute waveform of '{}': {}", path, e))
+          .ok()
+      } else {
+        None
+      };
+
+      files.push(ScannedFile {
+        path,
+        metadata: read_metadata(&full_path),
+        details,
+        details_read,
+        replay_gain,
+        size: file_metadata.map_or(0, |m| m.len()),
+        modified,
+        peaks,
+      });
+    }
+
+    Ok(Self { files })
+  }
+}
+
+/// Reads the tags embedded in the file (title, artist, ...), None if there are none we can read
+fn read_metadata(full_path: &PathBuf) -> Option<SampleMetadata> {
+  match loader::get_loader_for_file(full_path).and_then(|mut l| l.metadata(full_path)) {
+    Ok(metadata) => Some(metadata),
+    Err(e) => {
+      warn!(
+        "Failed to read metadata of '{}': {}",
+        full_path.to_string_lossy(),
+        e
+      );
+      None
+    }
+  }
+}
+
+/// Whether a relative path stays inside the library, i.e. is neither absolute nor goes up
+/// with `..`
+pub fn is_library_path(path: &str) -> bool {
+  Path::new(path).components().all(|c| match c {
+    Component::Normal(_) | Component::CurDir => true,
+    _ => false,
+  })
+}
+
+/// Walks the library of a shared database again, picking up new files. The files are read
+/// while the engines can still use the database.
+pub fn rescan(samplesdb: &SharedSamplesDB) -> Result<LibraryReport, SamplesDBError> {
+  let (base_path, stored) = {
+    let samplesdb = samplesdb.lock().unwrap();
+    (samplesdb.base_path.clone(), samplesdb.stored_files()?)
+  };
+  info!("Rescanning library '{}'", base_path.to_string_lossy());
+
+  let scan = LibraryScan::new(&base_path, &stored)?;
+
+  let mut samplesdb = samplesdb.lock().unwrap();
+  samplesdb.samples.clear();
+  samplesdb.load_samples(scan)?;
+  samplesdb.log_report();
+
+  Ok(samplesdb.report.clone())
+}
+
+pub struct SamplesDB {
+  samples: HashMap<i64, Sample>,
   tags: HashMap<i64, Tag>,
+  report: LibraryReport,
   pub base_path: PathBuf,
+  /// Remote samples that failed to download, with why
+  failed_downloads: HashMap<String, String>,
 
-  connection: Connection,
+  repository: Box<dyn SampleRepository>,
 }
 
-const SUPPORTED_AUDIO_FILES: [&str; 6] = ["aiff", "flac", "midi", "ogg", "wav", "mp3"];
+const SUPPORTED_AUDIO_FILES: [&str; 7] = ["aiff", "flac", "midi", "ogg", "opus", "wav", "mp3"];
 
-impl SamplesDB<'_> {
-  pub fn open(db_path: &Path, base_path: &Path) -> Result<Self, SamplesDBError> {
+impl SamplesDB {
+  pub fn open(
+    repository: Box<dyn SampleRepository>,
+    base_path: &Path,
+  ) -> Result<Self, SamplesDBError> {
     let mut db = Self {
       samples: HashMap::new(),
       tags: HashMap::new(),
+      report: LibraryReport::default(),
       base_path: base_path.to_owned(),
-      connection: Connection::open(db_path)?,
+      failed_downloads: HashMap::new(),
+      repository,
     };
 
-    db.setup_tables()?;
     db.load_tags()?;
-    db.load_samples()?;
+    let stored = db.stored_files()?;
+    db.load_samples(LibraryScan::new(base_path, &stored)?)?;
+    db.log_report();
 
     Ok(db)
   }
 
-  fn setup_tables(&self) -> Result<(), SamplesDBError> {
-    self.connection.execute(
-      "CREATE TABLE IF NOT EXISTS sample (
-                id   INTEGER PRIMARY KEY,
-                path TEXT NOT NULL UNIQUE 
-            )",
-      NO_PARAMS,
-    )?;
-
-    self.connection.execute(
-      "CREATE TABLE IF NOT EXISTS tag (
-                id   INT PRIMARY KEY,
-                name TEXT NOT NULL UNIQUE 
-            )",
-      NO_PARAMS,
-    )?;
-
-    self.connection.execute(
-      "CREATE TABLE IF NOT EXISTS sample_tag (
-                sample_id INT NOT NULL,
-                tag_id    INT NOT NULL,
-                UNIQUE(sample_id, tag_id) ON CONFLICT REPLACE
-            )",
-      NO_PARAMS,
-    )?;
-
-    Ok(())
+  fn log_report(&self) {
+    let report = &self.report;
+    info!(
+      "Library: {} samples ({} new, {} missing, {} broken, {} untagged), {:.0} minutes, {} MB",
+      report.total,
+      report.new,
+      report.missing.len(),
+      report.broken.len(),
+      report.untagged,
+      report.duration / 60.0,
+      report.disk_usage / (1024 * 1024)
+    );
   }
 
   fn load_tags(&mut self) -> Result<(), SamplesDBError> {
-    let mut stmt = self.connection.prepare("SELECT id, name FROM tag;")?;
-
-    let tags: Result<Vec<Tag>, _> = stmt
-      .query_map(NO_PARAMS, |row| {
-        Ok(Tag {
-          id: row.get(0)?,
-          name: row.get(1)?,
-        })
-      })?
-      .collect();
-
-    for tag in tags? {
-      self.tags.insert(tag.id, tag);
+    for (id, name) in block_on(self.repository.tags())? {
+      self.tags.insert(id, Tag { id, name });
     }
 
     Ok(())
   }
 
-  fn load_samples(&mut self) -> Result<(), SamplesDBError> {
-    for entry in WalkDir::new(&self.base_path) {
-      let path_str = entry?.path().to_path_buf();
+  /// Indexes the samples a scan found. Samples whose file is gone drop out of the index and
+  /// are listed as missing in the report.
+  fn load_samples(&mut self, scan: LibraryScan) -> Result<(), SamplesDBError> {
+    let mut known: HashMap<String, i64> = block_on(self.repository.samples())?
+      .into_iter()
+      .map(|(id, path)| (path, id))
+      .collect();
+    let mut sample_tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (sample_id, tag_id) in block_on(self.repository.sample_tags())? {
+      if let Some(tag) = self.tags.get(&tag_id) {
+        sample_tags
+          .entry(sample_id)
+          .or_insert_with(Vec::new)
+          .push(tag.name.clone());
+      }
+    }
+    let mut report = LibraryReport::default();
+
+    for file in scan.files {
+      if known.remove(&file.path).is_none() {
+        report.new += 1;
+      }
 
-      if let Some(extension) = path_str.extension() {
-        if SUPPORTED_AUDIO_FILES.iter().any(|&ext| ext == extension) {
-          self.add_sample(
-            (&path_str)
-              .strip_prefix(&self.base_path)
-              .unwrap()
-              .to_str()
-              .unwrap(),
-          )?;
+      let id = self.add_sample(&file.path)?;
+      self.store_metadata(id, file.metadata)?;
+      // Samples that were normalized or reset by hand keep their gain
+      if let Some(db) = file.replay_gain {
+        if block_on(self.repository.sample_gain(id))?.is_none() {
+          debug!("Using ReplayGain of {} dB for '{}'", db, file.path);
+          block_on(self.repository.set_sample_gain(id, replay_gain_factor(db)))?;
         }
       }
+      if let Some(ref peaks) = file.peaks {
+        if let Ok(json) = serde_json::to_string(peaks) {
+          block_on(self.repository.set_waveform(id, file.modified, &json))?;
+        }
+      }
+
+      report.total += 1;
+      match sample_tags.remove(&id) {
+        Some(tags) => self.samples.get_mut(&id).unwrap().tags = tags,
+        None => report.untagged += 1,
+      }
+      report.disk_usage += file.size;
+
+      match file.details {
+        Ok((duration, format)) => {
+          report.duration += duration;
+          if file.details_read {
+            block_on(
+              self
+                .repository
+                .set_sample_details(id, file.modified, duration, &format),
+            )?;
+          }
+
+          let sample = self.samples.get_mut(&id).unwrap();
+          sample.duration = Some(duration);
+          sample.format = Some(format);
+        }
+        Err(e) => {
+          warn!("Broken sample '{}': {}", file.path, e);
+          block_on(self.repository.remove_sample_details(id))?;
+          report.broken.push(file.path);
+        }
+      }
+    }
+
+    // Whatever wasn't found on disk is gone, what was read from its file isn't needed anymore
+    for id in known.values() {
+      block_on(self.repository.remove_waveform(*id))?;
+      block_on(self.repository.remove_sample_details(*id))?;
     }
+    report.missing = known.into_iter().map(|(path, _)| path).collect();
+    report.missing.sort();
+    self.report = report;
 
     Ok(())
   }
 
-  fn add_sample(&mut self, path: &str) -> Result<(), SamplesDBError> {
-    let result = self.connection.query_row(
-      "SELECT id FROM sample WHERE path = ?1;",
-      params![path],
-      |row| row.get(0),
-    );
-
-    let id: i64 = result.or_else(|_| -> Result<i64, SamplesDBError> {
-      self
-        .connection
-        .execute("INSERT INTO sample (path) VALUES (?1);", params![path])?;
-      Ok(self.connection.last_insert_rowid())
-    })?;
+  fn add_sample(&mut self, path: &str) -> Result<i64, SamplesDBError> {
+    let id = block_on(self.repository.add_sample(path))?;
 
     let sample = Sample {
       id,
       path: path.to_string(),
       tags: Vec::new(),
+      metadata: SampleMetadata::default(),
+      duration: None,
+      format: None,
     };
 
     self.samples.insert(sample.id, sample);
 
-    Ok(())
+    Ok(id)
   }
 
-  fn create_tag<P: Into<String> + Copy + rusqlite::ToSql>(
+  /// Stores the tags embedded in the file (title, artist, ...) alongside the sample. Files we
+  /// can't read metadata from keep whatever was stored during a previous scan.
+  fn store_metadata(
     &mut self,
-    name: P,
-  ) -> Result<i64, SamplesDBError> {
-    self
-      .connection
-      .execute("INSERT INTO tag (name) VALUES (?1);", params![&name])?;
-
-    let tag = Tag {
-      id: self.connection.last_insert_rowid(),
-      name: name.into().clone(),
+    sample_id: i64,
+    metadata: Option<SampleMetadata>,
+  ) -> Result<(), SamplesDBError> {
+    let metadata = match metadata {
+      Some(metadata) => metadata,
+      None => block_on(self.repository.metadata(sample_id))?.unwrap_or_default(),
     };
 
-    let opt = self.tags.insert(tag.id, tag);
+    block_on(self.repository.set_metadata(sample_id, &metadata))?;
+
+    if let Some(sample) = self.samples.get_mut(&sample_id) {
+      sample.metadata = metadata;
+    }
+
+    Ok(())
+  }
+
+  fn create_tag(&mut self, name: &str) -> Result<i64, SamplesDBError> {
+    let id = block_on(self.repository.add_tag(name))?;
+
+    self.tags.insert(
+      id,
+      Tag {
+        id,
+        name: name.to_string(),
+      },
+    );
 
-    Ok(opt.as_ref().unwrap().id)
+    Ok(id)
   }
 
   pub fn samples(&self) -> Values<i64, Sample> {
     self.samples.values()
   }
 
+  pub fn sample(&self, sample_id: i64) -> Option<&Sample> {
+    self.samples.get(&sample_id)
+  }
+
+  /// Names of all tags, sorted
+  pub fn tags(&self) -> Vec<&str> {
+    let mut tags: Vec<&str> = self.tags.values().map(|tag| tag.name.as_str()).collect();
+    tags.sort();
+    tags
+  }
+
+  fn tag_id(&self, name: &str) -> Option<i64> {
+    self
+      .tags
+      .values()
+      .find(|tag| tag.name == name)
+      .map(|tag| tag.id)
+  }
+
+  /// Tags a sample, creating the tag if it doesn't exist yet
+  pub fn add_tag_to_sample(&mut self, sample_id: i64, tag: &str) -> Result<(), SamplesDBError> {
+    if !self.samples.contains_key(&sample_id) {
+      return Err(SamplesDBError::UnknownSample(sample_id));
+    }
+
+    let tag_id = match self.tag_id(tag) {
+      Some(id) => id,
+      None => self.create_tag(tag)?,
+    };
+    block_on(self.repository.add_sample_tag(sample_id, tag_id))?;
+
+    let sample = self.samples.get_mut(&sample_id).unwrap();
+    if !sample.tags.iter().any(|t| t == tag) {
+      sample.tags.push(tag.to_string());
+    }
+
+    Ok(())
+  }
+
+  pub fn remove_tag(&mut self, sample_id: i64, tag: &str) -> Result<(), SamplesDBError> {
+    let tag_id = self
+      .tag_id(tag)
+      .ok_or_else(|| SamplesDBError::UnknownTag(tag.to_string()))?;
+    let sample = self
+      .samples
+      .get_mut(&sample_id)
+      .ok_or(SamplesDBError::UnknownSample(sample_id))?;
+
+    block_on(self.repository.remove_sample_tag(sample_id, tag_id))?;
+    sample.tags.retain(|t| t != tag);
+
+    Ok(())
+  }
+
+  pub fn samples_by_tag(&self, tag: &str) -> Vec<&Sample> {
+    self
+      .samples
+      .values()
+      .filter(|sample| sample.tags.iter().any(|t| t == tag))
+      .collect()
+  }
+
+  pub fn report(&self) -> &LibraryReport {
+    &self.report
+  }
+
+  /// Returns all samples whose path or embedded metadata contains `query` (case-insensitive).
+  pub fn search(&self, query: &str) -> Vec<&Sample> {
+    let query = query.to_lowercase();
+    self
+      .samples
+      .values()
+      .filter(|sample| sample.matches(&query))
+      .collect()
+  }
+
+  /// Remote samples among `files` that aren't in the cache yet, along with where they go.
+  /// Those that failed to download are left out until `forget_failed_downloads`.
+  pub fn pending_downloads<'a, I>(&self, files: I) -> Vec<(String, PathBuf)>
+  where
+    I: IntoIterator<Item = &'a String>,
+  {
+    let mut downloads: Vec<(String, PathBuf)> = Vec::new();
+
+    for file in files {
+      if !remote::is_remote(file)
+        || self.failed_downloads.contains_key(file)
+        || downloads.iter().any(|(url, _)| url == file)
+      {
+        continue;
+      }
+
+      // Unsupported URLs fail once the sample is resolved
+      if let Ok(path) = remote::cache_path_for(file, &SUPPORTED_AUDIO_FILES) {
+        let full_path = self.base_path.join(&path);
+        if !full_path.is_file() {
+          downloads.push((file.clone(), full_path));
+        }
+      }
+    }
+
+    downloads
+  }
+
+  pub fn record_failed_download(&mut self, url: String, message: String) {
+    self.failed_downloads.insert(url, message);
+  }
+
+  /// Lets the next theme try the failed downloads again
+  pub fn forget_failed_downloads(&mut self) {
+    self.failed_downloads.clear();
+  }
+
+  /// Looks up the sample a theme refers to. Remote samples (`http(s)://` URLs) have to be
+  /// downloaded beforehand, see `pending_downloads`, and are registered under their synthetic
+  /// cache path on first use.
+  pub fn resolve_sample(&mut self, file: &str) -> Result<Option<i64>, SamplesDBError> {
+    if !remote::is_remote(file) {
+      return Ok(self.sample_id_by_path(file));
+    }
+
+    let path = remote::cache_path_for(file, &SUPPORTED_AUDIO_FILES)?;
+    if let Some(id) = self.sample_id_by_path(&path) {
+      return Ok(Some(id));
+    }
+
+    let full_path = self.base_path.join(&path);
+    if !full_path.is_file() {
+      let message = self
+        .failed_downloads
+        .get(file)
+        .cloned()
+        .unwrap_or_else(|| "Not downloaded yet".to_string());
+      return Err(SamplesDBError::DownloadError(file.to_string(), message));
+    }
+
+    let id = self.add_sample(&path)?;
+    self.store_metadata(id, read_metadata(&full_path))?;
+
+    Ok(Some(id))
+  }
+
+  /// Looks up the sample a theme refers to like `resolve_sample`, without registering remote
+  /// samples that were downloaded but haven't been used yet
+  pub fn find_sample(&self, file: &str) -> Option<i64> {
+    if !remote::is_remote(file) {
+      return self.sample_id_by_path(file);
+    }
+
+    remote::cache_path_for(file, &SUPPORTED_AUDIO_FILES)
+      .ok()
+      .and_then(|path| self.sample_id_by_path(&path))
+  }
+
+  /// Returns the gain trim stored for a sound of a theme, 1.0 if there is none. Trims
+  /// stored before they were checked are clamped to what the backends accept.
+  pub fn sound_trim(&self, theme: &str, sound: &str) -> f32 {
+    match block_on(self.repository.sound_trim(theme, sound)) {
+      Ok(Some(gain)) if !gain.is_finite() => {
+        warn!("Ignoring invalid trim {} of sound '{}'", gain, sound);
+        1.0
+      }
+      Ok(gain) => gain.unwrap_or(1.0).max(0.0),
+      Err(e) => {
+        warn!("Failed to read trim of sound '{}': {}", sound, e);
+        1.0
+      }
+    }
+  }
+
+  pub fn set_sound_trim(&self, theme: &str, sound: &str, gain: f32) -> Result<(), SamplesDBError> {
+    block_on(self.repository.set_sound_trim(theme, sound, gain))
+  }
+
+  /// Returns the gain normalizing a sample, 1.0 if it hasn't been normalized
+  pub fn sample_gain(&self, sample_id: i64) -> f32 {
+    match block_on(self.repository.sample_gain(sample_id)) {
+      Ok(gain) => gain.unwrap_or(1.0),
+      Err(e) => {
+        warn!("Failed to read gain of sample {}: {}", sample_id, e);
+        1.0
+      }
+    }
+  }
+
+  pub fn set_sample_gain(&self, sample_id: i64, gain: f32) -> Result<(), SamplesDBError> {
+    if !self.samples.contains_key(&sample_id) {
+      return Err(SamplesDBError::UnknownSample(sample_id));
+    }
+
+    block_on(self.repository.set_sample_gain(sample_id, gain))
+  }
+
+  /// Peaks for drawing the sample's waveform. They are computed when a scan finds the file
+  /// new or changed, samples downloaded since the last scan don't have them yet.
+  pub fn waveform(&self, sample_id: i64) -> Result<Peaks, SamplesDBError> {
+    if !self.samples.contains_key(&sample_id) {
+      return Err(SamplesDBError::UnknownSample(sample_id));
+    }
+
+    match block_on(self.repository.waveform(sample_id))? {
+      Some((_, peaks)) => serde_json::from_str(&peaks).map_err(|e| {
+        warn!("Ignoring stored waveform of sample {}: {}", sample_id, e);
+        SamplesDBError::NoWaveform(sample_id)
+      }),
+      None => Err(SamplesDBError::NoWaveform(sample_id)),
+    }
+  }
+
+  /// Modification times of the files the stored peaks were computed from, by path
+  fn stored_files(&self) -> Result<StoredFiles, SamplesDBError> {
+    let paths: HashMap<i64, String> = block_on(self.repository.samples())?.into_iter().collect();
+
+    let waveforms = block_on(self.repository.waveform_dates())?
+      .into_iter()
+      .filter_map(|(id, modified)| paths.get(&id).map(|path| (path.clone(), modified)))
+      .collect();
+    let details = block_on(self.repository.sample_details())?
+      .into_iter()
+      .filter_map(|(id, modified, duration, format)| {
+        paths
+          .get(&id)
+          .map(|path| (path.clone(), (modified, duration, format)))
+      })
+      .collect();
+
+    Ok(StoredFiles { waveforms, details })
+  }
+
+  /// Runs a cheap query to tell whether the repository can still be reached
+  pub fn ping(&self) -> Result<(), SamplesDBError> {
+    block_on(self.repository.settings()).map(|_| ())
+  }
+
+  /// Server preferences, each field is stored as JSON under its own key. Values that can't
+  /// be parsed anymore are ignored.
+  pub fn settings(&self) -> Result<Settings, SamplesDBError> {
+    let mut fields = serde_json::Map::new();
+    for (key, value) in block_on(self.repository.settings())? {
+      match serde_json::from_str(&value) {
+        Ok(value) => {
+          fields.insert(key, value);
+        }
+        Err(e) => warn!("Ignoring stored setting '{}': {}", key, e),
+      }
+    }
+
+    Ok(
+      serde_json::from_value(serde_json::Value::Object(fields)).unwrap_or_else(|e| {
+        warn!("Ignoring stored settings: {}", e);
+        Settings::default()
+      }),
+    )
+  }
+
+  pub fn save_settings(&self, settings: &Settings) -> Result<(), SamplesDBError> {
+    if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(settings) {
+      for (key, value) in fields {
+        if !value.is_null() {
+          block_on(self.repository.set_setting(&key, &value.to_string()))?;
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Settings clients store on the server to sync them across devices, e.g. their keymap
+  pub fn client_setting(&self, client: &str, key: &str) -> Result<Option<String>, SamplesDBError> {
+    block_on(self.repository.client_setting(client, key))
+  }
+
+  pub fn set_client_setting(
+    &self,
+    client: &str,
+    key: &str,
+    value: &str,
+  ) -> Result<(), SamplesDBError> {
+    block_on(self.repository.set_client_setting(client, key, value))
+  }
+
+  /// Themes stored on the server so clients don't have to upload them every session
+  pub fn themes(&self) -> Result<Vec<String>, SamplesDBError> {
+    block_on(self.repository.themes())
+  }
+
+  pub fn theme(&self, name: &str) -> Result<Option<serde_json::Value>, SamplesDBError> {
+    match block_on(self.repository.theme(name))? {
+      Some(theme) => match serde_json::from_str(&theme) {
+        Ok(theme) => Ok(Some(theme)),
+        Err(e) => {
+          warn!("Ignoring stored theme '{}': {}", name, e);
+          Ok(None)
+        }
+      },
+      None => Ok(None),
+    }
+  }
+
+  pub fn save_theme(&self, name: &str, theme: &serde_json::Value) -> Result<(), SamplesDBError> {
+    block_on(self.repository.save_theme(name, &theme.to_string()))
+  }
+
+  /// Everything stored in the database, with `with_samples` the sample files as well. They
+  /// are only read when the backup is serialized.
+  pub fn backup(&self, with_samples: bool) -> Result<Backup, SamplesDBError> {
+    let mut samples = Vec::new();
+    for sample in self.samples.values() {
+      // Read when the backup is written
+      let data = if with_samples {
+        Some(SampleData::File(self.full_path_of_sample(sample.id)))
+      } else {
+        None
+      };
+
+      samples.push(BackupSample {
+        path: sample.path.clone(),
+        tags: sample.tags.clone(),
+        metadata: sample.metadata.clone(),
+        gain: block_on(self.repository.sample_gain(sample.id))?,
+        data,
+      });
+    }
+
+    let mut themes = Vec::new();
+    for name in self.themes()? {
+      if let Some(theme) = self.theme(&name)? {
+        themes.push(BackupTheme { name, theme });
+      }
+    }
+
+    Ok(Backup {
+      version: Backup::VERSION,
+      samples,
+      themes,
+      settings: block_on(self.repository.settings())?,
+      client_settings: block_on(self.repository.client_settings())?,
+      sound_trims: block_on(self.repository.sound_trims())?,
+    })
+  }
+
+  /// Restores a backup on top of what is stored already. Sample files included in the
+  /// backup are written into the library, replacing files of the same name.
+  pub fn restore(&mut self, backup: &Backup) -> Result<(), SamplesDBError> {
+    if backup.version != Backup::VERSION {
+      return Err(SamplesDBError::BackupError(format!(
+        "Unsupported backup version {}",
+        backup.version
+      )));
+    }
+
+    for sample in &backup.samples {
+      // Never write outside of the library
+      if !is_library_path(&sample.path) {
+        warn!("Skipping sample outside of the library '{}'", sample.path);
+        continue;
+      }
+
+      if let Some(ref data) = sample.data {
+        let full_path = self.base_path.join(&sample.path);
+        let contents = data.contents().map_err(|e| {
+          SamplesDBError::BackupError(format!("Invalid data of '{}': {}", sample.path, e))
+        })?;
+
+        full_path
+          .parent()
+          .map_or(Ok(()), fs::create_dir_all)
+          .and_then(|_| fs::write(&full_path, contents))
+          .map_err(|e| {
+            SamplesDBError::BackupError(format!(
+              "Failed to write '{}': {}",
+              full_path.to_string_lossy(),
+              e
+            ))
+          })?;
+      }
+
+      let id = match self.sample_id_by_path(&sample.path) {
+        Some(id) => id,
+        None => self.add_sample(&sample.path)?,
+      };
+
+      block_on(self.repository.set_metadata(id, &sample.metadata))?;
+      self.samples.get_mut(&id).unwrap().metadata = sample.metadata.clone();
+
+      for tag in &sample.tags {
+        self.add_tag_to_sample(id, tag)?;
+      }
+
+      if let Some(gain) = sample.gain {
+        block_on(self.repository.set_sample_gain(id, gain))?;
+      }
+    }
+
+    for theme in &backup.themes {
+      self.save_theme(&theme.name, &theme.theme)?;
+    }
+
+    for (key, value) in &backup.settings {
+      block_on(self.repository.set_setting(key, value))?;
+    }
+
+    for (client, key, value) in &backup.client_settings {
+      block_on(self.repository.set_client_setting(client, key, value))?;
+    }
+
+    for (theme, sound, gain) in &backup.sound_trims {
+      block_on(self.repository.set_sound_trim(theme, sound, *gain))?;
+    }
+
+    info!(
+      "Restored {} samples and {} themes",
+      backup.samples.len(),
+      backup.themes.len()
+    );
+
+    Ok(())
+  }
+
   pub fn sample_id_by_path(&self, path: &str) -> Option<i64> {
     for sample in self.samples.values() {
       if sample.path == path {
@@ -1,15 +1,45 @@
 use failure::Fail;
 
+use crate::audio_engine::loader::error::AudioFileLoaderError;
+
 #[derive(Fail, Debug)]
 pub enum SamplesDBError {
   #[fail(display = "SamplesDB Sqlite Error: {}", _0)]
   SqliteError(rusqlite::Error),
 
+  #[cfg(feature = "postgresql")]
+  #[fail(display = "SamplesDB Postgres Error: {}", _0)]
+  PostgresError(postgres::Error),
+
+  #[fail(display = "SamplesDB Unsupported database '{}'", _0)]
+  UnsupportedDatabase(String),
+
   #[fail(display = "SamplesDB WalkDir Error: {}", _0)]
   WalkDirError(walkdir::Error),
 
   #[fail(display = "SamplesDB Failed to create tag '{}'", _0)]
   TagCreationError(String),
+
+  #[fail(display = "SamplesDB Unknown sample {}", _0)]
+  UnknownSample(i64),
+
+  #[fail(display = "SamplesDB Unknown tag '{}'", _0)]
+  UnknownTag(String),
+
+  #[fail(display = "SamplesDB No waveform of sample {}", _0)]
+  NoWaveform(i64),
+
+  #[fail(display = "SamplesDB Failed to download '{}': {}", _0, _1)]
+  DownloadError(String, String),
+
+  #[fail(display = "SamplesDB Unsupported file type of remote sample '{}'", _0)]
+  UnsupportedRemoteFile(String),
+
+  #[fail(display = "SamplesDB Backup Error: {}", _0)]
+  BackupError(String),
+
+  #[fail(display = "SamplesDB {}", _0)]
+  LoaderError(AudioFileLoaderError),
 }
 
 impl From<rusqlite::Error> for SamplesDBError {
@@ -18,8 +48,21 @@ impl From<rusqlite::Error> for SamplesDBError {
   }
 }
 
+#[cfg(feature = "postgresql")]
+impl From<postgres::Error> for SamplesDBError {
+  fn from(e: postgres::Error) -> Self {
+    Self::PostgresError(e)
+  }
+}
+
 impl From<walkdir::Error> for SamplesDBError {
   fn from(e: walkdir::Error) -> Self {
     Self::WalkDirError(e)
   }
 }
+
+impl From<AudioFileLoaderError> for SamplesDBError {
+  fn from(e: AudioFileLoaderError) -> Self {
+    Self::LoaderError(e)
+  }
+}
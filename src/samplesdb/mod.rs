@@ -1,5 +1,20 @@
+pub mod backup;
 pub mod db;
 pub mod error;
+#[cfg(feature = "postgresql")]
+pub mod postgresql;
+pub mod remote;
+pub mod repository;
+pub mod snapshot;
+pub mod sqlite;
 
-pub use self::db::{Sample, SamplesDB, Tag};
+pub use self::backup::Backup;
+pub use self::db::{
+  is_library_path, rescan, LibraryReport, LibraryScan, Sample, SamplesDB, SharedSamplesDB, Tag,
+};
 pub use self::error::SamplesDBError;
+#[cfg(feature = "postgresql")]
+pub use self::postgresql::PostgresRepository;
+pub use self::repository::SampleRepository;
+pub use self::snapshot::Snapshots;
+pub use self::sqlite::SqliteRepository;
@@ -0,0 +1,441 @@
+use async_trait::async_trait;
+use postgres::{Connection, TlsMode};
+
+use crate::audio_engine::loader::base::{SampleFormat, SampleMetadata};
+use crate::samplesdb::error::SamplesDBError;
+use crate::samplesdb::repository::SampleRepository;
+
+/// Stores the samples database on a PostgreSQL server, so several instances can share one
+/// curated library.
+pub struct PostgresRepository {
+  connection: Connection,
+}
+
+impl PostgresRepository {
+  pub fn open(url: &str) -> Result<Self, SamplesDBError> {
+    let repository = Self {
+      connection: Connection::connect(url, TlsMode::None)?,
+    };
+
+    repository.setup_tables()?;
+
+    Ok(repository)
+  }
+
+  fn setup_tables(&self) -> Result<(), SamplesDBError> {
+    self.connection.batch_execute(
+      "CREATE TABLE IF NOT EXISTS sample (
+                id   BIGSERIAL PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE
+            );
+
+            CREATE TABLE IF NOT EXISTS tag (
+                id   BIGSERIAL PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE
+            );
+
+            CREATE TABLE IF NOT EXISTS sample_tag (
+                sample_id BIGINT NOT NULL,
+                tag_id    BIGINT NOT NULL,
+                PRIMARY KEY(sample_id, tag_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS sound_trim (
+                theme TEXT NOT NULL,
+                sound TEXT NOT NULL,
+                gain  REAL NOT NULL,
+                PRIMARY KEY(theme, sound)
+            );
+
+            CREATE TABLE IF NOT EXISTS sample_gain (
+                sample_id BIGINT PRIMARY KEY,
+                gain      REAL NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS settings (
+                key   TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS client_setting (
+                client TEXT NOT NULL,
+                key    TEXT NOT NULL,
+                value  TEXT NOT NULL,
+                PRIMARY KEY(client, key)
+            );
+
+            CREATE TABLE IF NOT EXISTS sample_metadata (
+                sample_id BIGINT PRIMARY KEY,
+                title     TEXT,
+                artist    TEXT,
+                album     TEXT,
+                genre     TEXT,
+                comment   TEXT
+            );
+
+            CREATE TABLE IF NOT EXISTS waveform (
+                sample_id BIGINT PRIMARY KEY,
+                modified  BIGINT NOT NULL,
+                peaks     TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS sample_details (
+                sample_id   BIGINT PRIMARY KEY,
+                modified    BIGINT NOT NULL,
+                duration    REAL NOT NULL,
+                sample_rate INTEGER NOT NULL,
+                channels    INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS theme (
+                name  TEXT PRIMARY KEY,
+                theme TEXT NOT NULL
+            );",
+    )?;
+
+    Ok(())
+  }
+}
+
+#[async_trait(?Send)]
+impl SampleRepository for PostgresRepository {
+  async fn add_sample(&self, path: &str) -> Result<i64, SamplesDBError> {
+    // The no-op update makes RETURNING yield the id of an already existing row as well
+    let rows = self.connection.query(
+      "INSERT INTO sample (path) VALUES ($1)
+         ON CONFLICT (path) DO UPDATE SET path = EXCLUDED.path
+         RETURNING id;",
+      &[&path],
+    )?;
+
+    Ok(rows.get(0).get(0))
+  }
+
+  async fn samples(&self) -> Result<Vec<(i64, String)>, SamplesDBError> {
+    let rows = self.connection.query("SELECT id, path FROM sample;", &[])?;
+
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+  }
+
+  async fn tags(&self) -> Result<Vec<(i64, String)>, SamplesDBError> {
+    let rows = self.connection.query("SELECT id, name FROM tag;", &[])?;
+
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+  }
+
+  async fn add_tag(&self, name: &str) -> Result<i64, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("INSERT INTO tag (name) VALUES ($1) RETURNING id;", &[&name])?;
+
+    Ok(rows.get(0).get(0))
+  }
+
+  async fn sample_tags(&self) -> Result<Vec<(i64, i64)>, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("SELECT sample_id, tag_id FROM sample_tag;", &[])?;
+
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+  }
+
+  async fn add_sample_tag(&self, sample_id: i64, tag_id: i64) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sample_tag (sample_id, tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;",
+      &[&sample_id, &tag_id],
+    )?;
+
+    Ok(())
+  }
+
+  async fn remove_sample_tag(&self, sample_id: i64, tag_id: i64) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "DELETE FROM sample_tag WHERE sample_id = $1 AND tag_id = $2;",
+      &[&sample_id, &tag_id],
+    )?;
+
+    Ok(())
+  }
+
+  async fn metadata(&self, sample_id: i64) -> Result<Option<SampleMetadata>, SamplesDBError> {
+    let rows = self.connection.query(
+      "SELECT title, artist, album, genre, comment FROM sample_metadata WHERE sample_id = $1;",
+      &[&sample_id],
+    )?;
+
+    Ok(rows.iter().next().map(|row| SampleMetadata {
+      title: row.get(0),
+      artist: row.get(1),
+      album: row.get(2),
+      genre: row.get(3),
+      comment: row.get(4),
+    }))
+  }
+
+  async fn set_metadata(
+    &self,
+    sample_id: i64,
+    metadata: &SampleMetadata,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sample_metadata (sample_id, title, artist, album, genre, comment)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT (sample_id) DO UPDATE SET
+           title = EXCLUDED.title,
+           artist = EXCLUDED.artist,
+           album = EXCLUDED.album,
+           genre = EXCLUDED.genre,
+           comment = EXCLUDED.comment;",
+      &[
+        &sample_id,
+        &metadata.title,
+        &metadata.artist,
+        &metadata.album,
+        &metadata.genre,
+        &metadata.comment,
+      ],
+    )?;
+
+    Ok(())
+  }
+
+  async fn sound_trim(&self, theme: &str, sound: &str) -> Result<Option<f32>, SamplesDBError> {
+    let rows = self.connection.query(
+      "SELECT gain FROM sound_trim WHERE theme = $1 AND sound = $2;",
+      &[&theme, &sound],
+    )?;
+
+    Ok(rows.iter().next().map(|row| row.get(0)))
+  }
+
+  async fn set_sound_trim(
+    &self,
+    theme: &str,
+    sound: &str,
+    gain: f32,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sound_trim (theme, sound, gain) VALUES ($1, $2, $3)
+         ON CONFLICT (theme, sound) DO UPDATE SET gain = EXCLUDED.gain;",
+      &[&theme, &sound, &gain],
+    )?;
+
+    Ok(())
+  }
+
+  async fn sound_trims(&self) -> Result<Vec<(String, String, f32)>, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("SELECT theme, sound, gain FROM sound_trim;", &[])?;
+
+    Ok(
+      rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect(),
+    )
+  }
+
+  async fn sample_gain(&self, sample_id: i64) -> Result<Option<f32>, SamplesDBError> {
+    let rows = self.connection.query(
+      "SELECT gain FROM sample_gain WHERE sample_id = $1;",
+      &[&sample_id],
+    )?;
+
+    Ok(rows.iter().next().map(|row| row.get(0)))
+  }
+
+  async fn set_sample_gain(&self, sample_id: i64, gain: f32) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sample_gain (sample_id, gain) VALUES ($1, $2)
+         ON CONFLICT (sample_id) DO UPDATE SET gain = EXCLUDED.gain;",
+      &[&sample_id, &gain],
+    )?;
+
+    Ok(())
+  }
+
+  async fn waveform(&self, sample_id: i64) -> Result<Option<(i64, String)>, SamplesDBError> {
+    let rows = self.connection.query(
+      "SELECT modified, peaks FROM waveform WHERE sample_id = $1;",
+      &[&sample_id],
+    )?;
+
+    Ok(rows.iter().next().map(|row| (row.get(0), row.get(1))))
+  }
+
+  async fn set_waveform(
+    &self,
+    sample_id: i64,
+    modified: i64,
+    peaks: &str,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO waveform (sample_id, modified, peaks) VALUES ($1, $2, $3)
+         ON CONFLICT (sample_id) DO UPDATE
+         SET modified = EXCLUDED.modified, peaks = EXCLUDED.peaks;",
+      &[&sample_id, &modified, &peaks],
+    )?;
+
+    Ok(())
+  }
+
+  async fn waveform_dates(&self) -> Result<Vec<(i64, i64)>, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("SELECT sample_id, modified FROM waveform;", &[])?;
+
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+  }
+
+  async fn remove_waveform(&self, sample_id: i64) -> Result<(), SamplesDBError> {
+    self
+      .connection
+      .execute("DELETE FROM waveform WHERE sample_id = $1;", &[&sample_id])?;
+
+    Ok(())
+  }
+
+  async fn sample_details(&self) -> Result<Vec<(i64, i64, f32, SampleFormat)>, SamplesDBError> {
+    let rows = self.connection.query(
+      "SELECT sample_id, modified, duration, sample_rate, channels FROM sample_details;",
+      &[],
+    )?;
+
+    Ok(
+      rows
+        .iter()
+        .map(|row| {
+          (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            SampleFormat {
+              sample_rate: row.get(3),
+              channels: row.get::<_, i32>(4) as u32,
+            },
+          )
+        })
+        .collect(),
+    )
+  }
+
+  async fn set_sample_details(
+    &self,
+    sample_id: i64,
+    modified: i64,
+    duration: f32,
+    format: &SampleFormat,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sample_details (sample_id, modified, duration, sample_rate, channels)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (sample_id) DO UPDATE
+         SET modified = EXCLUDED.modified, duration = EXCLUDED.duration,
+             sample_rate = EXCLUDED.sample_rate, channels = EXCLUDED.channels;",
+      &[
+        &sample_id,
+        &modified,
+        &duration,
+        &format.sample_rate,
+        &(format.channels as i32),
+      ],
+    )?;
+
+    Ok(())
+  }
+
+  async fn remove_sample_details(&self, sample_id: i64) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "DELETE FROM sample_details WHERE sample_id = $1;",
+      &[&sample_id],
+    )?;
+
+    Ok(())
+  }
+
+  async fn settings(&self) -> Result<Vec<(String, String)>, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("SELECT key, value FROM settings;", &[])?;
+
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+  }
+
+  async fn set_setting(&self, key: &str, value: &str) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO settings (key, value) VALUES ($1, $2)
+         ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value;",
+      &[&key, &value],
+    )?;
+
+    Ok(())
+  }
+
+  async fn client_setting(
+    &self,
+    client: &str,
+    key: &str,
+  ) -> Result<Option<String>, SamplesDBError> {
+    let rows = self.connection.query(
+      "SELECT value FROM client_setting WHERE client = $1 AND key = $2;",
+      &[&client, &key],
+    )?;
+
+    Ok(rows.iter().next().map(|row| row.get(0)))
+  }
+
+  async fn set_client_setting(
+    &self,
+    client: &str,
+    key: &str,
+    value: &str,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO client_setting (client, key, value) VALUES ($1, $2, $3)
+         ON CONFLICT (client, key) DO UPDATE SET value = EXCLUDED.value;",
+      &[&client, &key, &value],
+    )?;
+
+    Ok(())
+  }
+
+  async fn client_settings(&self) -> Result<Vec<(String, String, String)>, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("SELECT client, key, value FROM client_setting;", &[])?;
+
+    Ok(
+      rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect(),
+    )
+  }
+
+  async fn themes(&self) -> Result<Vec<String>, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("SELECT name FROM theme ORDER BY name;", &[])?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+  }
+
+  async fn theme(&self, name: &str) -> Result<Option<String>, SamplesDBError> {
+    let rows = self
+      .connection
+      .query("SELECT theme FROM theme WHERE name = $1;", &[&name])?;
+
+    Ok(rows.iter().next().map(|row| row.get(0)))
+  }
+
+  async fn save_theme(&self, name: &str, theme: &str) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO theme (name, theme) VALUES ($1, $2)
+         ON CONFLICT (name) DO UPDATE SET theme = EXCLUDED.theme;",
+      &[&name, &theme],
+    )?;
+
+    Ok(())
+  }
+}
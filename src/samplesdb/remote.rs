@@ -0,0 +1,130 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crossbeam_channel::{unbounded, Receiver};
+use sha2::{Digest, Sha256};
+
+use crate::samplesdb::error::SamplesDBError;
+
+/// Directory inside the sound library where downloaded samples are cached
+pub const REMOTE_CACHE_DIR: &str = ".remote";
+
+// Timeouts of a download in milliseconds, a server that stops sending gives up the download
+const CONNECT_TIMEOUT_MS: u64 = 5000;
+const READ_TIMEOUT_MS: u64 = 10000;
+
+/// Remote samples bigger than this aren't downloaded
+const MAX_DOWNLOAD_BYTES: u64 = 200 * 1024 * 1024;
+
+pub fn is_remote(file: &str) -> bool {
+  file.starts_with("http://") || file.starts_with("https://")
+}
+
+/// Returns the synthetic path (relative to the library) a remote sample is cached under. The
+/// name is a digest of the URL, so it stays the same across versions of the server.
+pub fn cache_path_for(url: &str, supported: &[&str]) -> Result<String, SamplesDBError> {
+  let extension = url
+    .split(|c| c == '?' || c == '#')
+    .next()
+    .and_then(|path| path.rsplit('/').next())
+    .and_then(|name| Path::new(name).extension())
+    .and_then(|ext| ext.to_str())
+    .map(str::to_lowercase);
+
+  let extension = match extension {
+    Some(ref ext) if supported.contains(&ext.as_str()) => ext.clone(),
+    _ => return Err(SamplesDBError::UnsupportedRemoteFile(url.to_string())),
+  };
+
+  Ok(format!(
+    "{}/{:x}.{}",
+    REMOTE_CACHE_DIR,
+    Sha256::digest(url.as_bytes()),
+    extension
+  ))
+}
+
+/// Downloads `url` to `destination`. The file only shows up once it has been downloaded completely.
+pub fn download(url: &str, destination: &Path) -> Result<(), SamplesDBError> {
+  let download_error = |message: String| SamplesDBError::DownloadError(url.to_string(), message);
+
+  info!("Downloading {} ...", url);
+
+  let response = ureq::get(url)
+    .timeout_connect(CONNECT_TIMEOUT_MS)
+    .timeout_read(READ_TIMEOUT_MS)
+    .call();
+  if let Some(e) = response.synthetic_error() {
+    return Err(download_error(e.to_string()));
+  }
+  if !response.ok() {
+    return Err(download_error(format!(
+      "{} {}",
+      response.status(),
+      response.status_text()
+    )));
+  }
+
+  let too_large = || download_error(format!("Larger than {} bytes", MAX_DOWNLOAD_BYTES));
+  let length = response
+    .header("Content-Length")
+    .and_then(|length| length.parse::<u64>().ok());
+  if length.map_or(false, |length| length > MAX_DOWNLOAD_BYTES) {
+    return Err(too_large());
+  }
+
+  if let Some(parent) = destination.parent() {
+    fs::create_dir_all(parent).map_err(|e| download_error(e.to_string()))?;
+  }
+
+  // Servers may leave out the length or lie about it, stop reading past the limit either way
+  let partial = destination.with_extension("part");
+  let mut file = File::create(&partial).map_err(|e| download_error(e.to_string()))?;
+  let mut reader = response.into_reader().take(MAX_DOWNLOAD_BYTES + 1);
+  let copied = io::copy(&mut reader, &mut file).map_err(|e| download_error(e.to_string()));
+
+  match copied {
+    Ok(bytes) if bytes <= MAX_DOWNLOAD_BYTES => {}
+    Ok(_) => {
+      let _ = fs::remove_file(&partial);
+      return Err(too_large());
+    }
+    Err(e) => {
+      let _ = fs::remove_file(&partial);
+      return Err(e);
+    }
+  }
+  fs::rename(&partial, destination).map_err(|e| download_error(e.to_string()))?;
+
+  info!("Downloaded {} to {}", url, destination.to_string_lossy());
+
+  Ok(())
+}
+
+/// Downloads each URL to its path on a worker thread, so nobody has to wait for slow servers.
+/// The receiver gets the URLs that failed along with why, once all downloads are done.
+pub fn download_all(downloads: Vec<(String, PathBuf)>) -> Receiver<Vec<(String, String)>> {
+  let (sender, receiver) = unbounded();
+
+  thread::spawn(move || {
+    let failed = downloads
+      .into_iter()
+      .filter_map(|(url, destination)| match download(&url, &destination) {
+        Ok(()) => None,
+        Err(e) => {
+          warn!("{}", e);
+          match e {
+            SamplesDBError::DownloadError(_, message) => Some((url, message)),
+            e => Some((url, e.to_string())),
+          }
+        }
+      })
+      .collect();
+
+    let _ = sender.send(failed);
+  });
+
+  receiver
+}
@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+
+use crate::audio_engine::loader::base::{SampleFormat, SampleMetadata};
+use crate::samplesdb::error::SamplesDBError;
+
+/// Persistent storage behind the SamplesDB. The SamplesDB keeps its own in-memory index of
+/// the library, the repository only has to store and return rows. Stores talking to a
+/// server can wait for it without blocking a thread; the SamplesDB itself is used from the
+/// engine thread and the API's worker threads, so it waits for the results right away.
+#[async_trait(?Send)]
+pub trait SampleRepository: Send {
+  /// Returns the id of the sample stored under `path`, registering it if it is unknown
+  async fn add_sample(&self, path: &str) -> Result<i64, SamplesDBError>;
+  /// All registered samples as (id, path), including those whose file is gone
+  async fn samples(&self) -> Result<Vec<(i64, String)>, SamplesDBError>;
+
+  async fn tags(&self) -> Result<Vec<(i64, String)>, SamplesDBError>;
+  async fn add_tag(&self, name: &str) -> Result<i64, SamplesDBError>;
+  /// All links between samples and tags as (sample id, tag id)
+  async fn sample_tags(&self) -> Result<Vec<(i64, i64)>, SamplesDBError>;
+  async fn add_sample_tag(&self, sample_id: i64, tag_id: i64) -> Result<(), SamplesDBError>;
+  async fn remove_sample_tag(&self, sample_id: i64, tag_id: i64) -> Result<(), SamplesDBError>;
+
+  async fn metadata(&self, sample_id: i64) -> Result<Option<SampleMetadata>, SamplesDBError>;
+  async fn set_metadata(
+    &self,
+    sample_id: i64,
+    metadata: &SampleMetadata,
+  ) -> Result<(), SamplesDBError>;
+
+  async fn sound_trim(&self, theme: &str, sound: &str) -> Result<Option<f32>, SamplesDBError>;
+  async fn set_sound_trim(&self, theme: &str, sound: &str, gain: f32)
+    -> Result<(), SamplesDBError>;
+  /// All trims as (theme, sound, gain)
+  async fn sound_trims(&self) -> Result<Vec<(String, String, f32)>, SamplesDBError>;
+
+  /// Gain normalizing a sample, applied whenever it is played
+  async fn sample_gain(&self, sample_id: i64) -> Result<Option<f32>, SamplesDBError>;
+  async fn set_sample_gain(&self, sample_id: i64, gain: f32) -> Result<(), SamplesDBError>;
+
+  /// Peaks of a sample as JSON, along with the modification time (seconds since the epoch)
+  /// of the file they were computed from
+  async fn waveform(&self, sample_id: i64) -> Result<Option<(i64, String)>, SamplesDBError>;
+  async fn set_waveform(
+    &self,
+    sample_id: i64,
+    modified: i64,
+    peaks: &str,
+  ) -> Result<(), SamplesDBError>;
+  /// Modification times of the files of all stored peaks as (sample id, modified)
+  async fn waveform_dates(&self) -> Result<Vec<(i64, i64)>, SamplesDBError>;
+  async fn remove_waveform(&self, sample_id: i64) -> Result<(), SamplesDBError>;
+
+  /// Length in seconds and format of all samples as read from their files, as (sample id,
+  /// modified, duration, format) with the modification time of the file they were read from
+  async fn sample_details(&self) -> Result<Vec<(i64, i64, f32, SampleFormat)>, SamplesDBError>;
+  async fn set_sample_details(
+    &self,
+    sample_id: i64,
+    modified: i64,
+    duration: f32,
+    format: &SampleFormat,
+  ) -> Result<(), SamplesDBError>;
+  async fn remove_sample_details(&self, sample_id: i64) -> Result<(), SamplesDBError>;
+
+  async fn settings(&self) -> Result<Vec<(String, String)>, SamplesDBError>;
+  async fn set_setting(&self, key: &str, value: &str) -> Result<(), SamplesDBError>;
+
+  async fn client_setting(&self, client: &str, key: &str)
+    -> Result<Option<String>, SamplesDBError>;
+  async fn set_client_setting(
+    &self,
+    client: &str,
+    key: &str,
+    value: &str,
+  ) -> Result<(), SamplesDBError>;
+  /// All client settings as (client, key, value)
+  async fn client_settings(&self) -> Result<Vec<(String, String, String)>, SamplesDBError>;
+
+  /// Names of the stored themes
+  async fn themes(&self) -> Result<Vec<String>, SamplesDBError>;
+  /// The stored theme as JSON
+  async fn theme(&self, name: &str) -> Result<Option<String>, SamplesDBError>;
+  async fn save_theme(&self, name: &str, theme: &str) -> Result<(), SamplesDBError>;
+}
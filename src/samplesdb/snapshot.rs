@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use futures::executor::block_on;
+use rusqlite::backup::Progress;
+use rusqlite::{Connection, DatabaseName, OpenFlags, NO_PARAMS};
+
+use crate::samplesdb::error::SamplesDBError;
+use crate::samplesdb::repository::SampleRepository;
+use crate::samplesdb::sqlite::SqliteRepository;
+
+const SNAPSHOT_DATABASE: &str = "samples.db";
+const SNAPSHOT_THEMES: &str = "themes";
+
+fn io_error(e: std::io::Error) -> SamplesDBError {
+  SamplesDBError::BackupError(e.to_string())
+}
+
+/// Theme names can contain anything, file names can't
+fn theme_file_name(name: &str) -> String {
+  let name: String = name
+    .chars()
+    .map(|c| {
+      if c.is_alphanumeric() || c == '-' || c == '_' || c == ' ' {
+        c
+      } else {
+        '_'
+      }
+    })
+    .collect();
+
+  format!("{}.json", name)
+}
+
+/// Copies of an SQLite samples database, each in a directory named after the time it was
+/// taken along with its stored themes as JSON files. Only the newest `keep` are kept.
+pub struct Snapshots {
+  dir: PathBuf,
+  keep: usize,
+}
+
+impl Snapshots {
+  pub fn new(dir: &Path, keep: usize) -> Self {
+    Self {
+      dir: dir.to_owned(),
+      keep,
+    }
+  }
+
+  /// Names of the snapshots, oldest first
+  pub fn list(&self) -> Result<Vec<String>, SamplesDBError> {
+    if !self.dir.exists() {
+      return Ok(Vec::new());
+    }
+
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&self.dir).map_err(io_error)? {
+      let path = entry.map_err(io_error)?.path();
+      if path.join(SNAPSHOT_DATABASE).is_file() {
+        if let Some(name) = path.file_name() {
+          names.push(name.to_string_lossy().into_owned());
+        }
+      }
+    }
+
+    names.sort();
+    Ok(names)
+  }
+
+  /// Snapshots the database using SQLite's online backup, so the server can keep on using
+  /// it meanwhile. Databases that fail SQLite's integrity check aren't snapshotted, they
+  /// would only push out the good snapshots.
+  pub fn take(&self, database: &Path) -> Result<String, SamplesDBError> {
+    let source = Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let check: String = source.query_row("PRAGMA quick_check;", NO_PARAMS, |row| row.get(0))?;
+    if check != "ok" {
+      return Err(SamplesDBError::BackupError(format!(
+        "'{}' is corrupted: {}",
+        database.to_string_lossy(),
+        check
+      )));
+    }
+
+    let name = Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let path = self.dir.join(&name);
+    fs::create_dir_all(path.join(SNAPSHOT_THEMES)).map_err(io_error)?;
+
+    source.backup(DatabaseName::Main, path.join(SNAPSHOT_DATABASE), None)?;
+
+    // Themes are also kept as plain files, so they can be recovered without the server
+    let snapshot = SqliteRepository::open(&path.join(SNAPSHOT_DATABASE))?;
+    for theme_name in block_on(snapshot.themes())? {
+      if let Some(theme) = block_on(snapshot.theme(&theme_name))? {
+        let file = path
+          .join(SNAPSHOT_THEMES)
+          .join(theme_file_name(&theme_name));
+        fs::write(file, theme).map_err(io_error)?;
+      }
+    }
+
+    self.prune()?;
+
+    Ok(name)
+  }
+
+  fn prune(&self) -> Result<(), SamplesDBError> {
+    let names = self.list()?;
+    let outdated = names.len().saturating_sub(self.keep);
+
+    for name in &names[..outdated] {
+      info!("Removing outdated snapshot '{}'", name);
+      fs::remove_dir_all(self.dir.join(name)).map_err(io_error)?;
+    }
+
+    Ok(())
+  }
+
+  /// Overwrites the database with the snapshot, the server must not be running
+  pub fn restore(&self, name: &str, database: &Path) -> Result<(), SamplesDBError> {
+    let snapshot = self.dir.join(name).join(SNAPSHOT_DATABASE);
+    if !snapshot.is_file() {
+      return Err(SamplesDBError::BackupError(format!(
+        "There is no snapshot '{}'",
+        name
+      )));
+    }
+
+    let mut connection = Connection::open(database)?;
+    connection.restore(DatabaseName::Main, snapshot, None::<fn(Progress)>)?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,514 @@
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, NO_PARAMS};
+use std::path::Path;
+
+use crate::audio_engine::loader::base::{SampleFormat, SampleMetadata};
+use crate::samplesdb::error::SamplesDBError;
+use crate::samplesdb::repository::SampleRepository;
+
+pub struct SqliteRepository {
+  connection: Connection,
+}
+
+impl SqliteRepository {
+  pub fn open(db_path: &Path) -> Result<Self, SamplesDBError> {
+    let repository = Self {
+      connection: Connection::open(db_path)?,
+    };
+
+    repository.setup_tables()?;
+
+    Ok(repository)
+  }
+
+  fn setup_tables(&self) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS sample (
+                id   INTEGER PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE 
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS tag (
+                id   INT PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE 
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS sample_tag (
+                sample_id INT NOT NULL,
+                tag_id    INT NOT NULL,
+                UNIQUE(sample_id, tag_id) ON CONFLICT REPLACE
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS sound_trim (
+                theme TEXT NOT NULL,
+                sound TEXT NOT NULL,
+                gain  REAL NOT NULL,
+                UNIQUE(theme, sound) ON CONFLICT REPLACE
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS sample_gain (
+                sample_id INTEGER NOT NULL,
+                gain      REAL NOT NULL,
+                UNIQUE(sample_id) ON CONFLICT REPLACE
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS settings (
+                key   TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS client_setting (
+                client TEXT NOT NULL,
+                key    TEXT NOT NULL,
+                value  TEXT NOT NULL,
+                UNIQUE(client, key) ON CONFLICT REPLACE
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS sample_metadata (
+                sample_id INTEGER PRIMARY KEY,
+                title     TEXT,
+                artist    TEXT,
+                album     TEXT,
+                genre     TEXT,
+                comment   TEXT
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS waveform (
+                sample_id INTEGER PRIMARY KEY,
+                modified  INTEGER NOT NULL,
+                peaks     TEXT NOT NULL
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS sample_details (
+                sample_id   INTEGER PRIMARY KEY,
+                modified    INTEGER NOT NULL,
+                duration    REAL NOT NULL,
+                sample_rate INTEGER NOT NULL,
+                channels    INTEGER NOT NULL
+            )",
+      NO_PARAMS,
+    )?;
+
+    self.connection.execute(
+      "CREATE TABLE IF NOT EXISTS theme (
+                name  TEXT PRIMARY KEY,
+                theme TEXT NOT NULL
+            )",
+      NO_PARAMS,
+    )?;
+
+    Ok(())
+  }
+}
+
+#[async_trait(?Send)]
+impl SampleRepository for SqliteRepository {
+  async fn add_sample(&self, path: &str) -> Result<i64, SamplesDBError> {
+    let result = self.connection.query_row(
+      "SELECT id FROM sample WHERE path = ?1;",
+      params![path],
+      |row| row.get(0),
+    );
+
+    result.or_else(|_| -> Result<i64, SamplesDBError> {
+      self
+        .connection
+        .execute("INSERT INTO sample (path) VALUES (?1);", params![path])?;
+      Ok(self.connection.last_insert_rowid())
+    })
+  }
+
+  async fn samples(&self) -> Result<Vec<(i64, String)>, SamplesDBError> {
+    let mut stmt = self.connection.prepare("SELECT id, path FROM sample;")?;
+
+    let samples: Result<Vec<(i64, String)>, _> = stmt
+      .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+      .collect();
+
+    Ok(samples?)
+  }
+
+  async fn tags(&self) -> Result<Vec<(i64, String)>, SamplesDBError> {
+    let mut stmt = self.connection.prepare("SELECT id, name FROM tag;")?;
+
+    let tags: Result<Vec<(i64, String)>, _> = stmt
+      .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+      .collect();
+
+    Ok(tags?)
+  }
+
+  async fn add_tag(&self, name: &str) -> Result<i64, SamplesDBError> {
+    // tag.id is declared INT, not INTEGER, so it isn't an alias of the rowid and has to be
+    // assigned here
+    self.connection.execute(
+      "INSERT INTO tag (id, name) SELECT IFNULL(MAX(id), 0) + 1, ?1 FROM tag;",
+      params![name],
+    )?;
+
+    Ok(self.connection.query_row(
+      "SELECT id FROM tag WHERE name = ?1;",
+      params![name],
+      |row| row.get(0),
+    )?)
+  }
+
+  async fn sample_tags(&self) -> Result<Vec<(i64, i64)>, SamplesDBError> {
+    let mut stmt = self
+      .connection
+      .prepare("SELECT sample_id, tag_id FROM sample_tag;")?;
+
+    let links: Result<Vec<(i64, i64)>, _> = stmt
+      .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+      .collect();
+
+    Ok(links?)
+  }
+
+  async fn add_sample_tag(&self, sample_id: i64, tag_id: i64) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sample_tag (sample_id, tag_id) VALUES (?1, ?2);",
+      params![sample_id, tag_id],
+    )?;
+
+    Ok(())
+  }
+
+  async fn remove_sample_tag(&self, sample_id: i64, tag_id: i64) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "DELETE FROM sample_tag WHERE sample_id = ?1 AND tag_id = ?2;",
+      params![sample_id, tag_id],
+    )?;
+
+    Ok(())
+  }
+
+  async fn metadata(&self, sample_id: i64) -> Result<Option<SampleMetadata>, SamplesDBError> {
+    let metadata = self
+      .connection
+      .query_row(
+        "SELECT title, artist, album, genre, comment FROM sample_metadata WHERE sample_id = ?1;",
+        params![sample_id],
+        |row| {
+          Ok(SampleMetadata {
+            title: row.get(0)?,
+            artist: row.get(1)?,
+            album: row.get(2)?,
+            genre: row.get(3)?,
+            comment: row.get(4)?,
+          })
+        },
+      )
+      .optional()?;
+
+    Ok(metadata)
+  }
+
+  async fn set_metadata(
+    &self,
+    sample_id: i64,
+    metadata: &SampleMetadata,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT OR REPLACE INTO sample_metadata (sample_id, title, artist, album, genre, comment)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+      params![
+        sample_id,
+        metadata.title,
+        metadata.artist,
+        metadata.album,
+        metadata.genre,
+        metadata.comment
+      ],
+    )?;
+
+    Ok(())
+  }
+
+  async fn sound_trim(&self, theme: &str, sound: &str) -> Result<Option<f32>, SamplesDBError> {
+    let gain = self
+      .connection
+      .query_row(
+        "SELECT gain FROM sound_trim WHERE theme = ?1 AND sound = ?2;",
+        params![theme, sound],
+        |row| row.get::<_, f64>(0),
+      )
+      .optional()?;
+
+    Ok(gain.map(|gain| gain as f32))
+  }
+
+  async fn set_sound_trim(
+    &self,
+    theme: &str,
+    sound: &str,
+    gain: f32,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sound_trim (theme, sound, gain) VALUES (?1, ?2, ?3);",
+      params![theme, sound, gain as f64],
+    )?;
+
+    Ok(())
+  }
+
+  async fn sound_trims(&self) -> Result<Vec<(String, String, f32)>, SamplesDBError> {
+    let mut stmt = self
+      .connection
+      .prepare("SELECT theme, sound, gain FROM sound_trim;")?;
+
+    let trims: Result<Vec<(String, String, f32)>, _> = stmt
+      .query_map(NO_PARAMS, |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get::<_, f64>(2)? as f32))
+      })?
+      .collect();
+
+    Ok(trims?)
+  }
+
+  async fn sample_gain(&self, sample_id: i64) -> Result<Option<f32>, SamplesDBError> {
+    let gain = self
+      .connection
+      .query_row(
+        "SELECT gain FROM sample_gain WHERE sample_id = ?1;",
+        params![sample_id],
+        |row| row.get::<_, f64>(0),
+      )
+      .optional()?;
+
+    Ok(gain.map(|gain| gain as f32))
+  }
+
+  async fn set_sample_gain(&self, sample_id: i64, gain: f32) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO sample_gain (sample_id, gain) VALUES (?1, ?2);",
+      params![sample_id, gain as f64],
+    )?;
+
+    Ok(())
+  }
+
+  async fn waveform(&self, sample_id: i64) -> Result<Option<(i64, String)>, SamplesDBError> {
+    let waveform = self
+      .connection
+      .query_row(
+        "SELECT modified, peaks FROM waveform WHERE sample_id = ?1;",
+        params![sample_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+      )
+      .optional()?;
+
+    Ok(waveform)
+  }
+
+  async fn set_waveform(
+    &self,
+    sample_id: i64,
+    modified: i64,
+    peaks: &str,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT OR REPLACE INTO waveform (sample_id, modified, peaks) VALUES (?1, ?2, ?3);",
+      params![sample_id, modified, peaks],
+    )?;
+
+    Ok(())
+  }
+
+  async fn waveform_dates(&self) -> Result<Vec<(i64, i64)>, SamplesDBError> {
+    let mut stmt = self
+      .connection
+      .prepare("SELECT sample_id, modified FROM waveform;")?;
+
+    let dates: Result<Vec<(i64, i64)>, _> = stmt
+      .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+      .collect();
+
+    Ok(dates?)
+  }
+
+  async fn remove_waveform(&self, sample_id: i64) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "DELETE FROM waveform WHERE sample_id = ?1;",
+      params![sample_id],
+    )?;
+
+    Ok(())
+  }
+
+  async fn sample_details(&self) -> Result<Vec<(i64, i64, f32, SampleFormat)>, SamplesDBError> {
+    let mut stmt = self.connection.prepare(
+      "SELECT sample_id, modified, duration, sample_rate, channels FROM sample_details;",
+    )?;
+
+    let details: Result<Vec<(i64, i64, f32, SampleFormat)>, _> = stmt
+      .query_map(NO_PARAMS, |row| {
+        Ok((
+          row.get(0)?,
+          row.get(1)?,
+          row.get::<_, f64>(2)? as f32,
+          SampleFormat {
+            sample_rate: row.get(3)?,
+            channels: row.get::<_, i64>(4)? as u32,
+          },
+        ))
+      })?
+      .collect();
+
+    Ok(details?)
+  }
+
+  async fn set_sample_details(
+    &self,
+    sample_id: i64,
+    modified: i64,
+    duration: f32,
+    format: &SampleFormat,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT OR REPLACE INTO sample_details (sample_id, modified, duration, sample_rate, channels)
+         VALUES (?1, ?2, ?3, ?4, ?5);",
+      params![
+        sample_id,
+        modified,
+        duration as f64,
+        format.sample_rate,
+        format.channels as i64
+      ],
+    )?;
+
+    Ok(())
+  }
+
+  async fn remove_sample_details(&self, sample_id: i64) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "DELETE FROM sample_details WHERE sample_id = ?1;",
+      params![sample_id],
+    )?;
+
+    Ok(())
+  }
+
+  async fn settings(&self) -> Result<Vec<(String, String)>, SamplesDBError> {
+    let mut stmt = self
+      .connection
+      .prepare("SELECT key, value FROM settings;")?;
+
+    let settings: Result<Vec<(String, String)>, _> = stmt
+      .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+      .collect();
+
+    Ok(settings?)
+  }
+
+  async fn set_setting(&self, key: &str, value: &str) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2);",
+      params![key, value],
+    )?;
+
+    Ok(())
+  }
+
+  async fn client_setting(
+    &self,
+    client: &str,
+    key: &str,
+  ) -> Result<Option<String>, SamplesDBError> {
+    let value = self
+      .connection
+      .query_row(
+        "SELECT value FROM client_setting WHERE client = ?1 AND key = ?2;",
+        params![client, key],
+        |row| row.get(0),
+      )
+      .optional()?;
+
+    Ok(value)
+  }
+
+  async fn set_client_setting(
+    &self,
+    client: &str,
+    key: &str,
+    value: &str,
+  ) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT INTO client_setting (client, key, value) VALUES (?1, ?2, ?3);",
+      params![client, key, value],
+    )?;
+
+    Ok(())
+  }
+
+  async fn client_settings(&self) -> Result<Vec<(String, String, String)>, SamplesDBError> {
+    let mut stmt = self
+      .connection
+      .prepare("SELECT client, key, value FROM client_setting;")?;
+
+    let settings: Result<Vec<(String, String, String)>, _> = stmt
+      .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+      .collect();
+
+    Ok(settings?)
+  }
+
+  async fn themes(&self) -> Result<Vec<String>, SamplesDBError> {
+    let mut stmt = self
+      .connection
+      .prepare("SELECT name FROM theme ORDER BY name;")?;
+
+    let themes: Result<Vec<String>, _> = stmt.query_map(NO_PARAMS, |row| row.get(0))?.collect();
+
+    Ok(themes?)
+  }
+
+  async fn theme(&self, name: &str) -> Result<Option<String>, SamplesDBError> {
+    let theme = self
+      .connection
+      .query_row(
+        "SELECT theme FROM theme WHERE name = ?1;",
+        params![name],
+        |row| row.get(0),
+      )
+      .optional()?;
+
+    Ok(theme)
+  }
+
+  async fn save_theme(&self, name: &str, theme: &str) -> Result<(), SamplesDBError> {
+    self.connection.execute(
+      "INSERT OR REPLACE INTO theme (name, theme) VALUES (?1, ?2);",
+      params![name, theme],
+    )?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,243 @@
+use std::time::Duration;
+
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web::web;
+use actix_web_actors::ws;
+use awc::ws::{Frame, Message};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::{SinkExt, StreamExt};
+
+use crate::api::ChannelSender;
+use crate::audio_engine::engine::state_version::StateVersion;
+use crate::audio_engine::messages::{
+    Command, MissingSampleBehavior, Request, Response, TriggerAction,
+};
+
+// Time to wait before reconnecting to the primary after losing the connection
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Time to wait for the engine to report whether a forwarded command failed
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Commands the primary forwards to its satellites
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum SatelliteMessage {
+    LoadTheme {
+        theme: serde_json::Value,
+        on_missing: MissingSampleBehavior,
+    },
+    Play,
+    Pause,
+    Trigger {
+        sound: String,
+        #[serde(default)]
+        action: TriggerAction,
+    },
+    FireTrigger {
+        trigger: String,
+        action: TriggerAction,
+    },
+    SetScene {
+        name: String,
+    },
+}
+
+impl SatelliteMessage {
+    fn into_command(self) -> Result<Command, serde_json::Error> {
+        Ok(match self {
+            SatelliteMessage::LoadTheme { theme, on_missing } => Command::LoadTheme {
+                theme: serde_json::from_value(theme)?,
+                on_missing,
+                // The primary already reported any warnings
+                strict: false,
+            },
+            SatelliteMessage::Play => Command::Play,
+            SatelliteMessage::Pause => Command::Pause,
+            SatelliteMessage::Trigger { sound, action } => Command::trigger(sound, action),
+            SatelliteMessage::FireTrigger { trigger, action } => {
+                Command::FireTrigger { trigger, action }
+            }
+            SatelliteMessage::SetScene { name } => Command::SetScene { name },
+        })
+    }
+}
+
+/// Websocket connection of a satellite on the primary, sends it the messages broadcast
+/// to all satellites
+pub struct SatelliteSocket {
+    messages: Option<UnboundedReceiver<String>>,
+}
+
+impl SatelliteSocket {
+    pub fn new(messages: UnboundedReceiver<String>) -> Self {
+        Self {
+            messages: Some(messages),
+        }
+    }
+}
+
+impl Actor for SatelliteSocket {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(messages) = self.messages.take() {
+            ctx.add_stream(messages);
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!("Satellite disconnected");
+    }
+}
+
+/// Messages broadcast to the satellites
+impl StreamHandler<String> for SatelliteSocket {
+    fn handle(&mut self, text: String, ctx: &mut Self::Context) {
+        ctx.text(text);
+    }
+}
+
+/// Frames sent by the satellite, it only ever answers pings and closes the connection
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for SatelliteSocket {
+    fn handle(&mut self, frame: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match frame {
+            Ok(ws::Message::Ping(data)) => ctx.pong(&data),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Connection to satellite failed: {}", e);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+/// Satellites connected to this instance
+pub struct Satellites {
+    connections: Vec<UnboundedSender<String>>,
+    // Last theme and play state, so satellites connecting later catch up
+    theme: Option<String>,
+    playback: Option<String>,
+    // Sent along with every message
+    version: StateVersion,
+}
+
+impl Satellites {
+    pub fn new(version: StateVersion) -> Self {
+        Self {
+            connections: Vec::new(),
+            theme: None,
+            playback: None,
+            version,
+        }
+    }
+
+    /// Returns the socket of a new satellite
+    pub fn connect(&mut self) -> SatelliteSocket {
+        let (sender, receiver) = unbounded();
+
+        for message in self.theme.iter().chain(self.playback.iter()) {
+            let _ = sender.unbounded_send(message.clone());
+        }
+
+        self.connections.push(sender);
+        info!("Satellite connected, {} connected", self.connections.len());
+
+        SatelliteSocket::new(receiver)
+    }
+
+    /// Sends the message to all satellites with the state version of the engine, which
+    /// satellites ignore
+    pub fn broadcast(&mut self, message: &SatelliteMessage) {
+        let mut frame = serde_json::to_value(message).unwrap();
+        frame["version"] = self.version.get().into();
+        let text = frame.to_string();
+
+        match message {
+            SatelliteMessage::LoadTheme { .. } => {
+                self.theme = Some(text.clone());
+                self.playback = None;
+            }
+            SatelliteMessage::Play | SatelliteMessage::Pause => self.playback = Some(text.clone()),
+            SatelliteMessage::Trigger { .. }
+            | SatelliteMessage::FireTrigger { .. }
+            | SatelliteMessage::SetScene { .. } => {}
+        }
+
+        // The sockets of satellites that went away have dropped their end of the channel
+        self.connections
+            .retain(|connection| connection.unbounded_send(text.clone()).is_ok());
+    }
+}
+
+fn handle_message(sender: &ChannelSender, text: &[u8]) {
+    let command = match serde_json::from_slice::<SatelliteMessage>(text)
+        .and_then(SatelliteMessage::into_command)
+    {
+        Ok(command) => command,
+        Err(e) => {
+            warn!("Ignoring invalid message from primary: {}", e);
+            return;
+        }
+    };
+
+    let (request, response) = Request::new(command);
+    if sender.send(request).is_err() {
+        error!("Audio engine has stopped, ignoring message from primary");
+        return;
+    }
+
+    // The commands are already queued in order, only the outcome is awaited off the
+    // connection so a busy engine doesn't hold up the next message
+    actix_rt::spawn(async move {
+        let received = web::block(move || response.recv_timeout(RESPONSE_TIMEOUT)).await;
+        if let Ok(Response::Error(error)) = received {
+            warn!("Failed to run command from primary: {}", error);
+        }
+    });
+}
+
+/// Plays whatever the primary at `primary` tells it to, reconnecting whenever the
+/// connection drops
+pub async fn run(primary: &str, token: &str, sender: ChannelSender) {
+    let url = format!("{}/satellite", primary.trim_end_matches('/'));
+
+    loop {
+        info!("Connecting to primary at {}", url);
+
+        match awc::Client::new()
+            .ws(&url)
+            .bearer_auth(token)
+            .connect()
+            .await
+        {
+            Ok((_, mut connection)) => {
+                info!("Connected to primary");
+
+                while let Some(frame) = connection.next().await {
+                    match frame {
+                        Ok(Frame::Text(text)) => handle_message(&sender, &text),
+                        Ok(Frame::Ping(data)) => {
+                            let _ = connection.send(Message::Pong(data)).await;
+                        }
+                        Ok(Frame::Close(_)) => break,
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("Connection to primary failed: {}", e);
+                            break;
+                        }
+                    }
+                }
+
+                warn!("Lost connection to primary");
+            }
+            Err(e) => warn!("Failed to connect to primary: {}", e),
+        }
+
+        actix_rt::time::delay_for(RECONNECT_DELAY).await;
+    }
+}
@@ -0,0 +1,359 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Local, Timelike};
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+
+/// When a sound plays by itself, given in the theme as either
+/// - `every 10-20 min`, optionally followed by `from 20:00 to 06:00`, with the units `s`,
+///   `min` and `h`, counted on the theme's (scaled) time
+/// - a cron expression `minute hour day month weekday`, e.g. `0 * * * *` for every full
+///   hour of the wall clock
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    source: String,
+    pub kind: ScheduleKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScheduleKind {
+    Every {
+        /// Milliseconds between two runs
+        interval: (u64, u64),
+        /// Minutes of the day the sound may play in, may wrap around midnight
+        window: Option<(u32, u32)>,
+    },
+    Cron(Cron),
+}
+
+/// Allowed values of each field, as bit sets
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Standard cron plays on either day if both of them are restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    pub fn matches(&self, time: &DateTime<Local>) -> bool {
+        let is_set = |set: u64, value: u32| set & (1u64 << value) != 0;
+
+        let day = is_set(self.days, time.day());
+        let weekday = is_set(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        };
+
+        is_set(self.minutes, time.minute())
+            && is_set(self.hours, time.hour())
+            && is_set(self.months, time.month())
+            && day_matches
+    }
+}
+
+/// Whether the time of day lies within the window, given in minutes of the day
+pub fn in_window(window: (u32, u32), time: &DateTime<Local>) -> bool {
+    let minute = time.hour() * 60 + time.minute();
+    let (from, to) = window;
+
+    if from <= to {
+        minute >= from && minute < to
+    } else {
+        minute >= from || minute < to
+    }
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => {
+                let step: u32 = part[i + 1..]
+                    .parse()
+                    .map_err(|_| format!("invalid step in '{}'", part))?;
+                (&part[..i], step.max(1))
+            }
+            None => (part, 1),
+        };
+
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some(i) = range.find('-') {
+            let from = range[..i]
+                .parse()
+                .map_err(|_| format!("invalid range '{}'", range))?;
+            let to = range[i + 1..]
+                .parse()
+                .map_err(|_| format!("invalid range '{}'", range))?;
+            (from, to)
+        } else {
+            let value = range
+                .parse()
+                .map_err(|_| format!("invalid value '{}'", range))?;
+            (value, value)
+        };
+
+        if from < min || to > max || from > to {
+            return Err(format!("'{}' is outside of {}-{}", part, min, max));
+        }
+
+        let mut value = from;
+        while value <= to {
+            set |= 1u64 << value;
+            value += step;
+        }
+    }
+
+    Ok(set)
+}
+
+fn parse_cron(source: &str) -> Result<Cron, String> {
+    let fields: Vec<&str> = source.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err("a cron schedule needs 5 fields".to_string());
+    }
+
+    // Sunday can be given as 0 or 7
+    let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+    if weekdays & (1u64 << 7) != 0 {
+        weekdays |= 1;
+    }
+
+    Ok(Cron {
+        minutes: parse_cron_field(fields[0], 0, 59)?,
+        hours: parse_cron_field(fields[1], 0, 23)?,
+        days: parse_cron_field(fields[2], 1, 31)?,
+        months: parse_cron_field(fields[3], 1, 12)?,
+        weekdays,
+        days_restricted: fields[2] != "*",
+        weekdays_restricted: fields[4] != "*",
+    })
+}
+
+fn parse_time_of_day(time: &str) -> Result<u32, String> {
+    let mut parts = time.splitn(2, ':');
+    let hour: u32 = parts
+        .next()
+        .and_then(|h| h.parse().ok())
+        .ok_or_else(|| format!("invalid time '{}'", time))?;
+    let minute: u32 = match parts.next() {
+        Some(m) => m.parse().map_err(|_| format!("invalid time '{}'", time))?,
+        None => 0,
+    };
+
+    if hour > 24 || minute > 59 {
+        return Err(format!("invalid time '{}'", time));
+    }
+
+    Ok(hour * 60 + minute)
+}
+
+/// `every 10-20 min [from 20:00 to 06:00]`, the words following `every`
+fn parse_every(words: &[&str]) -> Result<ScheduleKind, String> {
+    if words.len() < 2 {
+        return Err("expected e.g. 'every 10-20 min'".to_string());
+    }
+    let (amount, unit, rest) = (words[0], words[1], &words[2..]);
+
+    let factor = match unit {
+        "s" | "sec" | "second" | "seconds" => 1000,
+        "m" | "min" | "minute" | "minutes" => 60 * 1000,
+        "h" | "hour" | "hours" => 60 * 60 * 1000,
+        _ => return Err(format!("unknown unit '{}'", unit)),
+    };
+
+    let parse_amount = |a: &str| {
+        a.parse::<f32>()
+            .map_err(|_| format!("invalid interval '{}'", amount))
+    };
+    let (min, max) = match amount.find('-') {
+        Some(i) => (parse_amount(&amount[..i])?, parse_amount(&amount[i + 1..])?),
+        None => {
+            let value = parse_amount(amount)?;
+            (value, value)
+        }
+    };
+
+    if min <= 0.0 || min > max {
+        return Err(format!("invalid interval '{}'", amount));
+    }
+
+    let window = if rest.is_empty() {
+        None
+    } else if rest.len() == 4 && rest[0] == "from" && rest[2] == "to" {
+        Some((parse_time_of_day(rest[1])?, parse_time_of_day(rest[3])?))
+    } else {
+        return Err("expected e.g. 'from 20:00 to 06:00' after the interval".to_string());
+    };
+
+    Ok(ScheduleKind::Every {
+        interval: ((min * factor as f32) as u64, (max * factor as f32) as u64),
+        window,
+    })
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+
+        let kind = match words.first() {
+            Some(&"every") => parse_every(&words[1..])?,
+            Some(_) => ScheduleKind::Cron(parse_cron(s)?),
+            None => return Err("empty schedule".to_string()),
+        };
+
+        Ok(Schedule {
+            source: s.trim().to_string(),
+            kind,
+        })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Schedules are kept in themes as they were written
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        source
+            .parse()
+            .map_err(|e| D::Error::custom(format!("invalid schedule '{}': {}", source, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn cron(source: &str) -> Cron {
+        match source.parse::<Schedule>().unwrap().kind {
+            ScheduleKind::Cron(cron) => cron,
+            kind => panic!("'{}' parsed as {:?}", source, kind),
+        }
+    }
+
+    // 2019-10-16 was a Wednesday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.ymd(2019, 10, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn cron_fields() {
+        assert_eq!(parse_cron_field("*", 0, 3), Ok(0b1111));
+        assert_eq!(parse_cron_field("5", 0, 59), Ok(1 << 5));
+        assert_eq!(parse_cron_field("1-3", 0, 59), Ok(0b1110));
+        assert_eq!(parse_cron_field("1,4", 0, 59), Ok(0b10010));
+        assert_eq!(parse_cron_field("*/15", 0, 59), Ok(1 | 1 << 15 | 1 << 30 | 1 << 45));
+        assert_eq!(parse_cron_field("10-20/5", 0, 59), Ok(1 << 10 | 1 << 15 | 1 << 20));
+    }
+
+    #[test]
+    fn invalid_cron_fields() {
+        assert!(parse_cron_field("60", 0, 59).is_err());
+        assert!(parse_cron_field("0", 1, 31).is_err());
+        assert!(parse_cron_field("5-1", 0, 59).is_err());
+        assert!(parse_cron_field("a", 0, 59).is_err());
+        assert!(parse_cron_field("*/x", 0, 59).is_err());
+        assert!("0 * * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn cron_matches() {
+        let hourly = cron("0 * * * *");
+        assert!(hourly.matches(&at(16, 20, 0)));
+        assert!(!hourly.matches(&at(16, 20, 1)));
+
+        let evenings = cron("30 18-22 * * *");
+        assert!(evenings.matches(&at(16, 18, 30)));
+        assert!(!evenings.matches(&at(16, 23, 30)));
+    }
+
+    #[test]
+    fn cron_sunday_is_0_and_7() {
+        // 2019-10-20 was a Sunday
+        assert!(cron("0 12 * * 0").matches(&at(20, 12, 0)));
+        assert!(cron("0 12 * * 7").matches(&at(20, 12, 0)));
+        assert!(!cron("0 12 * * 7").matches(&at(16, 12, 0)));
+    }
+
+    #[test]
+    fn cron_day_or_weekday() {
+        // Both restricted: the 1st of the month or any Wednesday
+        let either = cron("0 12 1 * 3");
+        assert!(either.matches(&at(16, 12, 0)));
+        assert!(either.matches(&at(1, 12, 0)));
+        assert!(!either.matches(&at(17, 12, 0)));
+
+        // Only the day restricted: the weekday doesn't matter
+        let day = cron("0 12 17 * *");
+        assert!(day.matches(&at(17, 12, 0)));
+        assert!(!day.matches(&at(16, 12, 0)));
+    }
+
+    #[test]
+    fn every() {
+        let schedule: Schedule = "every 10-20 min from 20:00 to 06:00".parse().unwrap();
+        assert_eq!(
+            schedule.kind,
+            ScheduleKind::Every {
+                interval: (600_000, 1_200_000),
+                window: Some((20 * 60, 6 * 60)),
+            }
+        );
+        assert_eq!(schedule.to_string(), "every 10-20 min from 20:00 to 06:00");
+
+        let schedule: Schedule = "every 30 s".parse().unwrap();
+        assert_eq!(
+            schedule.kind,
+            ScheduleKind::Every {
+                interval: (30_000, 30_000),
+                window: None,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_every() {
+        assert!("every".parse::<Schedule>().is_err());
+        assert!("every 10 days".parse::<Schedule>().is_err());
+        assert!("every 20-10 min".parse::<Schedule>().is_err());
+        assert!("every 0 s".parse::<Schedule>().is_err());
+        assert!("every 10 min from 20:00".parse::<Schedule>().is_err());
+        assert!("every 10 min from 25:00 to 06:00".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn windows() {
+        let night = (20 * 60, 6 * 60);
+        assert!(in_window(night, &at(16, 23, 0)));
+        assert!(in_window(night, &at(16, 5, 59)));
+        assert!(!in_window(night, &at(16, 6, 0)));
+        assert!(!in_window(night, &at(16, 12, 0)));
+
+        let afternoon = (12 * 60, 18 * 60);
+        assert!(in_window(afternoon, &at(16, 12, 0)));
+        assert!(!in_window(afternoon, &at(16, 18, 0)));
+    }
+}
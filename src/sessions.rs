@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rand::{thread_rng, Rng};
+
+// Clients that haven't been seen for this long are no longer listed as connected
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Session {
+    name: String,
+    last_seen: Instant,
+}
+
+/// Keeps track of the named sessions clients register, so the status can tell who is
+/// connected and who issued the last state change.
+pub struct Sessions {
+    sessions: HashMap<String, Session>,
+    last_change_by: Option<String>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            last_change_by: None,
+        }
+    }
+
+    /// Registers a new session for a client with the given display name and returns its id
+    pub fn register(&mut self, name: String) -> String {
+        let id = format!("{:016x}", thread_rng().gen::<u64>());
+        info!("Client '{}' registered session {}", name, id);
+
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                name,
+                last_seen: Instant::now(),
+            },
+        );
+
+        id
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        match self.sessions.remove(id) {
+            Some(session) => {
+                info!("Client '{}' closed session {}", session.name, id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the session as alive and returns the name of the client owning it
+    pub fn touch(&mut self, id: Option<&str>) -> Option<String> {
+        let session = self.sessions.get_mut(id?)?;
+        session.last_seen = Instant::now();
+        Some(session.name.clone())
+    }
+
+    /// Remembers which client issued the last state change. Changes from clients without a
+    /// session are recorded as anonymous.
+    pub fn record_change(&mut self, id: Option<&str>) {
+        self.last_change_by = self.touch(id);
+    }
+
+    pub fn last_change_by(&self) -> Option<String> {
+        self.last_change_by.clone()
+    }
+
+    pub fn connected(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .sessions
+            .values()
+            .filter(|session| session.last_seen.elapsed() < SESSION_TIMEOUT)
+            .map(|session| session.name.clone())
+            .collect();
+
+        names.sort();
+        names
+    }
+}
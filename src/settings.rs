@@ -0,0 +1,42 @@
+use crate::audio_engine::backends::base::Equalizer;
+
+/// Preferences persisted in the samples database, applied when the server starts.
+/// Every field is optional, unset fields fall back to the built-in defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Settings {
+    /// Milliseconds to crossfade when a sound's file is replaced without an explicit time
+    pub crossfade: Option<u64>,
+    /// Master volume on startup
+    pub volume: Option<f32>,
+    /// Master EQ on startup
+    pub eq: Option<Equalizer>,
+    /// Turn the limiter on at startup
+    pub limiter: Option<bool>,
+    /// Name of the theme loaded last
+    pub last_theme: Option<String>,
+}
+
+impl Settings {
+    /// Takes over all fields that are set in `other`
+    pub fn merge(&mut self, other: Settings) {
+        if other.crossfade.is_some() {
+            self.crossfade = other.crossfade;
+        }
+
+        if other.volume.is_some() {
+            self.volume = other.volume;
+        }
+
+        if other.eq.is_some() {
+            self.eq = other.eq;
+        }
+
+        if other.limiter.is_some() {
+            self.limiter = other.limiter;
+        }
+
+        if other.last_theme.is_some() {
+            self.last_theme = other.last_theme;
+        }
+    }
+}
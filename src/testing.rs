@@ -0,0 +1,278 @@
+//! Scripted scenarios for themes, e.g. "load the theme, advance 90 s, the thunder played
+//! twice". The engine runs on the null backend in the calling thread and all time comes
+//! from the engine's clock, so nothing waits in real time and no audio hardware is needed.
+//! Requests go through the routes of the web service and its token authorization, the way
+//! a client's would.
+//!
+//! ```ignore
+//! let mut scenario = Scenario::new(Path::new("sounds"))?;
+//! scenario.load_theme(theme)?;
+//! scenario.play()?;
+//! scenario.advance(Duration::from_secs(90));
+//! assert_eq!(scenario.plays("Thunder")?, 2);
+//! ```
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use actix_web::http::{Method, StatusCode};
+use crossbeam_channel::{bounded, unbounded, TryRecvError};
+use serde_json::{json, Value};
+
+use crate::api::TestApi;
+use crate::audio_engine::backends::null::NullBackend;
+use crate::audio_engine::engine::heartbeat::Heartbeat;
+use crate::audio_engine::engine::state_version::StateVersion;
+use crate::audio_engine::engine::{AudioController, EngineConfig};
+use crate::audio_engine::messages::{ApiError, ErrorCode};
+use crate::audio_engine::resampler::Resampler;
+use crate::config::Config;
+use crate::samplesdb::{SamplesDB, SqliteRepository};
+use crate::theme::Theme;
+
+/// Access token of the scenario's web service
+pub const TOKEN: &str = "scenario";
+
+// Longest stretch of time the engine is moved forward by at once while advancing
+const STEP_MS: u64 = 10;
+
+/// An engine on the null backend, driven by the caller
+pub struct Scenario {
+    engine: AudioController<NullBackend>,
+    api: Arc<TestApi>,
+}
+
+impl Scenario {
+    /// Starts an engine on the samples below `sound_library`, with a samples database that
+    /// only lives in memory
+    pub fn new(sound_library: &Path) -> Result<Self, String> {
+        let repository =
+            SqliteRepository::open(Path::new(":memory:")).map_err(|e| e.to_string())?;
+        let samplesdb =
+            SamplesDB::open(Box::new(repository), sound_library).map_err(|e| e.to_string())?;
+        let samplesdb = Arc::new(Mutex::new(samplesdb));
+
+        let (sender, receiver) = unbounded();
+        let heartbeat = Heartbeat::default();
+        let version = StateVersion::default();
+        let config = EngineConfig {
+            ducking: None,
+            pause_fade: 0,
+            max_previews: 0,
+            queue_previews: false,
+            command_log: None,
+            output_rate: None,
+            resampler: Resampler::default(),
+            source_pool_warning: 1.0,
+            max_sources: 256,
+            tick: STEP_MS,
+            heartbeat: heartbeat.clone(),
+            version: version.clone(),
+        };
+        let api_config = Config {
+            token: Some(TOKEN.to_string()),
+            sound_library: Some(sound_library.to_path_buf()),
+            ..Config::default()
+        };
+        let api = TestApi::new(sender, heartbeat, version, samplesdb.clone(), api_config);
+
+        let engine =
+            AudioController::new(receiver, samplesdb, config).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            engine,
+            api: Arc::new(api),
+        })
+    }
+
+    /// Sends a request to the web service, with `token` as bearer token if given, and
+    /// returns the status and JSON body of the response (null if the body isn't JSON).
+    /// The engine handles the commands of the request without letting any time pass.
+    pub fn api(
+        &mut self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let (sender, receiver) = bounded(1);
+        let api = self.api.clone();
+        let route = path.to_string();
+        let token = token.map(str::to_string);
+
+        // The routes wait for the engine, which runs in this thread
+        thread::spawn(move || {
+            let mut system = actix_rt::System::new("scenario");
+            let token = token.as_ref().map(String::as_str);
+            let response = system.block_on(api.call(method, &route, token, body));
+            let _ = sender.send(response);
+        });
+
+        loop {
+            match receiver.try_recv() {
+                Ok((status, body)) => {
+                    return (status, serde_json::from_slice(&body).unwrap_or(Value::Null));
+                }
+                Err(TryRecvError::Empty) => {
+                    self.engine.handle_messages(Duration::from_millis(1));
+                }
+                Err(TryRecvError::Disconnected) => panic!("Request to {} panicked", path),
+            }
+        }
+    }
+
+    /// Like `api` with the scenario's token, turns responses other than success into the
+    /// error they carry
+    fn request(
+        &mut self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value, ApiError> {
+        let (status, body) = self.api(method, path, Some(TOKEN), body);
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        match body.get("Error").cloned().map(serde_json::from_value) {
+            Some(Ok(error)) => Err(error),
+            _ => Err(ApiError::new(
+                ErrorCode::Internal,
+                format!("{} answered with {}", path, status),
+            )),
+        }
+    }
+
+    /// Status of the engine, as `GET /status` returns it
+    fn status(&mut self) -> Result<Value, ApiError> {
+        let status = self.request(Method::GET, "/status", None)?;
+        Ok(status["Status"].clone())
+    }
+
+    /// Moves the engine forward, in small steps so sounds start and end about when they
+    /// would in real time
+    pub fn advance(&mut self, duration: Duration) {
+        let mut left = duration.as_secs() * 1000 + u64::from(duration.subsec_millis());
+
+        while left > 0 {
+            let step = left.min(STEP_MS);
+
+            if let Err(e) = self.engine.update(step) {
+                error!("Error while advancing scenario: {}", e);
+            }
+
+            left -= step;
+        }
+    }
+
+    /// Milliseconds the scenario has been advanced in total
+    pub fn elapsed(&self) -> u64 {
+        self.engine.clock().elapsed_ms()
+    }
+
+    pub fn load_theme(&mut self, theme: Theme) -> Result<(), ApiError> {
+        let theme =
+            serde_json::to_value(&theme).map_err(|e| ApiError::new(ErrorCode::InvalidValue, e))?;
+        self.request(Method::POST, "/theme", Some(theme))
+            .map(|_| ())
+    }
+
+    pub fn play(&mut self) -> Result<(), ApiError> {
+        self.request(Method::POST, "/play", None).map(|_| ())
+    }
+
+    pub fn pause(&mut self) -> Result<(), ApiError> {
+        self.request(Method::POST, "/pause", None).map(|_| ())
+    }
+
+    /// Starts a trigger sound, or cancels it if it is already playing
+    pub fn trigger(&mut self, sound: &str) -> Result<(), ApiError> {
+        self.request(Method::POST, "/trigger", Some(json!({ "name": sound })))
+            .map(|_| ())
+    }
+
+    /// Times the sound started playing since its theme was loaded
+    pub fn plays(&mut self, sound: &str) -> Result<u32, ApiError> {
+        self.status()?["sounds"][sound]["plays"]
+            .as_u64()
+            .map(|plays| plays as u32)
+            .ok_or_else(|| ApiError::unknown_sound(sound))
+    }
+
+    /// Whether the sound is playing right now
+    pub fn is_playing(&mut self, sound: &str) -> Result<bool, ApiError> {
+        Ok(self.status()?["sounds_playing"]
+            .as_array()
+            .map_or(false, |playing| playing.iter().any(|s| s == sound)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // The sounds of the tests are generated, so the library stays empty
+    fn sound_library() -> PathBuf {
+        let path = env::temp_dir().join("sinfonia-scenario-library");
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn storm() -> Theme {
+        serde_json::from_value(json!({
+            "version": 2,
+            "name": "Storm",
+            "sounds": [{
+                "name": "Thunder",
+                "type": "tone",
+                "duration": 1.0,
+                "volume": [1.0, 1.0],
+                "trigger": "thunder",
+                "enabled": true
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn triggered_sound_plays_once() {
+        let mut scenario = Scenario::new(&sound_library()).unwrap();
+        scenario.load_theme(storm()).unwrap();
+        scenario.play().unwrap();
+        assert_eq!(scenario.plays("Thunder").unwrap(), 0);
+
+        scenario.trigger("Thunder").unwrap();
+        scenario.advance(Duration::from_millis(500));
+        assert!(scenario.is_playing("Thunder").unwrap());
+        assert_eq!(scenario.elapsed(), 500);
+
+        scenario.advance(Duration::from_secs(1));
+        assert!(!scenario.is_playing("Thunder").unwrap());
+        assert_eq!(scenario.plays("Thunder").unwrap(), 1);
+    }
+
+    #[test]
+    fn unknown_sound_is_an_error() {
+        let mut scenario = Scenario::new(&sound_library()).unwrap();
+        scenario.load_theme(storm()).unwrap();
+
+        let error = scenario.trigger("Rain").unwrap_err();
+        assert_eq!(error.code, ErrorCode::UnknownSound);
+    }
+
+    #[test]
+    fn requests_need_the_token() {
+        let mut scenario = Scenario::new(&sound_library()).unwrap();
+
+        let (status, _) = scenario.api(Method::POST, "/play", None, None);
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = scenario.api(Method::POST, "/play", Some("wrong"), None);
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
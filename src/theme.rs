@@ -1,3 +1,14 @@
+use std::collections::HashMap;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+use crate::audio_engine::backends::base::{CustomReverb, Effect};
+use crate::migrate::{migrate_theme, THEME_VERSION};
+use crate::profiles::apply_profile;
+use crate::schedule::Schedule;
+
 fn get_default_count() -> (u32, u32) {
     (0, 0)
 }
@@ -6,6 +17,10 @@ fn get_default_fade_in() -> (f32, f32) {
     (0.0, 0.0)
 }
 
+fn get_default_start_offset() -> (f32, f32) {
+    (0.0, 0.0)
+}
+
 fn get_default_pitch() -> (f32, f32) {
     (1.0, 1.0)
 }
@@ -14,21 +29,157 @@ fn get_default_delay() -> (u64, u64) {
     (0, 0)
 }
 
+fn get_default_pan() -> (f32, f32) {
+    (0.0, 0.0)
+}
+
+fn get_default_variation() -> (f32, f32) {
+    (0.0, 0.0)
+}
+
+fn get_default_probability() -> f32 {
+    1.0
+}
+
+fn get_default_frequency() -> f32 {
+    880.0
+}
+
+fn get_default_duration() -> f32 {
+    0.2
+}
+
+fn get_default_path_step() -> u64 {
+    2000
+}
+
+fn get_default_playlist_volume() -> f32 {
+    1.0
+}
+
+fn get_default_repeat() -> bool {
+    true
+}
+
 fn get_default_reverb() -> String {
     "none".to_string()
 }
 
-#[derive(Deserialize)]
+fn get_theme_version() -> u32 {
+    THEME_VERSION
+}
+
+/// What happens to a sound's countdown to its next run while the theme is paused
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseBehavior {
+    /// The countdown stops and continues where it left off once resumed
+    Freeze,
+    /// The countdown keeps running, the sound starts right away when resumed if it is due
+    KeepRunning,
+    /// A new delay is picked once resumed
+    Reset,
+}
+
+impl Default for PauseBehavior {
+    fn default() -> Self {
+        PauseBehavior::Freeze
+    }
+}
+
+/// Where the audio of a sound comes from
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SoundType {
+    /// A sample from the sound library
+    File,
+    /// Noise generated when the theme is loaded, no file needed
+    Noise,
+    /// A synthesized tone, e.g. for countdown beeps
+    Tone,
+}
+
+impl Default for SoundType {
+    fn default() -> Self {
+        SoundType::File
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseColor {
+    White,
+    Pink,
+    Brown,
+}
+
+impl Default for NoiseColor {
+    fn default() -> Self {
+        NoiseColor::Pink
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Waveform {
+    Sine,
+    Square,
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        Waveform::Sine
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Sound {
     pub name: String,
+
+    /// Profile of the theme or built-in profile (one_shot, ambient_bed or music) the sound
+    /// takes the values it doesn't set itself from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    #[serde(default, rename = "type")]
+    pub sound_type: SoundType,
+
+    #[serde(default)]
     pub file: String,
+
+    /// Keep stereo files stereo instead of mixing them down to mono, e.g. for music.
+    /// Stereo sounds can't be positioned or panned.
+    #[serde(default)]
+    pub stereo: bool,
+
+    /// Color of the noise generated for sounds of type noise
+    #[serde(default)]
+    pub color: NoiseColor,
+
+    /// Waveform, frequency in Hz and duration in seconds of sounds of type tone
+    #[serde(default)]
+    pub waveform: Waveform,
+
+    #[serde(default = "get_default_frequency")]
+    pub frequency: f32,
+
+    #[serde(default = "get_default_duration")]
+    pub duration: f32,
+
     pub volume: (f32, f32),
+    /// Name of the trigger the sound waits for, firing it through `POST /trigger/{name}`
+    /// triggers all sounds sharing the name at once
     pub trigger: Option<String>,
     pub enabled: bool,
 
     #[serde(default = "get_default_reverb")]
     pub reverb: String,
 
+    /// Echo, chorus, distortion or further reverbs the sound is sent through on top of
+    /// its reverb
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+
     #[serde(default = "get_default_count")]
     pub repeat_count: (u32, u32),
 
@@ -44,6 +195,11 @@ pub struct Sound {
     #[serde(default)]
     pub loop_forever: bool,
 
+    /// Milliseconds the end of a loop overlaps with the start of the next one, hiding
+    /// the seam of samples that click when they start over
+    #[serde(default)]
+    pub loop_crossfade_ms: u64,
+
     #[serde(default)]
     pub pitch_enabled: bool,
 
@@ -62,15 +218,236 @@ pub struct Sound {
     #[serde(default = "get_default_pitch")]
     pub highpass: (f32, f32),
 
+    /// Stereo balance of each run, from -1.0 (left) to 1.0 (right). Ignored for sounds
+    /// with a position.
+    #[serde(default)]
+    pub pan_enabled: bool,
+
+    #[serde(default = "get_default_pan")]
+    pub pan: (f32, f32),
+
     #[serde(default)]
     pub fade_in_enabled: bool,
 
     #[serde(default = "get_default_fade_in")]
     pub fade_in: (f32, f32),
+
+    /// Seconds into the file each run starts at, so long loops like a rain ambience
+    /// don't start over from the same spot every time. Offsets past the end wrap around.
+    #[serde(default = "get_default_start_offset")]
+    pub start_offset: (f32, f32),
+
+    #[serde(default)]
+    pub variation_enabled: bool,
+
+    #[serde(default = "get_default_variation")]
+    pub variation: (f32, f32),
+
+    #[serde(default)]
+    pub on_pause: PauseBehavior,
+
+    #[serde(default = "get_default_probability")]
+    pub play_probability: f32,
+
+    /// Music is shown as the featured track on stream overlays
+    #[serde(default)]
+    pub music: bool,
+
+    /// Mixer group (e.g. "weather", "music", "sfx") whose volume applies on top of the
+    /// sound's own
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// Groups whose sounds are lowered while this sound plays, e.g. a thunder clap
+    /// ducking the "music"
+    #[serde(default)]
+    pub ducks: Vec<String>,
+
+    /// Position (x, y, z) of the sound in the room, with x to the right, y up and -z to the
+    /// front as seen from the origin, where the listener starts out. It is only relative
+    /// to the listener with `position_mode` listener. Sounds without a position are not
+    /// placed anywhere.
+    #[serde(default)]
+    pub position: Option<(f32, f32, f32)>,
+
+    /// Whether the position is fixed in the room or moves along with the listener
+    #[serde(default)]
+    pub position_mode: PositionMode,
+
+    /// Positions the sound moves along while playing, going back to the first after the
+    /// last one
+    #[serde(default)]
+    pub path: Vec<(f32, f32, f32)>,
+
+    /// Milliseconds it takes to move from one position of the path to the next
+    #[serde(default = "get_default_path_step")]
+    pub path_step: u64,
+
+    /// When the sound plays by itself, e.g. "every 10-20 min from 20:00 to 06:00" or the
+    /// cron expression "0 * * * *"
+    #[serde(default)]
+    pub schedule: Vec<Schedule>,
+
+    /// The sound starts out silenced while mixing, it keeps running
+    #[serde(default)]
+    pub muted: bool,
 }
 
-#[derive(Deserialize)]
+impl Sound {
+    /// Sounds with a trigger or a schedule wait until they are started instead of
+    /// playing right away
+    pub fn waits_for_trigger(&self) -> bool {
+        self.trigger.is_some() || !self.schedule.is_empty()
+    }
+}
+
+/// A single step of a theme macro
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MacroAction {
+    Trigger { sound: String },
+    Wait { seconds: f32 },
+    SetVolume { sound: String, volume: f32 },
+}
+
+/// What the position of a sound is relative to
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionMode {
+    /// Fixed in the room, e.g. for speakers set up around the table
+    World,
+    /// Relative to the listener, turning with them, e.g. for headphones
+    Listener,
+}
+
+impl Default for PositionMode {
+    fn default() -> Self {
+        PositionMode::World
+    }
+}
+
+/// Order the tracks of a playlist are played in
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistOrder {
+    Sequential,
+    /// A new random order every time the playlist starts over
+    Shuffle,
+}
+
+impl Default for PlaylistOrder {
+    fn default() -> Self {
+        PlaylistOrder::Sequential
+    }
+}
+
+/// Music played track after track once the theme plays, instead of being looped like
+/// ambience
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Playlist {
+    pub name: String,
+    /// Samples of the sound library, one per track
+    pub tracks: Vec<String>,
+
+    #[serde(default)]
+    pub order: PlaylistOrder,
+
+    /// Milliseconds of silence between two tracks
+    #[serde(default)]
+    pub gap: u64,
+
+    /// Milliseconds the end of a track overlaps with the start of the next one, used
+    /// instead of the gap
+    #[serde(default)]
+    pub crossfade: u64,
+
+    #[serde(default = "get_default_playlist_volume")]
+    pub volume: f32,
+
+    /// Start over after the last track, otherwise the playlist stops there
+    #[serde(default = "get_default_repeat")]
+    pub repeat: bool,
+
+    /// Play stereo tracks in stereo instead of mixing them down to mono
+    #[serde(default)]
+    pub stereo: bool,
+
+    /// Mixer group whose volume and ducking apply to the playlist like to the sounds
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+#[derive(Serialize)]
 pub struct Theme {
+    /// Version of the theme format, themes without one are converted based on what their
+    /// sounds look like
+    pub version: u32,
+
     pub name: String,
+
     pub sounds: Vec<Sound>,
+
+    pub macros: HashMap<String, Vec<MacroAction>>,
+
+    pub playlists: Vec<Playlist>,
+
+    /// Moods of the theme (e.g. "day", "night", "combat") and the sounds and playlists
+    /// each of them plays. Those that aren't part of any scene play in all of them.
+    pub scenes: HashMap<String, Vec<String>>,
+
+    /// Scene the theme starts out in, the first one by name if there is none
+    pub initial_scene: Option<String>,
+
+    /// Reverbs sounds can use by name besides the built-in presets
+    pub reverbs: HashMap<String, CustomReverb>,
+
+    /// Values sounds can take by naming the profile, besides the built-in profiles
+    pub profiles: HashMap<String, Map<String, Value>>,
+}
+
+/// Reads a theme of the current version, the fields have to match those of `Theme`
+#[derive(Deserialize)]
+#[serde(remote = "Theme")]
+struct ThemeDef {
+    #[serde(default = "get_theme_version")]
+    version: u32,
+    name: String,
+    sounds: Vec<Sound>,
+    #[serde(default)]
+    macros: HashMap<String, Vec<MacroAction>>,
+    #[serde(default)]
+    playlists: Vec<Playlist>,
+    #[serde(default)]
+    scenes: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    initial_scene: Option<String>,
+    #[serde(default)]
+    reverbs: HashMap<String, CustomReverb>,
+    #[serde(default)]
+    profiles: HashMap<String, Map<String, Value>>,
+}
+
+/// Themes are converted from the version they declare and their sounds are resolved
+/// against their profiles before they are read
+impl<'de> Deserialize<'de> for Theme {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut theme = Value::deserialize(deserializer)?;
+        migrate_theme(&mut theme).map_err(D::Error::custom)?;
+
+        let profiles = match theme.get("profiles") {
+            Some(Value::Object(profiles)) => profiles.clone(),
+            Some(Value::Null) | None => Map::new(),
+            Some(_) => return Err(D::Error::custom("profiles has to be an object")),
+        };
+
+        if let Some(Value::Array(sounds)) = theme.get_mut("sounds") {
+            for sound in sounds.iter_mut() {
+                if let Value::Object(fields) = sound {
+                    apply_profile(fields, &profiles).map_err(D::Error::custom)?;
+                }
+            }
+        }
+
+        ThemeDef::deserialize(theme).map_err(D::Error::custom)
+    }
 }